@@ -0,0 +1,726 @@
+//! ASTC block decoding for the LDR profile. HDR endpoint modes and
+//! malformed blocks decode to the error color, magenta.
+
+const ERROR_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+/// Decodes one `width` x `height` block into `out`. sRGB blocks come out
+/// still encoded, with the 8 bits of precision they have.
+pub fn decode_block(block: &[u8], width: u32, height: u32, srgb: bool, out: &mut [[f32; 4]]) {
+    let bits = u128::from_le_bytes(block.try_into().unwrap());
+    if decode(bits, width as usize, height as usize, srgb, out).is_none() {
+        out.iter_mut().for_each(|texel| *texel = ERROR_COLOR);
+    }
+}
+
+/// Extracts `count` bits starting at `start`.
+fn field(bits: u128, start: usize, count: usize) -> u32 {
+    (bits >> start) as u32 & ((1u64 << count) - 1) as u32
+}
+
+/// The weight grid and its encoding, from the 11-bit block mode.
+struct BlockMode {
+    grid_width: usize,
+    grid_height: usize,
+    dual_plane: bool,
+    weight_range: Range,
+}
+
+fn block_mode(mode: u32) -> Option<BlockMode> {
+    let bit = |n: u32| mode >> n & 1;
+    let a = (mode >> 5 & 3) as usize;
+    let b = (mode >> 7 & 3) as usize;
+    let (mut high_precision, mut dual_plane) = (bit(9) == 1, bit(10) == 1);
+    let (grid_width, grid_height, range_index) = if mode & 3 != 0 {
+        let range = bit(4) | (mode & 3) << 1;
+        let (w, h) = match mode >> 2 & 3 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if bit(8) == 0 => (a + 2, (b & 1) + 6),
+            _ => ((b & 1) + 2, a + 2),
+        };
+        (w, h, range)
+    } else {
+        let range = bit(4) | (mode >> 2 & 3) << 1;
+        if range < 2 {
+            return None;
+        }
+        let (w, h) = match mode >> 7 & 3 {
+            0 => (12, a + 2),
+            1 => (a + 2, 12),
+            2 => {
+                high_precision = false;
+                dual_plane = false;
+                (a + 6, (mode >> 9 & 3) as usize + 6)
+            }
+            _ => match a {
+                0 => (6, 10),
+                1 => (10, 6),
+                _ => return None,
+            },
+        };
+        (w, h, range)
+    };
+    // Each of the 3-bit range fields covers the second half of the table
+    // when the precision bit is set.
+    let weight_range = WEIGHT_RANGES[range_index as usize - 2 + 6 * high_precision as usize];
+    Some(BlockMode {
+        grid_width,
+        grid_height,
+        dual_plane,
+        weight_range,
+    })
+}
+
+/// A quantization range for integer sequence encoding: values are
+/// `bits` plain bits, optionally above a trit or a quint.
+#[derive(Clone, Copy)]
+struct Range {
+    trits: bool,
+    quints: bool,
+    bits: usize,
+}
+
+const fn range(trits: bool, quints: bool, bits: usize) -> Range {
+    Range {
+        trits,
+        quints,
+        bits,
+    }
+}
+
+impl Range {
+    /// Bits taken by `count` values.
+    fn sequence_bits(self, count: usize) -> usize {
+        let extra = if self.trits {
+            (8 * count).div_ceil(5)
+        } else if self.quints {
+            (7 * count).div_ceil(3)
+        } else {
+            0
+        };
+        count * self.bits + extra
+    }
+}
+
+/// Weight ranges in block mode order: 2, 3, 4, 5, 6 and 8 levels, then
+/// the high precision 10, 12, 16, 20, 24 and 32.
+const WEIGHT_RANGES: [Range; 12] = [
+    range(false, false, 1),
+    range(true, false, 0),
+    range(false, false, 2),
+    range(false, true, 0),
+    range(true, false, 1),
+    range(false, false, 3),
+    range(false, true, 1),
+    range(true, false, 2),
+    range(false, false, 4),
+    range(false, true, 2),
+    range(true, false, 3),
+    range(false, false, 5),
+];
+
+/// Color endpoint ranges from 6 to 256 levels, the ones a block can pick.
+const COLOR_RANGES: [Range; 17] = [
+    range(true, false, 1),
+    range(false, false, 3),
+    range(false, true, 1),
+    range(true, false, 2),
+    range(false, false, 4),
+    range(false, true, 2),
+    range(true, false, 3),
+    range(false, false, 5),
+    range(false, true, 3),
+    range(true, false, 4),
+    range(false, false, 6),
+    range(false, true, 4),
+    range(true, false, 5),
+    range(false, false, 7),
+    range(false, true, 5),
+    range(true, false, 6),
+    range(false, false, 8),
+];
+
+/// Reads an integer sequence of `count` values from `bits`, starting at
+/// `start`. Bits past `end` read as zero.
+fn decode_sequence(bits: u128, start: usize, end: usize, count: usize, range: Range) -> Vec<u32> {
+    let mut pos = start;
+    let mut read = |count: usize| {
+        let mut value = 0;
+        for i in 0..count {
+            if pos + i < end {
+                value |= field(bits, pos + i, 1) << i;
+            }
+        }
+        pos += count;
+        value
+    };
+    let mut values = Vec::with_capacity(count);
+    let n = range.bits;
+    if range.trits {
+        while values.len() < count {
+            // Five values share 8 bits of packed trits, interleaved with
+            // their low bits.
+            let mut low = [0; 5];
+            low[0] = read(n);
+            let mut packed = read(2);
+            low[1] = read(n);
+            packed |= read(2) << 2;
+            low[2] = read(n);
+            packed |= read(1) << 4;
+            low[3] = read(n);
+            packed |= read(2) << 5;
+            low[4] = read(n);
+            packed |= read(1) << 7;
+            let trits = unpack_trits(packed);
+            values.extend((0..5).map(|i| trits[i] << n | low[i]));
+        }
+    } else if range.quints {
+        while values.len() < count {
+            let mut low = [0; 3];
+            low[0] = read(n);
+            let mut packed = read(3);
+            low[1] = read(n);
+            packed |= read(2) << 3;
+            low[2] = read(n);
+            packed |= read(2) << 5;
+            let quints = unpack_quints(packed);
+            values.extend((0..3).map(|i| quints[i] << n | low[i]));
+        }
+    } else {
+        values.extend((0..count).map(|_| read(n)));
+    }
+    values.truncate(count);
+    values
+}
+
+fn unpack_trits(t: u32) -> [u32; 5] {
+    let bit = |v: u32, n: u32| v >> n & 1;
+    let (c, t3, t4);
+    if t >> 2 & 7 == 7 {
+        c = (t >> 5 & 7) << 2 | (t & 3);
+        t4 = 2;
+        t3 = 2;
+    } else {
+        c = t & 0x1f;
+        if t >> 5 & 3 == 3 {
+            t4 = 2;
+            t3 = bit(t, 7);
+        } else {
+            t4 = bit(t, 7);
+            t3 = t >> 5 & 3;
+        }
+    }
+    let (t0, t1, t2);
+    if c & 3 == 3 {
+        t2 = 2;
+        t1 = bit(c, 4);
+        t0 = bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3) & 1);
+    } else if c >> 2 & 3 == 3 {
+        t2 = 2;
+        t1 = 2;
+        t0 = c & 3;
+    } else {
+        t2 = bit(c, 4);
+        t1 = c >> 2 & 3;
+        t0 = bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1) & 1);
+    }
+    [t0, t1, t2, t3, t4]
+}
+
+fn unpack_quints(q: u32) -> [u32; 3] {
+    let bit = |v: u32, n: u32| v >> n & 1;
+    if q >> 1 & 3 == 3 && q >> 5 & 3 == 0 {
+        let q2 = bit(q, 0) << 2 | (bit(q, 4) & !bit(q, 0) & 1) << 1 | (bit(q, 3) & !bit(q, 0) & 1);
+        return [4, 4, q2];
+    }
+    let (q2, c) = if q >> 1 & 3 == 3 {
+        (4, (q >> 3 & 3) << 3 | (!(q >> 5) & 3) << 1 | bit(q, 0))
+    } else {
+        (q >> 5 & 3, q & 0x1f)
+    };
+    let (q0, q1) = if c & 7 == 5 {
+        (c >> 3 & 3, 4)
+    } else {
+        (c & 7, c >> 3 & 3)
+    };
+    [q0, q1, q2]
+}
+
+/// Scales a color endpoint value to 0-255.
+fn unquantize_color(value: u32, range: Range) -> u32 {
+    let n = range.bits;
+    if !range.trits && !range.quints {
+        // Replicate the bits to fill a byte.
+        let mut result = 0;
+        let mut shift = 8i32 - n as i32;
+        while shift > -(n as i32) {
+            result |= if shift >= 0 {
+                value << shift
+            } else {
+                value >> -shift
+            };
+            shift -= n as i32;
+        }
+        return result & 0xff;
+    }
+    let bit = |i: usize| value >> i & 1;
+    let d = value >> n;
+    let a = if value & 1 == 1 { 0x1ff } else { 0 };
+    let (b, c) = match (range.trits, n) {
+        (true, 1) => (0, 204),
+        (true, 2) => (bit(1) * 0b100010110, 93),
+        (true, 3) => {
+            let (b, c) = (bit(1), bit(2));
+            (c << 8 | b << 7 | c << 3 | b << 2 | c << 1 | b, 44)
+        }
+        (true, 4) => {
+            let (b, c, d) = (bit(1), bit(2), bit(3));
+            (d << 8 | c << 7 | b << 6 | d << 2 | c << 1 | b, 22)
+        }
+        (true, 5) => {
+            let (b, c, d, e) = (bit(1), bit(2), bit(3), bit(4));
+            (e << 8 | d << 7 | c << 6 | b << 5 | e << 1 | d, 11)
+        }
+        (true, _) => {
+            let (b, c, d, e, f) = (bit(1), bit(2), bit(3), bit(4), bit(5));
+            (f << 8 | e << 7 | d << 6 | c << 5 | b << 4 | f, 5)
+        }
+        (false, 1) => (0, 113),
+        (false, 2) => (bit(1) * 0b100001100, 54),
+        (false, 3) => {
+            let (b, c) = (bit(1), bit(2));
+            (c << 8 | b << 7 | c << 2 | b << 1 | c, 26)
+        }
+        (false, 4) => {
+            let (b, c, d) = (bit(1), bit(2), bit(3));
+            (d << 8 | c << 7 | b << 6 | d << 1 | c, 13)
+        }
+        (false, _) => {
+            let (b, c, d, e) = (bit(1), bit(2), bit(3), bit(4));
+            (e << 8 | d << 7 | c << 6 | b << 5 | e, 6)
+        }
+    };
+    let t = (d * c + b) ^ a;
+    (a & 0x80) | t >> 2
+}
+
+/// Scales a weight to 0-64.
+fn unquantize_weight(value: u32, range: Range) -> u32 {
+    let n = range.bits;
+    let result = if !range.trits && !range.quints {
+        // Replicate the bits to fill six.
+        let mut result = 0;
+        let mut shift = 6i32 - n as i32;
+        while shift > -(n as i32) {
+            result |= if shift >= 0 {
+                value << shift
+            } else {
+                value >> -shift
+            };
+            shift -= n as i32;
+        }
+        result & 0x3f
+    } else if n == 0 {
+        match range.trits {
+            true => [0, 32, 63][value as usize],
+            false => [0, 16, 32, 47, 63][value as usize],
+        }
+    } else {
+        let bit = |i: usize| value >> i & 1;
+        let d = value >> n;
+        let a = if value & 1 == 1 { 0x7f } else { 0 };
+        let (b, c) = match (range.trits, n) {
+            (true, 1) => (0, 50),
+            (true, 2) => (bit(1) * 0b1000101, 23),
+            (true, _) => {
+                let (b, c) = (bit(1), bit(2));
+                (c << 6 | b << 5 | c << 1 | b, 11)
+            }
+            (false, 1) => (0, 28),
+            (false, _) => (bit(1) * 0b1000010, 13),
+        };
+        let t = (d * c + b) ^ a;
+        (a & 0x20) | t >> 2
+    };
+    if result > 32 {
+        result + 1
+    } else {
+        result
+    }
+}
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_mul(0xeede0891);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+/// The partition of texel (x, y), from the block's partition index.
+fn select_partition(seed: u32, mut x: u32, mut y: u32, partitions: u32, small: bool) -> usize {
+    if small {
+        x <<= 1;
+        y <<= 1;
+    }
+    let seed = seed + (partitions - 1) * 1024;
+    let rnum = hash52(seed);
+    let mut seeds = [
+        rnum,
+        rnum >> 4,
+        rnum >> 8,
+        rnum >> 12,
+        rnum >> 16,
+        rnum >> 20,
+        rnum >> 24,
+        rnum >> 28,
+        rnum >> 18,
+        rnum >> 22,
+        rnum >> 26,
+        rnum.rotate_left(2),
+    ]
+    .map(|s| {
+        let s = s & 0xf;
+        s * s
+    });
+    let (sh1, sh2) = if seed & 1 == 1 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partitions == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partitions == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= match i {
+            0..=7 if i % 2 == 0 => sh1,
+            0..=7 => sh2,
+            _ => sh3,
+        };
+    }
+    // z is zero for 2D blocks, so seeds 9 to 12 only matter through rnum.
+    let a = (seeds[0] * x + seeds[1] * y + (rnum >> 14)) & 0x3f;
+    let b = (seeds[2] * x + seeds[3] * y + (rnum >> 10)) & 0x3f;
+    let mut c = (seeds[4] * x + seeds[5] * y + (rnum >> 6)) & 0x3f;
+    let mut d = (seeds[6] * x + seeds[7] * y + (rnum >> 2)) & 0x3f;
+    if partitions < 4 {
+        d = 0;
+    }
+    if partitions < 3 {
+        c = 0;
+    }
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+/// Moves the top bit of `a` into `b`, leaving `a` a signed 6-bit offset.
+fn bit_transfer_signed(a: &mut i32, b: &mut i32) {
+    *b >>= 1;
+    *b |= *a & 0x80;
+    *a >>= 1;
+    *a &= 0x3f;
+    if *a & 0x20 != 0 {
+        *a -= 0x40;
+    }
+}
+
+fn blue_contract(c: [i32; 4]) -> [i32; 4] {
+    [(c[0] + c[2]) >> 1, (c[1] + c[2]) >> 1, c[2], c[3]]
+}
+
+/// The two endpoints of an LDR color endpoint mode, or `None` for HDR
+/// modes.
+fn endpoints(mode: u32, v: &[u32]) -> Option<[[i32; 4]; 2]> {
+    let v: Vec<i32> = v.iter().map(|&v| v as i32).collect();
+    let clamp = |c: [i32; 4]| c.map(|c| c.clamp(0, 255));
+    let pair = match mode {
+        0 => [[v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]],
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xc0);
+            let l1 = (l0 + (v[1] & 0x3f)).min(255);
+            [[l0, l0, l0, 255], [l1, l1, l1, 255]]
+        }
+        4 => [[v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]],
+        5 => {
+            let (mut v0, mut v1, mut v2, mut v3) = (v[0], v[1], v[2], v[3]);
+            bit_transfer_signed(&mut v1, &mut v0);
+            bit_transfer_signed(&mut v3, &mut v2);
+            let l1 = v0 + v1;
+            [[v0, v0, v0, v2], clamp([l1, l1, l1, v2 + v3])]
+        }
+        6 => [
+            [
+                (v[0] * v[3]) >> 8,
+                (v[1] * v[3]) >> 8,
+                (v[2] * v[3]) >> 8,
+                255,
+            ],
+            [v[0], v[1], v[2], 255],
+        ],
+        8 | 12 => {
+            let (a0, a1) = if mode == 12 { (v[6], v[7]) } else { (255, 255) };
+            let (e0, e1) = ([v[0], v[2], v[4], a0], [v[1], v[3], v[5], a1]);
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                [e0, e1]
+            } else {
+                [blue_contract(e1), blue_contract(e0)]
+            }
+        }
+        9 | 13 => {
+            let mut base = [0, 0, 0, 255];
+            let mut offset = [0; 4];
+            let channels = if mode == 13 { 4 } else { 3 };
+            for c in 0..channels {
+                let (mut b, mut a) = (v[2 * c], v[2 * c + 1]);
+                bit_transfer_signed(&mut a, &mut b);
+                base[c] = b;
+                offset[c] = a;
+            }
+            let sum = [0, 1, 2, 3].map(|c| base[c] + offset[c]);
+            if offset[0] + offset[1] + offset[2] >= 0 {
+                [base, clamp(sum)]
+            } else {
+                [clamp(blue_contract(sum)), clamp(blue_contract(base))]
+            }
+        }
+        10 => [
+            [
+                (v[0] * v[3]) >> 8,
+                (v[1] * v[3]) >> 8,
+                (v[2] * v[3]) >> 8,
+                v[4],
+            ],
+            [v[0], v[1], v[2], v[5]],
+        ],
+        _ => return None,
+    };
+    Some(pair)
+}
+
+fn decode(bits: u128, width: usize, height: usize, srgb: bool, out: &mut [[f32; 4]]) -> Option<()> {
+    let mode = field(bits, 0, 11);
+    if mode & 0x1ff == 0x1fc {
+        // Void-extent: one color for the whole block.
+        // Bits 10 and 11 are reserved as ones, and the extents, when
+        // given, must not be empty.
+        let extents = [12, 25, 38, 51].map(|start| field(bits, start, 13));
+        let all_ones = extents.iter().all(|&e| e == 0x1fff);
+        let empty = extents[0] >= extents[1] || extents[2] >= extents[3];
+        if mode >> 9 & 1 == 1 || field(bits, 10, 2) != 3 || (!all_ones && empty) {
+            return None;
+        }
+        let color = [0, 1, 2, 3].map(|c| field(bits, 64 + 16 * c, 16));
+        let texel = color.map(|c| match srgb {
+            true => (c >> 8) as f32 / 255.0,
+            false => c as f32 / 65535.0,
+        });
+        out.iter_mut().for_each(|t| *t = texel);
+        return Some(());
+    }
+    let block_mode = block_mode(mode)?;
+    let (grid_width, grid_height) = (block_mode.grid_width, block_mode.grid_height);
+    if grid_width > width || grid_height > height {
+        return None;
+    }
+    let planes = 1 + block_mode.dual_plane as usize;
+    let weight_count = grid_width * grid_height * planes;
+    let weight_bits = block_mode.weight_range.sequence_bits(weight_count);
+    if weight_count > 64 || !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+    let partitions = field(bits, 11, 2) as usize + 1;
+    if partitions == 4 && block_mode.dual_plane {
+        return None;
+    }
+
+    // Color endpoint modes, and where the color data and the bits stored
+    // below the weights start.
+    let mut below_weights = 128 - weight_bits;
+    let (modes, color_start) = if partitions == 1 {
+        (vec![field(bits, 13, 4)], 17)
+    } else {
+        let cem = field(bits, 23, 6);
+        let modes = if cem & 3 == 0 {
+            vec![cem >> 2; partitions]
+        } else {
+            // Per-partition modes of two neighbouring classes: one class
+            // bit for each partition, then two mode bits for each.
+            let extra = 3 * partitions - 4;
+            below_weights -= extra;
+            let all = cem >> 2 | field(bits, below_weights, extra) << 4;
+            let base_class = (cem & 3) - 1;
+            (0..partitions)
+                .map(|i| {
+                    let class = base_class + (all >> i & 1);
+                    let mode = all >> (partitions + 2 * i) & 3;
+                    class << 2 | mode
+                })
+                .collect()
+        };
+        (modes, 29)
+    };
+    if block_mode.dual_plane {
+        below_weights -= 2;
+    }
+    let plane2_component = field(bits, below_weights, 2) as usize;
+    if below_weights < color_start {
+        return None;
+    }
+    let color_bits = below_weights - color_start;
+    let value_count: usize = modes.iter().map(|&m| 2 * ((m as usize >> 2) + 1)).sum();
+    if value_count > 18 {
+        return None;
+    }
+    let color_range = *COLOR_RANGES
+        .iter()
+        .rev()
+        .find(|r| r.sequence_bits(value_count) <= color_bits)?;
+    let color_end = color_start + color_range.sequence_bits(value_count);
+    let values = decode_sequence(bits, color_start, color_end, value_count, color_range);
+    let values: Vec<u32> = values
+        .iter()
+        .map(|&v| unquantize_color(v, color_range))
+        .collect();
+    let mut colors = Vec::with_capacity(partitions);
+    let mut offset = 0;
+    for &mode in &modes {
+        let count = 2 * ((mode as usize >> 2) + 1);
+        colors.push(endpoints(mode, &values[offset..offset + count])?);
+        offset += count;
+    }
+
+    // Weights are stored from the top of the block down.
+    let reversed = bits.reverse_bits();
+    let range = block_mode.weight_range;
+    let weights: Vec<u32> = decode_sequence(reversed, 0, weight_bits, weight_count, range)
+        .iter()
+        .map(|&w| unquantize_weight(w, range))
+        .collect();
+
+    let small = width * height < 31;
+    let seed = field(bits, 13, 10);
+    let ds = (1024 + width / 2) / (width - 1);
+    let dt = (1024 + height / 2) / (height - 1);
+    for y in 0..height {
+        for x in 0..width {
+            // Bilinear infill of the weight grid.
+            let gs = (ds * x * (grid_width - 1) + 32) >> 6;
+            let gt = (dt * y * (grid_height - 1) + 32) >> 6;
+            let (js, fs) = (gs >> 4, gs & 0xf);
+            let (jt, ft) = (gt >> 4, gt & 0xf);
+            let w11 = (fs * ft + 8) >> 4;
+            let taps = [
+                (0, 0, 16 + w11 - fs - ft),
+                (1, 0, fs - w11),
+                (0, 1, ft - w11),
+                (1, 1, w11),
+            ];
+            let weight = |plane: usize| {
+                let sum: usize = taps
+                    .iter()
+                    .filter(|&&(_, _, w)| w > 0)
+                    .map(|&(dx, dy, w)| {
+                        let index = (jt + dy) * grid_width + js + dx;
+                        weights[index * planes + plane] as usize * w
+                    })
+                    .sum();
+                ((sum + 8) >> 4) as i32
+            };
+            let (plane1, plane2) = (weight(0), weight(planes - 1));
+            let partition = match partitions {
+                1 => 0,
+                _ => select_partition(seed, x as u32, y as u32, partitions as u32, small),
+            };
+            let [e0, e1] = colors[partition];
+            out[y * width + x] = [0, 1, 2, 3].map(|c| {
+                let w = if block_mode.dual_plane && c == plane2_component {
+                    plane2
+                } else {
+                    plane1
+                };
+                let expand = |e: i32| match srgb {
+                    true => e << 8 | 0x80,
+                    false => e * 257,
+                };
+                let value = (expand(e0[c]) * (64 - w) + expand(e1[c]) * w + 32) >> 6;
+                match srgb {
+                    true => (value >> 8) as f32 / 255.0,
+                    false => value as f32 / 65535.0,
+                }
+            });
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_4x4(block: u128, srgb: bool) -> [[f32; 4]; 16] {
+        let mut out = [[0.0; 4]; 16];
+        decode_block(&block.to_le_bytes(), 4, 4, srgb, &mut out);
+        out
+    }
+
+    #[test]
+    fn void_extent() {
+        // Block mode and reserved bits, all-ones extents, then RGBA.
+        let mut block = u128::from_le_bytes([
+            0xfc, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        for (c, value) in [0xffffu128, 0x8000, 0, 0xffff].into_iter().enumerate() {
+            block |= value << (64 + 16 * c);
+        }
+        let unorm = decode_4x4(block, false);
+        assert!(unorm
+            .iter()
+            .all(|&t| t == [1.0, 0x8000 as f32 / 65535.0, 0.0, 1.0]));
+        let srgb = decode_4x4(block, true);
+        assert!(srgb.iter().all(|&t| t == [1.0, 128.0 / 255.0, 0.0, 1.0]));
+
+        // An HDR void-extent block isn't supported.
+        let hdr = decode_4x4(block | 1 << 9, false);
+        assert!(hdr.iter().all(|&t| t == ERROR_COLOR));
+    }
+
+    #[test]
+    fn single_partition() {
+        // A 4x4 grid of 4-bit weights, one partition and direct luminance
+        // endpoints 0 and 255 (mode 0, left as zero bits).
+        let mut block = 0x242 | 255 << 25;
+        let weights = [0u128, 15, 8, 4];
+        let packed = (0..16).fold(0, |packed, i| packed | weights[i % 4] << (4 * i));
+        block |= packed.reverse_bits();
+        let out = decode_4x4(block, false);
+        for (i, &texel) in out.iter().enumerate() {
+            // 4-bit weights replicate to 6 bits and skip 33.
+            let weight = [0, 64, 35, 17][i % 4];
+            let value = (65535 * weight + 32) >> 6;
+            let l = value as f32 / 65535.0;
+            assert_eq!(texel, [l, l, l, 1.0], "texel {i}");
+        }
+    }
+
+    #[test]
+    fn reserved_block_mode() {
+        let out = decode_4x4(0, false);
+        assert!(out.iter().all(|&t| t == ERROR_COLOR));
+    }
+}
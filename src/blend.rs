@@ -0,0 +1,81 @@
+//! Blend mode presets for color targets.
+
+/// How a draw's output is combined with what's already in the target.
+///
+/// `Alpha` and `Additive` expect straight alpha from the shader,
+/// `Premultiplied` expects color already multiplied by alpha. `Multiply` and
+/// `Screen` ignore the source alpha and leave the target's alpha as it was.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Overwrites the target.
+    #[default]
+    Opaque,
+    Alpha,
+    Premultiplied,
+    Additive,
+    Multiply,
+    Screen,
+}
+
+/// Keeps the target's alpha.
+const KEEP_ALPHA: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::Zero,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Add,
+};
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 6] = [
+        BlendMode::Opaque,
+        BlendMode::Alpha,
+        BlendMode::Premultiplied,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlendMode::Opaque => "Opaque",
+            BlendMode::Alpha => "Alpha",
+            BlendMode::Premultiplied => "Premultiplied",
+            BlendMode::Additive => "Additive",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+        }
+    }
+
+    /// Whether what's behind shows through. Transparent draws don't write
+    /// depth and have to be drawn back to front after the opaque ones.
+    pub fn is_transparent(self) -> bool {
+        self != BlendMode::Opaque
+    }
+
+    /// The blend state for the color target, `None` for opaque draws.
+    pub fn state(self) -> Option<wgpu::BlendState> {
+        let color = |src_factor, dst_factor| wgpu::BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: wgpu::BlendOperation::Add,
+        };
+        use wgpu::BlendFactor::*;
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+            BlendMode::Premultiplied => Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            BlendMode::Additive => Some(wgpu::BlendState {
+                color: color(SrcAlpha, One),
+                alpha: KEEP_ALPHA,
+            }),
+            BlendMode::Multiply => Some(wgpu::BlendState {
+                color: color(Dst, Zero),
+                alpha: KEEP_ALPHA,
+            }),
+            // 1 - (1 - src) * (1 - dst) = src + dst * (1 - src)
+            BlendMode::Screen => Some(wgpu::BlendState {
+                color: color(One, OneMinusSrc),
+                alpha: KEEP_ALPHA,
+            }),
+        }
+    }
+}
@@ -0,0 +1,1359 @@
+//! CPU decoders for the block-compressed formats, for devices that can't
+//! sample them: BC1-BC7, ETC2, EAC and (in `astc`) ASTC. Each block decodes
+//! to `[f32; 4]` texels in rows. Unorm channels are 0 to 1, snorm ones -1 to
+//! 1 and BC6H ones whatever half floats hold. sRGB formats come out still
+//! encoded.
+
+use wgpu::TextureFormat as F;
+
+/// Decodes one block of `format` into `out`, which holds
+/// `format.block_dimensions()` texels.
+pub fn decode_block(format: F, block: &[u8], out: &mut [[f32; 4]]) -> Result<(), String> {
+    match format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => bc1(block, out, true),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => {
+            bc1(&block[8..], out, false);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in out.iter_mut().enumerate() {
+                texel[3] = (alpha >> (4 * i) & 0xf) as f32 / 15.0;
+            }
+        }
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => {
+            bc1(&block[8..], out, false);
+            bc4(&block[..8], out, 3, false);
+        }
+        F::Bc4RUnorm | F::Bc4RSnorm => {
+            fill(out, [0.0, 0.0, 0.0, 1.0]);
+            bc4(block, out, 0, format == F::Bc4RSnorm);
+        }
+        F::Bc5RgUnorm | F::Bc5RgSnorm => {
+            fill(out, [0.0, 0.0, 0.0, 1.0]);
+            bc4(&block[..8], out, 0, format == F::Bc5RgSnorm);
+            bc4(&block[8..], out, 1, format == F::Bc5RgSnorm);
+        }
+        F::Bc6hRgbUfloat | F::Bc6hRgbFloat => bc6h(block, out, format == F::Bc6hRgbFloat),
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => bc7(block, out),
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => etc2(block, out, false),
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => etc2(block, out, true),
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => {
+            etc2(&block[8..], out, false);
+            eac(&block[..8], out, 3, Eac::Alpha);
+        }
+        F::EacR11Unorm | F::EacR11Snorm => {
+            fill(out, [0.0, 0.0, 0.0, 1.0]);
+            eac(block, out, 0, Eac::r11(format == F::EacR11Snorm));
+        }
+        F::EacRg11Unorm | F::EacRg11Snorm => {
+            fill(out, [0.0, 0.0, 0.0, 1.0]);
+            eac(&block[..8], out, 0, Eac::r11(format == F::EacRg11Snorm));
+            eac(&block[8..], out, 1, Eac::r11(format == F::EacRg11Snorm));
+        }
+        F::Astc { .. } => {
+            let (width, height) = format.block_dimensions();
+            crate::astc::decode_block(block, width, height, format.is_srgb(), out);
+        }
+        _ => return Err(format!("{format:?} isn't a block-compressed format")),
+    }
+    Ok(())
+}
+
+fn fill(out: &mut [[f32; 4]], texel: [f32; 4]) {
+    out.iter_mut().for_each(|t| *t = texel);
+}
+
+fn unorm8(value: u32) -> f32 {
+    value as f32 / 255.0
+}
+
+/// Expands an RGB565 color to 8 bits per channel.
+fn rgb565(color: u16) -> [u32; 3] {
+    let (r, g, b) = (
+        (color >> 11) as u32,
+        (color >> 5 & 0x3f) as u32,
+        (color & 0x1f) as u32,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// The color half of BC1-BC3. Only BC1 has the three color mode with
+/// transparent black, picked when the first endpoint isn't the larger one.
+fn bc1(block: &[u8], out: &mut [[f32; 4]], punchthrough: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mut palette = [[0.0; 4]; 4];
+    for c in 0..3 {
+        let (a, b) = (e0[c], e1[c]);
+        let mixed = if c0 > c1 || !punchthrough {
+            [(2 * a + b) / 3, (a + 2 * b) / 3]
+        } else {
+            [(a + b) / 2, 0]
+        };
+        for (entry, value) in palette.iter_mut().zip([a, b, mixed[0], mixed[1]]) {
+            entry[c] = unorm8(value);
+        }
+    }
+    for entry in &mut palette[..3] {
+        entry[3] = 1.0;
+    }
+    palette[3][3] = if c0 <= c1 && punchthrough { 0.0 } else { 1.0 };
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, texel) in out.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i) & 3) as usize];
+    }
+}
+
+/// One channel of BC3-BC5: two endpoints and 3-bit indices, with 0 and 1
+/// (or -1 and 1) as extra palette entries when the endpoints are in
+/// increasing order.
+fn bc4(block: &[u8], out: &mut [[f32; 4]], channel: usize, signed: bool) {
+    let bits = u64::from_le_bytes(block.try_into().unwrap());
+    let endpoint = |byte: u8| match signed {
+        true => (byte as i8).max(-127) as f32 / 127.0,
+        false => byte as f32 / 255.0,
+    };
+    let (a, b) = (endpoint(block[0]), endpoint(block[1]));
+    let ordered = match signed {
+        true => block[0] as i8 > block[1] as i8,
+        false => block[0] > block[1],
+    };
+    let mut palette = [a, b, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    if ordered {
+        for (i, entry) in palette[2..].iter_mut().enumerate() {
+            let t = (i + 1) as f32 / 7.0;
+            *entry = a + (b - a) * t;
+        }
+    } else {
+        for (i, entry) in palette[2..6].iter_mut().enumerate() {
+            let t = (i + 1) as f32 / 5.0;
+            *entry = a + (b - a) * t;
+        }
+        palette[6] = if signed { -1.0 } else { 0.0 };
+        palette[7] = 1.0;
+    }
+    for (i, texel) in out.iter_mut().enumerate() {
+        texel[channel] = palette[(bits >> (16 + 3 * i) & 7) as usize];
+    }
+}
+
+/// Reads a 128-bit block least significant bit first.
+struct Bits {
+    bits: u128,
+    pos: u32,
+}
+
+impl Bits {
+    fn new(block: &[u8]) -> Self {
+        Self {
+            bits: u128::from_le_bytes(block.try_into().unwrap()),
+            pos: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.pos) as u32 & ((1u64 << count) - 1) as u32;
+        self.pos += count;
+        value
+    }
+}
+
+/// BC7 and BC6H interpolation weights, out of 64, for 2, 3 and 4-bit
+/// indices.
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weight(bits: u32, index: u32) -> u32 {
+    match bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+
+/// Subset of each texel for the 2 and 3-subset partitionings, two bits per
+/// texel.
+const PARTITIONS_2: [u32; 64] = [
+    0x50505050, 0x40404040, 0x54545454, 0x54505040, 0x50404000, 0x55545450, 0x55545040, 0x54504000,
+    0x50400000, 0x55555450, 0x55544000, 0x54400000, 0x55555440, 0x55550000, 0x55555500, 0x55000000,
+    0x55150100, 0x00004054, 0x15010000, 0x00405054, 0x00004050, 0x15050100, 0x05010000, 0x40505054,
+    0x00404050, 0x05010100, 0x14141414, 0x05141450, 0x01155440, 0x00555500, 0x15014054, 0x05414150,
+    0x44444444, 0x55005500, 0x11441144, 0x05055050, 0x05500550, 0x11114444, 0x41144114, 0x44111144,
+    0x15055054, 0x01055040, 0x05041050, 0x05455150, 0x14414114, 0x50050550, 0x41411414, 0x00141400,
+    0x00041504, 0x00105410, 0x10541000, 0x04150400, 0x50410514, 0x41051450, 0x05415014, 0x14054150,
+    0x41050514, 0x41505014, 0x40011554, 0x54150140, 0x50505500, 0x00555050, 0x15151010, 0x54540404,
+];
+const PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// Texels whose index is one bit shorter: the first texel of each subset
+/// after the first, by partitioning.
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+/// The subset of texel `i` and whether it's its subset's anchor.
+fn subset(subsets: u32, partition: usize, i: usize) -> (usize, bool) {
+    match subsets {
+        1 => (0, i == 0),
+        2 => {
+            let s = (PARTITIONS_2[partition] >> (2 * i) & 3) as usize;
+            (s, i == [0, ANCHORS_2[partition] as usize][s])
+        }
+        _ => {
+            let s = (PARTITIONS_3[partition] >> (2 * i) & 3) as usize;
+            let anchors = [
+                0,
+                ANCHORS_3_SECOND[partition] as usize,
+                ANCHORS_3_THIRD[partition] as usize,
+            ];
+            (s, i == anchors[s])
+        }
+    }
+}
+
+/// Layout of a BC7 mode.
+struct Bc7Mode {
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint, or one shared by both endpoints of a subset.
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits_2: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn mode(
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits_2: u32,
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_pbits,
+        shared_pbits,
+        index_bits,
+        index_bits_2,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+fn bc7(block: &[u8], out: &mut [[f32; 4]]) {
+    let mut bits = Bits::new(block);
+    let Some(index) = (0..8).find(|&m| block[0] >> m & 1 == 1) else {
+        // Reserved mode.
+        return fill(out, [0.0; 4]);
+    };
+    let mode = &BC7_MODES[index];
+    bits.read(index as u32 + 1);
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoints = 2 * mode.subsets as usize;
+    let mut colors = [[0u32; 4]; 6];
+    for c in 0..4 {
+        let width = if c < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for color in &mut colors[..endpoints] {
+            color[c] = bits.read(width);
+        }
+    }
+    let mut widths = [
+        mode.color_bits,
+        mode.color_bits,
+        mode.color_bits,
+        mode.alpha_bits,
+    ];
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let pbits: Vec<u32> = match mode.endpoint_pbits {
+            true => (0..endpoints).map(|_| bits.read(1)).collect(),
+            false => (0..mode.subsets)
+                .flat_map(|_| {
+                    let p = bits.read(1);
+                    [p, p]
+                })
+                .collect(),
+        };
+        for (color, p) in colors.iter_mut().zip(pbits) {
+            for value in color.iter_mut() {
+                *value = *value << 1 | p;
+            }
+        }
+        // Modes without alpha stay opaque.
+        widths.iter_mut().filter(|w| **w > 0).for_each(|w| *w += 1);
+    }
+    for color in &mut colors[..endpoints] {
+        for (value, &width) in color.iter_mut().zip(&widths) {
+            *value = match width {
+                0 => 255,
+                _ => *value << (8 - width) | *value >> (2 * width).saturating_sub(8),
+            };
+        }
+    }
+
+    let read_indices = |bits: &mut Bits, width: u32| {
+        let mut indices = [0u32; 16];
+        for (i, index) in indices.iter_mut().enumerate() {
+            let anchor = subset(mode.subsets, partition, i).1;
+            *index = bits.read(width - anchor as u32);
+        }
+        indices
+    };
+    let indices = read_indices(&mut bits, mode.index_bits);
+    let indices_2 = match mode.index_bits_2 {
+        0 => indices,
+        width => read_indices(&mut bits, width),
+    };
+    // Mode 4's index selection bit swaps which index set goes to color.
+    let (color_indices, color_width, alpha_indices, alpha_width) =
+        match (mode.index_bits_2, index_selection) {
+            (0, _) => (indices, mode.index_bits, indices, mode.index_bits),
+            (_, 0) => (indices, mode.index_bits, indices_2, mode.index_bits_2),
+            _ => (indices_2, mode.index_bits_2, indices, mode.index_bits),
+        };
+
+    for (i, texel) in out.iter_mut().enumerate() {
+        let s = subset(mode.subsets, partition, i).0;
+        let (e0, e1) = (colors[2 * s], colors[2 * s + 1]);
+        let interpolate = |c: usize, w: u32| ((64 - w) * e0[c] + w * e1[c] + 32) >> 6;
+        let wc = weight(color_width, color_indices[i]);
+        let wa = weight(alpha_width, alpha_indices[i]);
+        let mut rgba = [
+            interpolate(0, wc),
+            interpolate(1, wc),
+            interpolate(2, wc),
+            interpolate(3, wa),
+        ];
+        if rotation > 0 {
+            rgba.swap(3, rotation as usize - 1);
+        }
+        *texel = rgba.map(unorm8);
+    }
+}
+
+/// Endpoints of a BC6H block, in the order the spec names them: w and x
+/// are the first subset's, y and z the second's.
+const W: usize = 0;
+const X: usize = 1;
+const Y: usize = 2;
+const Z: usize = 3;
+
+/// Where a BC6H mode stores its endpoint bits: each entry is an endpoint,
+/// a channel, the lowest bit and the number of bits, read in order.
+type Field = (usize, usize, u32, u32);
+
+/// Layout of a BC6H mode.
+struct Bc6hMode {
+    fields: &'static [Field],
+    /// Whether x, y and z are deltas from w.
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+}
+
+macro_rules! fields {
+    ($($e:ident $c:literal [$hi:literal : $lo:literal]),* $(,)?) => {
+        &[$(($e, $c, $lo, $hi - $lo + 1)),*]
+    };
+}
+
+#[rustfmt::skip]
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode {
+        fields: fields![
+            Y 1 [4:4], Y 2 [4:4], Z 2 [4:4], W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [4:0],
+            Z 1 [4:4], Y 1 [3:0], X 1 [4:0], Z 2 [0:0], Z 1 [3:0], X 2 [4:0], Z 2 [1:1],
+            Y 2 [3:0], Y 0 [4:0], Z 2 [2:2], Z 0 [4:0], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5],
+    },
+    Bc6hMode {
+        fields: fields![
+            Y 1 [5:5], Z 1 [4:4], Z 1 [5:5], W 0 [6:0], Z 2 [0:0], Z 2 [1:1], Y 2 [4:4],
+            W 1 [6:0], Y 2 [5:5], Z 2 [2:2], Y 1 [4:4], W 2 [6:0], Z 2 [3:3], Z 2 [5:5],
+            Z 2 [4:4], X 0 [5:0], Y 1 [3:0], X 1 [5:0], Z 1 [3:0], X 2 [5:0], Y 2 [3:0],
+            Y 0 [5:0], Z 0 [5:0],
+        ],
+        transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [4:0], W 0 [10:10], Y 1 [3:0], X 1 [3:0],
+            W 1 [10:10], Z 2 [0:0], Z 1 [3:0], X 2 [3:0], W 2 [10:10], Z 2 [1:1], Y 2 [3:0],
+            Y 0 [4:0], Z 2 [2:2], Z 0 [4:0], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [3:0], W 0 [10:10], Z 1 [4:4], Y 1 [3:0],
+            X 1 [4:0], W 1 [10:10], Z 1 [3:0], X 2 [3:0], W 2 [10:10], Z 2 [1:1], Y 2 [3:0],
+            Y 0 [3:0], Z 2 [0:0], Z 2 [2:2], Z 0 [3:0], Y 1 [4:4], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [3:0], W 0 [10:10], Y 2 [4:4], Y 1 [3:0],
+            X 1 [3:0], W 1 [10:10], Z 2 [0:0], Z 1 [3:0], X 2 [4:0], W 2 [10:10], Y 2 [3:0],
+            Y 0 [3:0], Z 2 [1:1], Z 2 [2:2], Z 0 [3:0], Z 2 [4:4], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [8:0], Y 2 [4:4], W 1 [8:0], Y 1 [4:4], W 2 [8:0], Z 2 [4:4], X 0 [4:0],
+            Z 1 [4:4], Y 1 [3:0], X 1 [4:0], Z 2 [0:0], Z 1 [3:0], X 2 [4:0], Z 2 [1:1],
+            Y 2 [3:0], Y 0 [4:0], Z 2 [2:2], Z 0 [4:0], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [7:0], Z 1 [4:4], Y 2 [4:4], W 1 [7:0], Z 2 [2:2], Y 1 [4:4], W 2 [7:0],
+            Z 2 [3:3], Z 2 [4:4], X 0 [5:0], Y 1 [3:0], X 1 [4:0], Z 2 [0:0], Z 1 [3:0],
+            X 2 [4:0], Z 2 [1:1], Y 2 [3:0], Y 0 [5:0], Z 0 [5:0],
+        ],
+        transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [7:0], Z 2 [0:0], Y 2 [4:4], W 1 [7:0], Y 1 [5:5], Y 1 [4:4], W 2 [7:0],
+            Z 1 [5:5], Z 2 [4:4], X 0 [4:0], Z 1 [4:4], Y 1 [3:0], X 1 [5:0], Z 1 [3:0],
+            X 2 [4:0], Z 2 [1:1], Y 2 [3:0], Y 0 [4:0], Z 2 [2:2], Z 0 [4:0], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [7:0], Z 2 [1:1], Y 2 [4:4], W 1 [7:0], Y 2 [5:5], Y 1 [4:4], W 2 [7:0],
+            Z 2 [5:5], Z 2 [4:4], X 0 [4:0], Z 1 [4:4], Y 1 [3:0], X 1 [4:0], Z 2 [0:0],
+            Z 1 [3:0], X 2 [5:0], Y 2 [3:0], Y 0 [4:0], Z 2 [2:2], Z 0 [4:0], Z 2 [3:3],
+        ],
+        transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [5:0], Z 1 [4:4], Z 2 [0:0], Z 2 [1:1], Y 2 [4:4], W 1 [5:0], Y 1 [5:5],
+            Y 2 [5:5], Z 2 [2:2], Y 1 [4:4], W 2 [5:0], Z 1 [5:5], Z 2 [3:3], Z 2 [5:5],
+            Z 2 [4:4], X 0 [5:0], Y 1 [3:0], X 1 [5:0], Z 1 [3:0], X 2 [5:0], Y 2 [3:0],
+            Y 0 [5:0], Z 0 [5:0],
+        ],
+        transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [9:0], X 1 [9:0], X 2 [9:0],
+        ],
+        transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [8:0], W 0 [10:10], X 1 [8:0], W 1 [10:10],
+            X 2 [8:0], W 2 [10:10],
+        ],
+        transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9],
+    },
+    // The high bits of w are stored most significant first from here on.
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [7:0], W 0 [11:11], W 0 [10:10],
+            X 1 [7:0], W 1 [11:11], W 1 [10:10], X 2 [7:0], W 2 [11:11], W 2 [10:10],
+        ],
+        transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8],
+    },
+    Bc6hMode {
+        fields: fields![
+            W 0 [9:0], W 1 [9:0], W 2 [9:0], X 0 [3:0], W 0 [15:15], W 0 [14:14],
+            W 0 [13:13], W 0 [12:12], W 0 [11:11], W 0 [10:10], X 1 [3:0], W 1 [15:15],
+            W 1 [14:14], W 1 [13:13], W 1 [12:12], W 1 [11:11], W 1 [10:10], X 2 [3:0],
+            W 2 [15:15], W 2 [14:14], W 2 [13:13], W 2 [12:12], W 2 [11:11], W 2 [10:10],
+        ],
+        transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4],
+    },
+];
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    value << (32 - bits) >> (32 - bits)
+}
+
+/// Scales an endpoint to 16 bits (15 and a sign when signed).
+fn bc6h_unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        match value {
+            _ if bits >= 15 => value,
+            0 => 0,
+            _ if value == (1 << bits) - 1 => 0xffff,
+            _ => ((value << 15) + 0x4000) >> (bits - 1),
+        }
+    } else {
+        let magnitude = value.abs();
+        let scaled = match magnitude {
+            _ if bits >= 16 => magnitude,
+            0 => 0,
+            _ if magnitude >= (1 << (bits - 1)) - 1 => 0x7fff,
+            _ => ((magnitude << 15) + 0x4000) >> (bits - 1),
+        };
+        if value < 0 {
+            -scaled
+        } else {
+            scaled
+        }
+    }
+}
+
+/// Turns an interpolated value back into half float bits.
+fn bc6h_finish(value: i32, signed: bool) -> f32 {
+    let bits = match signed {
+        false => ((value * 31) >> 6) as u16,
+        true if value < 0 => 0x8000 | (((-value) * 31) >> 5) as u16,
+        true => ((value * 31) >> 5) as u16,
+    };
+    half::f16::from_bits(bits).to_f32()
+}
+
+fn bc6h(block: &[u8], out: &mut [[f32; 4]], signed: bool) {
+    let mut bits = Bits::new(block);
+    let code = match bits.read(2) {
+        code @ (0 | 1) => code,
+        low => low | bits.read(3) << 2,
+    };
+    let index = match code {
+        0 => 0,
+        1 => 1,
+        _ if code & 0b11 == 2 && code >> 2 < 8 => 2 + (code >> 2) as usize,
+        0b00011 => 10,
+        0b00111 => 11,
+        0b01011 => 12,
+        0b01111 => 13,
+        // Reserved modes.
+        _ => return fill(out, [0.0, 0.0, 0.0, 1.0]),
+    };
+    let mode = &BC6H_MODES[index];
+    let mut endpoints = [[0i32; 3]; 4];
+    for &(endpoint, channel, low, count) in mode.fields {
+        endpoints[endpoint][channel] |= (bits.read(count) as i32) << low;
+    }
+    let subsets = if index < 10 { 2 } else { 1 };
+    let partition = if subsets == 2 {
+        bits.read(5) as usize
+    } else {
+        0
+    };
+
+    let used = &mut endpoints[..2 * subsets];
+    for c in 0..3 {
+        if signed {
+            used[0][c] = sign_extend(used[0][c], mode.endpoint_bits);
+        }
+        let base = used[0][c];
+        for endpoint in &mut used[1..] {
+            if mode.transformed {
+                let delta = sign_extend(endpoint[c], mode.delta_bits[c]);
+                let mask = (1 << mode.endpoint_bits) - 1;
+                endpoint[c] = (base + delta) & mask;
+                if signed {
+                    endpoint[c] = sign_extend(endpoint[c], mode.endpoint_bits);
+                }
+            } else if signed {
+                endpoint[c] = sign_extend(endpoint[c], mode.endpoint_bits);
+            }
+        }
+    }
+    for endpoint in used.iter_mut() {
+        for value in endpoint.iter_mut() {
+            *value = bc6h_unquantize(*value, mode.endpoint_bits, signed);
+        }
+    }
+
+    let width = if subsets == 2 { 3 } else { 4 };
+    for (i, texel) in out.iter_mut().enumerate() {
+        let (s, anchor) = subset(subsets as u32, partition, i);
+        let w = weight(width, bits.read(width - anchor as u32)) as i32;
+        let (e0, e1) = (endpoints[2 * s], endpoints[2 * s + 1]);
+        for c in 0..3 {
+            let value = ((64 - w) * e0[c] + w * e1[c] + 32) >> 6;
+            texel[c] = bc6h_finish(value, signed);
+        }
+        texel[3] = 1.0;
+    }
+}
+
+/// ETC1 intensity modifiers: the small and large step of each table.
+const ETC_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+/// ETC2 T and H mode distances.
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+fn extend4(value: u64) -> i32 {
+    (value * 17) as i32
+}
+
+fn extend5(value: u64) -> i32 {
+    (value << 3 | value >> 2) as i32
+}
+
+/// ETC2 RGB, which is ETC1 plus the T, H and planar modes that reuse
+/// overflowing differential colors. With `punchthrough` (RGB8A1) the
+/// differential bit says whether the block is opaque instead, and index 2
+/// of a non-opaque block is transparent black.
+fn etc2(block: &[u8], out: &mut [[f32; 4]], punchthrough: bool) {
+    let bits = u64::from_be_bytes(block.try_into().unwrap());
+    let field = |high: u32, count: u32| bits >> (high + 1 - count) & ((1 << count) - 1);
+    let differential = field(33, 1) == 1 || punchthrough;
+    let opaque = !punchthrough || field(33, 1) == 1;
+    let index = |i: usize| {
+        // Indices run down the columns.
+        let k = (i % 4) * 4 + i / 4;
+        (field(16 + k as u32, 1) << 1 | field(k as u32, 1)) as usize
+    };
+    let clamp = |color: [i32; 3]| color.map(|c| c.clamp(0, 255));
+    let write = |out: &mut [[f32; 4]], i: usize, color: [i32; 3], transparent: bool| {
+        out[i] = match transparent {
+            true => [0.0; 4],
+            false => [
+                unorm8(color[0] as u32),
+                unorm8(color[1] as u32),
+                unorm8(color[2] as u32),
+                1.0,
+            ],
+        };
+    };
+
+    let (r, g, b) = (field(63, 5), field(55, 5), field(47, 5));
+    let delta = |high| sign_extend(field(high, 3) as i32, 3);
+    let (r2, g2, b2) = (
+        r as i32 + delta(58),
+        g as i32 + delta(50),
+        b as i32 + delta(42),
+    );
+    let in_range = |c: i32| (0..32).contains(&c);
+    if differential && !in_range(r2) {
+        // T mode.
+        let c0 = [
+            extend4(field(60, 2) << 2 | field(57, 2)),
+            extend4(field(55, 4)),
+            extend4(field(51, 4)),
+        ];
+        let c1 = [
+            extend4(field(47, 4)),
+            extend4(field(43, 4)),
+            extend4(field(39, 4)),
+        ];
+        let d = ETC_DISTANCES[(field(35, 2) << 1 | field(32, 1)) as usize];
+        let paint = [c0, clamp(c1.map(|c| c + d)), c1, clamp(c1.map(|c| c - d))];
+        for i in 0..16 {
+            let p = index(i);
+            write(out, i, paint[p], !opaque && p == 2);
+        }
+    } else if differential && !in_range(g2) {
+        // H mode.
+        let c0 = [
+            field(62, 4),
+            field(58, 3) << 1 | field(52, 1),
+            field(51, 1) << 3 | field(49, 3),
+        ];
+        let c1 = [field(46, 4), field(42, 4), field(38, 4)];
+        let packed = |c: [u64; 3]| c[0] << 8 | c[1] << 4 | c[2];
+        let order = (packed(c0) >= packed(c1)) as u64;
+        let d = ETC_DISTANCES[(field(34, 1) << 2 | field(32, 1) << 1 | order) as usize];
+        let (c0, c1) = (c0.map(extend4), c1.map(extend4));
+        let paint = [
+            clamp(c0.map(|c| c + d)),
+            clamp(c0.map(|c| c - d)),
+            clamp(c1.map(|c| c + d)),
+            clamp(c1.map(|c| c - d)),
+        ];
+        for i in 0..16 {
+            let p = index(i);
+            write(out, i, paint[p], !opaque && p == 2);
+        }
+    } else if differential && !in_range(b2) {
+        // Planar mode, always opaque.
+        let extend6 = |v: u64| (v << 2 | v >> 4) as i32;
+        let extend7 = |v: u64| (v << 1 | v >> 6) as i32;
+        let origin = [
+            extend6(field(62, 6)),
+            extend7(field(56, 1) << 6 | field(54, 6)),
+            extend6(field(48, 1) << 5 | field(44, 2) << 3 | field(41, 3)),
+        ];
+        let horizontal = [
+            extend6(field(38, 5) << 1 | field(32, 1)),
+            extend7(field(31, 7)),
+            extend6(field(24, 6)),
+        ];
+        let vertical = [
+            extend6(field(18, 6)),
+            extend7(field(12, 7)),
+            extend6(field(5, 6)),
+        ];
+        for i in 0..16 {
+            let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+            let color = [0, 1, 2].map(|c| {
+                (x * (horizontal[c] - origin[c])
+                    + y * (vertical[c] - origin[c])
+                    + 4 * origin[c]
+                    + 2)
+                    >> 2
+            });
+            write(out, i, clamp(color), false);
+        }
+    } else {
+        let (base0, base1) = if differential {
+            (
+                [extend5(r), extend5(g), extend5(b)],
+                [r2, g2, b2].map(|c| extend5(c as u64)),
+            )
+        } else {
+            (
+                [field(63, 4), field(55, 4), field(47, 4)].map(extend4),
+                [field(59, 4), field(51, 4), field(43, 4)].map(extend4),
+            )
+        };
+        let tables = [field(39, 3) as usize, field(36, 3) as usize];
+        let flip = field(32, 1) == 1;
+        for i in 0..16 {
+            let (x, y) = (i % 4, i / 4);
+            let second = if flip { y >= 2 } else { x >= 2 };
+            let [small, large] = ETC_MODIFIERS[tables[second as usize]];
+            let p = index(i);
+            let modifier = match (p, opaque) {
+                (0, true) => small,
+                (0, false) | (2, false) => 0,
+                (1, _) => large,
+                (2, true) => -small,
+                _ => -large,
+            };
+            let base = if second { base1 } else { base0 };
+            write(out, i, clamp(base.map(|c| c + modifier)), !opaque && p == 2);
+        }
+    }
+}
+
+/// EAC modifier tables, indexed by the block's table index.
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// How an EAC block's values are scaled: 8-bit alpha, or 11-bit red.
+#[derive(Clone, Copy, PartialEq)]
+enum Eac {
+    Alpha,
+    Unsigned11,
+    Signed11,
+}
+
+impl Eac {
+    fn r11(signed: bool) -> Self {
+        match signed {
+            true => Self::Signed11,
+            false => Self::Unsigned11,
+        }
+    }
+}
+
+fn eac(block: &[u8], out: &mut [[f32; 4]], channel: usize, kind: Eac) {
+    let bits = u64::from_be_bytes(block.try_into().unwrap());
+    let base = (bits >> 56) as u8;
+    let multiplier = (bits >> 52 & 0xf) as i32;
+    let table = &EAC_MODIFIERS[(bits >> 48 & 0xf) as usize];
+    for (i, texel) in out.iter_mut().enumerate() {
+        // Indices run down the columns.
+        let k = (i % 4) * 4 + i / 4;
+        let modifier = table[(bits >> (45 - 3 * k) & 7) as usize];
+        texel[channel] = match kind {
+            Eac::Alpha => unorm8((base as i32 + modifier * multiplier).clamp(0, 255) as u32),
+            Eac::Unsigned11 => {
+                let scaled = match multiplier {
+                    0 => modifier,
+                    _ => modifier * multiplier * 8,
+                };
+                (base as i32 * 8 + 4 + scaled).clamp(0, 2047) as f32 / 2047.0
+            }
+            Eac::Signed11 => {
+                let base = (base as i8).max(-127) as i32;
+                let scaled = match multiplier {
+                    0 => modifier,
+                    _ => modifier * multiplier * 8,
+                };
+                (base * 8 + scaled).clamp(-1023, 1023) as f32 / 1023.0
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs `(width, value)` fields into a block, least significant bit
+    /// first.
+    fn pack(fields: &[(u32, u32)]) -> [u8; 16] {
+        let (mut bits, mut pos) = (0u128, 0);
+        for &(width, value) in fields {
+            bits |= (value as u128) << pos;
+            pos += width;
+        }
+        bits.to_le_bytes()
+    }
+
+    fn decode(format: F, block: &[u8]) -> [[f32; 4]; 16] {
+        let mut out = [[0.0; 4]; 16];
+        decode_block(format, block, &mut out).unwrap();
+        out
+    }
+
+    fn rgba8(texel: [f32; 4]) -> [u8; 4] {
+        texel.map(|c| (c * 255.0).round() as u8)
+    }
+
+    #[test]
+    fn bc1_solid_and_endpoints() {
+        let red = decode(F::Bc1RgbaUnorm, &[0x00, 0xf8, 0x00, 0xf8, 0, 0, 0, 0]);
+        assert!(red.iter().all(|&t| t == [1.0, 0.0, 0.0, 1.0]));
+
+        // White to black, with indices 0 to 3 across each row.
+        let ramp = decode(F::Bc1RgbaUnorm, &[0xff, 0xff, 0, 0, 0xe4, 0xe4, 0xe4, 0xe4]);
+        let row = [
+            [255; 4],
+            [0, 0, 0, 255],
+            [170, 170, 170, 255],
+            [85, 85, 85, 255],
+        ];
+        for (i, &texel) in ramp.iter().enumerate() {
+            assert_eq!(rgba8(texel), row[i % 4]);
+        }
+
+        // With the endpoints swapped, index 3 is transparent black.
+        let swapped = decode(F::Bc1RgbaUnorm, &[0, 0, 0xff, 0xff, 0xe4, 0xe4, 0xe4, 0xe4]);
+        assert_eq!(rgba8(swapped[2]), [127, 127, 127, 255]);
+        assert_eq!(swapped[3], [0.0; 4]);
+    }
+
+    #[test]
+    fn bc3_alpha_endpoints() {
+        // Alpha 200 and 100 with indices 0, 1 and 2, over solid green.
+        let mut block = [200, 100, 0x88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        block[8..12].copy_from_slice(&[0xe0, 0x07, 0xe0, 0x07]);
+        let out = decode(F::Bc3RgbaUnorm, &block);
+        assert_eq!(rgba8(out[0]), [0, 255, 0, 200]);
+        assert_eq!(rgba8(out[1]), [0, 255, 0, 100]);
+        assert_eq!(rgba8(out[2]), [0, 255, 0, 186]);
+        assert_eq!(rgba8(out[3]), [0, 255, 0, 200]);
+    }
+
+    #[test]
+    fn bc5_endpoints() {
+        // Red is solid 255. Green's endpoints are in increasing order, so
+        // indices 7 and 6 are the extra 1 and 0 entries.
+        let block = [255, 0, 0, 0, 0, 0, 0, 0, 0, 255, 0xb7, 0, 0, 0, 0, 0];
+        let out = decode(F::Bc5RgUnorm, &block);
+        assert_eq!(out[0], [1.0, 1.0, 0.0, 1.0]);
+        assert_eq!(out[1], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(rgba8(out[2]), [255, 51, 0, 255]);
+        assert_eq!(out[3], [1.0, 0.0, 0.0, 1.0]);
+
+        let block = [
+            0x7f, 0x81, 0, 0, 0, 0, 0, 0, 0x7f, 0x81, 0x01, 0, 0, 0, 0, 0,
+        ];
+        let out = decode(F::Bc5RgSnorm, &block);
+        assert_eq!(out[0], [1.0, -1.0, 0.0, 1.0]);
+        assert_eq!(out[1], [1.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bc7_mode_6() {
+        // Mode bits, then 7-bit R0 R1 G0 G1 B0 B1 A0 A1, two p-bits and the
+        // indices, the first one bit short.
+        let solid = pack(&[
+            (7, 0x40),
+            (7, 0x7f),
+            (7, 0x7f),
+            (7, 0x40),
+            (7, 0x40),
+            (7, 0),
+            (7, 0),
+            (7, 0x7f),
+            (7, 0x7f),
+        ]);
+        let out = decode(F::Bc7RgbaUnorm, &solid);
+        assert!(out.iter().all(|&t| rgba8(t) == [254, 128, 0, 254]));
+
+        // Black to white, with the second p-bit filling white out to 255.
+        let mut fields = vec![(7, 0x40)];
+        fields.extend([(7, 0), (7, 0x7f)].repeat(4));
+        fields.extend([(1, 0), (1, 1), (3, 0), (4, 15), (4, 8)]);
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        assert_eq!(out[0], [0.0; 4]);
+        assert_eq!(out[1], [1.0; 4]);
+        assert_eq!(rgba8(out[2]), [135; 4]);
+    }
+
+    #[test]
+    fn etc2_individual_mode() {
+        // 4-bit base colors (8, 4, 0) in both halves, table 0 and index 0,
+        // which adds 2.
+        let out = decode(F::Etc2Rgb8Unorm, &[0x88, 0x44, 0, 0, 0, 0, 0, 0]);
+        assert!(out.iter().all(|&t| rgba8(t) == [138, 70, 2, 255]));
+
+        // Red on the left two columns and black on the right.
+        let out = decode(F::Etc2Rgb8Unorm, &[0xf0, 0, 0, 0, 0, 0, 0, 0]);
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = if i % 4 < 2 {
+                [255, 2, 2, 255]
+            } else {
+                [2, 2, 2, 255]
+            };
+            assert_eq!(rgba8(texel), expected);
+        }
+    }
+
+    #[test]
+    fn bc2_explicit_alpha() {
+        // Alpha 0 to 15 in texel order, over BC1 black to white, which BC2
+        // always decodes as four colors.
+        let mut block = [
+            0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe, 0, 0, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        block[12..].copy_from_slice(&[0xe4; 4]);
+        let out = decode(F::Bc2RgbaUnorm, &block);
+        let row = [0, 255, 85, 170];
+        for (i, &texel) in out.iter().enumerate() {
+            let gray = row[i % 4];
+            assert_eq!(rgba8(texel), [gray, gray, gray, 17 * i as u8]);
+        }
+    }
+
+    #[test]
+    fn bc4_both_palettes() {
+        // Decreasing endpoints interpolate six values in between, indices
+        // 0 to 7 on the first row and a half.
+        let mut block = [255, 0, 0, 0, 0, 0, 0, 0];
+        let indices: u64 = (0..8).map(|i| i << (3 * i)).sum();
+        block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+        let out = decode(F::Bc4RUnorm, &block);
+        let expected = [255, 0, 219, 182, 146, 109, 73, 36];
+        for (texel, red) in out.iter().zip(expected) {
+            assert_eq!(rgba8(*texel), [red, 0, 0, 255]);
+        }
+        assert_eq!(out[8], [1.0, 0.0, 0.0, 1.0]);
+
+        // Increasing signed endpoints interpolate four, then -1 and 1.
+        block[..2].copy_from_slice(&[0x81, 0x7f]);
+        let out = decode(F::Bc4RSnorm, &block);
+        let expected = [-1.0, 1.0, -0.6, -0.2, 0.2, 0.6, -1.0, 1.0];
+        for (texel, red) in out.iter().zip(expected) {
+            assert!((texel[0] - red).abs() < 1e-6, "{texel:?} {red}");
+        }
+
+        // -128 is the same as -127.
+        let out = decode(F::Bc4RSnorm, &[0x80, 0x7f, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(out[0][0], -1.0);
+    }
+
+    fn half(bits: u16) -> f32 {
+        half::f16::from_bits(bits).to_f32()
+    }
+
+    #[test]
+    fn bc6h_single_subset() {
+        // Mode 11: 10-bit endpoints stored whole, then 4-bit indices. 495
+        // unquantizes to the half float 1.0 and 1023 to the largest one.
+        let mut fields = vec![(5, 0b00011)];
+        fields.extend([495, 0, 0, 0, 495, 1023].map(|v| (10, v)));
+        fields.extend([(3, 0), (4, 15), (4, 8)]);
+        fields.extend([(4, 0); 13]);
+        let block = pack(&fields);
+        let out = decode(F::Bc6hRgbUfloat, &block);
+        assert_eq!(out[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(out[1], [0.0, 1.0, 65504.0, 1.0]);
+        // Halfway is halfway between the half float bit patterns.
+        assert_eq!(out[2], [half(0x1c20), half(0x1fe0), half(0x41df), 1.0]);
+
+        // Signed, from -248 to 248.
+        let mut fields = vec![(5, 0b00011)];
+        fields.extend([1024 - 248, 0, 0, 248, 0, 0].map(|v| (10, v)));
+        fields.extend([(3, 0), (4, 15), (4, 8)]);
+        fields.extend([(4, 0); 13]);
+        let out = decode(F::Bc6hRgbFloat, &pack(&fields));
+        assert_eq!(out[0], [half(0xbc2f), 0.0, 0.0, 1.0]);
+        assert_eq!(out[1], [half(0x3c2f), 0.0, 0.0, 1.0]);
+        assert_eq!(out[2], [half(0x03c2), 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bc6h_two_subsets_with_deltas() {
+        // Mode 1, partition 13 (top half, bottom half): w = (100, 200, 300)
+        // and x, y and z as deltas from it of (5, -3, 0), (10, 0, -10) and
+        // (-1, 1, 15). Indices are 0 except texels 1 and 9 at 7 and texel
+        // 15, the second subset's anchor, at 3.
+        let block = [
+            0x88, 0x0c, 0x64, 0x58, 0x2a, 0xa0, 0x0f, 0xd0, 0xd4, 0xbf, 0x71, 0x00, 0x00, 0x70,
+            0x00, 0xc0,
+        ];
+        let out = decode(F::Bc6hRgbUfloat, &block);
+        let expected = |bits: [u16; 3]| [half(bits[0]), half(bits[1]), half(bits[2]), 1.0];
+        // w = (100, 200, 300) and x = (105, 197, 300).
+        assert_eq!(out[0], expected([0x0c2b, 0x1847, 0x2463]));
+        assert_eq!(out[7], out[0]);
+        assert_eq!(out[1], expected([0x0cc6, 0x17ea, 0x2463]));
+        // y = (110, 200, 290) and z = (99, 201, 315).
+        assert_eq!(out[8], expected([0x0d61, 0x1847, 0x232d]));
+        assert_eq!(out[9], expected([0x0c0c, 0x1866, 0x2634]));
+        assert_eq!(out[15], expected([0x0cd1, 0x1854, 0x2474]));
+    }
+
+    /// The subset of each texel in the 3-subset partitioning 0 and the
+    /// 2-subset partitioning 13, from the BC7 partition tables.
+    const THREE_SUBSETS_0: [usize; 16] = [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2];
+    const TWO_SUBSETS_13: [usize; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1];
+
+    /// Index fields for every texel: `width` bits, one less for `anchors`.
+    fn indices(width: u32, anchors: &[usize], values: &[(usize, u32)]) -> Vec<(u32, u32)> {
+        (0..16)
+            .map(|i| {
+                let value = values.iter().find(|&&(t, _)| t == i).map_or(0, |v| v.1);
+                (width - anchors.contains(&i) as u32, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bc7_mode_0() {
+        // Partition 0. 4-bit endpoints with a p-bit each, which only the
+        // second subset's second endpoint sets, filling white out to 255.
+        let mut fields = vec![(1, 1), (4, 0)];
+        fields.extend([15, 0, 0, 15, 0, 15].map(|v| (4, v)));
+        fields.extend([0, 0, 15, 15, 0, 0].map(|v| (4, v)));
+        fields.extend([0, 15, 0, 15, 15, 0].map(|v| (4, v)));
+        fields.extend([0, 0, 0, 1, 0, 0].map(|p| (1, p)));
+        fields.extend(indices(3, &[0, 3, 15], &[(5, 7), (6, 4)]));
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        let colors = [[247, 0, 0, 255], [0, 247, 0, 255], [0, 0, 247, 255]];
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = match i {
+                5 => [0, 0, 247, 255],
+                6 => [147, 252, 147, 255],
+                _ => colors[THREE_SUBSETS_0[i]],
+            };
+            assert_eq!(rgba8(texel), expected, "texel {i}");
+        }
+    }
+
+    #[test]
+    fn bc7_mode_1() {
+        // Partition 13. 6-bit endpoints with a p-bit shared by each subset.
+        let mut fields = vec![(2, 0b10), (6, 13)];
+        fields.extend([63, 0, 0, 63].map(|v| (6, v)));
+        fields.extend([0, 63, 0, 63].map(|v| (6, v)));
+        fields.extend([0, 0, 63, 63].map(|v| (6, v)));
+        fields.extend([(1, 0), (1, 1)]);
+        fields.extend(indices(3, &[0, 15], &[(1, 7), (9, 2)]));
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        let colors = [[253, 0, 0, 255], [2, 2, 255, 255]];
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = match i {
+                1 => [0, 253, 0, 255],
+                9 => [73, 73, 255, 255],
+                _ => colors[TWO_SUBSETS_13[i]],
+            };
+            assert_eq!(rgba8(texel), expected, "texel {i}");
+        }
+    }
+
+    #[test]
+    fn bc7_mode_2() {
+        // Partition 0. 5-bit endpoints, no p-bits, 2-bit indices.
+        let mut fields = vec![(3, 0b100), (6, 0)];
+        fields.extend([31, 0, 0, 31, 0, 0].map(|v| (5, v)));
+        fields.extend([0, 0, 31, 31, 0, 0].map(|v| (5, v)));
+        fields.extend([0, 0, 0, 31, 31, 0].map(|v| (5, v)));
+        fields.extend(indices(2, &[0, 3, 15], &[(4, 1), (11, 3), (15, 1)]));
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = match i {
+                4 => [171, 0, 0, 255],
+                11 => [255, 255, 255, 255],
+                15 => [0, 0, 171, 255],
+                _ => colors[THREE_SUBSETS_0[i]],
+            };
+            assert_eq!(rgba8(texel), expected, "texel {i}");
+        }
+    }
+
+    #[test]
+    fn bc7_mode_3() {
+        // Partition 13. 7-bit endpoints with a p-bit each.
+        let mut fields = vec![(4, 0b1000), (6, 13)];
+        fields.extend([127, 0, 0, 127].map(|v| (7, v)));
+        fields.extend([0, 0, 127, 127].map(|v| (7, v)));
+        fields.extend([0, 0, 0, 127].map(|v| (7, v)));
+        fields.extend([1, 0, 0, 1].map(|p| (1, p)));
+        fields.extend(indices(2, &[0, 15], &[(1, 3), (8, 2), (15, 1)]));
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        let colors = [[255, 1, 1, 255], [0, 254, 0, 255]];
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = match i {
+                1 => [0, 0, 0, 255],
+                8 => [171, 255, 171, 255],
+                15 => [84, 254, 84, 255],
+                _ => colors[TWO_SUBSETS_13[i]],
+            };
+            assert_eq!(rgba8(texel), expected, "texel {i}");
+        }
+    }
+
+    /// A mode 4 block from red to blue and opaque to transparent, with
+    /// texel 1 at the last index of both sets and texel 2 at 1 of the 2-bit
+    /// set and 4 of the 3-bit one.
+    fn bc7_mode_4(rotation: u32, index_selection: u32) -> [u8; 16] {
+        let mut fields = vec![(5, 0b10000), (2, rotation), (1, index_selection)];
+        fields.extend([31, 0, 0, 0, 0, 31].map(|v| (5, v)));
+        fields.extend([(6, 63), (6, 0)]);
+        fields.extend(indices(2, &[0], &[(1, 3), (2, 1)]));
+        fields.extend(indices(3, &[0], &[(1, 7), (2, 4)]));
+        pack(&fields)
+    }
+
+    #[test]
+    fn bc7_mode_4_index_selection_and_rotation() {
+        // Color from the 2-bit indices, alpha from the 3-bit ones.
+        let out = decode(F::Bc7RgbaUnorm, &bc7_mode_4(0, 0));
+        assert_eq!(rgba8(out[0]), [255, 0, 0, 255]);
+        assert_eq!(rgba8(out[1]), [0, 0, 255, 0]);
+        assert_eq!(rgba8(out[2]), [171, 0, 84, 108]);
+
+        // And the other way around.
+        let out = decode(F::Bc7RgbaUnorm, &bc7_mode_4(0, 1));
+        assert_eq!(rgba8(out[1]), [0, 0, 255, 0]);
+        assert_eq!(rgba8(out[2]), [108, 0, 147, 171]);
+
+        // Rotation 1 swaps red and alpha.
+        let out = decode(F::Bc7RgbaUnorm, &bc7_mode_4(1, 0));
+        assert_eq!(rgba8(out[0]), [255, 0, 0, 255]);
+        assert_eq!(rgba8(out[2]), [108, 0, 84, 171]);
+    }
+
+    #[test]
+    fn bc7_mode_5() {
+        // 7-bit color and 8-bit alpha endpoints, each with 2-bit indices.
+        let block = |rotation| {
+            let mut fields = vec![(6, 0b100000), (2, rotation)];
+            fields.extend([127, 0, 0, 127, 64, 0].map(|v| (7, v)));
+            fields.extend([(8, 255), (8, 0)]);
+            fields.extend(indices(2, &[0], &[(1, 3)]));
+            fields.extend(indices(2, &[0], &[(1, 1)]));
+            pack(&fields)
+        };
+        let out = decode(F::Bc7RgbaUnorm, &block(0));
+        assert_eq!(rgba8(out[0]), [255, 0, 129, 255]);
+        assert_eq!(rgba8(out[1]), [0, 255, 0, 171]);
+
+        // Rotation 2 swaps green and alpha.
+        let out = decode(F::Bc7RgbaUnorm, &block(2));
+        assert_eq!(rgba8(out[0]), [255, 255, 129, 0]);
+        assert_eq!(rgba8(out[1]), [0, 171, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_7() {
+        // Partition 13. 5-bit color and alpha endpoints with a p-bit each.
+        let mut fields = vec![(8, 0x80), (6, 13)];
+        fields.extend([31, 0, 0, 31].map(|v| (5, v)));
+        fields.extend([0, 0, 31, 31].map(|v| (5, v)));
+        fields.extend([0, 0, 0, 31].map(|v| (5, v)));
+        fields.extend([31, 0, 15, 31].map(|v| (5, v)));
+        fields.extend([1, 0, 0, 1].map(|p| (1, p)));
+        fields.extend(indices(2, &[0, 15], &[(1, 3), (15, 1)]));
+        let out = decode(F::Bc7RgbaUnorm, &pack(&fields));
+        let colors = [[255, 4, 4, 255], [0, 251, 0, 121]];
+        for (i, &texel) in out.iter().enumerate() {
+            let expected = match i {
+                1 => [0, 0, 0, 0],
+                15 => [84, 252, 84, 165],
+                _ => colors[TWO_SUBSETS_13[i]],
+            };
+            assert_eq!(rgba8(texel), expected, "texel {i}");
+        }
+    }
+
+    /// The first row of an ETC2 block, whose indices are 0 to 3 from left
+    /// to right, and 0 everywhere else.
+    fn first_row(out: &[[f32; 4]; 16]) -> [[u8; 4]; 4] {
+        assert!(out[4..].iter().all(|&t| t == out[0]));
+        [0, 1, 2, 3].map(|i| rgba8(out[i]))
+    }
+
+    #[test]
+    fn etc2_t_mode() {
+        // Red overflows. Colors (10, 5, 0) and (8, 8, 8), distance 11.
+        let block = [0xf2, 0x50, 0x88, 0x86, 0x11, 0x00, 0x10, 0x10];
+        let out = decode(F::Etc2Rgb8Unorm, &block);
+        assert_eq!(
+            first_row(&out),
+            [
+                [170, 85, 0, 255],
+                [147, 147, 147, 255],
+                [136, 136, 136, 255],
+                [125, 125, 125, 255],
+            ]
+        );
+
+        // With punchthrough alpha and the opaque bit clear, index 2 is
+        // transparent black.
+        let block = [0xf2, 0x50, 0x88, 0x84, 0x11, 0x00, 0x10, 0x10];
+        let out = decode(F::Etc2Rgb8A1Unorm, &block);
+        assert_eq!(rgba8(out[1]), [147, 147, 147, 255]);
+        assert_eq!(out[2], [0.0; 4]);
+    }
+
+    #[test]
+    fn etc2_h_mode() {
+        // Green overflows. Colors (12, 6, 3) and (2, 2, 2); the first is
+        // larger, which makes the distance 16.
+        let block = [0x63, 0x05, 0x91, 0x13, 0x11, 0x00, 0x10, 0x10];
+        let out = decode(F::Etc2Rgb8Unorm, &block);
+        assert_eq!(
+            first_row(&out),
+            [
+                [220, 118, 67, 255],
+                [188, 86, 35, 255],
+                [50, 50, 50, 255],
+                [18, 18, 18, 255],
+            ]
+        );
+    }
+
+    #[test]
+    fn etc2_planar_mode() {
+        // Blue overflows. Black at the origin, red across and blue down.
+        let block = [0x00, 0x00, 0x04, 0x7f, 0x00, 0x00, 0x00, 0x3f];
+        let out = decode(F::Etc2Rgb8Unorm, &block);
+        let ramp = [0, 64, 128, 191];
+        for (i, &texel) in out.iter().enumerate() {
+            assert_eq!(rgba8(texel), [ramp[i % 4], 0, ramp[i / 4], 255]);
+        }
+    }
+
+    #[test]
+    fn eac_alpha_and_r11() {
+        // Base 128, multiplier 2 and table 0, with texel 1 at index 7 (+14)
+        // and texel 4 at index 3 (-15); the rest are at index 0 (-3).
+        let eac = [0x80, 0x20, 0x0c, 0x0e, 0x00, 0x00, 0x00, 0x00];
+        let mut block = eac.to_vec();
+        block.extend([0x88, 0x44, 0, 0, 0, 0, 0, 0]);
+        let out = decode(F::Etc2Rgba8Unorm, &block);
+        assert_eq!(rgba8(out[0]), [138, 70, 2, 122]);
+        assert_eq!(rgba8(out[1]), [138, 70, 2, 156]);
+        assert_eq!(rgba8(out[4]), [138, 70, 2, 98]);
+
+        // Multiplier 15 clamps.
+        block[..2].copy_from_slice(&[250, 0xf0]);
+        let out = decode(F::Etc2Rgba8Unorm, &block);
+        assert_eq!([0, 1, 4].map(|i| rgba8(out[i])[3]), [205, 255, 25]);
+
+        // 11 bits: base * 8 + 4, plus modifier * multiplier * 8.
+        let out = decode(F::EacR11Unorm, &eac);
+        assert_eq!(
+            [0, 1, 4].map(|i| out[i][0] * 2047.0),
+            [980.0, 1252.0, 788.0]
+        );
+        assert_eq!(out[0][1..], [0.0, 0.0, 1.0]);
+
+        // Multiplier 0 adds the modifier unscaled.
+        let out = decode(F::EacR11Unorm, &[0x80, 0x00, 0x0c, 0x0e, 0, 0, 0, 0]);
+        assert_eq!(
+            [0, 1, 4].map(|i| out[i][0] * 2047.0),
+            [1025.0, 1042.0, 1013.0]
+        );
+
+        // Signed, from base -128 (read as -127) and multiplier 1.
+        let out = decode(F::EacR11Snorm, &[0x80, 0x10, 0x0c, 0x0e, 0, 0, 0, 0]);
+        assert_eq!(
+            [0, 1, 4].map(|i| out[i][0] * 1023.0),
+            [-1023.0, -904.0, -1023.0]
+        );
+    }
+
+    #[test]
+    fn rejects_uncompressed_formats() {
+        let mut out = [[0.0; 4]; 16];
+        assert!(decode_block(F::Rgba8Unorm, &[0; 16], &mut out).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Builders for the render pipeline and bind group descriptors, which are
+//! mostly the same handful of defaults spelled out in full every time.
+
+use crate::blend::BlendMode;
+use crate::pipeline_cache::{PipelineCache, PipelineKey};
+use crate::TargetFormats;
+use std::rc::Rc;
+
+/// A render pipeline drawing triangle lists with `vs_main` and `fs_main`,
+/// unculled and unblended, into one color target with no depth buffer and a
+/// single sample. Each method overrides one of those defaults; `depth_only`
+/// starts from no color target and no fragment shader instead.
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    layout: &'a wgpu::PipelineLayout,
+    shader: &'a wgpu::ShaderModule,
+    vertex_entry_point: &'a str,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'a>>,
+    color_format: Option<wgpu::TextureFormat>,
+    blend: Option<wgpu::BlendState>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    sample_count: u32,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new(
+        label: &'a str,
+        layout: &'a wgpu::PipelineLayout,
+        shader: &'a wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            label,
+            layout,
+            shader,
+            vertex_entry_point: "vs_main",
+            vertex_buffers: vec![],
+            color_format: Some(color_format),
+            blend: None,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            sample_count: 1,
+        }
+    }
+
+    /// Only runs `vs_main`, into the depth buffer given with `depth`.
+    pub fn depth_only(
+        label: &'a str,
+        layout: &'a wgpu::PipelineLayout,
+        shader: &'a wgpu::ShaderModule,
+    ) -> Self {
+        Self {
+            color_format: None,
+            ..Self::new(label, layout, shader, wgpu::TextureFormat::Rgba8Unorm)
+        }
+    }
+
+    /// Renders into the main pass: its color format, a depth buffer that is
+    /// tested with `Less` and written, and its sample count.
+    pub fn targets(self, targets: TargetFormats) -> Self {
+        Self {
+            color_format: Some(targets.color),
+            sample_count: targets.sample_count,
+            ..self
+        }
+        .depth(targets.depth, wgpu::CompareFunction::Less, true)
+    }
+
+    pub fn vertex_entry_point(mut self, entry_point: &'a str) -> Self {
+        self.vertex_entry_point = entry_point;
+        self
+    }
+
+    /// Adds the layout of the next vertex buffer slot.
+    pub fn vertex_buffer(mut self, layout: wgpu::VertexBufferLayout<'a>) -> Self {
+        self.vertex_buffers.push(layout);
+        self
+    }
+
+    pub fn blend(mut self, blend: wgpu::BlendState) -> Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    /// Only sets the blend state; transparent draws usually also want
+    /// `depth` with writes disabled.
+    pub fn blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend = mode.state();
+        self
+    }
+
+    pub fn cull(mut self, face: wgpu::Face) -> Self {
+        self.primitive.cull_mode = Some(face);
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    /// Anything other than `Fill` needs the matching device feature.
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth(
+        mut self,
+        format: wgpu::TextureFormat,
+        compare: wgpu::CompareFunction,
+        write_enabled: bool,
+    ) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: write_enabled,
+            depth_compare: compare,
+            stencil: Default::default(),
+            bias: Default::default(),
+        });
+        self
+    }
+
+    /// Offsets the depth written, which has to come after `depth`.
+    pub fn depth_bias(mut self, constant: i32, slope_scale: f32) -> Self {
+        let depth_stencil = self
+            .depth_stencil
+            .as_mut()
+            .expect("depth_bias needs a depth buffer");
+        depth_stencil.bias = wgpu::DepthBiasState {
+            constant,
+            slope_scale,
+            clamp: 0.0,
+        };
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    /// Like `build`, but returns the pipeline from `cache` if one with the
+    /// same description, shader source and layout was built before.
+    pub fn build_cached(
+        self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) -> Rc<wgpu::RenderPipeline> {
+        let key = PipelineKey {
+            shader: cache.shader_key(self.shader),
+            layout: self.layout.global_id(),
+            vertex_entry_point: self.vertex_entry_point.to_string(),
+            vertex_buffers: self
+                .vertex_buffers
+                .iter()
+                .map(|b| (b.array_stride, b.step_mode, b.attributes.to_vec()))
+                .collect(),
+            color_format: self.color_format,
+            blend: self.blend,
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            sample_count: self.sample_count,
+        };
+        cache.render_pipeline(key, || self.build(device))
+    }
+
+    pub fn build(self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let targets = [self.color_format.map(|format| wgpu::ColorTargetState {
+            format,
+            blend: self.blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(self.layout),
+            vertex: wgpu::VertexState {
+                module: self.shader,
+                entry_point: self.vertex_entry_point,
+                buffers: &self.vertex_buffers,
+            },
+            fragment: self.color_format.map(|_| wgpu::FragmentState {
+                module: self.shader,
+                entry_point: "fs_main",
+                targets: &targets,
+            }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+}
+
+/// A bind group and its layout, with one binding per resource in the order
+/// they're added. The layout entries are derived from the resources.
+pub struct BindGroupBuilder<'a> {
+    label: &'a str,
+    entries: Vec<wgpu::BindGroupLayoutEntry>,
+    resources: Vec<wgpu::BindingResource<'a>>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub fn new(label: &'a str) -> Self {
+        Self {
+            label,
+            entries: vec![],
+            resources: vec![],
+        }
+    }
+
+    fn add(
+        mut self,
+        visibility: wgpu::ShaderStages,
+        ty: wgpu::BindingType,
+        resource: wgpu::BindingResource<'a>,
+    ) -> Self {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility,
+            ty,
+            count: None,
+        });
+        self.resources.push(resource);
+        self
+    }
+
+    /// The whole of `buffer`, which has to be at least as large as the
+    /// shader's struct.
+    pub fn uniform(self, visibility: wgpu::ShaderStages, buffer: &'a wgpu::Buffer) -> Self {
+        let ty = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(buffer.size()),
+        };
+        self.add(visibility, ty, buffer.as_entire_binding())
+    }
+
+    /// The first `size` bytes of `buffer`, moved along it by the offset
+    /// passed to `set_bind_group`.
+    pub fn dynamic_uniform(
+        self,
+        visibility: wgpu::ShaderStages,
+        buffer: &'a wgpu::Buffer,
+        size: wgpu::BufferSize,
+    ) -> Self {
+        let ty = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: Some(size),
+        };
+        let resource = wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer,
+            offset: 0,
+            size: Some(size),
+        });
+        self.add(visibility, ty, resource)
+    }
+
+    pub fn storage(
+        self,
+        visibility: wgpu::ShaderStages,
+        buffer: &'a wgpu::Buffer,
+        read_only: bool,
+    ) -> Self {
+        let ty = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        self.add(visibility, ty, buffer.as_entire_binding())
+    }
+
+    /// `view` of `texture`, which decides the sample type, dimension and
+    /// whether it's multisampled. Arrays of more than one layer bind as
+    /// `texture_2d_array`.
+    pub fn texture(
+        self,
+        visibility: wgpu::ShaderStages,
+        texture: &wgpu::Texture,
+        view: &'a wgpu::TextureView,
+    ) -> Self {
+        let view_dimension = match texture.dimension() {
+            wgpu::TextureDimension::D1 => wgpu::TextureViewDimension::D1,
+            wgpu::TextureDimension::D2 if texture.depth_or_array_layers() > 1 => {
+                wgpu::TextureViewDimension::D2Array
+            }
+            wgpu::TextureDimension::D2 => wgpu::TextureViewDimension::D2,
+            wgpu::TextureDimension::D3 => wgpu::TextureViewDimension::D3,
+        };
+        self.texture_as(visibility, texture, view, view_dimension)
+    }
+
+    /// Like `texture`, for views that aren't the texture's own shape: cubes,
+    /// and arrays that may have a single layer.
+    pub fn texture_as(
+        self,
+        visibility: wgpu::ShaderStages,
+        texture: &wgpu::Texture,
+        view: &'a wgpu::TextureView,
+        view_dimension: wgpu::TextureViewDimension,
+    ) -> Self {
+        let ty = wgpu::BindingType::Texture {
+            sample_type: texture
+                .format()
+                .sample_type(None)
+                .expect("texture format has a sample type"),
+            view_dimension,
+            multisampled: texture.sample_count() > 1,
+        };
+        self.add(visibility, ty, wgpu::BindingResource::TextureView(view))
+    }
+
+    pub fn sampler(
+        self,
+        visibility: wgpu::ShaderStages,
+        sampler: &'a wgpu::Sampler,
+        ty: wgpu::SamplerBindingType,
+    ) -> Self {
+        self.add(
+            visibility,
+            wgpu::BindingType::Sampler(ty),
+            wgpu::BindingResource::Sampler(sampler),
+        )
+    }
+
+    /// The layout entries `build` will create the layout from.
+    pub fn layout_entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.entries
+    }
+
+    pub fn build(self, device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{} bind group layout", self.label)),
+            entries: &self.entries,
+        });
+        let bind_group = self.build_with_layout(device, &layout);
+        (layout, bind_group)
+    }
+
+    /// Like `build`, sharing the layout with every other bind group in
+    /// `cache` that has the same entries.
+    pub fn build_cached(
+        self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) -> (Rc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
+        let label = format!("{} bind group layout", self.label);
+        let layout = cache.bind_group_layout(device, &label, &self.entries);
+        let bind_group = self.build_with_layout(device, &layout);
+        (layout, bind_group)
+    }
+
+    fn build_with_layout(
+        self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        let entries: Vec<_> = self
+            .resources
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} bind group", self.label)),
+            layout,
+            entries: &entries,
+        })
+    }
+}
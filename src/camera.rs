@@ -0,0 +1,262 @@
+use crate::input::{Action, Input};
+use crate::reflect::shader_struct;
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, Vector3};
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+use winit::{dpi::PhysicalPosition, event::*};
+
+// wgpu's clip space has z in [0, 1] where cgmath assumes OpenGL's [-1, 1].
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Stop just short of straight up/down so the view matrix doesn't degenerate.
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+// Radians per second with a stick all the way over.
+const STICK_TURN_SPEED: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+}
+
+impl Camera {
+    pub fn new(position: Point3<f32>, yaw: Rad<f32>, pitch: Rad<f32>) -> Self {
+        Self {
+            position,
+            yaw,
+            pitch,
+        }
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), Vector3::unit_y())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    pub aspect: f32,
+    pub fovy: Rad<f32>,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Projection {
+    pub fn new(width: u32, height: u32, fovy: Rad<f32>, znear: f32, zfar: f32) -> Self {
+        Self {
+            aspect: width as f32 / height as f32,
+            fovy,
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height as f32;
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * cgmath::perspective(self.fovy, self.aspect, self.znear, self.zfar)
+    }
+}
+
+/// Matches `Camera` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    view_proj: [[f32; 4]; 4],
+    position: [f32; 4],
+}
+
+shader_struct!(CameraUniform = "Camera" { view, proj, view_proj, position });
+
+impl CameraUniform {
+    pub fn new(camera: &Camera, projection: &Projection) -> Self {
+        let view = camera.view_matrix();
+        let proj = projection.matrix();
+        Self {
+            view: view.into(),
+            proj: proj.into(),
+            view_proj: (proj * view).into(),
+            position: camera.position.to_homogeneous().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerMode {
+    /// The movement actions move the camera, dragging looks around.
+    Fly,
+    /// Dragging rotates around `CameraController::target`, scrolling zooms.
+    Orbit,
+}
+
+/// Turns window events and input actions into camera motion. Movement
+/// actions are read as held or not each frame and applied in
+/// `update_camera`, so speed doesn't depend on the key repeat rate.
+#[derive(Debug)]
+pub struct CameraController {
+    pub mode: ControllerMode,
+    pub speed: f32,
+    pub sensitivity: f32,
+    pub target: Point3<f32>,
+    pub distance: f32,
+    amount_left: f32,
+    amount_right: f32,
+    amount_forward: f32,
+    amount_backward: f32,
+    amount_up: f32,
+    amount_down: f32,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    scroll: f32,
+    // Gamepad sticks, from -1 to 1 with +y up. Moving works like the
+    // movement actions, looking like dragging.
+    move_stick: [f32; 2],
+    look_stick: [f32; 2],
+    dragging: bool,
+    last_cursor: Option<PhysicalPosition<f64>>,
+}
+
+impl CameraController {
+    pub fn new(mode: ControllerMode, speed: f32, sensitivity: f32) -> Self {
+        Self {
+            mode,
+            speed,
+            sensitivity,
+            target: Point3::new(0.0, 0.0, 0.0),
+            distance: 5.0,
+            amount_left: 0.0,
+            amount_right: 0.0,
+            amount_forward: 0.0,
+            amount_backward: 0.0,
+            amount_up: 0.0,
+            amount_down: 0.0,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            scroll: 0.0,
+            move_stick: [0.0, 0.0],
+            look_stick: [0.0, 0.0],
+            dragging: false,
+            last_cursor: None,
+        }
+    }
+
+    /// Orbits around a sphere of `radius` centered at `center`, and scales the
+    /// fly speed to match.
+    pub fn frame(&mut self, camera: &mut Camera, center: Point3<f32>, radius: f32) {
+        self.target = center;
+        self.distance = radius * 2.5;
+        self.speed = radius.max(0.1) * 2.0;
+        camera.position = center - camera.forward() * self.distance;
+    }
+
+    pub fn toggle_mode(&mut self, camera: &Camera) {
+        self.mode = match self.mode {
+            ControllerMode::Fly => {
+                // Keep looking at whatever is in front of us.
+                self.target = camera.position + camera.forward() * self.distance;
+                ControllerMode::Orbit
+            }
+            ControllerMode::Orbit => ControllerMode::Fly,
+        };
+        log::info!("Camera mode: {:?}", self.mode);
+    }
+
+    /// Mouse dragging and scrolling. Returns true if the event was consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.dragging, self.last_cursor) {
+                    self.rotate_horizontal += (position.x - last.x) as f32;
+                    self.rotate_vertical += (position.y - last.y) as f32;
+                }
+                self.last_cursor = Some(*position);
+                self.dragging
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => *y as f32 / 50.0,
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets where the gamepad sticks are, held until the next call.
+    pub fn set_sticks(&mut self, move_stick: [f32; 2], look_stick: [f32; 2]) {
+        self.move_stick = move_stick;
+        self.look_stick = look_stick;
+    }
+
+    /// Reads which movement actions are held, until the next call.
+    pub fn handle_actions(&mut self, input: &Input) {
+        let amount = |action| if input.held(action) { 1.0 } else { 0.0 };
+        self.amount_forward = amount(Action::MoveForward);
+        self.amount_backward = amount(Action::MoveBackward);
+        self.amount_left = amount(Action::MoveLeft);
+        self.amount_right = amount(Action::MoveRight);
+        self.amount_up = amount(Action::MoveUp);
+        self.amount_down = amount(Action::MoveDown);
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        camera.yaw += Rad(self.rotate_horizontal * self.sensitivity);
+        camera.pitch += Rad(-self.rotate_vertical * self.sensitivity);
+        camera.yaw += Rad(self.look_stick[0] * STICK_TURN_SPEED * dt);
+        camera.pitch += Rad(self.look_stick[1] * STICK_TURN_SPEED * dt);
+        camera.pitch.0 = camera.pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        match self.mode {
+            ControllerMode::Fly => {
+                let forward = camera.forward();
+                let right = forward.cross(Vector3::unit_y()).normalize();
+                let forward_amount =
+                    self.amount_forward - self.amount_backward + self.move_stick[1];
+                camera.position += forward * forward_amount * self.speed * dt;
+                // Scrolling is a one-off nudge rather than a held key.
+                camera.position += forward * self.scroll * self.speed * 0.1;
+                let right_amount = self.amount_right - self.amount_left + self.move_stick[0];
+                camera.position += right * right_amount * self.speed * dt;
+                camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+            }
+            ControllerMode::Orbit => {
+                // Scrolling zooms by a fraction of the current distance so it
+                // feels the same close up and far away.
+                self.distance *= 1.0 - self.scroll * 0.1 - self.move_stick[1] * dt;
+                self.distance = self.distance.max(0.01);
+                camera.position = self.target - camera.forward() * self.distance;
+            }
+        }
+        self.scroll = 0.0;
+    }
+}
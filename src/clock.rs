@@ -0,0 +1,208 @@
+//! Where animation time comes from. Everything that moves reads the time
+//! from a `Clock` instead of the system clock, so swapping in a fixed-step
+//! or paused one makes frames reproducible.
+
+use std::time::{Duration, Instant};
+
+/// Time since the clock started, advanced once per frame.
+pub trait Clock {
+    /// Moves on to the next frame and returns its time.
+    fn tick(&mut self) -> Duration;
+    /// The time `tick` last returned.
+    fn now(&self) -> Duration;
+}
+
+/// Follows the system clock.
+pub struct RealClock {
+    start: Instant,
+    offset: Duration,
+    now: Duration,
+}
+
+impl RealClock {
+    /// Starts at `offset` and runs in real time from there.
+    pub fn new(offset: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            offset,
+            now: offset,
+        }
+    }
+}
+
+impl Clock for RealClock {
+    fn tick(&mut self) -> Duration {
+        self.now = self.offset + self.start.elapsed();
+        self.now
+    }
+
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+/// Advances by exactly `step` every frame, however long frames really take.
+pub struct FixedClock {
+    pub step: Duration,
+    now: Duration,
+    started: bool,
+}
+
+impl FixedClock {
+    /// The first `tick` returns `start`.
+    pub fn new(start: Duration, step: Duration) -> Self {
+        Self {
+            step,
+            now: start,
+            started: false,
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn tick(&mut self) -> Duration {
+        if std::mem::replace(&mut self.started, true) {
+            self.now += self.step;
+        }
+        self.now
+    }
+
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+/// Stays at one time.
+pub struct PausedClock(pub Duration);
+
+impl Clock for PausedClock {
+    fn tick(&mut self) -> Duration {
+        self.0
+    }
+
+    fn now(&self) -> Duration {
+        self.0
+    }
+}
+
+/// Runs another clock faster or slower. `scale` can change at any time
+/// without the time jumping, and 0 stops it.
+pub struct ScaledClock {
+    pub scale: f64,
+    source: Box<dyn Clock>,
+    source_now: Duration,
+    now: Duration,
+}
+
+impl ScaledClock {
+    pub fn new(source: Box<dyn Clock>, scale: f64) -> Self {
+        Self {
+            scale,
+            source_now: source.now(),
+            now: source.now(),
+            source,
+        }
+    }
+
+    /// Moves the time on by `by`, whatever the scale. Steps one frame while
+    /// stopped.
+    pub fn skip(&mut self, by: Duration) {
+        self.now += by;
+    }
+
+    /// Catches up with the source without moving the time, so a stretch
+    /// where nothing was rendered isn't one long frame.
+    pub fn resync(&mut self) {
+        self.source_now = self.source.tick();
+    }
+}
+
+impl Clock for ScaledClock {
+    fn tick(&mut self) -> Duration {
+        let source_now = self.source.tick();
+        self.now += source_now
+            .saturating_sub(self.source_now)
+            .mul_f64(self.scale);
+        self.source_now = source_now;
+        self.now
+    }
+
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Runs at `scale` over a clock that steps 100ms a frame from 0.
+    fn scaled(scale: f64) -> ScaledClock {
+        ScaledClock::new(Box::new(FixedClock::new(ms(0), ms(100))), scale)
+    }
+
+    #[test]
+    fn fixed_clock_starts_at_start() {
+        let mut clock = FixedClock::new(ms(5000), ms(100));
+        assert_eq!(clock.now(), ms(5000));
+        assert_eq!(clock.tick(), ms(5000));
+        assert_eq!(clock.tick(), ms(5100));
+        assert_eq!(clock.tick(), ms(5200));
+        assert_eq!(clock.now(), ms(5200));
+    }
+
+    #[test]
+    fn scale_changes_without_jumping() {
+        let mut clock = scaled(1.0);
+        assert_eq!(clock.tick(), ms(0));
+        assert_eq!(clock.tick(), ms(100));
+        assert_eq!(clock.tick(), ms(200));
+        // Only frames after the change run at the new scale.
+        clock.scale = 2.0;
+        assert_eq!(clock.tick(), ms(400));
+        clock.scale = 0.5;
+        assert_eq!(clock.tick(), ms(450));
+    }
+
+    #[test]
+    fn zero_scale_stops_the_clock() {
+        let mut clock = scaled(1.0);
+        clock.tick();
+        clock.tick();
+        clock.scale = 0.0;
+        assert_eq!(clock.tick(), ms(100));
+        assert_eq!(clock.tick(), ms(100));
+        // Picks up from where it stopped.
+        clock.scale = 1.0;
+        assert_eq!(clock.tick(), ms(200));
+    }
+
+    #[test]
+    fn skip_steps_a_stopped_clock() {
+        let mut clock = scaled(0.0);
+        clock.tick();
+        clock.skip(ms(40));
+        assert_eq!(clock.now(), ms(40));
+        assert_eq!(clock.tick(), ms(40));
+        clock.scale = 1.0;
+        clock.skip(ms(40));
+        assert_eq!(clock.tick(), ms(180));
+    }
+
+    #[test]
+    fn resync_skips_the_time_missed() {
+        let mut clock = scaled(1.0);
+        clock.tick();
+        clock.tick();
+        // Three frames pass on the source without being rendered.
+        for _ in 0..3 {
+            clock.resync();
+        }
+        assert_eq!(clock.now(), ms(100));
+        assert_eq!(clock.tick(), ms(200));
+    }
+}
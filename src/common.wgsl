@@ -0,0 +1,47 @@
+// Helpers shared between shaders, pulled in with `#include "common.wgsl"`.
+
+// Wraps `texel` into a texture of `dims` texels, repeating the texture in
+// every direction. With TILING_MIRROR every other repeat is mirrored, so the
+// edges line up.
+fn tile_texel(texel: vec2i, dims: vec2i) -> vec2i {
+#ifdef TILING_MIRROR
+    let period = dims * 2;
+    // `%` keeps the sign of the dividend, so wrap negative coordinates too.
+    let t = (texel % period + period) % period;
+    return select(t, period - 1 - t, t >= dims);
+#else
+    return (texel % dims + dims) % dims;
+#endif
+}
+
+// Tonemapping curves, numbered like `tonemap::Operator`.
+const OPERATOR_ACES: u32 = 0u;
+const OPERATOR_REINHARD: u32 = 1u;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3f) -> vec3f {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return saturate((x * (a * x + b)) / (x * (c * x + d) + e));
+}
+
+fn reinhard(x: vec3f) -> vec3f {
+    return x / (1.0 + x);
+}
+
+fn linear_to_srgb(x: vec3f) -> vec3f {
+    let low = x * 12.92;
+    let high = 1.055 * pow(x, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, x <= vec3f(0.0031308));
+}
+
+// Maps linear HDR `color` into 0-1 with the curve `curve`.
+fn tonemap(color: vec3f, curve: u32) -> vec3f {
+    if curve == OPERATOR_REINHARD {
+        return reinhard(color);
+    }
+    return aces(color);
+}
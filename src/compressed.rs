@@ -0,0 +1,411 @@
+//! KTX2 and DDS containers holding pre-compressed (BCn, ETC2, EAC, ASTC) or
+//! RGBA8 textures with their mip chains.
+
+use half::f16;
+use wgpu::TextureFormat as F;
+
+/// A texture read from a KTX2 or DDS file, still in its file format.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels from the base down, each tightly packed rows of blocks.
+    pub levels: Vec<Vec<u8>>,
+}
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, b'\r', b'\n', 0x1a, b'\n',
+];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+/// Whether `bytes` start like a KTX2 or DDS file.
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(&KTX2_MAGIC) || bytes.starts_with(&DDS_MAGIC)
+}
+
+/// ASTC block sizes in the order KTX2 (and Vulkan) number them.
+const ASTC_BLOCKS: [wgpu::AstcBlock; 14] = [
+    wgpu::AstcBlock::B4x4,
+    wgpu::AstcBlock::B5x4,
+    wgpu::AstcBlock::B5x5,
+    wgpu::AstcBlock::B6x5,
+    wgpu::AstcBlock::B6x6,
+    wgpu::AstcBlock::B8x5,
+    wgpu::AstcBlock::B8x6,
+    wgpu::AstcBlock::B8x8,
+    wgpu::AstcBlock::B10x5,
+    wgpu::AstcBlock::B10x6,
+    wgpu::AstcBlock::B10x8,
+    wgpu::AstcBlock::B10x10,
+    wgpu::AstcBlock::B12x10,
+    wgpu::AstcBlock::B12x12,
+];
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    Some(match format {
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        // BC1 without alpha decodes to opaque in the RGBA format anyway.
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        _ => {
+            let index = format
+                .value()
+                .checked_sub(K::ASTC_4x4_UNORM_BLOCK.value())?;
+            F::Astc {
+                block: *ASTC_BLOCKS.get(index as usize / 2)?,
+                channel: if index % 2 == 0 {
+                    wgpu::AstcChannel::Unorm
+                } else {
+                    wgpu::AstcChannel::UnormSrgb
+                },
+            }
+        }
+    })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    Some(match format {
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbFloat,
+        D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Fails if a `width` x `height` texture can't have `levels` mips, which
+/// would also shift `level_bytes` past the width of a `u32`.
+fn check_level_count(width: u32, height: u32, levels: u32) -> Result<(), String> {
+    let max = 32 - width.max(height).leading_zeros();
+    if levels > max {
+        return Err(format!(
+            "{levels} mips, but a {width}x{height} texture has at most {max}"
+        ));
+    }
+    Ok(())
+}
+
+/// Bytes in mip `level` of a `width` x `height` texture of `format`.
+fn level_bytes(format: wgpu::TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (bw, bh) = format.block_dimensions();
+    let (w, h) = ((width >> level).max(1), (height >> level).max(1));
+    let blocks = w.div_ceil(bw) as usize * h.div_ceil(bh) as usize;
+    blocks * format.block_size(None).unwrap() as usize
+}
+
+impl CompressedImage {
+    /// Reads a KTX2 or DDS file. Only single 2D images are supported: no
+    /// arrays, cubemaps, volumes or supercompression.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(&KTX2_MAGIC) {
+            Self::from_ktx2(bytes)
+        } else if bytes.starts_with(&DDS_MAGIC) {
+            Self::from_dds(bytes)
+        } else {
+            Err("not a KTX2 or DDS file".to_string())
+        }
+    }
+
+    fn from_ktx2(bytes: &[u8]) -> Result<Self, String> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| e.to_string())?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(format!("unsupported supercompression {scheme:?}"));
+        }
+        if header.pixel_height == 0 || header.pixel_depth > 0 {
+            return Err("only 2D textures are supported".to_string());
+        }
+        if header.layer_count > 1 || header.face_count != 1 {
+            return Err("texture arrays and cubemaps aren't supported".to_string());
+        }
+        let format = header
+            .format
+            .ok_or_else(|| "no format (Basis Universal?)".to_string())?;
+        let format = ktx2_format(format).ok_or_else(|| format!("unsupported format {format:?}"))?;
+        check_level_count(
+            header.pixel_width,
+            header.pixel_height,
+            header.level_count.max(1),
+        )?;
+        let levels = reader
+            .levels()
+            .map(|level| level.data.to_vec())
+            .collect::<Vec<_>>();
+        for (i, level) in levels.iter().enumerate() {
+            let expected = level_bytes(format, header.pixel_width, header.pixel_height, i as u32);
+            if level.len() != expected {
+                return Err(format!(
+                    "mip {i} has {} bytes, expected {expected}",
+                    level.len()
+                ));
+            }
+        }
+        Ok(Self {
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height,
+            levels,
+        })
+    }
+
+    fn from_dds(bytes: &[u8]) -> Result<Self, String> {
+        let dds = ddsfile::Dds::read(bytes).map_err(|e| e.to_string())?;
+        if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+            return Err("texture arrays, cubemaps and volumes aren't supported".to_string());
+        }
+        let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(format), _) => {
+                dxgi_format(format).ok_or_else(|| format!("unsupported format {format:?}"))?
+            }
+            (None, Some(ddsfile::D3DFormat::A8B8G8R8)) => F::Rgba8Unorm,
+            (None, format) => return Err(format!("unsupported format {format:?}")),
+        };
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let level_count = dds.get_num_mipmap_levels().max(1);
+        check_level_count(width, height, level_count)?;
+        // `get_data` guesses each mip is a quarter of the last, which is
+        // short for odd sizes. With a single layer it's all the data anyway.
+        let mut data = &dds.data[..];
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let len = level_bytes(format, width, height, level);
+            if data.len() < len {
+                return Err(format!("mip {level} is truncated"));
+            }
+            let (level, rest) = data.split_at(len);
+            levels.push(level.to_vec());
+            data = rest;
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    /// Whether a device with `features` can sample this format as-is.
+    /// Compressed textures also need a base size that's whole blocks.
+    pub fn supported(&self, features: wgpu::Features) -> bool {
+        let (bw, bh) = self.format.block_dimensions();
+        features.contains(self.format.required_features())
+            && self.width.is_multiple_of(bw)
+            && self.height.is_multiple_of(bh)
+    }
+
+    /// Bytes the mip chain takes on the GPU in this format.
+    pub fn gpu_bytes(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    /// Bytes the same mip chain would take as RGBA8.
+    pub fn rgba8_bytes(&self) -> usize {
+        (0..self.levels.len() as u32)
+            .map(|level| level_bytes(F::Rgba8Unorm, self.width, self.height, level))
+            .sum()
+    }
+
+    /// Decodes every mip on the CPU, for devices without the format. LDR
+    /// formats become Rgba8Unorm (or its sRGB twin, still encoded), while
+    /// BC6H and the signed formats become Rgba16Float to keep their range.
+    pub fn decompress(&self) -> Result<Self, String> {
+        if !self.format.is_compressed() {
+            return Ok(self.clone());
+        }
+        let float = matches!(
+            self.format,
+            F::Bc6hRgbUfloat
+                | F::Bc6hRgbFloat
+                | F::Bc4RSnorm
+                | F::Bc5RgSnorm
+                | F::EacR11Snorm
+                | F::EacRg11Snorm
+        );
+        let format = match (float, self.format.is_srgb()) {
+            (true, _) => F::Rgba16Float,
+            (false, true) => F::Rgba8UnormSrgb,
+            (false, false) => F::Rgba8Unorm,
+        };
+        let (bw, bh) = self.format.block_dimensions();
+        let block_size = self.format.block_size(None).unwrap() as usize;
+        let mut texels = vec![[0.0; 4]; (bw * bh) as usize];
+        let mut levels = Vec::with_capacity(self.levels.len());
+        for (level, data) in self.levels.iter().enumerate() {
+            let w = (self.width >> level).max(1);
+            let h = (self.height >> level).max(1);
+            let blocks_w = w.div_ceil(bw);
+            let mut pixels = vec![[0.0; 4]; (w * h) as usize];
+            for (i, block) in data.chunks_exact(block_size).enumerate() {
+                let (x0, y0) = (i as u32 % blocks_w * bw, i as u32 / blocks_w * bh);
+                crate::block_decode::decode_block(self.format, block, &mut texels)?;
+                // Blocks hang over the right and bottom edges of odd sizes.
+                for y in 0..bh.min(h - y0) {
+                    for x in 0..bw.min(w - x0) {
+                        pixels[((y0 + y) * w + x0 + x) as usize] = texels[(y * bw + x) as usize];
+                    }
+                }
+            }
+            levels.push(if float {
+                pixels
+                    .iter()
+                    .flatten()
+                    .flat_map(|&c| f16::from_f32(c).to_ne_bytes())
+                    .collect()
+            } else {
+                pixels
+                    .iter()
+                    .flatten()
+                    .map(|&c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+                    .collect()
+            });
+        }
+        Ok(Self {
+            format,
+            width: self.width,
+            height: self.height,
+            levels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KTX2 file with an empty data format descriptor and no key/value
+    /// data, with `levels` tightly packed after the index.
+    fn ktx2(vk_format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = KTX2_MAGIC.to_vec();
+        let header = [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0];
+        bytes.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+        let dfd_offset = 80 + 24 * levels.len() as u32;
+        bytes.extend([dfd_offset, 8, 0, 0].iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend([0u64, 0].iter().flat_map(|v| v.to_le_bytes()));
+        let mut offset = dfd_offset as u64 + 8;
+        for level in levels {
+            let len = level.len() as u64;
+            bytes.extend([offset, len, len].iter().flat_map(|v| v.to_le_bytes()));
+            offset += len;
+        }
+        bytes.extend([8u32, 0].iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend(levels.iter().flatten());
+        bytes
+    }
+
+    fn assert_solid(img: &CompressedImage, sizes: &[(u32, u32)], texel: [u8; 4]) {
+        assert_eq!(img.levels.len(), sizes.len());
+        for (level, &(w, h)) in img.levels.iter().zip(sizes) {
+            assert_eq!(level.len(), (4 * w * h) as usize);
+            assert!(level.chunks(4).all(|t| t == texel));
+        }
+    }
+
+    #[test]
+    fn ktx2_falls_back_to_rgba8() {
+        // ETC2 RGB8 (VK_FORMAT_ETC2_R8G8B8_UNORM_BLOCK), 6x6 with three
+        // mips, each block a solid (138, 70, 2).
+        let block = [0x88, 0x44, 0, 0, 0, 0, 0, 0];
+        let levels = [4, 1, 1].map(|blocks| block.repeat(blocks));
+        let img = CompressedImage::from_bytes(&ktx2(147, 6, 6, &levels)).unwrap();
+        assert_eq!(img.format, F::Etc2Rgb8Unorm);
+        assert!(!img.supported(wgpu::Features::empty()));
+        let rgba = img.decompress().unwrap();
+        assert_eq!(rgba.format, F::Rgba8Unorm);
+        assert_solid(&rgba, &[(6, 6), (3, 3), (1, 1)], [138, 70, 2, 255]);
+    }
+
+    #[test]
+    fn dds_falls_back_to_rgba8() {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 4,
+            width: 8,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC7_UNorm_sRGB,
+            mipmap_levels: Some(2),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        // Three solid BC7 mode 6 blocks of (254, 128, 0, 254).
+        let block = [
+            0xc0, 0xff, 0x1f, 0x08, 0x04, 0x00, 0xfe, 0x7f, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        dds.data = block.repeat(3);
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        let img = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.format, F::Bc7RgbaUnormSrgb);
+        assert!(!img.supported(wgpu::Features::empty()));
+        let rgba = img.decompress().unwrap();
+        assert_eq!(rgba.format, F::Rgba8UnormSrgb);
+        assert_solid(&rgba, &[(8, 4), (4, 2)], [254, 128, 0, 254]);
+    }
+
+    #[test]
+    fn rejects_more_mips_than_the_size_allows() {
+        // 4x4 has three mips, 4x4, 2x2 and 1x1.
+        let levels = vec![vec![0; 8]; 40];
+        let error = CompressedImage::from_bytes(&ktx2(147, 4, 4, &levels)).unwrap_err();
+        assert_eq!(error, "40 mips, but a 4x4 texture has at most 3");
+
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC1_UNorm,
+            mipmap_levels: Some(3),
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        })
+        .unwrap();
+        dds.header.mip_map_count = Some(33);
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        let error = CompressedImage::from_bytes(&bytes).unwrap_err();
+        assert_eq!(error, "33 mips, but a 4x4 texture has at most 3");
+    }
+}
@@ -0,0 +1,486 @@
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{self, shader_struct};
+use crate::TargetFormats;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3};
+use std::f32::consts::TAU;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Duration;
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    /// Scene units, seen through the camera.
+    World,
+    /// Pixels from the top left of the window. The z coordinate is ignored.
+    Screen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DebugStyle {
+    /// Linear RGBA. World-space lines are drawn before tonemapping, so they
+    /// come out slightly darker than screen-space ones.
+    pub color: [f32; 4],
+    pub space: Space,
+    /// Hides world-space lines behind scene geometry. Screen-space lines are
+    /// always drawn on top.
+    pub depth_test: bool,
+    /// Keeps the primitive for this long. Zero draws it for one frame only.
+    pub duration: Duration,
+}
+
+impl DebugStyle {
+    pub fn world(color: [f32; 4]) -> Self {
+        Self {
+            color,
+            space: Space::World,
+            depth_test: true,
+            duration: Duration::ZERO,
+        }
+    }
+
+    pub fn screen(color: [f32; 4]) -> Self {
+        Self {
+            space: Space::Screen,
+            depth_test: false,
+            ..Self::world(color)
+        }
+    }
+}
+
+/// Matches `Vertex` in debug_draw.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Matches `Params` in debug_draw.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    view_proj: [[f32; 4]; 4],
+    screen_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+shader_struct!(DebugUniform = "Params" { view_proj, screen_size });
+
+/// Which pipeline a line is drawn with, in drawing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bucket {
+    WorldDepthTested = 0,
+    WorldOnTop = 1,
+    Screen = 2,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DebugLine {
+    a: [f32; 3],
+    b: [f32; 3],
+    color: [f32; 4],
+    bucket: Bucket,
+    /// Seconds left before the line is dropped.
+    remaining: f32,
+}
+
+/// Immediate-mode debug lines. Primitives are added during `update`, turned
+/// into one vertex buffer by `prepare`, and drawn with line-list pipelines:
+/// world-space lines in the main pass via `draw_world`, screen-space lines
+/// over the finished frame via `render_screen`.
+pub struct DebugDraw {
+    pub enabled: bool,
+    lines: Vec<DebugLine>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    shader: Rc<wgpu::ShaderModule>,
+    pipeline_layout: wgpu::PipelineLayout,
+    depth_tested_pipeline: Rc<wgpu::RenderPipeline>,
+    on_top_pipeline: Rc<wgpu::RenderPipeline>,
+    screen_pipeline: Rc<wgpu::RenderPipeline>,
+    vertex_buffer: wgpu::Buffer,
+    /// Vertex range of each `Bucket` after `prepare`.
+    ranges: [Range<u32>; 3],
+}
+
+impl DebugDraw {
+    /// `targets` are the main pass attachments, `output_format` the format
+    /// of the view `render_screen` draws into.
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug draw uniform buffer"),
+            size: std::mem::size_of::<DebugUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("Debug draw")
+            .uniform(wgpu::ShaderStages::VERTEX, &uniform_buffer)
+            .build_cached(device, cache);
+        let source = include_str!("debug_draw.wgsl");
+        reflect::assert_matches("debug_draw.wgsl", source, |shader| {
+            shader.check_uniform::<DebugUniform>()
+        });
+        let shader = cache.shader_module(device, "Debug draw shader", source);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug draw pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let screen_pipeline =
+            pipeline_builder(&pipeline_layout, &shader, "vs_screen", output_format)
+                .build_cached(device, cache);
+        let (depth_tested_pipeline, on_top_pipeline) =
+            Self::create_world_pipelines(device, cache, &pipeline_layout, &shader, targets);
+        Self {
+            enabled: true,
+            lines: vec![],
+            uniform_buffer,
+            bind_group,
+            shader,
+            pipeline_layout,
+            depth_tested_pipeline,
+            on_top_pipeline,
+            screen_pipeline,
+            vertex_buffer: Self::create_vertex_buffer(device, 1024),
+            ranges: [0..0, 0..0, 0..0],
+        }
+    }
+
+    fn create_world_pipelines(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        targets: TargetFormats,
+    ) -> (Rc<wgpu::RenderPipeline>, Rc<wgpu::RenderPipeline>) {
+        let mut world = |depth_compare| {
+            pipeline_builder(layout, shader, "vs_world", targets.color)
+                .depth(targets.depth, depth_compare, false)
+                .sample_count(targets.sample_count)
+                .build_cached(device, cache)
+        };
+        (
+            world(wgpu::CompareFunction::LessEqual),
+            world(wgpu::CompareFunction::Always),
+        )
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug draw vertex buffer"),
+            size: capacity * std::mem::size_of::<DebugVertex>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn set_targets(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+    ) {
+        (self.depth_tested_pipeline, self.on_top_pipeline) = Self::create_world_pipelines(
+            device,
+            cache,
+            &self.pipeline_layout,
+            &self.shader,
+            targets,
+        );
+    }
+
+    /// Ages persistent primitives by `dt` and drops the expired ones,
+    /// including everything from the last frame that had no duration. Call
+    /// before adding this frame's primitives.
+    pub fn update(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        self.lines.retain_mut(|line| {
+            line.remaining -= dt;
+            line.remaining > 0.0
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, style: &DebugStyle) {
+        if !self.enabled {
+            return;
+        }
+        let bucket = match (style.space, style.depth_test) {
+            (Space::World, true) => Bucket::WorldDepthTested,
+            (Space::World, false) => Bucket::WorldOnTop,
+            (Space::Screen, _) => Bucket::Screen,
+        };
+        self.lines.push(DebugLine {
+            a: a.into(),
+            b: b.into(),
+            color: style.color,
+            bucket,
+            // Slightly positive so zero-duration lines survive until the
+            // next `update`.
+            remaining: style.duration.as_secs_f32().max(f32::MIN_POSITIVE),
+        });
+    }
+
+    fn polyline(&mut self, points: &[Point3<f32>], closed: bool, style: &DebugStyle) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], style);
+        }
+        if closed && points.len() > 2 {
+            self.line(points[points.len() - 1], points[0], style);
+        }
+    }
+
+    /// A line from `from` to `to` with a head at `to`.
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, style: &DebugStyle) {
+        self.line(from, to, style);
+        let along = to - from;
+        let length = along.magnitude();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let dir = along / length;
+        let back = to - dir * (length * 0.2);
+        let head = length * 0.08;
+        let sides = match style.space {
+            Space::Screen => vec![Vector3::new(-dir.y, dir.x, 0.0).normalize()],
+            Space::World => {
+                let side = perpendicular(dir);
+                vec![side, dir.cross(side)]
+            }
+        };
+        for side in sides {
+            self.line(to, back + side * head, style);
+            self.line(to, back - side * head, style);
+        }
+    }
+
+    /// The rectangle spanning `center ± half_u ± half_v`. In screen space
+    /// use x and y axes, e.g. `rect(center, Vector3::unit_x() * w / 2.0, ...)`.
+    pub fn rect(
+        &mut self,
+        center: Point3<f32>,
+        half_u: Vector3<f32>,
+        half_v: Vector3<f32>,
+        style: &DebugStyle,
+    ) {
+        let corners = [
+            center - half_u - half_v,
+            center + half_u - half_v,
+            center + half_u + half_v,
+            center - half_u + half_v,
+        ];
+        self.polyline(&corners, true, style);
+    }
+
+    /// A circle around `normal`. Screen-space circles should use +z.
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        style: &DebugStyle,
+    ) {
+        let u = perpendicular(normal.normalize());
+        let v = normal.normalize().cross(u);
+        let points: Vec<Point3<f32>> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let (sin, cos) = (i as f32 / CIRCLE_SEGMENTS as f32 * TAU).sin_cos();
+                center + (u * cos + v * sin) * radius
+            })
+            .collect();
+        self.polyline(&points, true, style);
+    }
+
+    /// `rect` divided into `divisions` cells along each axis.
+    pub fn grid(
+        &mut self,
+        center: Point3<f32>,
+        half_u: Vector3<f32>,
+        half_v: Vector3<f32>,
+        divisions: u32,
+        style: &DebugStyle,
+    ) {
+        let divisions = divisions.max(1);
+        for i in 0..=divisions {
+            let t = i as f32 / divisions as f32 * 2.0 - 1.0;
+            self.line(
+                center + half_u * t - half_v,
+                center + half_u * t + half_v,
+                style,
+            );
+            self.line(
+                center - half_u + half_v * t,
+                center + half_u + half_v * t,
+                style,
+            );
+        }
+    }
+
+    /// The twelve edges of an axis-aligned box.
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, style: &DebugStyle) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), style);
+                }
+            }
+        }
+    }
+
+    /// The x, y and z axes of `transform` in red, green and blue, `length`
+    /// units long. Only the alpha of `style.color` is used.
+    pub fn axes(&mut self, transform: Matrix4<f32>, length: f32, style: &DebugStyle) {
+        let origin = Point3::from_homogeneous(transform * Point3::origin().to_homogeneous());
+        let alpha = style.color[3];
+        for (axis, color) in [
+            (Vector3::unit_x(), [1.0, 0.0, 0.0, alpha]),
+            (Vector3::unit_y(), [0.0, 1.0, 0.0, alpha]),
+            (Vector3::unit_z(), [0.0, 0.0, 1.0, alpha]),
+        ] {
+            let end = Point3::from_homogeneous(
+                transform * (Point3::origin() + axis * length).to_homogeneous(),
+            );
+            self.line(origin, end, &DebugStyle { color, ..*style });
+        }
+    }
+
+    /// Uploads the current primitives. `view_proj` maps world space to clip
+    /// space; `width` and `height` are the size of the screen-space target.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view_proj: Matrix4<f32>,
+        width: u32,
+        height: u32,
+    ) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&DebugUniform {
+                view_proj: view_proj.into(),
+                screen_size: [width as f32, height as f32],
+                _pad: [0.0; 2],
+            }),
+        );
+        let mut vertices = Vec::with_capacity(self.lines.len() * 2);
+        for (i, bucket) in [Bucket::WorldDepthTested, Bucket::WorldOnTop, Bucket::Screen]
+            .into_iter()
+            .enumerate()
+        {
+            let start = vertices.len() as u32;
+            for line in self.lines.iter().filter(|line| line.bucket == bucket) {
+                vertices.push(DebugVertex {
+                    position: line.a,
+                    color: line.color,
+                });
+                vertices.push(DebugVertex {
+                    position: line.b,
+                    color: line.color,
+                });
+            }
+            self.ranges[i] = start..vertices.len() as u32;
+        }
+        let capacity = self.vertex_buffer.size() / std::mem::size_of::<DebugVertex>() as u64;
+        if vertices.len() as u64 > capacity {
+            let capacity = (vertices.len() as u64).next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Draws the world-space lines. Belongs in the main pass, after the
+    /// scene, so depth-tested lines can be hidden by it.
+    pub fn draw_world<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        for (pipeline, range) in [
+            (&self.depth_tested_pipeline, &self.ranges[0]),
+            (&self.on_top_pipeline, &self.ranges[1]),
+        ] {
+            if !range.is_empty() {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw(range.clone(), 0..1);
+            }
+        }
+    }
+
+    /// Draws the screen-space lines over the existing contents of `output`,
+    /// which must have the format passed to `new`.
+    pub fn render_screen(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let range = &self.ranges[Bucket::Screen as usize];
+        if range.is_empty() {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug draw pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.screen_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(range.clone(), 0..1);
+    }
+}
+
+/// Alpha-blended line lists drawn by `vertex_entry_point`.
+fn pipeline_builder<'a>(
+    layout: &'a wgpu::PipelineLayout,
+    shader: &'a wgpu::ShaderModule,
+    vertex_entry_point: &'a str,
+    format: wgpu::TextureFormat,
+) -> PipelineBuilder<'a> {
+    PipelineBuilder::new("Debug draw pipeline", layout, shader, format)
+        .vertex_entry_point(vertex_entry_point)
+        .vertex_buffer(DebugVertex::desc())
+        .blend(wgpu::BlendState::ALPHA_BLENDING)
+        .topology(wgpu::PrimitiveTopology::LineList)
+}
+
+/// Any unit vector perpendicular to `v`.
+fn perpendicular(v: Vector3<f32>) -> Vector3<f32> {
+    let other = if v.y.abs() < 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    v.cross(other).normalize()
+}
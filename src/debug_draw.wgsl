@@ -0,0 +1,49 @@
+// Colored line lists for debug drawing, in world space through the camera or
+// in screen pixels.
+
+struct Params {
+    view_proj: mat4x4f,
+    // Target size in pixels.
+    screen_size: vec2f,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+
+// Pulls world-space lines towards the camera in normalized depth, so lines
+// lying on a surface win the depth test against it. Depth bias only applies
+// to triangles.
+const LINE_DEPTH_OFFSET: f32 = 0.000001;
+
+struct Vertex {
+    @location(0) position: vec3f,
+    @location(1) color: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) color: vec4f,
+}
+
+@vertex
+fn vs_world(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let clip = params.view_proj * vec4f(vertex.position, 1.0);
+    out.clip_position = vec4f(clip.xy, clip.z - LINE_DEPTH_OFFSET * clip.w, clip.w);
+    out.color = vertex.color;
+    return out;
+}
+
+@vertex
+fn vs_screen(vertex: Vertex) -> VertexOutput {
+    // Pixel centers, so one-pixel lines land on whole pixels.
+    let ndc = (vertex.position.xy + 0.5) / params.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4f(ndc.x, -ndc.y, 0.0, 1.0);
+    out.color = vertex.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return in.color;
+}
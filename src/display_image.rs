@@ -0,0 +1,166 @@
+//! The 2D view's image, kept in a texture format that holds what the file
+//! did: 8-bit color as `Rgba8Unorm`, grayscale as `R8Unorm` (spread over
+//! RGB by the shader), 16-bit PNGs at 16 bits, and HDR images (EXR, Radiance
+//! `.hdr`) as floats, which the shader tonemaps.
+
+use crate::streaming::Rect;
+use image::DynamicImage;
+use std::path::Path;
+
+/// Largest finite `f16`. HDR images with anything brighter need 32-bit
+/// floats.
+const F16_MAX: f32 = 65504.0;
+
+pub struct DisplayImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed rows of `format` texels.
+    pub data: Vec<u8>,
+    /// Only the red channel is stored.
+    pub gray: bool,
+    /// Linear values that can go past 1, which need exposure and
+    /// tonemapping, rather than sRGB-encoded ones.
+    pub hdr: bool,
+}
+
+impl DisplayImage {
+    /// Decodes `path`, whatever format the `image` crate recognizes it as.
+    /// `features` are the device's, which decide how 16-bit images are
+    /// kept.
+    pub fn load(path: &Path, features: wgpu::Features) -> Result<Self, String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        // Falls back to the extension for formats without a signature, like
+        // TGA.
+        let reader = image::io::Reader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|e| err(&e))?;
+        let image = match reader.format() {
+            // `decode` clamps Radiance images to 8 bits.
+            Some(image::ImageFormat::Hdr) => {
+                let decoder = image::codecs::hdr::HdrDecoder::new(reader.into_inner())
+                    .map_err(|e| err(&e))?;
+                let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+                let pixels = decoder.read_image_hdr().map_err(|e| err(&e))?;
+                let values = pixels.into_iter().flat_map(|pixel| pixel.0).collect();
+                image::Rgb32FImage::from_raw(width, height, values)
+                    .unwrap()
+                    .into()
+            }
+            _ => reader.decode().map_err(|e| err(&e))?,
+        };
+        let display = Self::new(image, features);
+        log::info!(
+            "Loaded {} as {}x{} {:?}",
+            path.display(),
+            display.width,
+            display.height,
+            display.format
+        );
+        Ok(display)
+    }
+
+    pub fn new(image: DynamicImage, features: wgpu::Features) -> Self {
+        let (width, height) = (image.width(), image.height());
+        // Without 16-bit normalized formats, 32-bit floats still hold every
+        // 16-bit value exactly.
+        let norm16 = features.contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM);
+        let gray = !image.color().has_color();
+        let hdr = matches!(
+            image,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+        );
+        let (format, data) = match image {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) => {
+                (wgpu::TextureFormat::R8Unorm, image.into_luma8().into_raw())
+            }
+            DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => {
+                let values = image.into_luma16().into_raw();
+                match norm16 {
+                    true => (wgpu::TextureFormat::R16Unorm, cast(&values)),
+                    false => (wgpu::TextureFormat::R32Float, unorm16_to_f32(&values)),
+                }
+            }
+            DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_) => {
+                let values = image.into_rgba16().into_raw();
+                match norm16 {
+                    true => (wgpu::TextureFormat::Rgba16Unorm, cast(&values)),
+                    false => (wgpu::TextureFormat::Rgba32Float, unorm16_to_f32(&values)),
+                }
+            }
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                let values = image.into_rgba32f().into_raw();
+                if values.iter().all(|v| v.abs() <= F16_MAX) {
+                    let data = values
+                        .iter()
+                        .flat_map(|&v| half::f16::from_f32(v).to_ne_bytes())
+                        .collect();
+                    (wgpu::TextureFormat::Rgba16Float, data)
+                } else {
+                    (wgpu::TextureFormat::Rgba32Float, cast(&values))
+                }
+            }
+            _ => (
+                wgpu::TextureFormat::Rgba8Unorm,
+                image.into_rgba8().into_raw(),
+            ),
+        };
+        Self {
+            format,
+            width,
+            height,
+            data,
+            gray,
+            hdr,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.format.block_size(None).unwrap()
+    }
+
+    /// The texels in `rect`, tightly packed.
+    pub fn region(&self, rect: Rect) -> Vec<u8> {
+        let texel = self.bytes_per_pixel() as usize;
+        let stride = self.width as usize * texel;
+        let row_bytes = rect.width as usize * texel;
+        (rect.y..rect.y + rect.height)
+            .flat_map(|y| {
+                let start = y as usize * stride + rect.x as usize * texel;
+                &self.data[start..start + row_bytes]
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Appends `color`, with channels from 0 to 1, as one texel of this
+    /// image's format. Grayscale images keep the luminance.
+    pub fn encode(&self, color: [f32; 4], out: &mut Vec<u8>) {
+        let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+        let channels = if self.gray { &[luma][..] } else { &color[..] };
+        for &c in channels {
+            match self.format {
+                wgpu::TextureFormat::R8Unorm | wgpu::TextureFormat::Rgba8Unorm => {
+                    out.push((c.clamp(0.0, 1.0) * 255.0).round() as u8)
+                }
+                wgpu::TextureFormat::R16Unorm | wgpu::TextureFormat::Rgba16Unorm => out
+                    .extend_from_slice(
+                        &((c.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes(),
+                    ),
+                wgpu::TextureFormat::Rgba16Float => {
+                    out.extend_from_slice(&half::f16::from_f32(c).to_ne_bytes())
+                }
+                _ => out.extend_from_slice(&c.to_ne_bytes()),
+            }
+        }
+    }
+}
+
+fn cast<T: bytemuck::Pod>(values: &[T]) -> Vec<u8> {
+    bytemuck::cast_slice(values).to_vec()
+}
+
+fn unorm16_to_f32(values: &[u16]) -> Vec<u8> {
+    let floats: Vec<f32> = values.iter().map(|&v| v as f32 / 65535.0).collect();
+    cast(&floats)
+}
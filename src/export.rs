@@ -0,0 +1,257 @@
+//! Rendering an animation offscreen and writing it out, with no window or
+//! display needed. Time comes from a fixed-step clock, so an export is the
+//! same however long each frame takes to render. The 3D view is what gets
+//! exported: a turntable and a recording from `--record` can move the
+//! camera while it plays.
+
+use crate::clock::FixedClock;
+use crate::headless::{Headless, OffscreenScene};
+use crate::input::Bindings;
+use crate::options::Options;
+use crate::replay::{RecordedEvent, Recording};
+use cgmath::Rad;
+use image::RgbaImage;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    /// A directory for a numbered PNG sequence, or an animated `.gif` or
+    /// `.png` (APNG) file.
+    pub path: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub duration: Duration,
+    /// Renders at this many times the size and averages it down, for
+    /// smoother edges than MSAA alone.
+    pub supersample: u32,
+    /// How fast the camera turns around the scene, in degrees per second.
+    pub turntable: f32,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            width: 512,
+            height: 512,
+            fps: 30,
+            duration: Duration::from_secs(2),
+            supersample: 1,
+            turntable: 0.0,
+        }
+    }
+}
+
+impl ExportSettings {
+    pub fn frame_count(&self) -> u32 {
+        (self.duration.as_secs_f64() * self.fps as f64)
+            .ceil()
+            .max(1.0) as u32
+    }
+
+    pub fn frame_step(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.fps as f64)
+    }
+}
+
+/// Where frames go, picked from the output path.
+enum Output {
+    Sequence(PathBuf),
+    Gif(image::codecs::gif::GifEncoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+impl Output {
+    fn create(path: &Path, settings: &ExportSettings) -> Result<Self, String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        let extension = path.extension().and_then(|e| e.to_str());
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("gif") => {
+                let file = File::create(path).map_err(|e| err(&e))?;
+                let mut encoder = image::codecs::gif::GifEncoder::new(BufWriter::new(file));
+                encoder
+                    .set_repeat(image::codecs::gif::Repeat::Infinite)
+                    .map_err(|e| err(&e))?;
+                Ok(Output::Gif(encoder))
+            }
+            Some("png") => {
+                let file = File::create(path).map_err(|e| err(&e))?;
+                let mut encoder =
+                    png::Encoder::new(BufWriter::new(file), settings.width, settings.height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+                encoder
+                    .set_animated(settings.frame_count(), 0)
+                    .map_err(|e| err(&e))?;
+                // Each frame shows for 1/fps seconds, as a 16-bit fraction.
+                let fps = u16::try_from(settings.fps)
+                    .map_err(|_| err(&format!("{} fps is too high for APNG", settings.fps)))?;
+                encoder.set_frame_delay(1, fps).map_err(|e| err(&e))?;
+                Ok(Output::Apng(encoder.write_header().map_err(|e| err(&e))?))
+            }
+            None => {
+                std::fs::create_dir_all(path).map_err(|e| err(&e))?;
+                Ok(Output::Sequence(path.to_owned()))
+            }
+            Some(other) => Err(format!(
+                "{}: can't export to .{other}, only to .gif, .png or a directory",
+                path.display()
+            )),
+        }
+    }
+
+    fn write(&mut self, index: u32, image: RgbaImage, fps: u32) -> Result<(), String> {
+        match self {
+            Output::Sequence(dir) => {
+                let path = dir.join(format!("frame_{index:04}.png"));
+                image
+                    .save(&path)
+                    .map_err(|e| format!("{}: {e}", path.display()))
+            }
+            Output::Gif(encoder) => {
+                let delay = image::Delay::from_numer_denom_ms(1000, fps);
+                encoder
+                    .encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+                    .map_err(|e| e.to_string())
+            }
+            Output::Apng(writer) => writer.write_image_data(&image).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            Output::Apng(writer) => writer.finish().map_err(|e| e.to_string()),
+            // The GIF trailer is written when the encoder is dropped.
+            Output::Sequence(_) | Output::Gif(_) => Ok(()),
+        }
+    }
+}
+
+/// Exports the models in `options`, or the golden lighting scene without
+/// any, as `options.export` describes. Plays `options.replay` back along
+/// the way if set, at the export's frame rate.
+pub fn run(options: &Options) -> Result<(), String> {
+    let settings = &options.export;
+    let path = settings.path.as_deref().ok_or("Nothing to export to")?;
+    let recording = options.replay.as_deref().map(Recording::load).transpose()?;
+    let headless = pollster::block_on(Headless::new())?;
+    let desc = crate::load_scene_desc(options).unwrap_or_else(|| {
+        log::warn!("No models to export, using the golden lighting scene");
+        crate::golden::lighting_scene().0
+    });
+
+    let max_size = headless.device.limits().max_texture_dimension_2d;
+    let scaled = |size: u32| {
+        size.checked_mul(settings.supersample)
+            .filter(|&size| size <= max_size)
+    };
+    let (Some(width), Some(height)) = (scaled(settings.width), scaled(settings.height)) else {
+        return Err(format!(
+            "{}x{} at {}x supersampling is too big to render, the limit is {max_size}x{max_size}",
+            settings.width, settings.height, settings.supersample
+        ));
+    };
+    let mut offscreen = OffscreenScene::new(&headless, &desc, width, height);
+    offscreen.input.bindings = Bindings::load_or_default(&options.input_config);
+    let step = settings.frame_step();
+    offscreen.clock = Box::new(FixedClock::new(options.start_time, step));
+    let mut replayed = recording.iter().flat_map(|r| &r.frames).peekable();
+
+    let mut output = Output::create(path, settings)?;
+    let frames = settings.frame_count();
+    for index in 0..frames {
+        // Everything recorded up to the time of this frame.
+        let time = options.start_time + step * index;
+        while let Some(frame) = replayed.next_if(|frame| frame.time() <= time) {
+            for event in &frame.events {
+                // The export keeps its own size.
+                if !matches!(event, RecordedEvent::Resized { .. }) {
+                    offscreen.input(&event.to_event());
+                }
+            }
+        }
+        if index > 0 {
+            let turn = settings.turntable.to_radians() * step.as_secs_f32();
+            offscreen.scene.camera.yaw += Rad(turn);
+        }
+        offscreen.update(&headless);
+        let image = downsample(&offscreen.render(&headless), settings.supersample);
+        output.write(index, image, settings.fps)?;
+    }
+    output.finish()?;
+    println!(
+        "Exported {frames} frames ({:.2}s to {:.2}s at {} fps) to {}",
+        options.start_time.as_secs_f64(),
+        offscreen.clock.now().as_secs_f64(),
+        settings.fps,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Averages each `factor` x `factor` block of `image` into one pixel, in
+/// linear light so edges don't come out too dark.
+fn downsample(image: &RgbaImage, factor: u32) -> RgbaImage {
+    if factor <= 1 {
+        return image.clone();
+    }
+    let to_linear = |c: u8| (c as f32 / 255.0).powf(2.2);
+    let to_srgb = |c: f32| (c.powf(1.0 / 2.2) * 255.0).round() as u8;
+    let samples = (factor * factor) as f32;
+    RgbaImage::from_fn(image.width() / factor, image.height() / factor, |x, y| {
+        let mut sum = [0.0f32; 4];
+        for dy in 0..factor {
+            for dx in 0..factor {
+                let pixel = image.get_pixel(x * factor + dx, y * factor + dy);
+                for c in 0..3 {
+                    sum[c] += to_linear(pixel[c]);
+                }
+                sum[3] += pixel[3] as f32 / 255.0;
+            }
+        }
+        image::Rgba([
+            to_srgb(sum[0] / samples),
+            to_srgb(sum[1] / samples),
+            to_srgb(sum[2] / samples),
+            (sum[3] / samples * 255.0).round() as u8,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_in_linear_light() {
+        let image = RgbaImage::from_fn(5, 3, |x, _| match x % 2 {
+            0 => image::Rgba([0, 0, 0, 0]),
+            _ => image::Rgba([255, 255, 255, 255]),
+        });
+        assert_eq!(downsample(&image, 1), image);
+        // The leftover column and row are dropped.
+        let half = downsample(&image, 2);
+        assert_eq!(half.dimensions(), (2, 1));
+        assert!(half.pixels().all(|p| p.0 == [186, 186, 186, 128]));
+    }
+
+    #[test]
+    fn output_follows_the_extension() {
+        let dir = std::env::temp_dir().join("wgpu-setup-export-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let settings = ExportSettings::default();
+        let create = |name: &str| Output::create(&dir.join(name), &settings);
+        assert!(matches!(create("out.gif"), Ok(Output::Gif(_))));
+        assert!(matches!(create("out.GIF"), Ok(Output::Gif(_))));
+        assert!(matches!(create("out.png"), Ok(Output::Apng(_))));
+        assert!(matches!(create("frames"), Ok(Output::Sequence(_))));
+        assert!(dir.join("frames").is_dir());
+        let error = create("out.mp4").err().unwrap();
+        assert!(error.ends_with("can't export to .mp4, only to .gif, .png or a directory"));
+    }
+}
@@ -0,0 +1,645 @@
+//! Headless golden-image tests. Each scene is rendered offscreen at a fixed
+//! size and compared against `assets/golden/<name>.png`; run with
+//! `--golden` to check and `--golden --bless` to rewrite the references.
+
+use crate::blend::BlendMode;
+use crate::camera::{Camera, ControllerMode};
+use crate::debug_draw::{DebugDraw, DebugStyle};
+use crate::headless::{Headless, OffscreenScene, OffscreenTarget};
+use crate::light::Light;
+use crate::material::{MaterialData, ShadingModel};
+use crate::model::{MeshData, ModelData};
+use crate::pipeline_cache::PipelineCache;
+use crate::replay::{self, Recording};
+use crate::resilience::Fault;
+use crate::scene::{MeshScene, Object, SceneDesc};
+use crate::text::{self, Align, Rasterization, TextRenderer, TextStyle};
+use crate::tiled::{TilePyramid, TiledView};
+use crate::ui::Ui;
+use crate::TargetFormats;
+use cgmath::{Deg, Matrix4, Point3, Rad, Vector3};
+use std::path::{Path, PathBuf};
+
+pub const GOLDEN_DIR: &str = "assets/golden";
+const REPLAY: &str = "assets/replay/orbit.ron";
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+/// Channels may differ by this much before a pixel counts as different, to
+/// absorb rasterization differences between drivers.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to differ before the test fails.
+const PIXEL_TOLERANCE: f32 = 0.005;
+
+/// A lit cube on a ground plane, with one light of each kind.
+pub fn lighting_scene() -> (SceneDesc, Camera) {
+    let cube = ModelData::load(Path::new("assets/cube.obj")).expect("assets/cube.obj");
+    let mut plane = MeshData::plane(6.0);
+    plane.material = Some(0);
+    let ground = ModelData {
+        meshes: vec![plane],
+        materials: vec![MaterialData {
+            name: "ground".to_string(),
+            albedo: [0.8, 0.8, 0.8, 1.0],
+            roughness: 0.8,
+            specular: [0.1, 0.1, 0.1],
+            shininess: 8.0,
+            ..Default::default()
+        }],
+    };
+    let desc = SceneDesc {
+        models: vec![cube, ground],
+        objects: vec![
+            Object {
+                model: 0,
+                transform: Matrix4::from_translation(Vector3::new(0.0, 0.5, 0.0))
+                    * Matrix4::from_angle_y(Deg(30.0)),
+            },
+            Object {
+                model: 1,
+                transform: Matrix4::from_scale(1.0),
+            },
+        ],
+        lights: vec![
+            Light::directional(Vector3::new(-0.3, -1.0, -0.5), [1.0, 1.0, 1.0], 1.5),
+            Light::point(Point3::new(1.5, 1.0, 1.5), [1.0, 0.4, 0.2], 8.0, 6.0),
+            Light::spot(
+                Point3::new(-2.0, 3.0, 1.0),
+                Vector3::new(2.0, -3.0, -1.0),
+                [0.3, 0.5, 1.0],
+                16.0,
+                10.0,
+                Deg(12.0),
+                Deg(20.0),
+            ),
+        ],
+        ambient: [0.0, 0.0, 0.0],
+        environment: None,
+        environment_intensity: 0.15,
+        skybox: None,
+    };
+    let camera = Camera::new(
+        Point3::new(0.0, 2.5, 4.0),
+        Rad(-std::f32::consts::FRAC_PI_2),
+        Rad(-0.5),
+    );
+    (desc, camera)
+}
+
+/// The lighting scene with Blinn-Phong materials, lit only by its lights
+/// and a flat ambient term.
+pub fn blinn_phong_scene() -> (SceneDesc, Camera) {
+    let (mut desc, camera) = lighting_scene();
+    for model in &mut desc.models {
+        model.set_shading(ShadingModel::BlinnPhong);
+    }
+    for (light, intensity) in desc.lights.iter_mut().zip([0.6, 4.0, 8.0]) {
+        light.intensity = intensity;
+    }
+    desc.ambient = [0.05, 0.05, 0.05];
+    desc.environment_intensity = 0.0;
+    (desc, camera)
+}
+
+/// The lighting scene under a low directional light and a spot light, both
+/// casting long shadows across the ground.
+pub fn shadow_scene() -> (SceneDesc, Camera) {
+    let (mut desc, camera) = lighting_scene();
+    desc.lights = vec![
+        Light::directional(Vector3::new(-1.0, -0.9, 0.2), [1.0, 0.95, 0.9], 2.0),
+        Light::spot(
+            Point3::new(-1.0, 2.5, -2.5),
+            Vector3::new(1.0, -2.5, 2.5),
+            [0.3, 0.5, 1.0],
+            16.0,
+            10.0,
+            Deg(20.0),
+            Deg(30.0),
+        ),
+    ];
+    (desc, camera)
+}
+
+/// A row of spheres from dielectric to metal over a row from smooth to rough,
+/// lit mostly by the environment.
+pub fn pbr_scene() -> (SceneDesc, Camera) {
+    let sphere = |metallic, roughness| ModelData {
+        meshes: vec![MeshData {
+            material: Some(0),
+            ..MeshData::sphere(0.4, 24, 48)
+        }],
+        materials: vec![MaterialData {
+            name: format!("m{metallic}r{roughness}"),
+            albedo: [0.9, 0.6, 0.3, 1.0],
+            metallic,
+            roughness,
+            ..Default::default()
+        }],
+    };
+    let mut models = vec![];
+    let mut objects = vec![];
+    for row in 0..2 {
+        for column in 0..4 {
+            let roughness = 0.1 + 0.3 * column as f32;
+            objects.push(Object {
+                model: models.len(),
+                transform: Matrix4::from_translation(Vector3::new(
+                    column as f32 - 1.5,
+                    row as f32 - 0.5,
+                    0.0,
+                )),
+            });
+            models.push(sphere(row as f32, roughness));
+        }
+    }
+    let desc = SceneDesc {
+        models,
+        objects,
+        lights: vec![Light::directional(
+            Vector3::new(-0.5, -0.6, -1.0),
+            [1.0, 1.0, 1.0],
+            2.0,
+        )],
+        ambient: [0.0, 0.0, 0.0],
+        environment: None,
+        environment_intensity: 1.0,
+        skybox: None,
+    };
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 4.5),
+        Rad(-std::f32::consts::FRAC_PI_2),
+        Rad(0.0),
+    );
+    (desc, camera)
+}
+
+/// A mirror sphere in front of a skybox whose faces are distinct colors,
+/// each with a dark top-left quadrant, seen towards the +X +Y -Z corner so
+/// face order and orientation both show up.
+pub fn skybox_scene() -> (SceneDesc, Camera) {
+    let colors = [
+        [255, 0, 0],
+        [0, 255, 255],
+        [0, 255, 0],
+        [255, 0, 255],
+        [0, 0, 255],
+        [255, 255, 0],
+    ];
+    let faces = colors.map(|[r, g, b]| {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 64, |x, y| {
+            let shade = if x < 32 && y < 32 { 4 } else { 1 };
+            image::Rgba([r / shade, g / shade, b / shade, 255])
+        }))
+    });
+    let (mut desc, _) = pbr_scene();
+    desc.models.truncate(1);
+    desc.models[0].materials[0].metallic = 1.0;
+    desc.models[0].materials[0].roughness = 0.1;
+    desc.objects = vec![Object {
+        model: 0,
+        transform: Matrix4::from_scale(1.0),
+    }];
+    desc.skybox = Some(faces);
+    let camera = Camera::new(
+        Point3::new(-1.5, -1.0, 1.5),
+        Rad(-std::f32::consts::FRAC_PI_4),
+        Rad(0.4406),
+    );
+    (desc, camera)
+}
+
+/// An opaque sphere behind three overlapping quads: two alpha blended and
+/// one additive, partly hidden by the sphere. The quads are listed front to
+/// back so they only come out right when sorted.
+pub fn transparency_scene() -> (SceneDesc, Camera) {
+    let (mut desc, camera) = pbr_scene();
+    desc.models.truncate(1);
+    desc.objects = vec![Object {
+        model: 0,
+        transform: Matrix4::from_scale(1.0),
+    }];
+    let quads = [
+        ([1.0, 0.2, 0.2, 0.5], BlendMode::Alpha, [-0.3, 0.2, 1.2]),
+        ([0.2, 1.0, 0.2, 0.5], BlendMode::Alpha, [0.3, -0.2, 0.8]),
+        ([0.2, 0.3, 1.0, 1.0], BlendMode::Additive, [0.0, 0.0, -0.6]),
+    ];
+    for (albedo, blend_mode, [x, y, z]) in quads {
+        desc.objects.push(Object {
+            model: desc.models.len(),
+            // The plane faces +y, turn it towards the camera.
+            transform: Matrix4::from_translation(Vector3::new(x, y, z))
+                * Matrix4::from_angle_x(Deg(90.0)),
+        });
+        desc.models.push(ModelData {
+            meshes: vec![MeshData {
+                material: Some(0),
+                ..MeshData::plane(1.0)
+            }],
+            materials: vec![MaterialData {
+                name: format!("{blend_mode:?}"),
+                albedo,
+                emissive: [albedo[0], albedo[1], albedo[2]],
+                blend_mode,
+                ..Default::default()
+            }],
+        });
+    }
+    (desc, camera)
+}
+
+/// Renders `desc` from `camera` into an image, tonemapped the same way as the
+/// window. `debug` may add debug lines, which are drawn like in the window.
+pub fn render_scene(
+    headless: &Headless,
+    desc: &SceneDesc,
+    camera: Camera,
+    width: u32,
+    height: u32,
+    debug: Option<fn(&MeshScene, &mut DebugDraw)>,
+) -> image::RgbaImage {
+    let mut offscreen = OffscreenScene::new(headless, desc, width, height);
+    offscreen.scene.camera = camera;
+    offscreen.scene.camera_controller.mode = ControllerMode::Fly;
+    offscreen.update(headless);
+    if let Some(debug) = debug {
+        debug(&offscreen.scene, &mut offscreen.debug_draw);
+    }
+    offscreen.render(headless)
+}
+
+/// Every debug primitive over the lighting scene: depth-tested bounds that
+/// the cube partly hides, a circle drawn through it, and screen-space shapes.
+fn draw_debug_primitives(scene: &MeshScene, debug: &mut DebugDraw) {
+    scene.draw_debug(debug);
+    let on_top = DebugStyle {
+        depth_test: false,
+        ..DebugStyle::world([1.0, 0.0, 1.0, 1.0])
+    };
+    debug.circle(Point3::new(0.0, 0.5, 0.0), Vector3::unit_x(), 0.9, &on_top);
+    let hidden = DebugStyle::world([0.0, 1.0, 1.0, 1.0]);
+    debug.aabb(
+        Point3::new(-0.3, 0.0, -1.5),
+        Point3::new(0.3, 1.4, -0.9),
+        &hidden,
+    );
+    let screen = DebugStyle::screen([1.0, 1.0, 1.0, 1.0]);
+    debug.rect(
+        Point3::new(40.0, 40.0, 0.0),
+        Vector3::unit_x() * 30.0,
+        Vector3::unit_y() * 20.0,
+        &screen,
+    );
+    debug.circle(
+        Point3::new(216.0, 40.0, 0.0),
+        Vector3::unit_z(),
+        24.0,
+        &screen,
+    );
+    debug.arrow(
+        Point3::new(100.0, 30.0, 0.0),
+        Point3::new(160.0, 50.0, 0.0),
+        &screen,
+    );
+    debug.line(
+        Point3::new(0.0, 250.0, 0.0),
+        Point3::new(255.0, 250.0, 0.0),
+        &DebugStyle::screen([1.0, 0.5, 0.0, 0.5]),
+    );
+}
+
+/// Bitmap and SDF text at several sizes, wrapped and aligned three ways.
+pub fn render_text(headless: &Headless, width: u32, height: u32) -> image::RgbaImage {
+    let (device, queue) = (&headless.device, &headless.queue);
+    let targets = TargetFormats {
+        color: wgpu::TextureFormat::Rgba8UnormSrgb,
+        depth: wgpu::TextureFormat::Depth24Plus,
+        sample_count: 1,
+    };
+    let target = OffscreenTarget::new(device, width, height, targets);
+    let font = text::load_font(Path::new(text::DEFAULT_FONT)).expect(text::DEFAULT_FONT);
+    let mut cache = PipelineCache::new();
+    let mut bitmap = TextRenderer::new(
+        device,
+        &mut cache,
+        targets.color,
+        font.clone(),
+        Rasterization::Bitmap,
+    );
+    let mut sdf = TextRenderer::new(device, &mut cache, targets.color, font, Rasterization::Sdf);
+    let margin = 8.0;
+    let column = Some(width as f32 - 2.0 * margin);
+    bitmap.queue_text(
+        "Bitmap 13px: the quick brown fox jumps over the lazy dog.",
+        [margin, margin],
+        &TextStyle {
+            size: 13.0,
+            max_width: column,
+            ..Default::default()
+        },
+    );
+    sdf.queue_text(
+        "SDF centered",
+        [margin, 56.0],
+        &TextStyle {
+            size: 28.0,
+            color: [1.0, 0.8, 0.2, 1.0],
+            align: Align::Center,
+            max_width: column,
+            ..Default::default()
+        },
+    );
+    sdf.queue_text(
+        "Right aligned and wrapped, with kerning: AVATAR To Wavy",
+        [margin, 100.0],
+        &TextStyle {
+            size: 18.0,
+            color: [0.5, 0.8, 1.0, 1.0],
+            align: Align::Right,
+            max_width: column,
+            ..Default::default()
+        },
+    );
+    sdf.queue_text(
+        "Ag",
+        [margin, 170.0],
+        &TextStyle {
+            size: 72.0,
+            color: [1.0, 1.0, 1.0, 0.8],
+            ..Default::default()
+        },
+    );
+    bitmap.prepare(device, queue, width, height);
+    sdf.prepare(device, queue, width, height);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden text encoder"),
+    });
+    {
+        let background = wgpu::Color {
+            r: 0.02,
+            g: 0.03,
+            b: 0.05,
+            a: 1.0,
+        };
+        target.begin_render_pass(&mut encoder, background);
+    }
+    bitmap.render(&mut encoder, target.color_view());
+    sdf.render(&mut encoder, target.color_view());
+    queue.submit(std::iter::once(encoder.finish()));
+    target.read(device, queue)
+}
+
+/// A settings panel with one of each widget, at fixed values.
+pub fn render_ui(headless: &Headless, width: u32, height: u32) -> image::RgbaImage {
+    let (device, queue) = (&headless.device, &headless.queue);
+    let targets = TargetFormats {
+        color: wgpu::TextureFormat::Rgba8UnormSrgb,
+        depth: wgpu::TextureFormat::Depth24Plus,
+        sample_count: 1,
+    };
+    let target = OffscreenTarget::new(device, width, height, targets);
+    let font = text::load_font(Path::new(text::DEFAULT_FONT)).expect(text::DEFAULT_FONT);
+    let mut cache = PipelineCache::new();
+    let mut ui = Ui::new(device, &mut cache, targets.color, font);
+    ui.begin_frame();
+    {
+        let mut panel = ui.panel("Settings", [8.0, 8.0]);
+        panel.label("60 fps (16.67 ms)");
+        panel.separator();
+        panel.color_edit("Color", &mut [1.0, 0.5, 0.25, 0.75]);
+        panel.slider("Tile scale", &mut 1.5, 0.25..=4.0);
+        panel.checkbox("Debug draw", &mut true);
+        panel.choice("Present mode", &mut 1, &["Fifo", "Mailbox"]);
+    }
+    ui.prepare(device, queue, width, height);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden UI encoder"),
+    });
+    {
+        let background = wgpu::Color {
+            r: 0.3,
+            g: 0.15,
+            b: 0.1,
+            a: 1.0,
+        };
+        target.begin_render_pass(&mut encoder, background);
+    }
+    ui.render(&mut encoder, target.color_view());
+    queue.submit(std::iter::once(encoder.finish()));
+    target.read(device, queue)
+}
+
+/// A gradient under a checkerboard of tiles and a grid, wider than the
+/// default 8192 texture limit, viewed past that edge at above full
+/// resolution once every visible tile has been streamed in.
+pub fn render_tiled(headless: &Headless, width: u32, height: u32) -> image::RgbaImage {
+    let (device, queue) = (&headless.device, &headless.queue);
+    let targets = TargetFormats {
+        color: wgpu::TextureFormat::Rgba8UnormSrgb,
+        depth: wgpu::TextureFormat::Depth24Plus,
+        sample_count: 1,
+    };
+    let target = OffscreenTarget::new(device, width, height, targets);
+    let image = image::RgbaImage::from_fn(8300, 1200, |x, y| {
+        if x % 64 == 0 || y % 64 == 0 {
+            return image::Rgba([255, 255, 255, 255]);
+        }
+        let checker = ((x / 256 + y / 256) % 2) as u8 * 64;
+        let r = (x * 255 / 8300) as u8;
+        let g = (y * 255 / 1200) as u8;
+        image::Rgba([r, g, checker + 96, 255])
+    });
+    let mut cache = PipelineCache::new();
+    let pyramid = std::rc::Rc::new(TilePyramid::new(image));
+    let mut view = TiledView::new(device, &mut cache, targets, pyramid, width, height);
+    view.camera.center = [8200.0, 600.0];
+    view.camera.zoom = 1.5;
+    for _ in 0..64 {
+        view.update(device, queue, width, height);
+        if view.stats.fallbacks == 0 && view.stats.uploaded == 0 {
+            break;
+        }
+    }
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Golden tiled encoder"),
+    });
+    {
+        let mut render_pass = target.begin_render_pass(&mut encoder, wgpu::Color::BLACK);
+        view.draw(&mut render_pass);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+    target.read(device, queue)
+}
+
+/// Returns an error describing the mismatch if `actual` differs from
+/// `expected` by more than the tolerances.
+pub fn compare(actual: &image::RgbaImage, expected: &image::RgbaImage) -> Result<(), String> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "size {:?} doesn't match expected {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+    let differing = actual
+        .pixels()
+        .zip(expected.pixels())
+        .filter(|(a, e)| {
+            a.0.iter()
+                .zip(e.0.iter())
+                .any(|(a, e)| a.abs_diff(*e) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let fraction = differing as f32 / (actual.width() * actual.height()) as f32;
+    if fraction > PIXEL_TOLERANCE {
+        return Err(format!(
+            "{differing} pixels ({:.2}%) differ",
+            fraction * 100.0
+        ));
+    }
+    Ok(())
+}
+
+/// Renders every golden scene and checks (or with `bless`, rewrites) the
+/// reference images. Returns whether all of them passed.
+pub fn run(bless: bool) -> bool {
+    let headless = match pollster::block_on(Headless::new()) {
+        Ok(headless) => headless,
+        Err(e) => {
+            log::error!("{e}");
+            return false;
+        }
+    };
+
+    let scenes = [
+        ("lighting", lighting_scene()),
+        ("blinn_phong", blinn_phong_scene()),
+        ("shadows", shadow_scene()),
+        ("pbr", pbr_scene()),
+        ("skybox", skybox_scene()),
+        ("transparency", transparency_scene()),
+    ];
+    let mut passed = true;
+    for (name, (desc, camera)) in scenes {
+        let actual = render_scene(&headless, &desc, camera, WIDTH, HEIGHT, None);
+        passed &= check(name, &actual, bless);
+    }
+    let (desc, camera) = lighting_scene();
+    let actual = render_scene(
+        &headless,
+        &desc,
+        camera,
+        WIDTH,
+        HEIGHT,
+        Some(draw_debug_primitives),
+    );
+    passed &= check("debug_draw", &actual, bless);
+    passed &= check("text", &render_text(&headless, WIDTH, HEIGHT), bless);
+    passed &= check("ui", &render_ui(&headless, WIDTH, HEIGHT), bless);
+    passed &= check("tiled", &render_tiled(&headless, WIDTH, HEIGHT), bless);
+    passed &= check_replay(&headless, bless);
+    passed &= check_recovery(&headless);
+    passed
+}
+
+/// Replays `assets/replay/orbit.ron` over the lighting scene twice. Every
+/// frame has to match between the two, and the last one the reference.
+fn check_replay(headless: &Headless, bless: bool) -> bool {
+    let recording = match Recording::load(Path::new(REPLAY)) {
+        Ok(recording) => recording,
+        Err(e) => {
+            println!("replay: FAILED: {e}");
+            return false;
+        }
+    };
+    let (desc, _) = lighting_scene();
+    let play = || {
+        let mut frames = vec![];
+        let mut offscreen = OffscreenScene::new(headless, &desc, WIDTH, HEIGHT);
+        replay::render(headless, &mut offscreen, &recording, |_, image| {
+            frames.push(image)
+        });
+        frames
+    };
+    let (first, second) = (play(), play());
+    if let Some(index) = (0..first.len()).find(|&i| first[i] != second[i]) {
+        println!("replay: FAILED: frame {index} differs between two replays");
+        return false;
+    }
+    match first.last() {
+        Some(last) => check("replay", last, bless),
+        None => {
+            println!("replay: FAILED: {REPLAY} has no frames");
+            false
+        }
+    }
+}
+
+/// Renders a frame of the lighting scene, injects a lost device and
+/// recovers onto a new one. The next frame has to match the lighting
+/// reference.
+fn check_recovery(headless: &Headless) -> bool {
+    let (desc, camera) = lighting_scene();
+    let mut offscreen = OffscreenScene::new(headless, &desc, WIDTH, HEIGHT);
+    offscreen.scene.camera = camera;
+    offscreen.scene.camera_controller.mode = ControllerMode::Fly;
+    offscreen.update(headless);
+    offscreen.render(headless);
+
+    headless.health.inject(Fault::DeviceLost);
+    let fault = headless.health.check();
+    if !fault.is_some_and(Fault::needs_new_device) {
+        println!("recovery: FAILED: the injected fault came back as {fault:?}");
+        return false;
+    }
+    let replacement = match pollster::block_on(headless.replace()) {
+        Ok(replacement) => replacement,
+        Err(e) => {
+            println!("recovery: FAILED: {e}");
+            return false;
+        }
+    };
+    let mut offscreen = offscreen.recover(&replacement);
+    offscreen.update(&replacement);
+    let actual = offscreen.render(&replacement);
+    check_against("recovery", "lighting", &actual, false)
+}
+
+/// Compares one rendered image against its reference, saving the actual
+/// image under `target/golden` on failure so it can be inspected.
+pub fn check(name: &str, actual: &image::RgbaImage, bless: bool) -> bool {
+    check_against(name, name, actual, bless)
+}
+
+/// `check`, against the reference image of `reference` instead of `name`'s
+/// own.
+fn check_against(name: &str, reference: &str, actual: &image::RgbaImage, bless: bool) -> bool {
+    let path = PathBuf::from(GOLDEN_DIR).join(format!("{reference}.png"));
+    if bless {
+        std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+        actual.save(&path).unwrap();
+        println!("{name}: blessed {}", path.display());
+        return true;
+    }
+    let result = image::open(&path)
+        .map_err(|e| format!("{}: {e}", path.display()))
+        .and_then(|expected| compare(actual, &expected.to_rgba8()));
+    match result {
+        Ok(()) => {
+            println!("{name}: ok");
+            true
+        }
+        Err(e) => {
+            let out = PathBuf::from("target/golden").join(format!("{name}.png"));
+            std::fs::create_dir_all(out.parent().unwrap()).unwrap();
+            actual.save(&out).unwrap();
+            println!(
+                "{name}: FAILED: {e} (actual image saved to {})",
+                out.display()
+            );
+            false
+        }
+    }
+}
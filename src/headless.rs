@@ -0,0 +1,387 @@
+use crate::clock::{Clock, PausedClock};
+use crate::debug_draw::DebugDraw;
+use crate::input::{Bindings, Input};
+use crate::pipeline_cache::PipelineCache;
+use crate::resilience::DeviceHealth;
+use crate::scene::{MeshScene, SceneDesc};
+use crate::shadow::ShadowSettings;
+use crate::tonemap::{Tonemap, HDR_FORMAT};
+use crate::{request_device, TargetFormats};
+use std::rc::Rc;
+use std::time::Duration;
+use winit::event::WindowEvent;
+
+/// A device with no window or surface, for rendering offscreen.
+pub struct Headless {
+    instance: Rc<wgpu::Instance>,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub health: DeviceHealth,
+}
+
+impl Headless {
+    pub async fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            ..Default::default()
+        });
+        Self::from_instance(Rc::new(instance)).await
+    }
+
+    /// A new adapter and device from the same instance, like the window's
+    /// `Gpu::replace`.
+    pub async fn replace(&self) -> Result<Self, String> {
+        Self::from_instance(self.instance.clone()).await
+    }
+
+    async fn from_instance(instance: Rc<wgpu::Instance>) -> Result<Self, String> {
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                ..Default::default()
+            })
+            .await
+            .ok_or("No suitable adapter for headless rendering")?;
+        log::info!("Headless adapter: {:?}", adapter.get_info());
+        let (device, queue) = request_device(&adapter).await?;
+        let health = DeviceHealth::new(&device);
+        Ok(Self {
+            instance,
+            device,
+            queue,
+            health,
+        })
+    }
+}
+
+/// Color (plus MSAA and depth) attachments that can be read back into an
+/// image, standing in for a surface texture.
+pub struct OffscreenTarget {
+    pub width: u32,
+    pub height: u32,
+    pub targets: TargetFormats,
+    color_texture: wgpu::Texture,
+    color_view: wgpu::TextureView,
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, targets: TargetFormats) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let attachment = |label, format, sample_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let color_texture = attachment(
+            "Offscreen color texture",
+            targets.color,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let msaa_view = (targets.sample_count > 1).then(|| {
+            attachment(
+                "Offscreen MSAA texture",
+                targets.color,
+                targets.sample_count,
+                wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+            .create_view(&Default::default())
+        });
+        let depth_view = attachment(
+            "Offscreen depth texture",
+            targets.depth,
+            targets.sample_count,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+        .create_view(&Default::default());
+        Self {
+            width,
+            height,
+            targets,
+            color_view: color_texture.create_view(&Default::default()),
+            color_texture,
+            msaa_view,
+            depth_view,
+        }
+    }
+
+    /// The single-sampled color texture, e.g. to tonemap it into another
+    /// target.
+    pub fn color_texture(&self) -> &wgpu::Texture {
+        &self.color_texture
+    }
+
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+
+    /// Starts a pass that clears to `clear` and resolves into the color
+    /// texture.
+    pub fn begin_render_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        clear: wgpu::Color,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Offscreen render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.msaa_view.as_ref().unwrap_or(&self.color_view),
+                resolve_target: self.msaa_view.as_ref().map(|_| &self.color_view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    /// Copies the color texture back to the CPU, blocking until the GPU is
+    /// done. Only 8-bit RGBA and BGRA formats are supported.
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> image::RgbaImage {
+        // Rows in a buffer copy have to be padded to a multiple of 256 bytes.
+        let unpadded_bytes_per_row = 4 * self.width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen readback buffer"),
+            size: (padded_bytes_per_row * self.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.color_texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.color_texture.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+
+        let bgra = matches!(
+            self.targets.color,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        buffer.unmap();
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap()
+    }
+}
+
+/// A 3D scene rendered offscreen frame after frame, tonemapped the same way
+/// as the window.
+pub struct OffscreenScene {
+    pub scene: MeshScene,
+    pub debug_draw: DebugDraw,
+    pub tonemap: Tonemap,
+    /// Events passed to `input`, read by the scene and tonemapping at the
+    /// next `update`.
+    pub input: Input,
+    /// What `update` reads the time from. Starts stopped at zero.
+    pub clock: Box<dyn Clock>,
+    /// The time of the last `update`.
+    time: Duration,
+    /// What the scene was built from, to rebuild it after a device loss.
+    desc: SceneDesc,
+    cache: PipelineCache,
+    target: OffscreenTarget,
+    output: OffscreenTarget,
+    tonemap_input: wgpu::BindGroup,
+}
+
+impl OffscreenScene {
+    pub fn new(headless: &Headless, desc: &SceneDesc, width: u32, height: u32) -> Self {
+        let (targets, output_targets) = Self::targets();
+        let mut cache = PipelineCache::new();
+        let mut tonemap = Tonemap::new(&headless.device, &mut cache, output_targets.color);
+        tonemap.update(&headless.queue);
+        let scene = MeshScene::new(
+            &headless.device,
+            &headless.queue,
+            &mut cache,
+            targets,
+            width,
+            height,
+            desc,
+            ShadowSettings::default(),
+        );
+        let debug_draw =
+            DebugDraw::new(&headless.device, &mut cache, targets, output_targets.color);
+        let target = OffscreenTarget::new(&headless.device, width, height, targets);
+        let output = OffscreenTarget::new(&headless.device, width, height, output_targets);
+        let tonemap_input = tonemap.bind_input(
+            &headless.device,
+            &mut cache,
+            target.color_texture(),
+            target.color_view(),
+        );
+        Self {
+            scene,
+            debug_draw,
+            tonemap,
+            input: Input::new(Bindings::default()),
+            clock: Box::new(PausedClock(Duration::ZERO)),
+            time: Duration::ZERO,
+            desc: desc.clone(),
+            cache,
+            target,
+            output,
+            tonemap_input,
+        }
+    }
+
+    /// The HDR target the scene draws into, and the 8-bit one it's
+    /// tonemapped into.
+    fn targets() -> (TargetFormats, TargetFormats) {
+        let targets = TargetFormats {
+            color: HDR_FORMAT,
+            depth: wgpu::TextureFormat::Depth24Plus,
+            sample_count: 1,
+        };
+        let output_targets = TargetFormats {
+            color: wgpu::TextureFormat::Rgba8UnormSrgb,
+            ..targets
+        };
+        (targets, output_targets)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.output.width, self.output.height)
+    }
+
+    pub fn resize(&mut self, headless: &Headless, width: u32, height: u32) {
+        let (targets, output_targets) = Self::targets();
+        self.target = OffscreenTarget::new(&headless.device, width, height, targets);
+        self.output = OffscreenTarget::new(&headless.device, width, height, output_targets);
+        self.tonemap_input = self.tonemap.bind_input(
+            &headless.device,
+            &mut self.cache,
+            self.target.color_texture(),
+            self.target.color_view(),
+        );
+        self.scene.resize(width, height);
+    }
+
+    /// Rebuilds every GPU resource on `headless`, which replaces a device
+    /// that was lost, carrying over the camera, lights, tonemapping, input
+    /// and time like the window's `State::recover`.
+    pub fn recover(self, headless: &Headless) -> Self {
+        let (width, height) = self.size();
+        let mut desc = self.desc;
+        desc.lights = self.scene.lights.lights.clone();
+        desc.ambient = self.scene.lights.ambient;
+        let mut s = Self::new(headless, &desc, width, height);
+        s.scene.camera = self.scene.camera;
+        s.scene.camera_controller = self.scene.camera_controller;
+        s.tonemap.operator = self.tonemap.operator;
+        s.tonemap.exposure = self.tonemap.exposure;
+        s.input = self.input;
+        s.clock = self.clock;
+        s.time = self.time;
+        s
+    }
+
+    /// Passes `event` on like the window does. Returns true if it was
+    /// consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.input.event(event);
+        self.scene.input(event)
+    }
+
+    /// Ticks the clock and moves the scene on to its time.
+    pub fn update(&mut self, headless: &Headless) {
+        let time = self.clock.tick();
+        self.scene.handle_actions(&self.input);
+        self.tonemap.handle_actions(&self.input);
+        self.scene.update(
+            &headless.device,
+            &headless.queue,
+            &mut self.cache,
+            time.saturating_sub(self.time),
+        );
+        self.tonemap.update(&headless.queue);
+        self.input.end_frame();
+        self.time = time;
+    }
+
+    /// Renders the frame at exactly `time`, stopping the clock there.
+    pub fn render_at(&mut self, headless: &Headless, time: Duration) -> image::RgbaImage {
+        self.clock = Box::new(PausedClock(time));
+        self.update(headless);
+        self.render(headless)
+    }
+
+    /// Renders a frame and reads it back, blocking until the GPU is done.
+    pub fn render(&mut self, headless: &Headless) -> image::RgbaImage {
+        let (width, height) = self.size();
+        self.debug_draw.prepare(
+            &headless.device,
+            &headless.queue,
+            self.scene.view_proj(),
+            width,
+            height,
+        );
+        let mut encoder = headless
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen scene encoder"),
+            });
+        self.scene.render_shadows(&mut encoder);
+        {
+            let mut render_pass = self
+                .target
+                .begin_render_pass(&mut encoder, wgpu::Color::BLACK);
+            self.scene.draw(&mut render_pass);
+            self.debug_draw.draw_world(&mut render_pass);
+        }
+        self.tonemap
+            .render(&mut encoder, &self.tonemap_input, self.output.color_view());
+        self.debug_draw
+            .render_screen(&mut encoder, self.output.color_view());
+        headless.queue.submit(std::iter::once(encoder.finish()));
+        self.output.read(&headless.device, &headless.queue)
+    }
+}
@@ -0,0 +1,495 @@
+use crate::builder::BindGroupBuilder;
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{self, shader_struct, ShaderReflection};
+use cgmath::{InnerSpace, Vector3};
+use std::f32::consts::PI;
+use std::path::Path;
+use std::rc::Rc;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness 0 at the top level up to 1 at the last.
+const PREFILTERED_LEVELS: u32 = 5;
+const PREFILTER_SAMPLES: u32 = 128;
+const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_SAMPLES: u32 = 256;
+const WORKGROUP_SIZE: u32 = 8;
+/// Bytes per packed rgba16float texel in the compute output buffer.
+const TEXEL_BYTES: u32 = 8;
+
+/// Matches `Params` in ibl.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct IblParams {
+    size: u32,
+    row_texels: u32,
+    roughness: f32,
+    sample_count: u32,
+    environment_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+shader_struct!(IblParams = "Params" {
+    size,
+    row_texels,
+    roughness,
+    sample_count,
+    environment_size,
+});
+
+/// Matches `Environment` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    intensity: f32,
+    prefiltered_levels: f32,
+    _pad: [f32; 2],
+}
+
+shader_struct!(EnvironmentUniform = "Environment" { intensity, prefiltered_levels });
+
+/// Reads an equirectangular environment map, normally Radiance `.hdr`.
+pub fn load_equirect(path: &Path) -> Result<image::Rgba32FImage, String> {
+    image::open(path)
+        .map(|img| img.into_rgba32f())
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// An equirectangular sky for scenes without an environment map: a blue
+/// gradient above the horizon, dim ground below, and a bright sun.
+pub fn procedural_sky(width: u32, height: u32) -> image::Rgba32FImage {
+    let sun = Vector3::new(0.4, 0.6, 0.5).normalize();
+    let zenith = Vector3::new(0.15, 0.3, 0.7);
+    let horizon = Vector3::new(0.8, 0.85, 0.9);
+    let ground = Vector3::new(0.2, 0.18, 0.16);
+    image::Rgba32FImage::from_fn(width, height, |x, y| {
+        // Inverse of `sample_environment` in ibl.wgsl.
+        let phi = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+        let theta = (y as f32 + 0.5) / height as f32 * PI;
+        let dir = Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        let mut color = if dir.y > 0.0 {
+            horizon + (zenith - horizon) * dir.y.powf(0.5)
+        } else {
+            horizon + (ground - horizon) * (-dir.y * 8.0).min(1.0)
+        };
+        if dir.dot(sun) > 0.9995 {
+            color += Vector3::new(1.0, 0.95, 0.85) * 200.0;
+        }
+        image::Rgba([color.x, color.y, color.z, 1.0])
+    })
+}
+
+/// Image-based lighting for the mesh shader: diffuse irradiance and
+/// prefiltered specular cubemaps plus the split-sum BRDF lookup table, all
+/// precomputed on the GPU from an equirectangular HDR environment.
+pub struct Environment {
+    pub bind_group_layout: Rc<wgpu::BindGroupLayout>,
+    pub bind_group: wgpu::BindGroup,
+    // Kept alive alongside the bind group that references them.
+    _buffer: wgpu::Buffer,
+    _irradiance: wgpu::Texture,
+    _prefiltered: wgpu::Texture,
+    _brdf_lut: wgpu::Texture,
+}
+
+impl Environment {
+    /// Checks `EnvironmentUniform` against the mesh shader.
+    pub fn check_shader(shader: &ShaderReflection) -> Result<(), String> {
+        shader.check_uniform::<EnvironmentUniform>()
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        equirect: &image::Rgba32FImage,
+        intensity: f32,
+    ) -> Self {
+        let source = upload_equirect(device, queue, equirect);
+        let source_view = source.create_view(&Default::default());
+        let sampler = equirect_sampler(device);
+
+        let cube = |label, size, mip_level_count| {
+            create_cube_texture(device, label, size, mip_level_count)
+        };
+        let irradiance = cube("Irradiance cubemap", IRRADIANCE_SIZE, 1);
+        let prefiltered = cube("Prefiltered cubemap", PREFILTERED_SIZE, PREFILTERED_LEVELS);
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let precompute = Precompute::new(
+            device,
+            cache,
+            &source,
+            &source_view,
+            &sampler,
+            equirect.dimensions(),
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL encoder"),
+        });
+        precompute.run(
+            &mut encoder,
+            "irradiance",
+            &irradiance,
+            0,
+            IRRADIANCE_SIZE,
+            0.0,
+            0,
+        );
+        for level in 0..PREFILTERED_LEVELS {
+            precompute.run(
+                &mut encoder,
+                "prefilter",
+                &prefiltered,
+                level,
+                PREFILTERED_SIZE >> level,
+                level as f32 / (PREFILTERED_LEVELS - 1) as f32,
+                PREFILTER_SAMPLES,
+            );
+        }
+        precompute.run(
+            &mut encoder,
+            "brdf_lut",
+            &brdf_lut,
+            0,
+            BRDF_LUT_SIZE,
+            0.0,
+            BRDF_LUT_SAMPLES,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment buffer"),
+            size: std::mem::size_of::<EnvironmentUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(
+            &buffer,
+            0,
+            bytemuck::bytes_of(&EnvironmentUniform {
+                intensity,
+                prefiltered_levels: PREFILTERED_LEVELS as f32,
+                _pad: [0.0; 2],
+            }),
+        );
+
+        let cube_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let irradiance_view = cube_view(&irradiance);
+        let prefiltered_view = cube_view(&prefiltered);
+        let brdf_lut_view = brdf_lut.create_view(&Default::default());
+        let lookup_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        let cube = wgpu::TextureViewDimension::Cube;
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("Environment")
+            .uniform(fragment, &buffer)
+            .texture_as(fragment, &irradiance, &irradiance_view, cube)
+            .texture_as(fragment, &prefiltered, &prefiltered_view, cube)
+            .texture(fragment, &brdf_lut, &brdf_lut_view)
+            .sampler(
+                fragment,
+                &lookup_sampler,
+                wgpu::SamplerBindingType::Filtering,
+            )
+            .build_cached(device, cache);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            _buffer: buffer,
+            _irradiance: irradiance,
+            _prefiltered: prefiltered,
+            _brdf_lut: brdf_lut,
+        }
+    }
+}
+
+/// Converts an equirectangular image into an Rgba16Float cubemap of `size`
+/// texels per face on the GPU.
+pub fn equirect_to_cube(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    cache: &mut PipelineCache,
+    equirect: &image::Rgba32FImage,
+    size: u32,
+) -> wgpu::Texture {
+    let source = upload_equirect(device, queue, equirect);
+    let source_view = source.create_view(&Default::default());
+    let sampler = equirect_sampler(device);
+    let cube = create_cube_texture(device, "Environment cubemap", size, 1);
+    let precompute = Precompute::new(
+        device,
+        cache,
+        &source,
+        &source_view,
+        &sampler,
+        equirect.dimensions(),
+    );
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Equirect conversion encoder"),
+    });
+    precompute.run(&mut encoder, "equirect_to_cube", &cube, 0, size, 0.0, 0);
+    queue.submit(std::iter::once(encoder.finish()));
+    cube
+}
+
+/// A six-layer Rgba16Float texture that can be viewed as a cube, written by
+/// copies.
+fn create_cube_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+/// Wraps around horizontally, where the equirect's seam is.
+fn equirect_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Environment sampler"),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// Uploads `img` as Rgba16Float with a full mip chain, box filtered on the
+/// CPU, so the convolutions can read pre-blurred levels.
+fn upload_equirect(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    img: &image::Rgba32FImage,
+) -> wgpu::Texture {
+    let (width, height) = img.dimensions();
+    let mip_level_count = width.max(height).ilog2() + 1;
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Environment equirect texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let mut level = img.clone();
+    for mip_level in 0..mip_level_count {
+        let (w, h) = level.dimensions();
+        let texels: Vec<u16> = level
+            .as_raw()
+            .iter()
+            .map(|&c| half::f16::from_f32(c).to_bits())
+            .collect();
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(TEXEL_BYTES * w),
+                rows_per_image: Some(h),
+            },
+            wgpu::Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            },
+        );
+        level = downsample(&level);
+    }
+    texture
+}
+
+/// Halves each dimension (down to 1) by averaging 2x2 blocks.
+fn downsample(img: &image::Rgba32FImage) -> image::Rgba32FImage {
+    let (width, height) = img.dimensions();
+    image::Rgba32FImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let mut sum = [0.0; 4];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let p = img.get_pixel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1));
+            for (s, c) in sum.iter_mut().zip(p.0) {
+                *s += c / 4.0;
+            }
+        }
+        image::Rgba(sum)
+    })
+}
+
+/// The compute pipelines in ibl.wgsl, bound to one source environment.
+struct Precompute<'a> {
+    device: &'a wgpu::Device,
+    shader: Rc<wgpu::ShaderModule>,
+    source: &'a wgpu::Texture,
+    source_view: &'a wgpu::TextureView,
+    sampler: &'a wgpu::Sampler,
+    environment_size: [f32; 2],
+}
+
+impl<'a> Precompute<'a> {
+    fn new(
+        device: &'a wgpu::Device,
+        cache: &mut PipelineCache,
+        source: &'a wgpu::Texture,
+        source_view: &'a wgpu::TextureView,
+        sampler: &'a wgpu::Sampler,
+        (width, height): (u32, u32),
+    ) -> Self {
+        let wgsl = include_str!("ibl.wgsl");
+        reflect::assert_matches("ibl.wgsl", wgsl, |shader| {
+            shader.check_uniform::<IblParams>()
+        });
+        let shader = cache.shader_module(device, "IBL shader", wgsl);
+        Self {
+            device,
+            shader,
+            source,
+            source_view,
+            sampler,
+            environment_size: [width as f32, height as f32],
+        }
+    }
+
+    /// Dispatches `entry_point` over every texel of `mip_level` in `target`
+    /// (all of its layers) and copies the result in.
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        entry_point: &str,
+        target: &wgpu::Texture,
+        mip_level: u32,
+        size: u32,
+        roughness: f32,
+        sample_count: u32,
+    ) {
+        let device = self.device;
+        let layers = target.depth_or_array_layers();
+        // Rows in a buffer copy have to be padded to a multiple of 256 bytes.
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT / TEXEL_BYTES;
+        let row_texels = size.div_ceil(align) * align;
+        let params = IblParams {
+            size,
+            row_texels,
+            roughness,
+            sample_count,
+            environment_size: self.environment_size,
+            _pad: [0.0; 2],
+        };
+        let params_buffer = wgpu::util::DeviceExt::create_buffer_init(
+            device,
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("IBL params buffer"),
+                contents: bytemuck::bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            },
+        );
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("IBL output buffer"),
+            size: (row_texels * size * layers * TEXEL_BYTES) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let compute = wgpu::ShaderStages::COMPUTE;
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("IBL")
+            .uniform(compute, &params_buffer)
+            .texture(compute, self.source, self.source_view)
+            .sampler(compute, self.sampler, wgpu::SamplerBindingType::Filtering)
+            .storage(compute, &output, false)
+            .build(device);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module: &self.shader,
+            entry_point,
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(entry_point),
+            });
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            let groups = size.div_ceil(WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups, groups, layers);
+        }
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &output,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(row_texels * TEXEL_BYTES),
+                    rows_per_image: Some(size),
+                },
+            },
+            wgpu::ImageCopyTexture {
+                texture: target,
+                mip_level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+        );
+    }
+}
@@ -0,0 +1,188 @@
+// Image-based lighting precomputation. Converts an equirectangular HDR
+// environment into a cubemap, convolves it into irradiance and prefiltered
+// specular cubemaps, and integrates the split-sum BRDF lookup table. Texels
+// are written to a buffer as packed rgba16float and copied into the textures
+// afterwards.
+
+const PI: f32 = 3.14159265359;
+const IRRADIANCE_PHI_STEPS: u32 = 64u;
+const IRRADIANCE_THETA_STEPS: u32 = 16u;
+
+struct Params {
+    // Output face size in texels.
+    size: u32,
+    // Output row length in texels, padded for buffer-to-texture copies.
+    row_texels: u32,
+    roughness: f32,
+    sample_count: u32,
+    // Size of the environment's top mip level.
+    environment_size: vec2f,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var environment: texture_2d<f32>;
+@group(0) @binding(2) var environment_sampler: sampler;
+@group(0) @binding(3) var<storage, read_write> output: array<vec2u>;
+
+fn store(id: vec3u, value: vec4f) {
+    let index = (id.z * params.size + id.y) * params.row_texels + id.x;
+    output[index] = vec2u(pack2x16float(value.xy), pack2x16float(value.zw));
+}
+
+// Direction through the center of texel (x, y) on a cube face, using the
+// usual +X, -X, +Y, -Y, +Z, -Z layer order.
+fn cube_direction(id: vec3u) -> vec3f {
+    let uv = (vec2f(id.xy) + 0.5) / f32(params.size) * 2.0 - 1.0;
+    switch id.z {
+        case 0u: { return normalize(vec3f(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3f(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3f(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3f(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3f(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3f(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+fn sample_environment(direction: vec3f, lod: f32) -> vec3f {
+    let uv = vec2f(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    return textureSampleLevel(environment, environment_sampler, uv, lod).rgb;
+}
+
+// Orthonormal basis with `n` as its z axis.
+fn tangent_frame(n: vec3f) -> mat3x3f {
+    let up = select(vec3f(0.0, 1.0, 0.0), vec3f(1.0, 0.0, 0.0), abs(n.y) > 0.999);
+    let tangent = normalize(cross(up, n));
+    return mat3x3f(tangent, cross(n, tangent), n);
+}
+
+fn radical_inverse(bits_in: u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2f {
+    return vec2f(f32(i) / f32(count), radical_inverse(i));
+}
+
+// Half vector around `n` distributed like GGX with alpha `a`.
+fn importance_sample_ggx(xi: vec2f, n: vec3f, a: f32) -> vec3f {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3f(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_frame(n) * h);
+}
+
+fn distribution_ggx(n_dot_h: f32, a: f32) -> f32 {
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_schlick_ggx(n_dot_x: f32, k: f32) -> f32 {
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id: vec3u) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    store(id, vec4f(sample_environment(cube_direction(id), 0.0), 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id: vec3u) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let frame = tangent_frame(cube_direction(id));
+    // The result is very low frequency, so a coarse mip avoids aliasing
+    // between the fixed sample directions.
+    let lod = max(log2(params.environment_size.x / f32(IRRADIANCE_PHI_STEPS)), 0.0);
+    var sum = vec3f(0.0);
+    for (var i = 0u; i < IRRADIANCE_PHI_STEPS; i++) {
+        let phi = (f32(i) + 0.5) / f32(IRRADIANCE_PHI_STEPS) * 2.0 * PI;
+        for (var j = 0u; j < IRRADIANCE_THETA_STEPS; j++) {
+            let theta = (f32(j) + 0.5) / f32(IRRADIANCE_THETA_STEPS) * 0.5 * PI;
+            let local = vec3f(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            sum += sample_environment(frame * local, lod) * cos(theta) * sin(theta);
+        }
+    }
+    let count = f32(IRRADIANCE_PHI_STEPS * IRRADIANCE_THETA_STEPS);
+    store(id, vec4f(PI * sum / count, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id: vec3u) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    // Assumes the view direction is the reflection direction, as usual for
+    // the split-sum approximation.
+    let n = cube_direction(id);
+    let a = params.roughness * params.roughness;
+    let texel_solid_angle = 4.0 * PI / (params.environment_size.x * params.environment_size.y);
+    var sum = vec3f(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, a);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // Filtered importance sampling: read from the mip whose texels
+            // cover about as much solid angle as the sample does.
+            let pdf = distribution_ggx(max(dot(n, h), 0.0), a) / 4.0;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf + 0.0001);
+            let lod = select(
+                max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0),
+                0.0,
+                params.roughness == 0.0,
+            );
+            sum += sample_environment(l, lod) * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    store(id, vec4f(sum / max(weight, 0.0001), 1.0));
+}
+
+// Scale (r) and bias (g) applied to F0 for the split-sum specular term,
+// indexed by n·v along x and roughness along y.
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id: vec3u) {
+    if id.x >= params.size || id.y >= params.size {
+        return;
+    }
+    let n_dot_v = (f32(id.x) + 0.5) / f32(params.size);
+    let roughness = (f32(id.y) + 0.5) / f32(params.size);
+    let a = roughness * roughness;
+    let k = a / 2.0;
+    let n = vec3f(0.0, 0.0, 1.0);
+    let v = vec3f(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, params.sample_count), n, a);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = saturate(l.z);
+        let n_dot_h = saturate(h.z);
+        let v_dot_h = saturate(dot(v, h));
+        if n_dot_l > 0.0 {
+            let g = geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    let count = f32(params.sample_count);
+    store(id, vec4f(scale / count, bias / count, 0.0, 1.0));
+}
@@ -0,0 +1,512 @@
+//! Keyboard, mouse and gamepad state gathered from events, and the named
+//! actions bound to it in `assets/input.toml`. Events are fed in as they
+//! arrive; `update` queries what changed since the last frame and calls
+//! `end_frame` once it's done.
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+pub const DEFAULT_CONFIG: &str = "assets/input.toml";
+
+/// Something the user can ask for, bound to any number of buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    CycleMsaa,
+    ToggleDebugDraw,
+    CyclePresentMode,
+    /// Pretends the device was lost, to exercise recovery.
+    InjectDeviceLoss,
+    /// Stops and restarts animation time.
+    TogglePause,
+    /// Moves animation time on by one frame while it's stopped.
+    StepFrame,
+    /// Runs animation time at a quarter speed, or back at full speed.
+    ToggleSlowMotion,
+    /// Shows and hides the settings panel.
+    ToggleUi,
+    /// Flies the 3D camera while held.
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// Switches the 3D camera between flying and orbiting.
+    ToggleCameraMode,
+    /// Picks the light the other light actions edit.
+    SelectNextLight,
+    ToggleLight,
+    ToggleLightShadow,
+    BrightenLight,
+    DimLight,
+    /// Turns the light's direction, or orbits its position, around +y.
+    RotateLightLeft,
+    RotateLightRight,
+    RaiseAmbient,
+    LowerAmbient,
+    /// Switches the 3D view's tonemapping curve.
+    CycleTonemap,
+    RaiseExposure,
+    LowerExposure,
+    /// Scrolls the tiled view while held.
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    /// Zooms the tiled view around its center while held.
+    ZoomIn,
+    ZoomOut,
+}
+
+impl Action {
+    pub const ALL: [Action; 34] = [
+        Action::Quit,
+        Action::CycleMsaa,
+        Action::ToggleDebugDraw,
+        Action::CyclePresentMode,
+        Action::InjectDeviceLoss,
+        Action::TogglePause,
+        Action::StepFrame,
+        Action::ToggleSlowMotion,
+        Action::ToggleUi,
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::ToggleCameraMode,
+        Action::SelectNextLight,
+        Action::ToggleLight,
+        Action::ToggleLightShadow,
+        Action::BrightenLight,
+        Action::DimLight,
+        Action::RotateLightLeft,
+        Action::RotateLightRight,
+        Action::RaiseAmbient,
+        Action::LowerAmbient,
+        Action::CycleTonemap,
+        Action::RaiseExposure,
+        Action::LowerExposure,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::PanUp,
+        Action::PanDown,
+        Action::ZoomIn,
+        Action::ZoomOut,
+    ];
+
+    /// The name the config file uses.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::CycleMsaa => "cycle_msaa",
+            Action::ToggleDebugDraw => "toggle_debug_draw",
+            Action::CyclePresentMode => "cycle_present_mode",
+            Action::InjectDeviceLoss => "inject_device_loss",
+            Action::TogglePause => "toggle_pause",
+            Action::StepFrame => "step_frame",
+            Action::ToggleSlowMotion => "toggle_slow_motion",
+            Action::ToggleUi => "toggle_ui",
+            Action::MoveForward => "move_forward",
+            Action::MoveBackward => "move_backward",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::ToggleCameraMode => "toggle_camera_mode",
+            Action::SelectNextLight => "select_next_light",
+            Action::ToggleLight => "toggle_light",
+            Action::ToggleLightShadow => "toggle_light_shadow",
+            Action::BrightenLight => "brighten_light",
+            Action::DimLight => "dim_light",
+            Action::RotateLightLeft => "rotate_light_left",
+            Action::RotateLightRight => "rotate_light_right",
+            Action::RaiseAmbient => "raise_ambient",
+            Action::LowerAmbient => "lower_ambient",
+            Action::CycleTonemap => "cycle_tonemap",
+            Action::RaiseExposure => "raise_exposure",
+            Action::LowerExposure => "lower_exposure",
+            Action::PanLeft => "pan_left",
+            Action::PanRight => "pan_right",
+            Action::PanUp => "pan_up",
+            Action::PanDown => "pan_down",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+        }
+    }
+}
+
+/// Buttons on a standard gamepad layout, named after their position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    const ALL: [GamepadButton; 17] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::North,
+        GamepadButton::West,
+        GamepadButton::LeftTrigger,
+        GamepadButton::LeftTrigger2,
+        GamepadButton::RightTrigger,
+        GamepadButton::RightTrigger2,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::Mode,
+        GamepadButton::LeftThumb,
+        GamepadButton::RightThumb,
+        GamepadButton::DPadUp,
+        GamepadButton::DPadDown,
+        GamepadButton::DPadLeft,
+        GamepadButton::DPadRight,
+    ];
+}
+
+/// Analog gamepad inputs, from -1 to 1 with +y up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+}
+
+/// A physical input that can be pressed and released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    /// The button on whichever gamepad pressed it.
+    Gamepad(GamepadButton),
+}
+
+impl Button {
+    /// Parses a binding from the config file, see `assets/input.toml`.
+    fn parse(name: &str) -> Result<Button, String> {
+        if let Some(button) = name.strip_prefix("Mouse:") {
+            return match button {
+                "Left" => Ok(Button::Mouse(MouseButton::Left)),
+                "Right" => Ok(Button::Mouse(MouseButton::Right)),
+                "Middle" => Ok(Button::Mouse(MouseButton::Middle)),
+                _ => button
+                    .parse()
+                    .map(|n| Button::Mouse(MouseButton::Other(n)))
+                    .map_err(|_| format!("unknown mouse button {name:?}")),
+            };
+        }
+        if let Some(button) = name.strip_prefix("Pad:") {
+            return GamepadButton::ALL
+                .into_iter()
+                .find(|b| format!("{b:?}") == button)
+                .map(Button::Gamepad)
+                .ok_or_else(|| format!("unknown gamepad button {name:?}"));
+        }
+        // winit only names its key codes through serde.
+        let deserializer = serde::de::value::StrDeserializer::<serde::de::value::Error>::new(name);
+        VirtualKeyCode::deserialize(deserializer)
+            .map(Button::Key)
+            .map_err(|_| format!("unknown key {name:?}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Config {
+    actions: HashMap<String, Vec<String>>,
+}
+
+/// The buttons bound to each action.
+#[derive(Debug, Clone)]
+pub struct Bindings(HashMap<Action, Vec<Button>>);
+
+impl Bindings {
+    /// Reads bindings from a TOML file like `assets/input.toml`, on top of
+    /// the ones built in from it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut bindings = Self::default();
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        bindings
+            .apply(&source)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(bindings)
+    }
+
+    /// `load`, falling back to the built-in bindings with a warning.
+    pub fn load_or_default(path: &Path) -> Self {
+        Self::load(path).unwrap_or_else(|e| {
+            log::warn!("Using the default input bindings: {e}");
+            Self::default()
+        })
+    }
+
+    /// Rebinds every action listed in `source`. Unknown actions and buttons
+    /// are errors, so typos don't silently leave an action unbound.
+    fn apply(&mut self, source: &str) -> Result<(), String> {
+        let config: Config = toml::from_str(source).map_err(|e| e.to_string())?;
+        for (name, buttons) in config.actions {
+            let action = Action::ALL
+                .into_iter()
+                .find(|a| a.name() == name)
+                .ok_or_else(|| format!("unknown action {name:?}"))?;
+            let buttons = buttons
+                .iter()
+                .map(|b| Button::parse(b))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{name}: {e}"))?;
+            self.0.insert(action, buttons);
+        }
+        Ok(())
+    }
+
+    pub fn buttons(&self, action: Action) -> &[Button] {
+        self.0.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self(HashMap::new());
+        bindings
+            .apply(include_str!("../assets/input.toml"))
+            .expect("built-in input bindings are valid");
+        bindings
+    }
+}
+
+/// What the user is doing this frame.
+pub struct Input {
+    pub bindings: Bindings,
+    held: HashSet<Button>,
+    /// Went down since the last `end_frame`.
+    pressed: HashSet<Button>,
+    axes: HashMap<GamepadAxis, f32>,
+    /// Gamepads are shared by every window, only the focused one listens.
+    focused: bool,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+}
+
+impl Input {
+    /// Keyboard and mouse only, as fed to `event`.
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            axes: HashMap::new(),
+            focused: true,
+            #[cfg(feature = "gamepad")]
+            gilrs: None,
+        }
+    }
+
+    /// Also reads gamepads in `poll_gamepads`, with the `gamepad` feature.
+    pub fn with_gamepads(self) -> Self {
+        Self {
+            #[cfg(feature = "gamepad")]
+            gilrs: gilrs::Gilrs::new()
+                .map_err(|e| log::warn!("No gamepad input: {e}"))
+                .ok(),
+            ..self
+        }
+    }
+
+    /// Records `event`. Doesn't consume anything, every event can be passed
+    /// on as well.
+    pub fn event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.button(Button::Key(*key), *state),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.button(Button::Mouse(*button), *state)
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                // Releases won't arrive while another window has focus.
+                if !focused {
+                    self.held.clear();
+                    self.axes.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn button(&mut self, button: Button, state: ElementState) {
+        match state {
+            // Key repeats arrive as more presses.
+            ElementState::Pressed => {
+                if self.held.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&button);
+            }
+        }
+    }
+
+    /// Reads the gamepad events that arrived since the last call. Does
+    /// nothing without the `gamepad` feature.
+    pub fn poll_gamepads(&mut self) {
+        #[cfg(feature = "gamepad")]
+        while let Some(event) = self.gilrs.as_mut().and_then(gilrs::Gilrs::next_event) {
+            if !self.focused {
+                continue;
+            }
+            match event.event {
+                gilrs::EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = gamepad_button(button) {
+                        self.button(Button::Gamepad(button), ElementState::Pressed);
+                    }
+                }
+                gilrs::EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = gamepad_button(button) {
+                        self.button(Button::Gamepad(button), ElementState::Released);
+                    }
+                }
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    let axis = match axis {
+                        gilrs::Axis::LeftStickX => GamepadAxis::LeftStickX,
+                        gilrs::Axis::LeftStickY => GamepadAxis::LeftStickY,
+                        gilrs::Axis::RightStickX => GamepadAxis::RightStickX,
+                        gilrs::Axis::RightStickY => GamepadAxis::RightStickY,
+                        _ => continue,
+                    };
+                    self.axes.insert(axis, value);
+                }
+                gilrs::EventType::Connected => log::info!("Gamepad {} connected", event.id),
+                gilrs::EventType::Disconnected => {
+                    log::info!("Gamepad {} disconnected", event.id)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Forgets what was pressed. Call at the end of every `update`.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+    }
+
+    /// Whether any button bound to `action` went down this frame.
+    pub fn pressed(&self, action: Action) -> bool {
+        self.any(action, |b| self.pressed.contains(b))
+    }
+
+    /// Whether any button bound to `action` is down.
+    pub fn held(&self, action: Action) -> bool {
+        self.any(action, |b| self.held.contains(b))
+    }
+
+    fn any(&self, action: Action, test: impl Fn(&Button) -> bool) -> bool {
+        self.bindings.buttons(action).iter().any(test)
+    }
+
+    /// Zero without a gamepad.
+    pub fn axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn gamepad_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button as B;
+    Some(match button {
+        B::South => GamepadButton::South,
+        B::East => GamepadButton::East,
+        B::North => GamepadButton::North,
+        B::West => GamepadButton::West,
+        B::LeftTrigger => GamepadButton::LeftTrigger,
+        B::LeftTrigger2 => GamepadButton::LeftTrigger2,
+        B::RightTrigger => GamepadButton::RightTrigger,
+        B::RightTrigger2 => GamepadButton::RightTrigger2,
+        B::Select => GamepadButton::Select,
+        B::Start => GamepadButton::Start,
+        B::Mode => GamepadButton::Mode,
+        B::LeftThumb => GamepadButton::LeftThumb,
+        B::RightThumb => GamepadButton::RightThumb,
+        B::DPadUp => GamepadButton::DPadUp,
+        B::DPadDown => GamepadButton::DPadDown,
+        B::DPadLeft => GamepadButton::DPadLeft,
+        B::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::RecordedEvent;
+
+    #[test]
+    fn every_action_has_a_default_binding() {
+        let bindings = Bindings::default();
+        for action in Action::ALL {
+            assert!(!bindings.buttons(action).is_empty(), "{action:?}");
+        }
+    }
+
+    #[test]
+    fn rebinding_replaces_the_default() {
+        let mut bindings = Bindings::default();
+        bindings.apply("[actions]\ntoggle_ui = [\"F2\"]").unwrap();
+        let mut input = Input::new(bindings);
+        let key = |key, state| {
+            RecordedEvent::Key {
+                scancode: 0,
+                key: Some(key),
+                state,
+            }
+            .to_event()
+        };
+        input.event(&key(VirtualKeyCode::F1, ElementState::Pressed));
+        assert!(!input.pressed(Action::ToggleUi));
+        input.event(&key(VirtualKeyCode::F2, ElementState::Pressed));
+        assert!(input.pressed(Action::ToggleUi) && input.held(Action::ToggleUi));
+        input.end_frame();
+        assert!(!input.pressed(Action::ToggleUi) && input.held(Action::ToggleUi));
+        input.event(&key(VirtualKeyCode::F2, ElementState::Released));
+        assert!(!input.held(Action::ToggleUi));
+    }
+
+    #[test]
+    fn unknown_actions_and_keys_are_errors() {
+        let mut bindings = Bindings::default();
+        let error = bindings.apply("[actions]\nfly = [\"W\"]").unwrap_err();
+        assert_eq!(error, "unknown action \"fly\"");
+        let error = bindings
+            .apply("[actions]\nzoom_in = [\"Zoom\"]")
+            .unwrap_err();
+        assert_eq!(error, "zoom_in: unknown key \"Zoom\"");
+    }
+}
@@ -0,0 +1,380 @@
+use crate::builder::BindGroupBuilder;
+use crate::input::{Action, Input};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{shader_struct, ShaderReflection};
+use crate::shadow::{self, ShadowMaps};
+use cgmath::{Deg, InnerSpace, Point3, Quaternion, Rotation, Rotation3, Vector3};
+use std::rc::Rc;
+
+/// The light storage buffer is allocated once with room for this many lights.
+pub const MAX_LIGHTS: usize = 64;
+
+/// How much the ambient actions change each channel of the ambient term by.
+const AMBIENT_STEP: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    Directional = 0,
+    Point = 1,
+    Spot = 2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored for directional lights.
+    pub position: Point3<f32>,
+    /// The direction light travels in. Ignored for point lights.
+    pub direction: Vector3<f32>,
+    /// Linear RGB.
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which point and spot lights fade out completely.
+    pub range: f32,
+    /// Spot cone: full intensity inside `inner_angle`, none outside `outer_angle`.
+    pub inner_angle: Deg<f32>,
+    pub outer_angle: Deg<f32>,
+    pub enabled: bool,
+    /// Point lights never cast shadows.
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            position: Point3::new(0.0, 0.0, 0.0),
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: Deg(0.0),
+            outer_angle: Deg(0.0),
+            enabled: true,
+            cast_shadows: true,
+        }
+    }
+
+    pub fn point(position: Point3<f32>, color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: Vector3::unit_y(),
+            color,
+            intensity,
+            range,
+            inner_angle: Deg(0.0),
+            outer_angle: Deg(0.0),
+            enabled: true,
+            cast_shadows: false,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range,
+            inner_angle,
+            outer_angle,
+            enabled: true,
+            cast_shadows: true,
+        }
+    }
+
+    fn to_raw(self, shadow_layer: Option<u32>) -> LightRaw {
+        LightRaw {
+            position: self.position.into(),
+            kind: self.kind as u32,
+            direction: self.direction.into(),
+            range: self.range,
+            color: self.color,
+            intensity: if self.enabled { self.intensity } else { 0.0 },
+            inner_cos: cgmath::Angle::cos(self.inner_angle),
+            outer_cos: cgmath::Angle::cos(self.outer_angle),
+            shadow_layer: shadow_layer.map_or(-1, |layer| layer as i32),
+            _pad: 0,
+        }
+    }
+}
+
+/// Matches `Light` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    inner_cos: f32,
+    outer_cos: f32,
+    /// First layer in the shadow map array, or -1 for no shadow.
+    shadow_layer: i32,
+    _pad: u32,
+}
+
+shader_struct!(LightRaw = "Light" {
+    position,
+    kind,
+    direction,
+    range,
+    color,
+    intensity,
+    inner_cos,
+    outer_cos,
+    shadow_layer,
+});
+
+/// Matches `LightHeader` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightHeader {
+    ambient: [f32; 3],
+    count: u32,
+}
+
+shader_struct!(LightHeader = "LightHeader" { ambient, count });
+
+/// Every light in the scene plus the ambient term, mirrored into a uniform
+/// header and a storage buffer of `LightRaw`. The bind group also carries the
+/// shadow maps so the shader sees lights and their shadows together.
+pub struct Lights {
+    pub lights: Vec<Light>,
+    pub ambient: [f32; 3],
+    /// The light that runtime edits apply to.
+    pub selected: usize,
+    /// First shadow map layer of each light, from `shadow::assign_layers`.
+    pub shadow_layers: Vec<Option<u32>>,
+    shadow_cascades: u32,
+    header_buffer: wgpu::Buffer,
+    storage_buffer: wgpu::Buffer,
+    pub bind_group_layout: Rc<wgpu::BindGroupLayout>,
+    pub bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl Lights {
+    /// Checks `LightRaw` and `LightHeader` against the mesh shader.
+    pub fn check_shader(shader: &ShaderReflection) -> Result<(), String> {
+        shader.check_struct::<LightRaw>()?;
+        shader.check_uniform::<LightHeader>()
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        lights: Vec<Light>,
+        ambient: [f32; 3],
+        shadows: &ShadowMaps,
+    ) -> Self {
+        let header_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light header buffer"),
+            size: std::mem::size_of::<LightHeader>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light storage buffer"),
+            size: (std::mem::size_of::<LightRaw>() * MAX_LIGHTS) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (bind_group_layout, bind_group) =
+            Self::create_bind_group(device, cache, &header_buffer, &storage_buffer, shadows);
+        if lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "Only the first {MAX_LIGHTS} of {} lights are used",
+                lights.len()
+            );
+        }
+        Self {
+            lights,
+            ambient,
+            selected: 0,
+            shadow_layers: vec![],
+            shadow_cascades: shadows.settings.cascades,
+            header_buffer,
+            storage_buffer,
+            bind_group_layout,
+            bind_group,
+            dirty: true,
+        }
+    }
+
+    /// A directional key light plus a point and a spot light placed around a
+    /// bounding sphere.
+    pub fn default_rig(center: Point3<f32>, radius: f32) -> Vec<Light> {
+        let radius = radius.max(0.1);
+        vec![
+            Light::directional(Vector3::new(-0.4, -1.0, -0.6), [1.0, 0.96, 0.9], 3.0),
+            Light::point(
+                center + Vector3::new(radius * 1.5, radius, radius * 1.5),
+                [1.0, 0.5, 0.3],
+                12.0,
+                radius * 6.0,
+            ),
+            Light::spot(
+                center + Vector3::new(-radius * 2.0, radius * 2.0, radius),
+                Vector3::new(2.0, -2.0, -1.0),
+                [0.4, 0.6, 1.0],
+                18.0,
+                radius * 8.0,
+                Deg(15.0),
+                Deg(25.0),
+            ),
+        ]
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        header: &wgpu::Buffer,
+        storage: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+    ) -> (Rc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        BindGroupBuilder::new("Light")
+            .uniform(fragment, header)
+            .storage(fragment, storage, true)
+            .uniform(fragment, &shadows.header_buffer)
+            .storage(fragment, &shadows.views_buffer, true)
+            .texture_as(
+                fragment,
+                &shadows.texture,
+                &shadows.array_view,
+                wgpu::TextureViewDimension::D2Array,
+            )
+            .sampler(
+                fragment,
+                &shadows.sampler,
+                wgpu::SamplerBindingType::Comparison,
+            )
+            .build_cached(device, cache)
+    }
+
+    /// Rebinds the shadow maps after `ShadowMaps::update` grew them.
+    pub fn rebind_shadows(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        shadows: &ShadowMaps,
+    ) {
+        (_, self.bind_group) = Self::create_bind_group(
+            device,
+            cache,
+            &self.header_buffer,
+            &self.storage_buffer,
+            shadows,
+        );
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        let count = self.lights.len().min(MAX_LIGHTS);
+        let lights = &self.lights[..count];
+        self.shadow_layers = shadow::assign_layers(lights, self.shadow_cascades);
+        for (i, (light, layer)) in lights.iter().zip(&self.shadow_layers).enumerate() {
+            let casts = light.enabled && light.cast_shadows && light.kind != LightKind::Point;
+            if casts && layer.is_none() {
+                log::warn!("Out of shadow map layers, light {i} casts no shadow");
+            }
+        }
+        let raw: Vec<LightRaw> = lights
+            .iter()
+            .zip(&self.shadow_layers)
+            .map(|(l, &layer)| l.to_raw(layer))
+            .collect();
+        queue.write_buffer(
+            &self.header_buffer,
+            0,
+            bytemuck::bytes_of(&LightHeader {
+                ambient: self.ambient,
+                count: count as u32,
+            }),
+        );
+        if !raw.is_empty() {
+            queue.write_buffer(&self.storage_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+        self.dirty = false;
+    }
+
+    /// Runtime light editing, from the light and ambient actions. The
+    /// light ones apply to `selected`.
+    pub fn handle_actions(&mut self, input: &Input) {
+        let ambient_step = match (
+            input.pressed(Action::RaiseAmbient),
+            input.pressed(Action::LowerAmbient),
+        ) {
+            (true, false) => AMBIENT_STEP,
+            (false, true) => -AMBIENT_STEP,
+            _ => 0.0,
+        };
+        if ambient_step != 0.0 {
+            self.ambient = self.ambient.map(|c| (c + ambient_step).max(0.0));
+            log::info!("Ambient: {:?}", self.ambient);
+            self.dirty = true;
+        }
+        if self.lights.is_empty() {
+            return;
+        }
+        if input.pressed(Action::SelectNextLight) {
+            self.selected = (self.selected + 1) % self.lights.len();
+            let light = &self.lights[self.selected];
+            log::info!("Selected light {}: {:?}", self.selected, light.kind);
+        }
+        let Some(light) = self.lights.get_mut(self.selected) else {
+            return;
+        };
+        let edits = [
+            Action::ToggleLight,
+            Action::ToggleLightShadow,
+            Action::BrightenLight,
+            Action::DimLight,
+            Action::RotateLightLeft,
+            Action::RotateLightRight,
+        ];
+        let edited = edits.into_iter().any(|action| input.pressed(action));
+        if input.pressed(Action::ToggleLight) {
+            light.enabled = !light.enabled;
+        }
+        if input.pressed(Action::ToggleLightShadow) {
+            light.cast_shadows = !light.cast_shadows;
+        }
+        if input.pressed(Action::BrightenLight) {
+            light.intensity *= 1.25;
+        }
+        if input.pressed(Action::DimLight) {
+            light.intensity *= 0.8;
+        }
+        for (action, angle) in [
+            (Action::RotateLightLeft, Deg(-15.0)),
+            (Action::RotateLightRight, Deg(15.0)),
+        ] {
+            if input.pressed(action) {
+                let rotation = Quaternion::from_angle_y(angle);
+                light.direction = rotation.rotate_vector(light.direction);
+                light.position = rotation.rotate_point(light.position);
+            }
+        }
+        if edited {
+            log::info!("Light {}: {:?}", self.selected, light);
+            self.dirty = true;
+        }
+    }
+}
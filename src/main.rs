@@ -1,14 +1,89 @@
+mod astc;
+mod blend;
+mod block_decode;
+mod builder;
+mod camera;
+mod clock;
+mod compressed;
+mod debug_draw;
+mod display_image;
+mod export;
+mod golden;
+mod headless;
+mod ibl;
+mod input;
+mod light;
+mod material;
+mod model;
+mod options;
+mod pipeline_cache;
+mod preprocessor;
+mod reflect;
+mod replay;
+mod resilience;
+mod scene;
+mod shadow;
+mod skybox;
+mod streaming;
+mod text;
+mod texture;
+mod tiled;
+mod tonemap;
+mod ui;
+
 fn main() {
-    pollster::block_on(run());
+    env_logger::init();
+    let options = Options::from_args();
+    if options.golden {
+        let passed = golden::run(options.bless);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if options.export.path.is_some() {
+        if let Err(e) = export::run(&options) {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let (Some(replay), Some(out)) = (&options.replay, &options.replay_out) {
+        if let Err(e) = replay_offscreen(&options, replay, out) {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(e) = pollster::block_on(run(options)) {
+        log::error!("{e}");
+        std::process::exit(1);
+    }
 }
 
-use image::EncodableLayout;
-use std::{default, iter, mem::size_of};
-use wgpu::{
-    util::DeviceExt, BindGroupDescriptor, BindGroupLayout, BindGroupLayoutDescriptor,
-    BufferBindingType, Limits, RenderPassDepthStencilAttachment, ShaderStages, TextureUsages,
-    TextureViewDescriptor,
+use blend::BlendMode;
+use builder::{BindGroupBuilder, PipelineBuilder};
+use clock::{Clock, ScaledClock};
+use debug_draw::DebugDraw;
+use display_image::DisplayImage;
+use headless::{Headless, OffscreenScene};
+use input::{Action, Bindings, Button, GamepadAxis, Input};
+use model::ModelData;
+use options::Options;
+use pipeline_cache::PipelineCache;
+use reflect::{shader_struct, ShaderReflection};
+use replay::{Recorder, Recording};
+use resilience::{DeviceHealth, Fault};
+use scene::{MeshScene, SceneDesc};
+use std::{
+    collections::{HashMap, VecDeque},
+    iter,
+    path::Path,
+    rc::Rc,
 };
+use streaming::{Rect, StreamingTexture};
+use text::{Rasterization, TextRenderer, TextStyle};
+use tiled::{TilePyramid, TiledView};
+use tonemap::{Operator, Tonemap, HDR_FORMAT};
+use ui::Ui;
+use wgpu::{util::DeviceExt, Limits, ShaderStages, TextureUsages};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
@@ -20,6 +95,8 @@ use winit::{
 struct Vertex {
     position: [f32; 3],
     color: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
 }
 
 impl Vertex {
@@ -38,6 +115,16 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
@@ -47,18 +134,26 @@ const VERTICES: &[Vertex] = &[
     Vertex {
         position: [-1.0, -1.0, 0.0],
         color: [1.0, 1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        uv: [0.0, 1.0],
     },
     Vertex {
         position: [-1.0, 1.0, 0.0],
         color: [1.0, 1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        uv: [0.0, 0.0],
     },
     Vertex {
         position: [1.0, 1.0, 0.0],
         color: [1.0, 1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        uv: [1.0, 0.0],
     },
     Vertex {
         position: [1.0, -1.0, 0.0],
         color: [1.0, 1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        uv: [1.0, 1.0],
     },
 ];
 #[rustfmt::skip]
@@ -70,106 +165,549 @@ const INDICES: &[u16] = &[
 #[repr(C, align(16))]
 #[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
 struct UniformExample {
+    /// Tint applied to the texture.
     color: [f32; 4],
     time: f32,
-    _pad: [f32; 3],
+    /// Screen pixels per texel.
+    tile_scale: f32,
+    /// Shifts the tiling, in fractions of the texture size.
+    tile_offset: [f32; 2],
+    /// HDR images are multiplied by this before tonemapping.
+    exposure: f32,
+    /// A `tonemap::Operator`.
+    curve: u32,
+    /// Set for grayscale images, which only have a red channel.
+    gray: u32,
+    /// Set for HDR images, which are tonemapped instead of gamma corrected.
+    hdr: u32,
 }
 
-#[repr(C, align(16))]
-#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
-struct TiledTexture {
-    size: [u32; 2],
-    _pad: [u32; 2],
+impl UniformExample {
+    fn new(image: &DisplayImage) -> Self {
+        Self {
+            color: [1.0; 4],
+            tile_scale: 1.0,
+            exposure: 1.0,
+            curve: Operator::Aces as u32,
+            gray: image.gray as u32,
+            hdr: image.hdr as u32,
+            ..Default::default()
+        }
+    }
 }
+
+shader_struct!(UniformExample = "ExampleUniform" {
+    color,
+    time,
+    tile_scale,
+    tile_offset,
+    exposure,
+    curve,
+    gray,
+    hdr,
+});
+
 //const UNIFORM: &[UniformExample] = &[UniformExample { utime: 0.0 }];
 
+/// Preprocessor flags `shader.wgsl` is compiled with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ShaderFlags {
+    gamma_correct: bool,
+    tiling_mirror: bool,
+    /// Output color multiplied by alpha, for `BlendMode::Premultiplied`.
+    premultiplied_alpha: bool,
+}
+
+impl Default for ShaderFlags {
+    fn default() -> Self {
+        Self {
+            gamma_correct: true,
+            tiling_mirror: false,
+            premultiplied_alpha: false,
+        }
+    }
+}
+
+impl ShaderFlags {
+    fn defines(&self) -> Vec<&'static str> {
+        let mut defines = vec![];
+        if self.gamma_correct {
+            defines.push("GAMMA_CORRECT");
+        }
+        if self.tiling_mirror {
+            defines.push("TILING_MIRROR");
+        }
+        if self.premultiplied_alpha {
+            defines.push("PREMULTIPLIED_ALPHA");
+        }
+        defines
+    }
+}
+
+/// Preprocesses shader.wgsl with `flags` and compiles it, after checking
+/// `UniformExample` and `layout_entries`, which are written by hand, against
+/// it.
+fn load_shader(
+    device: &wgpu::Device,
+    cache: &mut PipelineCache,
+    flags: ShaderFlags,
+    layout_entries: &[wgpu::BindGroupLayoutEntry],
+) -> Result<Rc<wgpu::ShaderModule>, String> {
+    let shader = preprocessor::preprocess("shader.wgsl", &flags.defines())?;
+    let reflection = ShaderReflection::new(&shader.name, shader.parse()?)?;
+    reflection
+        .check_struct::<UniformExample>()
+        .and_then(|()| reflection.check_bind_group_layout(0, layout_entries))
+        .map_err(|e| format!("shader.wgsl doesn't match the Rust side: {e}"))?;
+    Ok(cache.shader_module(device, "Shader", &shader.source))
+}
+
+/// Sample counts we're willing to run MSAA at, in the order the toggle key
+/// cycles through them.
+const MSAA_SAMPLE_COUNTS: &[u32] = &[1, 2, 4, 8];
+
 struct State {
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    // Shared with every other window.
+    adapter: Rc<wgpu::Adapter>,
+    device: Rc<wgpu::Device>,
+    queue: Rc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
+    shader: Rc<wgpu::ShaderModule>,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: Rc<wgpu::RenderPipeline>,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
     depth_texture: Option<wgpu::Texture>,
     depth_texture_view: Option<wgpu::TextureView>,
+    // Multisampled color target, resolved into the swapchain view. `None`
+    // when running with a sample count of 1.
+    msaa_texture: Option<wgpu::Texture>,
+    msaa_texture_view: Option<wgpu::TextureView>,
+    sample_count: u32,
+    // Format the scene is drawn in: the surface format for the 2D view,
+    // `HDR_FORMAT` for the 3D view.
+    color_format: wgpu::TextureFormat,
+    // Maps the HDR target into the surface. Only used by the 3D view.
+    tonemap: Option<Tonemap>,
+    hdr_texture_view: Option<wgpu::TextureView>,
+    hdr_bind_group: Option<wgpu::BindGroup>,
     display_texture: wgpu::Texture,
-    display_texture_view: wgpu::TextureView,
+    // `display_texture`'s contents, streamed to it as they change.
+    display: StreamingTexture,
+    // The image `display` started out as.
+    display_source: DisplayImage,
+    // Slides a procedurally generated band down the 2D view's texture, to
+    // exercise streaming. The band's last position is put back next frame.
+    animate_display: bool,
+    display_band: Option<Rect>,
     texture_depth_format: wgpu::TextureFormat,
-    timestamp: std::time::Instant,
+    // Animation time. Runs over a real or fixed-step clock, and is stopped,
+    // stepped and slowed down with the `toggle_pause`, `step_frame` and
+    // `toggle_slow_motion` actions.
+    clock: ScaledClock,
+    time_paused: bool,
+    slow_motion: bool,
+    // How far `step_frame` moves the time on.
+    frame_step: std::time::Duration,
     num_indices: u32,
     window: Window,
+    // Created with `Options::alpha_mode` set, so the 2D view clears to
+    // transparent and shows the desktop wherever it's blended.
+    transparent_window: bool,
     count: usize,
+    // The 3D view, present when models were passed on the command line.
+    mesh_scene: Option<MeshScene>,
+    // Takes over the 2D view for images too large for `display_texture`,
+    // or with `--tiled`.
+    tiled_view: Option<TiledView>,
+    // The animation time of the last `update`. Comes from the recording
+    // instead of the clock while replaying.
+    last_update: std::time::Duration,
+    // When the last `update` ran in real time, for the frame rate.
+    last_frame: std::time::Instant,
+    // Frame time overlay in the top right corner. `None` if the font couldn't
+    // be loaded.
+    text: Option<TextRenderer>,
+    // Exponential moving average of the time between updates, in seconds.
+    frame_time: f32,
+    // How many frames the CPU may queue up before `render` waits for the
+    // GPU. wgpu 0.16 has no swapchain setting for it.
+    frame_latency: u32,
+    in_flight: VecDeque<wgpu::SubmissionIndex>,
+    // Toggled with the `toggle_debug_draw` action (G). Shows the 3D view's bounds, lights and axes.
+    debug_draw: DebugDraw,
+    // Settings panel, toggled with `Action::ToggleUi`. `None` if the font couldn't be loaded.
+    ui: Option<Ui>,
+    // Uploaded every frame, with `time` filled in by `render`.
+    uniforms: UniformExample,
+    polygon_mode: wgpu::PolygonMode,
+    // Lets MSAA and polygon mode switches reuse pipelines built before.
+    pipeline_cache: PipelineCache,
+    shader_flags: ShaderFlags,
+    // What the GPU state was created from, kept to recreate it after the
+    // device is lost.
+    options: Options,
+    scene_desc: Option<SceneDesc>,
+    health: Rc<DeviceHealth>,
+    // Device recoveries since startup, and since the last frame that
+    // rendered.
+    recoveries: u32,
+    consecutive_recoveries: u32,
+    // Rendering is paused while either is set.
+    minimized: bool,
+    occluded: bool,
+    // How the 2D view's quad is blended over the clear color.
+    blend_mode: BlendMode,
+    // Buttons held and pressed this frame, and the actions bound to them.
+    input: Input,
+    // Set by the `quit` action, `run` closes the window after `update`.
+    close_requested: bool,
+    recorder: Option<Recorder>,
+    // Frames left to play back, see `Options::replay`.
+    replay: Option<std::vec::IntoIter<replay::Frame>>,
+    // What `shader` is checked against whenever it's recompiled.
+    shader_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
 }
 
-fn gen_texture_data2(width: usize, height: usize) -> Vec<[u8; 4]> {
-    let mut res_arr = vec![];
-    for i in 0..width {
-        for j in 0..height {
-            res_arr.push([0 as u8, 0 as u8, 0, 255]);
-        }
-    }
-    let res = std::fs::read("assets/sshot.png").unwrap();
-    let res = image::load_from_memory_with_format(res.as_slice(), image::ImageFormat::Png);
-    let res = res.unwrap().into_rgba8();
-    assert!(res.width() < width as u32);
-    assert!(res.height() < height as u32);
-    let res_bytes = res.as_bytes();
-    for i in 0..res.width() {
-        for j in 0..res.height() {
-            res_arr[(i + j * width as u32) as usize][0] = res_bytes[(i + j * res.width()) as usize];
-            res_arr[(i + j * width as u32) as usize][1] =
-                res_bytes[(i + j * res.width()) as usize + 1];
-            res_arr[(i + j * width as u32) as usize][2] =
-                res_bytes[(i + j * res.width()) as usize + 2];
-            res_arr[(i + j * width as u32) as usize][3] =
-                res_bytes[(i + j * res.width()) as usize + 3];
-        }
-    }
-    res_arr
+/// The attachment formats every pipeline drawing into the main pass has to
+/// agree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TargetFormats {
+    color: wgpu::TextureFormat,
+    depth: wgpu::TextureFormat,
+    sample_count: u32,
 }
-fn gen_texture_data(width: usize, height: usize) -> (Vec<u8>, usize, usize) {
-    let res = std::fs::read("assets/sshot.png").unwrap();
-    let res = image::load_from_memory_with_format(res.as_slice(), image::ImageFormat::Png);
-    let res = res.unwrap().into_rgba8();
-    (
-        res.iter().copied().collect(),
-        res.width() as usize,
-        res.height() as usize,
-    )
+
+/// The device and queue shared by every window.
+struct Gpu {
+    instance: Rc<wgpu::Instance>,
+    adapter: Rc<wgpu::Adapter>,
+    device: Rc<wgpu::Device>,
+    queue: Rc<wgpu::Queue>,
+    health: Rc<DeviceHealth>,
 }
 
-impl State {
-    async fn new(window: Window) -> Self {
-        let size = window.inner_size();
+impl Gpu {
+    async fn new() -> Result<Self, String> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             ..Default::default()
         });
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        Self::from_instance(Rc::new(instance)).await
+    }
+
+    /// A new adapter and device from the same instance, to replace a lost
+    /// device. Some backends can't have two instances alive at once.
+    async fn replace(&self) -> Result<Self, String> {
+        Self::from_instance(self.instance.clone()).await
+    }
+
+    async fn from_instance(instance: Rc<wgpu::Instance>) -> Result<Self, String> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 ..Default::default()
             })
             .await
-            .unwrap();
+            .ok_or("No suitable adapter")?;
+        let (device, queue) = request_device(&adapter).await?;
+        let health = DeviceHealth::new(&device);
+        Ok(Self {
+            instance,
+            adapter: Rc::new(adapter),
+            device: Rc::new(device),
+            queue: Rc::new(queue),
+            health: Rc::new(health),
+        })
+    }
+}
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_LINE,
-                    limits: Limits {
-                        //max_bind_groups: 1,
-                        ..Default::default()
-                    },
+/// Requests a device with the features every part of the app relies on.
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), String> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                // Without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES only 1x and 4x
+                // MSAA are usable, whatever the adapter reports. Everything here
+                // is optional, so only ask for what the adapter has.
+                features: adapter.features()
+                    & (wgpu::Features::POLYGON_MODE_LINE
+                        | wgpu::Features::POLYGON_MODE_POINT
+                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | wgpu::Features::TEXTURE_FORMAT_16BIT_NORM
+                        | wgpu::Features::TEXTURE_COMPRESSION_BC
+                        | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                        | wgpu::Features::TEXTURE_COMPRESSION_ASTC),
+                limits: Limits {
+                    //max_bind_groups: 1,
                     ..Default::default()
                 },
-                /*trace_path=*/ None,
-            )
-            .await
-            .unwrap();
+                ..Default::default()
+            },
+            /*trace_path=*/ None,
+        )
+        .await
+        .map_err(|e| format!("Couldn't create a device: {e}"))
+}
+
+/// Returns the sample counts in `MSAA_SAMPLE_COUNTS` that every one of
+/// `formats` can be rendered (and, for color formats, resolved) at.
+fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<u32> {
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    MSAA_SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .filter(|&count| {
+            formats.iter().all(|format| {
+                let features = if adapter_specific {
+                    adapter.get_texture_format_features(*format)
+                } else {
+                    format.guaranteed_format_features(device.features())
+                };
+                let resolvable = format.has_depth_aspect()
+                    || count == 1
+                    || features
+                        .flags
+                        .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+                features.flags.sample_count_supported(count) && resolvable
+            })
+        })
+        .collect()
+}
+
+/// Fill, plus whichever of Line and Point the device was created with.
+fn supported_polygon_modes(device: &wgpu::Device) -> Vec<wgpu::PolygonMode> {
+    let features = device.features();
+    let mut modes = vec![wgpu::PolygonMode::Fill];
+    if features.contains(wgpu::Features::POLYGON_MODE_LINE) {
+        modes.push(wgpu::PolygonMode::Line);
+    }
+    if features.contains(wgpu::Features::POLYGON_MODE_POINT) {
+        modes.push(wgpu::PolygonMode::Point);
+    }
+    modes
+}
+
+/// Present modes that can be picked, in the order V cycles through them.
+const PRESENT_MODES: &[wgpu::PresentMode] = &[
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::FifoRelaxed,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+/// `requested` if it's in `supported`, otherwise the closest mode that is:
+/// relaxed vsync falls back to vsync, and the two that don't wait for vsync
+/// fall back to each other before vsync. `Fifo` is always supported.
+fn pick_present_mode(
+    supported: &[wgpu::PresentMode],
+    requested: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    use wgpu::PresentMode::*;
+    let fallbacks: &[wgpu::PresentMode] = match requested {
+        Mailbox => &[Mailbox, Immediate],
+        Immediate => &[Immediate, Mailbox],
+        _ => &[requested],
+    };
+    let present_mode = fallbacks
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(Fifo);
+    if present_mode != requested {
+        log::warn!("{requested:?} present mode isn't supported, using {present_mode:?}");
+    }
+    present_mode
+}
+
+/// `requested` if the surface supports it, otherwise its first mode.
+fn pick_alpha_mode(
+    caps: &wgpu::SurfaceCapabilities,
+    requested: wgpu::CompositeAlphaMode,
+) -> wgpu::CompositeAlphaMode {
+    let alpha_mode = if caps.alpha_modes.contains(&requested) {
+        requested
+    } else {
+        if requested != wgpu::CompositeAlphaMode::Auto {
+            log::warn!("{requested:?} surface alpha isn't supported");
+        }
+        caps.alpha_modes[0]
+    };
+    log::info!("Using {alpha_mode:?} surface alpha");
+    alpha_mode
+}
+
+/// Picks the highest supported sample count that doesn't exceed `requested`.
+fn pick_sample_count(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    formats: &[wgpu::TextureFormat],
+    requested: u32,
+) -> u32 {
+    let supported = supported_sample_counts(adapter, device, formats);
+    let count = supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1);
+    if count != requested {
+        log::warn!("{requested}x MSAA is not supported (supported: {supported:?}), using {count}x");
+    }
+    count
+}
+
+/// The 3D view's scene, from the models and environment in `options`.
+/// `None` shows the 2D view instead.
+fn load_scene_desc(options: &Options) -> Option<SceneDesc> {
+    let models: Vec<ModelData> = options
+        .models
+        .iter()
+        .filter_map(|path| {
+            ModelData::load(path)
+                .map_err(|e| log::error!("Failed to load model: {e}"))
+                .ok()
+        })
+        .collect();
+    (!models.is_empty()).then(|| {
+        let mut desc = SceneDesc::from_models(models);
+        if let Some(shading) = options.shading {
+            for model in &mut desc.models {
+                model.set_shading(shading);
+            }
+        }
+        if let Some(path) = &options.environment {
+            desc.environment = ibl::load_equirect(path)
+                .map_err(|e| log::error!("Failed to load environment: {e}"))
+                .ok();
+        }
+        if let Some(dir) = &options.skybox {
+            desc.skybox = texture::load_cube_faces(dir)
+                .map_err(|e| log::error!("Failed to load skybox: {e}"))
+                .ok();
+        }
+        desc
+    })
+}
+
+/// The 2D view's image split into tiles, when `--tiled` asks for it or it's
+/// larger than `device` can fit in one texture. `None` shows it whole.
+fn load_tile_pyramid(
+    options: &Options,
+    device: &wgpu::Device,
+) -> Result<Option<Rc<TilePyramid>>, String> {
+    let max = device.limits().max_texture_dimension_2d;
+    let too_large = image::image_dimensions(&options.image)
+        .is_ok_and(|(width, height)| width.max(height) > max);
+    if !options.tiled && !too_large {
+        return Ok(None);
+    }
+    let pyramid = TilePyramid::load(&options.image)?;
+    Ok(Some(Rc::new(pyramid)))
+}
+
+/// Renders the recording at `path` offscreen into `out/frame_NNNN.png`, over
+/// the models in `options` or the golden lighting scene without any.
+fn replay_offscreen(options: &Options, path: &Path, out: &Path) -> Result<(), String> {
+    let recording = Recording::load(path)?;
+    let headless = pollster::block_on(Headless::new())?;
+    let desc = load_scene_desc(options).unwrap_or_else(|| {
+        log::warn!("No models to replay over, using the golden lighting scene");
+        golden::lighting_scene().0
+    });
+    let [width, height] = recording.size;
+    let mut offscreen = OffscreenScene::new(&headless, &desc, width, height);
+    offscreen.input.bindings = Bindings::load_or_default(&options.input_config);
+    std::fs::create_dir_all(out).map_err(|e| format!("{}: {e}", out.display()))?;
+    let mut result = Ok(());
+    replay::render(&headless, &mut offscreen, &recording, |index, image| {
+        let path = out.join(format!("frame_{index:04}.png"));
+        if result.is_ok() {
+            result = image
+                .save(&path)
+                .map_err(|e| format!("{}: {e}", path.display()));
+        }
+    });
+    result?;
+    println!(
+        "Rendered {} frames into {}",
+        recording.frames.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+impl State {
+    fn new(gpu: &Gpu, window: Window, options: &Options) -> Result<Self, String> {
+        let scene_desc = load_scene_desc(options);
+        let pyramid = match scene_desc {
+            Some(_) => None,
+            None => load_tile_pyramid(options, &gpu.device)?,
+        };
+        // The tiled view only needs a stand-in for the image it replaces.
+        let display_source = match &pyramid {
+            Some(pyramid) => DisplayImage::new(
+                image::DynamicImage::ImageRgba8(pyramid.thumbnail().clone()),
+                gpu.device.features(),
+            ),
+            None => DisplayImage::load(&options.image, gpu.device.features())?,
+        };
+        let mut s = Self::create(
+            gpu,
+            window,
+            options.clone(),
+            scene_desc,
+            pyramid,
+            display_source,
+        );
+        s.recorder = options
+            .record
+            .clone()
+            .map(|path| Recorder::new(path, s.size));
+        if let Some(path) = &options.replay {
+            match Recording::load(path) {
+                Ok(recording) => {
+                    let [width, height] = recording.size;
+                    if [s.size.width, s.size.height] != recording.size {
+                        // Resized comes later, before the first frame.
+                        s.window
+                            .set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+                    }
+                    log::info!("Replaying {} frames", recording.frames.len());
+                    s.replay = Some(recording.frames.into_iter());
+                }
+                Err(e) => log::error!("Failed to load the input recording: {e}"),
+            }
+        }
+        Ok(s)
+    }
+
+    /// Creates the window's surface and every GPU resource it renders
+    /// with on `gpu`, the 3D view's from `scene_desc` if there is one and
+    /// the tiled 2D view's from `pyramid` if there is one. The 2D view
+    /// shows `display_source`.
+    fn create(
+        gpu: &Gpu,
+        window: Window,
+        options: Options,
+        scene_desc: Option<SceneDesc>,
+        pyramid: Option<Rc<TilePyramid>>,
+        display_source: DisplayImage,
+    ) -> Self {
+        let size = window.inner_size();
+        let surface = unsafe { gpu.instance.create_surface(&window) }.unwrap();
+        let adapter = gpu.adapter.clone();
+        let device = gpu.device.clone();
+        let queue = gpu.queue.clone();
+        let health = gpu.health.clone();
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -183,108 +721,40 @@ impl State {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode: pick_present_mode(&surface_caps.present_modes, options.present_mode),
+            alpha_mode: pick_alpha_mode(&surface_caps, options.alpha_mode),
             view_formats: vec![],
         };
         surface.configure(&device, &config);
+        log::info!(
+            "Using {:?} present mode, at most {} frames in flight",
+            config.present_mode,
+            options.frame_latency
+        );
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
-        let bind_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Example bind group"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    // The binding index as used in the @binding attribute in the shader
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: std::num::NonZeroU64::new(
-                            size_of::<UniformExample>() as _
-                        ),
-                    },
-                    //count: std::num::NonZeroU32::new(1),
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    // The binding index as used in the @binding attribute in the shader
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-            ],
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&bind_layout],
-                push_constant_ranges: &[],
-            });
-
+        let mut pipeline_cache = PipelineCache::new();
         let texture_depth_format = wgpu::TextureFormat::Depth24Plus;
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                //cull_mode: Some(wgpu::Face::Back),
-                cull_mode: None,
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                //polygon_mode: wgpu::PolygonMode::Line,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture_depth_format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-        });
+        // The 3D view renders in HDR and is tonemapped into the surface.
+        let color_format = if scene_desc.is_none() {
+            config.format
+        } else {
+            HDR_FORMAT
+        };
+
+        let sample_count = pick_sample_count(
+            &adapter,
+            &device,
+            &[color_format, texture_depth_format],
+            options.msaa,
+        );
+        log::info!("Using {sample_count}x MSAA");
 
+        let targets = TargetFormats {
+            color: color_format,
+            depth: texture_depth_format,
+            sample_count,
+        };
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(VERTICES),
@@ -303,19 +773,27 @@ impl State {
             mapped_at_creation: false,
         });
 
-        let (_tex, width, height) = gen_texture_data(config.width as usize, config.height as usize);
+        let display = StreamingTexture::new(
+            &device,
+            display_source.format,
+            display_source.width,
+            display_source.height,
+            display_source.data.clone(),
+        );
+        let mut uniforms = UniformExample::new(&display_source);
+        uniforms.exposure = options.exposure;
 
         let display_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("z-Depth texture"),
+            label: Some("Display texture"),
             size: wgpu::Extent3d {
-                width: width as u32,
-                height: height as u32,
+                width: display_source.width,
+                height: display_source.height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format: display_source.format,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
             view_formats: &[],
         });
@@ -331,32 +809,94 @@ impl State {
             array_layer_count: Some(1),
         });
 
-        let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &bind_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &uniform_buffer,
-                        offset: 0,
-                        size: std::num::NonZeroU64::new(size_of::<UniformExample>() as _),
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&display_texture_view),
-                },
-            ],
+        let bind_group = BindGroupBuilder::new("Example")
+            .uniform(ShaderStages::VERTEX_FRAGMENT, &uniform_buffer)
+            .texture(
+                ShaderStages::FRAGMENT,
+                &display_texture,
+                &display_texture_view,
+            );
+        let shader_flags = ShaderFlags::default();
+        let shader_layout_entries = bind_group.layout_entries().to_vec();
+        let shader = load_shader(
+            &device,
+            &mut pipeline_cache,
+            shader_flags,
+            &shader_layout_entries,
+        )
+        .unwrap_or_else(|e| panic!("{e}"));
+        let (bind_layout, uniform_bind_group) =
+            bind_group.build_cached(&device, &mut pipeline_cache);
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[&bind_layout],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = PipelineBuilder::new(
+            "Render Pipeline",
+            &render_pipeline_layout,
+            &shader,
+            color_format,
+        )
+        .vertex_buffer(Vertex::desc())
+        .targets(targets)
+        .build_cached(&device, &mut pipeline_cache);
+
+        let mesh_scene = scene_desc.as_ref().map(|desc| {
+            MeshScene::new(
+                &device,
+                &queue,
+                &mut pipeline_cache,
+                targets,
+                config.width,
+                config.height,
+                desc,
+                options.shadow,
+            )
+        });
+        let tiled_view = pyramid.map(|pyramid| {
+            TiledView::new(
+                &device,
+                &mut pipeline_cache,
+                targets,
+                pyramid,
+                config.width,
+                config.height,
+            )
         });
+        let tonemap = mesh_scene
+            .as_ref()
+            .map(|_| Tonemap::new(&device, &mut pipeline_cache, config.format));
+        let font = text::load_font(std::path::Path::new(text::DEFAULT_FONT))
+            .map_err(|e| log::warn!("No text overlay: {e}"))
+            .ok();
+        let text = font.clone().map(|font| {
+            TextRenderer::new(
+                &device,
+                &mut pipeline_cache,
+                config.format,
+                font,
+                Rasterization::Bitmap,
+            )
+        });
+        let ui = font.map(|font| Ui::new(&device, &mut pipeline_cache, config.format, font));
+
+        let mut debug_draw = DebugDraw::new(&device, &mut pipeline_cache, targets, config.format);
+        debug_draw.enabled = false;
+
+        let bindings = Bindings::load_or_default(&options.input_config);
 
         let mut s = Self {
             count: 0,
             surface,
+            adapter,
             device,
             queue,
             config,
             size,
+            shader,
+            render_pipeline_layout,
             render_pipeline,
             vertex_buffer,
             index_buffer,
@@ -365,16 +905,171 @@ impl State {
             texture_depth_format,
             depth_texture: None,
             depth_texture_view: None,
+            msaa_texture: None,
+            msaa_texture_view: None,
+            sample_count,
+            color_format,
+            tonemap,
+            hdr_texture_view: None,
+            hdr_bind_group: None,
             display_texture,
-            display_texture_view,
+            display,
+            display_source,
+            animate_display: false,
+            display_band: None,
             num_indices,
             window,
-            timestamp: std::time::Instant::now(),
+            transparent_window: options.transparent_window(),
+            clock: ScaledClock::new(options.clock(), 1.0),
+            time_paused: false,
+            slow_motion: false,
+            frame_step: options.fixed_step.unwrap_or(DEFAULT_FRAME_STEP),
+            mesh_scene,
+            tiled_view,
+            last_update: options.start_time,
+            last_frame: std::time::Instant::now(),
+            text,
+            frame_time: 0.0,
+            frame_latency: options.frame_latency.max(1),
+            in_flight: VecDeque::new(),
+            debug_draw,
+            ui,
+            uniforms,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            pipeline_cache,
+            shader_flags,
+            options,
+            scene_desc,
+            health,
+            recoveries: 0,
+            consecutive_recoveries: 0,
+            minimized: false,
+            occluded: false,
+            blend_mode: BlendMode::Opaque,
+            input: Input::new(bindings).with_gamepads(),
+            close_requested: false,
+            recorder: None,
+            replay: None,
+            shader_layout_entries,
         };
         s.configue_texture_depth_buffer();
         s
     }
 
+    /// Moves the window to `gpu`, a replacement for the device `fault` left
+    /// unusable, recreating every GPU resource from `options` and
+    /// `scene_desc` and carrying over the settings changed since startup.
+    fn recover(mut self, gpu: &Gpu, fault: Fault) -> Self {
+        let mut options = self.options.clone();
+        options.msaa = self.sample_count;
+        options.present_mode = self.config.present_mode;
+        options.alpha_mode = self.config.alpha_mode;
+        options.frame_latency = self.frame_latency;
+        let mut scene_desc = self.scene_desc.clone();
+        if let (Some(desc), Some(mesh_scene)) = (&mut scene_desc, &self.mesh_scene) {
+            desc.lights = mesh_scene.lights.lights.clone();
+            desc.ambient = mesh_scene.lights.ambient;
+        }
+        let tonemap = self.tonemap.as_ref().map(|t| (t.operator, t.exposure));
+        let ui_visible = self.ui.as_ref().is_some_and(|ui| ui.visible);
+        let debug_draw = self.debug_draw.enabled;
+        let animate_display = self.animate_display;
+        let (uniforms, polygon_mode, shader_flags, blend_mode) = (
+            self.uniforms,
+            self.polygon_mode,
+            self.shader_flags,
+            self.blend_mode,
+        );
+        let (time_paused, slow_motion, last_update, count, recoveries, consecutive_recoveries) = (
+            self.time_paused,
+            self.slow_motion,
+            self.last_update,
+            self.count,
+            self.recoveries,
+            self.consecutive_recoveries,
+        );
+        let (recorder, replay) = (self.recorder.take(), self.replay.take());
+
+        // Moving out of a temporary drops everything else, old surface
+        // included, before the window gets a new one.
+        let State {
+            window,
+            mesh_scene,
+            tiled_view,
+            clock,
+            display_source,
+            ..
+        } = { self };
+        let camera = mesh_scene.map(|mesh_scene| (mesh_scene.camera, mesh_scene.camera_controller));
+        let (pyramid, pan_zoom) = tiled_view
+            .map(|tiled_view| (tiled_view.pyramid, tiled_view.camera))
+            .unzip();
+
+        let mut s = Self::create(gpu, window, options, scene_desc, pyramid, display_source);
+        s.uniforms = uniforms;
+        s.set_polygon_mode(polygon_mode);
+        s.set_shader_flags(shader_flags);
+        s.set_blend_mode(blend_mode);
+        s.debug_draw.enabled = debug_draw;
+        s.animate_display = animate_display;
+        if let Some(ui) = &mut s.ui {
+            ui.visible = ui_visible;
+        }
+        if let (Some(tonemap), Some((operator, exposure))) = (&mut s.tonemap, tonemap) {
+            tonemap.operator = operator;
+            tonemap.exposure = exposure;
+        }
+        if let (Some(mesh_scene), Some((camera, controller))) = (&mut s.mesh_scene, camera) {
+            mesh_scene.camera = camera;
+            mesh_scene.camera_controller = controller;
+        }
+        if let (Some(tiled_view), Some(pan_zoom)) = (&mut s.tiled_view, pan_zoom) {
+            tiled_view.camera = pan_zoom;
+        }
+        s.clock = clock;
+        s.time_paused = time_paused;
+        s.slow_motion = slow_motion;
+        s.apply_time_scale();
+        s.last_update = last_update;
+        s.count = count;
+        s.recorder = recorder;
+        s.replay = replay;
+        s.recoveries = recoveries + 1;
+        s.consecutive_recoveries = consecutive_recoveries + 1;
+        log::warn!(
+            "Recovered from {fault:?} with a new device ({} recoveries so far)",
+            s.recoveries
+        );
+        s
+    }
+
+    /// Waits for the window's frames to finish before it's dropped, which
+    /// closes it.
+    fn close(self) {
+        self.device.poll(wgpu::Maintain::Wait);
+        log::info!("Closed window {:?}", self.window.id());
+    }
+
+    fn paused(&self) -> bool {
+        self.minimized || self.occluded
+    }
+
+    fn set_visibility(&mut self, minimized: bool, occluded: bool) {
+        let was_paused = self.paused();
+        self.minimized = minimized;
+        self.occluded = occluded;
+        if self.paused() != was_paused {
+            if self.paused() {
+                log::info!("Paused rendering while the window can't be seen");
+            } else {
+                log::info!("Resumed rendering");
+                // Don't count the pause as one long frame.
+                self.clock.resync();
+                self.last_frame = std::time::Instant::now();
+            }
+        }
+    }
+
     fn configue_texture_depth_buffer(&mut self) {
         let depth_texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("z-Depth texture"),
@@ -384,7 +1079,7 @@ impl State {
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: self.sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: self.texture_depth_format,
             usage: TextureUsages::RENDER_ATTACHMENT,
@@ -403,6 +1098,239 @@ impl State {
 
         self.depth_texture = Some(depth_texture);
         self.depth_texture_view = Some(depth_texture_view);
+        self.configure_msaa_texture();
+        self.configure_hdr_texture();
+    }
+
+    /// (Re)creates the HDR color target the 3D view resolves into before
+    /// tonemapping.
+    fn configure_hdr_texture(&mut self) {
+        let Some(tonemap) = &self.tonemap else {
+            return;
+        };
+        let hdr_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("HDR color texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.color_format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let hdr_texture_view = hdr_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("HDR color texture view"),
+            ..Default::default()
+        });
+        self.hdr_bind_group = Some(tonemap.bind_input(
+            &self.device,
+            &mut self.pipeline_cache,
+            &hdr_texture,
+            &hdr_texture_view,
+        ));
+        self.hdr_texture_view = Some(hdr_texture_view);
+    }
+
+    /// (Re)creates the multisampled color target. Like the depth buffer it has
+    /// to match the surface size and the pipeline's sample count.
+    fn configure_msaa_texture(&mut self) {
+        if self.sample_count == 1 {
+            self.msaa_texture = None;
+            self.msaa_texture_view = None;
+            return;
+        }
+        let msaa_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MSAA color texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: self.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.color_format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let msaa_texture_view = msaa_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("MSAA color texture view"),
+            ..Default::default()
+        });
+
+        self.msaa_texture = Some(msaa_texture);
+        self.msaa_texture_view = Some(msaa_texture_view);
+    }
+
+    /// Switches to `sample_count`, rebuilding the pipeline and render targets
+    /// that depend on it.
+    fn set_sample_count(&mut self, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+        self.render_pipeline = self.create_render_pipeline();
+        let targets = self.targets();
+        if let Some(mesh_scene) = &mut self.mesh_scene {
+            mesh_scene.set_targets(&self.device, &mut self.pipeline_cache, targets);
+        }
+        self.debug_draw
+            .set_targets(&self.device, &mut self.pipeline_cache, targets);
+        if let Some(tiled_view) = &mut self.tiled_view {
+            tiled_view.set_targets(&self.device, &mut self.pipeline_cache, targets);
+        }
+        self.configue_texture_depth_buffer();
+        log::info!("Switched to {sample_count}x MSAA");
+    }
+
+    fn create_render_pipeline(&mut self) -> Rc<wgpu::RenderPipeline> {
+        let targets = self.targets();
+        PipelineBuilder::new(
+            "Render Pipeline",
+            &self.render_pipeline_layout,
+            &self.shader,
+            self.color_format,
+        )
+        .vertex_buffer(Vertex::desc())
+        .polygon_mode(self.polygon_mode)
+        .targets(targets)
+        .blend_mode(self.blend_mode)
+        .build_cached(&self.device, &mut self.pipeline_cache)
+    }
+
+    /// Switches both views between filled, wireframe and point rendering.
+    fn set_polygon_mode(&mut self, polygon_mode: wgpu::PolygonMode) {
+        if polygon_mode == self.polygon_mode {
+            return;
+        }
+        self.polygon_mode = polygon_mode;
+        self.render_pipeline = self.create_render_pipeline();
+        if let Some(mesh_scene) = &mut self.mesh_scene {
+            mesh_scene.set_polygon_mode(&self.device, &mut self.pipeline_cache, polygon_mode);
+        }
+        log::info!("Switched to {polygon_mode:?} polygon mode");
+    }
+
+    /// Recompiles the 2D view's shader with `flags`, keeping the current one
+    /// if that fails.
+    fn set_shader_flags(&mut self, flags: ShaderFlags) {
+        if flags == self.shader_flags {
+            return;
+        }
+        match load_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            flags,
+            &self.shader_layout_entries,
+        ) {
+            Ok(shader) => {
+                self.shader = shader;
+                self.shader_flags = flags;
+                self.render_pipeline = self.create_render_pipeline();
+                log::info!("Switched to shader flags {:?}", flags.defines());
+            }
+            Err(e) => log::error!("Failed to compile shader.wgsl: {e}"),
+        }
+    }
+
+    /// Blends the 2D view's quad with `mode`, switching the shader to
+    /// premultiplied output for `BlendMode::Premultiplied`.
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        if mode == self.blend_mode {
+            return;
+        }
+        self.blend_mode = mode;
+        let premultiplied_alpha = mode == BlendMode::Premultiplied;
+        if premultiplied_alpha != self.shader_flags.premultiplied_alpha {
+            // Also rebuilds the pipeline.
+            self.set_shader_flags(ShaderFlags {
+                premultiplied_alpha,
+                ..self.shader_flags
+            });
+        } else {
+            self.render_pipeline = self.create_render_pipeline();
+        }
+        log::info!("Switched to {} blending", mode.name());
+    }
+
+    /// Only takes effect on windows created transparent, see
+    /// `Options::alpha_mode`.
+    fn set_alpha_mode(&mut self, alpha_mode: wgpu::CompositeAlphaMode) {
+        if alpha_mode == self.config.alpha_mode {
+            return;
+        }
+        self.config.alpha_mode = alpha_mode;
+        self.surface.configure(&self.device, &self.config);
+        log::info!("Switched to {alpha_mode:?} surface alpha");
+    }
+
+    /// Reconfigures the surface with `present_mode`, or the closest
+    /// supported one.
+    fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        let supported = self.surface.get_capabilities(&self.adapter).present_modes;
+        let present_mode = pick_present_mode(&supported, present_mode);
+        if present_mode == self.config.present_mode {
+            return;
+        }
+        self.config.present_mode = present_mode;
+        self.surface.configure(&self.device, &self.config);
+        log::info!("Switched to {present_mode:?} present mode");
+    }
+
+    /// Advances to the next supported entry of `PRESENT_MODES`.
+    fn cycle_present_mode(&mut self) {
+        let supported = self.surface.get_capabilities(&self.adapter).present_modes;
+        let current = PRESENT_MODES
+            .iter()
+            .position(|&mode| mode == self.config.present_mode)
+            .unwrap_or(0);
+        let next = (1..PRESENT_MODES.len())
+            .map(|i| PRESENT_MODES[(current + i) % PRESENT_MODES.len()])
+            .find(|mode| supported.contains(mode))
+            .unwrap_or(wgpu::PresentMode::Fifo);
+        self.set_present_mode(next);
+    }
+
+    /// Frame rate, frame time and present mode.
+    fn frame_stats(&self) -> String {
+        let speed = match (self.time_paused, self.slow_motion) {
+            (true, _) => ", paused".to_string(),
+            (false, true) => format!(", {SLOW_MOTION_SCALE}x"),
+            (false, false) => String::new(),
+        };
+        format!(
+            "{:.0} fps ({:.2} ms), {:?}{speed}",
+            1.0 / self.frame_time.max(1e-6),
+            self.frame_time * 1000.0,
+            self.config.present_mode
+        )
+    }
+
+    fn targets(&self) -> TargetFormats {
+        TargetFormats {
+            color: self.color_format,
+            depth: self.texture_depth_format,
+            sample_count: self.sample_count,
+        }
+    }
+
+    /// Advances to the next supported sample count, wrapping back to 1.
+    fn cycle_sample_count(&mut self) {
+        let supported = supported_sample_counts(
+            &self.adapter,
+            &self.device,
+            &[self.color_format, self.texture_depth_format],
+        );
+        let next = supported
+            .iter()
+            .copied()
+            .find(|&count| count > self.sample_count)
+            .unwrap_or(1);
+        self.set_sample_count(next);
     }
 
     pub fn window(&self) -> &Window {
@@ -410,27 +1338,340 @@ impl State {
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.set_visibility(new_size.width == 0 || new_size.height == 0, self.occluded);
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
             self.configue_texture_depth_buffer();
+            if let Some(mesh_scene) = &mut self.mesh_scene {
+                mesh_scene.resize(new_size.width, new_size.height);
+            }
         }
     }
 
-    #[allow(unused_variables)]
     fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+        // Live input would make the replay diverge.
+        if self.replay.is_some() {
+            return false;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.event(event);
+        }
+        self.apply_input(event)
+    }
+
+    /// Handles a live or replayed event.
+    fn apply_input(&mut self, event: &WindowEvent) -> bool {
+        // Always recorded, so a release isn't missed when the UI takes it.
+        self.input.event(event);
+        if self.ui.as_mut().is_some_and(|ui| ui.input(event)) {
+            return true;
+        }
+        match event {
+            WindowEvent::Occluded(occluded) => {
+                self.set_visibility(self.minimized, *occluded);
+                true
+            }
+            _ => {
+                self.mesh_scene.as_mut().is_some_and(|m| m.input(event))
+                    || self.tiled_view.as_mut().is_some_and(|t| t.input(event))
+            }
+        }
     }
 
-    fn update(&mut self) {}
+    fn update(&mut self) {
+        let replayed = match &mut self.replay {
+            Some(frames) => match frames.next() {
+                Some(frame) => {
+                    for event in &frame.events {
+                        self.apply_input(&event.to_event());
+                    }
+                    Some(frame.time())
+                }
+                None => {
+                    log::info!("Replay finished");
+                    self.replay = None;
+                    self.close_requested = true;
+                    return;
+                }
+            },
+            None => None,
+        };
+        self.input.poll_gamepads();
+        // Before ticking, so pausing and stepping apply to this frame.
+        self.handle_actions();
+        let now = replayed.unwrap_or_else(|| self.clock.tick());
+        let dt = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.end_frame(now);
+        }
+        let frame = std::time::Instant::now();
+        let frame_time = (frame - self.last_frame).as_secs_f32();
+        self.last_frame = frame;
+        self.frame_time += (frame_time - self.frame_time) * 0.05;
+        self.debug_draw.update(dt);
+        self.update_display_band();
+        if let Some(mesh_scene) = &mut self.mesh_scene {
+            mesh_scene.handle_actions(&self.input);
+            mesh_scene.camera_controller.set_sticks(
+                [
+                    self.input.axis(GamepadAxis::LeftStickX),
+                    self.input.axis(GamepadAxis::LeftStickY),
+                ],
+                [
+                    self.input.axis(GamepadAxis::RightStickX),
+                    self.input.axis(GamepadAxis::RightStickY),
+                ],
+            );
+            mesh_scene.update(&self.device, &self.queue, &mut self.pipeline_cache, dt);
+            mesh_scene.draw_debug(&mut self.debug_draw);
+        }
+        if let Some(tiled_view) = &mut self.tiled_view {
+            tiled_view.handle_actions(&self.input, dt);
+            tiled_view.update(
+                &self.device,
+                &self.queue,
+                self.config.width,
+                self.config.height,
+            );
+        }
+        self.update_ui();
+        if let Some(tonemap) = &mut self.tonemap {
+            tonemap.handle_actions(&self.input);
+            tonemap.update(&self.queue);
+        }
+        self.input.end_frame();
+    }
+
+    /// Puts back the image under the band's last position, and streams it
+    /// in at the next one while `animate_display` is set.
+    fn update_display_band(&mut self) {
+        if let Some(band) = self.display_band.take() {
+            self.display.write(band, &self.display_source.region(band));
+        }
+        if !self.animate_display {
+            return;
+        }
+        let (width, height) = self.display.size();
+        let band_height = DISPLAY_BAND_HEIGHT.min(height);
+        let time = self.last_update.as_secs_f32();
+        let y = (time * DISPLAY_BAND_SPEED) as u32 % (height - band_height + 1);
+        let band = Rect::new(0, y, width, band_height);
+        let mut pixels = vec![];
+        for row in 0..band_height {
+            for x in 0..width {
+                let wave = (x as f32 * 0.05 + time * 3.0).sin() * 0.5 + 0.5;
+                let fade = row as f32 / band_height as f32;
+                self.display_source
+                    .encode([wave, fade, 1.0 - wave, 1.0], &mut pixels);
+            }
+        }
+        self.display.write(band, &pixels);
+        self.display_band = Some(band);
+    }
+
+    fn handle_actions(&mut self) {
+        if self.input.pressed(Action::CycleMsaa) {
+            self.cycle_sample_count();
+        }
+        if self.input.pressed(Action::ToggleDebugDraw) {
+            self.debug_draw.enabled = !self.debug_draw.enabled;
+            self.debug_draw.clear();
+        }
+        if self.input.pressed(Action::CyclePresentMode) {
+            self.cycle_present_mode();
+        }
+        if self.input.pressed(Action::InjectDeviceLoss) {
+            self.health.inject(Fault::DeviceLost);
+        }
+        if self.input.pressed(Action::Quit) {
+            self.close_requested = true;
+        }
+        if self.input.pressed(Action::TogglePause) {
+            self.time_paused = !self.time_paused;
+            self.apply_time_scale();
+        }
+        if self.input.pressed(Action::ToggleSlowMotion) {
+            self.slow_motion = !self.slow_motion;
+            self.apply_time_scale();
+        }
+        if self.input.pressed(Action::StepFrame) && self.time_paused {
+            self.clock.skip(self.frame_step);
+        }
+        if let Some(ui) = self
+            .ui
+            .as_mut()
+            .filter(|_| self.input.pressed(Action::ToggleUi))
+        {
+            ui.visible = !ui.visible;
+        }
+    }
+
+    fn apply_time_scale(&mut self) {
+        self.clock.scale = match (self.time_paused, self.slow_motion) {
+            (true, _) => 0.0,
+            (false, true) => SLOW_MOTION_SCALE,
+            (false, false) => 1.0,
+        };
+    }
+
+    /// Declares the settings panel, applying whatever was changed in it.
+    fn update_ui(&mut self) {
+        let stats = self.frame_stats();
+        let Some(ui) = &mut self.ui else {
+            return;
+        };
+        ui.begin_frame();
+        if !ui.visible {
+            return;
+        }
+        let supported = self.surface.get_capabilities(&self.adapter).present_modes;
+        let present_modes: Vec<wgpu::PresentMode> = PRESENT_MODES
+            .iter()
+            .copied()
+            .filter(|mode| supported.contains(mode))
+            .collect();
+        let mut present_index = present_modes
+            .iter()
+            .position(|&mode| mode == self.config.present_mode)
+            .unwrap_or(0);
+        let alpha_modes = self.surface.get_capabilities(&self.adapter).alpha_modes;
+        let mut alpha_index = alpha_modes
+            .iter()
+            .position(|&mode| mode == self.config.alpha_mode)
+            .unwrap_or(0);
+        let mut blend_index = BlendMode::ALL
+            .iter()
+            .position(|&mode| mode == self.blend_mode)
+            .unwrap_or(0);
+        let polygon_modes = supported_polygon_modes(&self.device);
+        let mut polygon_index = polygon_modes
+            .iter()
+            .position(|&mode| mode == self.polygon_mode)
+            .unwrap_or(0);
+
+        let mut shader_flags = self.shader_flags;
+
+        // Named after the first key that toggles it, F1 unless rebound.
+        let title = match self.input.bindings.buttons(Action::ToggleUi).first() {
+            Some(Button::Key(key)) => format!("Settings ({key:?})"),
+            _ => "Settings".to_string(),
+        };
+        let mut panel = ui.panel(&title, [8.0, 8.0]);
+        panel.label(&stats);
+        panel.label(&format!(
+            "{}x{}, {}x MSAA",
+            self.config.width, self.config.height, self.sample_count
+        ));
+        let pipelines = self.pipeline_cache.stats().render_pipelines;
+        panel.label(&format!(
+            "Pipeline cache: {} hits, {} misses",
+            pipelines.hits, pipelines.misses
+        ));
+        panel.separator();
+        if let Some(tonemap) = &mut self.tonemap {
+            panel.slider("Exposure", &mut tonemap.exposure, 0.1..=4.0);
+        } else if let Some(tiled_view) = &self.tiled_view {
+            let pyramid = &tiled_view.pyramid;
+            let tiles = tiled_view.stats;
+            panel.label(&format!(
+                "{}x{}, level {} of {}",
+                pyramid.width(),
+                pyramid.height(),
+                tiles.level,
+                pyramid.level_count()
+            ));
+            panel.label(&format!(
+                "Tiles: {} visible, {} from coarser levels",
+                tiles.visible, tiles.fallbacks
+            ));
+            panel.label(&format!(
+                "Cache: {}/{} tiles, {} uploaded",
+                tiles.resident,
+                tiled::CACHE_SLOTS * tiled::CACHE_SLOTS,
+                tiles.uploaded
+            ));
+        } else {
+            panel.color_edit("Color", &mut self.uniforms.color);
+            panel.slider("Tile scale", &mut self.uniforms.tile_scale, 0.25..=4.0);
+            panel.slider(
+                "Tile offset x",
+                &mut self.uniforms.tile_offset[0],
+                0.0..=1.0,
+            );
+            panel.slider(
+                "Tile offset y",
+                &mut self.uniforms.tile_offset[1],
+                0.0..=1.0,
+            );
+            panel.checkbox("Mirror tiling", &mut shader_flags.tiling_mirror);
+            panel.checkbox("Gamma correct", &mut shader_flags.gamma_correct);
+            if self.display_source.hdr {
+                panel.slider("Exposure", &mut self.uniforms.exposure, 0.1..=4.0);
+                let mut curve = self.uniforms.curve as usize;
+                panel.choice("Tonemapping", &mut curve, &["ACES", "Reinhard"]);
+                self.uniforms.curve = curve as u32;
+            }
+            let names = BlendMode::ALL.map(BlendMode::name);
+            panel.choice("Blend mode", &mut blend_index, &names);
+            panel.checkbox("Animate texture", &mut self.animate_display);
+            let stream = self.display.stats;
+            panel.label(&format!(
+                "Upload: {:.1} KiB, {} deferred",
+                stream.uploaded_bytes as f32 / 1024.0,
+                stream.deferred_frames
+            ));
+        }
+        panel.checkbox("Debug draw (G)", &mut self.debug_draw.enabled);
+        panel.checkbox("Pause time (P)", &mut self.time_paused);
+        panel.checkbox("Slow motion (O)", &mut self.slow_motion);
+        panel.separator();
+        let names: Vec<String> = present_modes.iter().map(|m| format!("{m:?}")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        panel.choice("Present mode", &mut present_index, &names);
+        let mut latency = self.frame_latency as usize - 1;
+        panel.choice("Frame latency", &mut latency, &["1", "2", "3"]);
+        self.frame_latency = latency as u32 + 1;
+        let names: Vec<String> = alpha_modes.iter().map(|m| format!("{m:?}")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        panel.choice("Surface alpha", &mut alpha_index, &names);
+        let names: Vec<String> = polygon_modes.iter().map(|m| format!("{m:?}")).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        panel.choice("Polygon mode", &mut polygon_index, &names);
+        drop(panel);
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.set_shader_flags(shader_flags);
+        self.set_blend_mode(BlendMode::ALL[blend_index]);
+        self.set_present_mode(present_modes[present_index]);
+        self.set_alpha_mode(alpha_modes[alpha_index]);
+        self.set_polygon_mode(polygon_modes[polygon_index]);
+        self.apply_time_scale();
+    }
+
+    fn render(&mut self) -> Result<(), Fault> {
         self.count += 1;
         //if self.count > 1 {
         //    return Ok(());
         //}
+        if let Some((fault, _)) = self
+            .options
+            .inject_fault
+            .filter(|&(_, frame)| frame == self.count)
+        {
+            self.health.inject(fault);
+        }
+        if let Some(fault) = self.health.check() {
+            return Err(fault);
+        }
+        // Don't get more than `frame_latency` frames ahead of the GPU.
+        while self.in_flight.len() >= self.frame_latency as usize {
+            let index = self.in_flight.pop_front().unwrap();
+            self.device
+                .poll(wgpu::Maintain::WaitForSubmissionIndex(index));
+        }
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -441,7 +1682,26 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        self.display
+            .upload(&self.device, &mut encoder, &self.display_texture);
+        if let Some(mesh_scene) = &self.mesh_scene {
+            mesh_scene.render_shadows(&mut encoder);
+        }
+        // The 2D view has no camera, so world-space lines are in clip space.
+        let view_proj = self
+            .mesh_scene
+            .as_ref()
+            .map_or(cgmath::Matrix4::from_scale(1.0), MeshScene::view_proj);
+        self.debug_draw.prepare(
+            &self.device,
+            &self.queue,
+            view_proj,
+            self.config.width,
+            self.config.height,
+        );
 
+        // The 3D view draws into the HDR target and tonemaps it afterwards.
+        let scene_view = self.hdr_texture_view.as_ref().unwrap_or(&view);
         {
             let depth_stencil_attachment = wgpu::RenderPassDepthStencilAttachment {
                 view: self.depth_texture_view.as_ref().unwrap(),
@@ -457,14 +1717,18 @@ impl State {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self.msaa_texture_view.as_ref().unwrap_or(scene_view),
+                    resolve_target: self.msaa_texture_view.as_ref().map(|_| scene_view),
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
+                        load: wgpu::LoadOp::Clear(if self.transparent_window {
+                            wgpu::Color::TRANSPARENT
+                        } else {
+                            wgpu::Color {
+                                r: 0.1,
+                                g: 0.2,
+                                b: 0.3,
+                                a: 1.0,
+                            }
                         }),
                         store: true,
                     },
@@ -472,84 +1736,127 @@ impl State {
                 depth_stencil_attachment: Some(depth_stencil_attachment),
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
-            //render_pass.draw(0..VERTICES.len() as u32, 0..1);
-        }
-        let (tex, width, height) =
-            gen_texture_data(self.config.width as usize, self.config.height as usize);
-
-        self.queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &self.display_texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: Default::default(),
-            },
-            bytemuck::cast_slice(tex.as_slice()),
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * width as u32),
-                rows_per_image: Some(height as u32),
-            },
-            wgpu::Extent3d {
-                width: width as u32,
-                height: height as u32,
-                depth_or_array_layers: 1,
-            },
-            //wgpu::Extent3d {
-            //    width: self.display_texture.width(),
-            //    height: self.display_texture.height(),
-            //    depth_or_array_layers: 1,
-            //},
-        );
-
+            if let Some(mesh_scene) = &self.mesh_scene {
+                mesh_scene.draw(&mut render_pass);
+            } else if let Some(tiled_view) = &self.tiled_view {
+                tiled_view.draw(&mut render_pass);
+            } else {
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+                //render_pass.draw(0..VERTICES.len() as u32, 0..1);
+            }
+            self.debug_draw.draw_world(&mut render_pass);
+        }
+        if let (Some(tonemap), Some(hdr_bind_group)) = (&self.tonemap, &self.hdr_bind_group) {
+            tonemap.render(&mut encoder, hdr_bind_group, &view);
+        }
+        self.debug_draw.render_screen(&mut encoder, &view);
+        // The settings panel shows the same stats.
+        let ui_visible = self.ui.as_ref().is_some_and(|ui| ui.visible);
+        let stats = self.frame_stats();
+        if let Some(text) = self.text.as_mut().filter(|_| !ui_visible) {
+            let style = TextStyle::default();
+            let [width, _] = text.measure(&stats, &style);
+            text.queue_text(
+                &stats,
+                [self.config.width as f32 - width - 8.0, 8.0],
+                &style,
+            );
+            text.prepare(
+                &self.device,
+                &self.queue,
+                self.config.width,
+                self.config.height,
+            );
+            text.render(&mut encoder, &view);
+        }
+        if let Some(ui) = &mut self.ui {
+            ui.prepare(
+                &self.device,
+                &self.queue,
+                self.config.width,
+                self.config.height,
+            );
+            ui.render(&mut encoder, &view);
+        }
         self.queue.write_buffer(
             &self.uniform_buffer,
             /*offset=*/ 0,
             bytemuck::bytes_of(&UniformExample {
-                color: [0.5, 1.0, 0.4, 1.0],
-                time: self.timestamp.elapsed().as_secs_f32(),
-                ..Default::default()
+                time: self.last_update.as_secs_f32(),
+                ..self.uniforms
             }),
         );
-        self.queue.submit(iter::once(encoder.finish()));
+        self.in_flight
+            .push_back(self.queue.submit(iter::once(encoder.finish())));
+        self.display.submitted();
         output.present();
 
         Ok(())
     }
 }
 
-pub async fn run() {
-    env_logger::init();
+/// How far `step_frame` moves the time on without `--fixed-step`.
+const DEFAULT_FRAME_STEP: std::time::Duration = std::time::Duration::from_nanos(16_666_667);
+/// Rows in the band `animate_display` slides down the 2D view's texture.
+const DISPLAY_BAND_HEIGHT: u32 = 32;
+/// How fast the band slides, in rows per second.
+const DISPLAY_BAND_SPEED: f32 = 60.0;
+/// How fast time runs in slow motion.
+const SLOW_MOTION_SCALE: f64 = 0.25;
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+/// Device recoveries, or failed attempts at one, without a frame rendering
+/// in between before the window gives up and closes.
+const MAX_CONSECUTIVE_RECOVERIES: u32 = 3;
 
-    let mut state = State::new(window).await;
-    let mut timer = std::time::Instant::now();
+#[allow(clippy::collapsible_match)]
+pub async fn run(options: Options) -> Result<(), String> {
+    let event_loop = EventLoop::new();
+    let mut gpu = Gpu::new().await?;
+    // Every open window by id. Closing the last one exits.
+    let mut windows: HashMap<winit::window::WindowId, State> = HashMap::new();
+    let window = WindowBuilder::new()
+        .with_title("wgpu-setup")
+        .with_transparent(options.transparent_window())
+        .build(&event_loop)
+        .unwrap();
+    windows.insert(window.id(), State::new(&gpu, window, &options)?);
+    if options.image_window {
+        let window = WindowBuilder::new()
+            .with_title("wgpu-setup: image")
+            .with_transparent(options.transparent_window())
+            .build(&event_loop)
+            .unwrap();
+        let image_options = Options {
+            models: vec![],
+            record: None,
+            replay: None,
+            ..options.clone()
+        };
+        windows.insert(window.id(), State::new(&gpu, window, &image_options)?);
+    }
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window().id() => {
+            } => {
+                let Some(state) = windows.get_mut(&window_id) else {
+                    return;
+                };
                 if !state.input(event) {
                     match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    state: ElementState::Pressed,
-                                    virtual_keycode: Some(VirtualKeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
+                        WindowEvent::CloseRequested => {
+                            windows.remove(&window_id).unwrap().close();
+                            if windows.is_empty() {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
                         WindowEvent::Resized(physical_size) => {
                             state.resize(*physical_size);
                             state.window().request_redraw();
@@ -563,25 +1870,66 @@ pub async fn run() {
                     }
                 }
             }
-            Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+            Event::RedrawRequested(window_id) => {
+                let Some(state) = windows.get_mut(&window_id).filter(|s| !s.paused()) else {
+                    return;
+                };
                 state.update();
-                println!("Time: {}", timer.elapsed().as_millis());
+                if state.close_requested {
+                    windows.remove(&window_id).unwrap().close();
+                    if windows.is_empty() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    return;
+                }
                 match state.render() {
-                    Ok(_) => {}
+                    Ok(_) => state.consecutive_recoveries = 0,
+                    // Recreating the device again and again won't help.
+                    Err(fault) if state.consecutive_recoveries >= MAX_CONSECUTIVE_RECOVERIES => {
+                        log::error!("{fault:?} again after {MAX_CONSECUTIVE_RECOVERIES} recoveries, giving up");
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    // Every window shares the device, so all of them move
+                    // to the new one.
+                    Err(fault) if fault.needs_new_device() => match pollster::block_on(gpu.replace()) {
+                        Ok(replacement) => {
+                            windows = std::mem::take(&mut windows)
+                                .into_iter()
+                                .map(|(id, state)| (id, state.recover(&replacement, fault)))
+                                .collect();
+                            gpu = replacement;
+                        }
+                        // No device right after losing one. Report the
+                        // fault again next frame to retry, counting the
+                        // attempt towards giving up.
+                        Err(e) => {
+                            log::error!("Recovering from {fault:?} failed: {e}");
+                            state.health.inject(fault);
+                            for state in windows.values_mut() {
+                                state.consecutive_recoveries += 1;
+                            }
+                        }
+                    },
+                    // Skip the frame.
+                    Err(Fault::Timeout) => log::warn!("Surface timeout"),
                     // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
+                    Err(fault) => {
+                        state.resize(state.size);
+                        log::warn!("Reconfigured the surface after {fault:?}");
                     }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // We're ignoring timeouts
-                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
                 }
             }
             Event::MainEventsCleared => {
                 // RedrawRequested will only trigger once, unless we manually
-                // request it.
-                //state.window().request_redraw();
+                // request it. Nothing is drawn while every window is paused,
+                // so wait for events instead.
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = ControlFlow::Wait;
+                    for state in windows.values().filter(|state| !state.paused()) {
+                        *control_flow = ControlFlow::Poll;
+                        state.window().request_redraw();
+                    }
+                }
             }
             _ => {}
         }
@@ -0,0 +1,212 @@
+use crate::blend::BlendMode;
+use crate::builder::BindGroupBuilder;
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{shader_struct, ShaderReflection};
+use crate::texture::{Texture, TextureData};
+use std::rc::Rc;
+use wgpu::util::DeviceExt;
+
+/// How a material responds to the scene's lights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// Cook-Torrance with image-based ambient, from `metallic` and
+    /// `roughness`.
+    #[default]
+    MetallicRoughness,
+    /// Lambert diffuse plus a Blinn-Phong highlight, from `specular` and
+    /// `shininess`, with only diffuse ambient. Ignores the metallic-roughness
+    /// texture.
+    BlinnPhong,
+}
+
+/// CPU-side material parameters, as read from a model file. Follows glTF 2.0
+/// metallic-roughness semantics unless `shading` says otherwise; factors
+/// multiply their textures.
+#[derive(Debug, Clone)]
+pub struct MaterialData {
+    pub name: String,
+    /// Linear RGBA base color, multiplied with the albedo texture and vertex
+    /// color.
+    pub albedo: [f32; 4],
+    pub albedo_texture: Option<TextureData>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Roughness in the green channel and metalness in blue.
+    pub metallic_roughness_texture: Option<TextureData>,
+    /// Tangent-space normals, +Y up.
+    pub normal_texture: Option<TextureData>,
+    pub normal_scale: f32,
+    /// Ambient occlusion in the red channel.
+    pub occlusion_texture: Option<TextureData>,
+    pub occlusion_strength: f32,
+    /// Linear RGB.
+    pub emissive: [f32; 3],
+    pub emissive_texture: Option<TextureData>,
+    pub shading: ShadingModel,
+    /// Linear RGB highlight color, for Blinn-Phong.
+    pub specular: [f32; 3],
+    /// Blinn-Phong exponent: higher is a smaller, sharper highlight.
+    pub shininess: f32,
+    /// Meshes with a transparent mode are drawn after the opaque ones, back
+    /// to front. The shader outputs straight alpha.
+    pub blend_mode: BlendMode,
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            albedo: [1.0, 1.0, 1.0, 1.0],
+            albedo_texture: None,
+            metallic: 0.0,
+            roughness: 0.5,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            shading: ShadingModel::MetallicRoughness,
+            specular: [0.5, 0.5, 0.5],
+            shininess: 32.0,
+            blend_mode: BlendMode::Opaque,
+        }
+    }
+}
+
+/// Matches `Material` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    albedo: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    /// Zero when there's no normal texture, which skips normal mapping.
+    normal_scale: f32,
+    occlusion_strength: f32,
+    /// `SHADING_*` in mesh.wgsl.
+    shading: u32,
+    specular: [f32; 3],
+    shininess: f32,
+}
+
+shader_struct!(MaterialUniform = "Material" {
+    albedo,
+    emissive,
+    metallic,
+    roughness,
+    normal_scale,
+    occlusion_strength,
+    shading,
+    specular,
+    shininess,
+});
+
+pub struct Material {
+    pub bind_group: wgpu::BindGroup,
+    pub blend_mode: BlendMode,
+    // Kept alive alongside the bind group that references them.
+    _buffer: wgpu::Buffer,
+    _textures: [Texture; 5],
+}
+
+impl Material {
+    /// Checks `MaterialUniform` against the mesh shader.
+    pub fn check_shader(shader: &ShaderReflection) -> Result<(), String> {
+        shader.check_uniform::<MaterialUniform>()
+    }
+
+    /// The layout every material's bind group shares, which is only known
+    /// from the resources bound, so it comes from a default material's.
+    pub fn bind_group_layout(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+    ) -> Rc<wgpu::BindGroupLayout> {
+        Self::create(device, queue, cache, &MaterialData::default()).0
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        data: &MaterialData,
+    ) -> Self {
+        Self::create(device, queue, cache, data).1
+    }
+
+    /// Binding 0 is the uniform, 2 the sampler shared by every texture, and
+    /// the rest are the albedo, metallic-roughness, normal, occlusion and
+    /// emissive textures in that order.
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        data: &MaterialData,
+    ) -> (Rc<wgpu::BindGroupLayout>, Self) {
+        // Missing textures are 1x1 stand-ins that leave the factors as-is.
+        let texture = |img: &Option<TextureData>, default, suffix, srgb| {
+            let label = format!("{} {suffix}", data.name);
+            match img {
+                Some(img) => Texture::from_data(device, queue, img, &label, srgb),
+                None => Texture::solid(device, queue, default, &label, srgb),
+            }
+        };
+        let textures = [
+            texture(&data.albedo_texture, [255; 4], "albedo", true),
+            texture(
+                &data.metallic_roughness_texture,
+                [255; 4],
+                "metallic-roughness",
+                false,
+            ),
+            texture(&data.normal_texture, [128, 128, 255, 255], "normal", false),
+            texture(&data.occlusion_texture, [255; 4], "occlusion", false),
+            texture(&data.emissive_texture, [255; 4], "emissive", true),
+        ];
+        let uniform = MaterialUniform {
+            albedo: data.albedo,
+            emissive: data.emissive,
+            metallic: data.metallic,
+            roughness: data.roughness,
+            normal_scale: if data.normal_texture.is_some() {
+                data.normal_scale
+            } else {
+                0.0
+            },
+            occlusion_strength: data.occlusion_strength,
+            shading: data.shading as u32,
+            specular: data.specular,
+            shininess: data.shininess,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} material buffer", data.name)),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        let label = format!("{} material", data.name);
+        let [albedo, rest @ ..] = &textures;
+        let mut builder = BindGroupBuilder::new(&label)
+            .uniform(fragment, &buffer)
+            .texture(fragment, &albedo.texture, &albedo.view)
+            .sampler(
+                fragment,
+                &albedo.sampler,
+                wgpu::SamplerBindingType::Filtering,
+            );
+        for texture in rest {
+            builder = builder.texture(fragment, &texture.texture, &texture.view);
+        }
+        let (layout, bind_group) = builder.build_cached(device, cache);
+        let material = Self {
+            bind_group,
+            blend_mode: data.blend_mode,
+            _buffer: buffer,
+            _textures: textures,
+        };
+        (layout, material)
+    }
+}
@@ -0,0 +1,299 @@
+struct Camera {
+    view: mat4x4f,
+    proj: mat4x4f,
+    view_proj: mat4x4f,
+    position: vec4f,
+}
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3f,
+    kind: u32,
+    direction: vec3f,
+    range: f32,
+    color: vec3f,
+    intensity: f32,
+    inner_cos: f32,
+    outer_cos: f32,
+    // First layer in `shadow_maps`, or -1 for no shadow.
+    shadow_layer: i32,
+}
+
+struct LightHeader {
+    ambient: vec3f,
+    count: u32,
+}
+
+struct ShadowHeader {
+    // View-space distance at which each cascade ends.
+    cascade_splits: vec4f,
+    cascade_count: u32,
+    pcf_radius: u32,
+    texel_size: f32,
+    normal_bias: f32,
+}
+
+const SHADING_METALLIC_ROUGHNESS: u32 = 0u;
+const SHADING_BLINN_PHONG: u32 = 1u;
+
+struct Material {
+    albedo: vec4f,
+    emissive: vec3f,
+    metallic: f32,
+    roughness: f32,
+    // Zero when the material has no normal texture.
+    normal_scale: f32,
+    occlusion_strength: f32,
+    shading: u32,
+    specular: vec3f,
+    shininess: f32,
+}
+
+struct Environment {
+    intensity: f32,
+    prefiltered_levels: f32,
+}
+
+const PI: f32 = 3.14159265359;
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@group(1) @binding(0) var<uniform> light_header: LightHeader;
+@group(1) @binding(1) var<storage, read> lights: array<Light>;
+@group(1) @binding(2) var<uniform> shadow_header: ShadowHeader;
+@group(1) @binding(3) var<storage, read> shadow_views: array<mat4x4f>;
+@group(1) @binding(4) var shadow_maps: texture_depth_2d_array;
+@group(1) @binding(5) var shadow_sampler: sampler_comparison;
+
+@group(2) @binding(0) var<uniform> material: Material;
+@group(2) @binding(1) var albedo_texture: texture_2d<f32>;
+@group(2) @binding(2) var material_sampler: sampler;
+@group(2) @binding(3) var metallic_roughness_texture: texture_2d<f32>;
+@group(2) @binding(4) var normal_texture: texture_2d<f32>;
+@group(2) @binding(5) var occlusion_texture: texture_2d<f32>;
+@group(2) @binding(6) var emissive_texture: texture_2d<f32>;
+
+@group(3) @binding(0) var<uniform> environment: Environment;
+@group(3) @binding(1) var irradiance_map: texture_cube<f32>;
+@group(3) @binding(2) var prefiltered_map: texture_cube<f32>;
+@group(3) @binding(3) var brdf_lut: texture_2d<f32>;
+@group(3) @binding(4) var environment_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3f,
+    @location(1) color: vec3f,
+    @location(2) normal: vec3f,
+    @location(3) uv: vec2f,
+}
+// Per-object model matrix, one column per location, followed by the
+// inverse-transpose used for normals.
+struct InstanceInput {
+    @location(5) model_0: vec4f,
+    @location(6) model_1: vec4f,
+    @location(7) model_2: vec4f,
+    @location(8) model_3: vec4f,
+    @location(9) normal_0: vec3f,
+    @location(10) normal_1: vec3f,
+    @location(11) normal_2: vec3f,
+}
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec3f,
+    @location(1) world_position: vec3f,
+    @location(2) world_normal: vec3f,
+    @location(3) uv: vec2f,
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = mat4x4f(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let normal_matrix = mat3x3f(instance.normal_0, instance.normal_1, instance.normal_2);
+    let world_position = model * vec4f(in.position, 1.0);
+    var out: VertexOutput;
+    out.position = camera.view_proj * world_position;
+    out.color = in.color;
+    out.world_position = world_position.xyz;
+    out.world_normal = normal_matrix * in.normal;
+    out.uv = in.uv;
+    return out;
+}
+
+// Smooth falloff to zero at `range`, on top of inverse-square attenuation.
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let falloff = saturate(1.0 - pow(distance / range, 4.0));
+    return falloff * falloff / (distance * distance + 1.0);
+}
+
+// Fraction of `light` reaching `position`, filtered over a square PCF kernel.
+fn shadow_factor(light: Light, position: vec3f, normal: vec3f) -> f32 {
+    if light.shadow_layer < 0 {
+        return 1.0;
+    }
+    var layer = u32(light.shadow_layer);
+    // Rough world-space size of one shadow texel, to scale the normal offset.
+    var texel_world: f32;
+    if light.kind == LIGHT_DIRECTIONAL {
+        let depth = -(camera.view * vec4f(position, 1.0)).z;
+        var cascade = 0u;
+        while cascade < shadow_header.cascade_count && depth > shadow_header.cascade_splits[cascade] {
+            cascade++;
+        }
+        if cascade == shadow_header.cascade_count {
+            return 1.0;
+        }
+        layer += cascade;
+        texel_world = 2.0 * shadow_header.cascade_splits[cascade] * shadow_header.texel_size;
+    } else {
+        texel_world = 2.0 * distance(light.position, position) * shadow_header.texel_size;
+    }
+
+    let offset_position = position + normal * shadow_header.normal_bias * texel_world;
+    let clip = shadow_views[layer] * vec4f(offset_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    if clip.w <= 0.0 || ndc.z > 1.0 || any(abs(ndc.xy) > vec2f(1.0)) {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2f(0.5, -0.5) + 0.5;
+
+    let radius = i32(shadow_header.pcf_radius);
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let sample_uv = uv + vec2f(f32(x), f32(y)) * shadow_header.texel_size;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, sample_uv, layer, ndc.z);
+        }
+    }
+    let taps = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / taps;
+}
+
+fn distribution_ggx(n_dot_h: f32, a: f32) -> f32 {
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3f) -> vec3f {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Fresnel for the ambient term, where there's no single half vector.
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3f, roughness: f32) -> vec3f {
+    return f0 + (max(vec3f(1.0 - roughness), f0) - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Applies the normal map with a tangent frame built from screen-space
+// derivatives of position and uv, so meshes don't need tangents.
+fn perturb_normal(
+    n: vec3f,
+    dp1: vec3f,
+    dp2: vec3f,
+    duv1: vec2f,
+    duv2: vec2f,
+    tangent_normal: vec3f,
+) -> vec3f {
+    let dp2_perp = cross(dp2, n);
+    let dp1_perp = cross(n, dp1);
+    let t = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    // glTF's v axis points down the image, the opposite of the normal map's +Y.
+    let b = -(dp2_perp * duv1.y + dp1_perp * duv2.y);
+    let scale = inverseSqrt(max(max(dot(t, t), dot(b, b)), 1e-20));
+    return normalize(mat3x3f(t * scale, b * scale, n) * tangent_normal);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let albedo = material.albedo * textureSample(albedo_texture, material_sampler, in.uv)
+        * vec4f(in.color, 1.0);
+    let metallic_roughness = textureSample(metallic_roughness_texture, material_sampler, in.uv);
+    let metallic = material.metallic * metallic_roughness.b;
+    let roughness = clamp(material.roughness * metallic_roughness.g, 0.045, 1.0);
+    let occlusion = 1.0 + material.occlusion_strength
+        * (textureSample(occlusion_texture, material_sampler, in.uv).r - 1.0);
+    let emissive = material.emissive * textureSample(emissive_texture, material_sampler, in.uv).rgb;
+    let tangent_normal = (textureSample(normal_texture, material_sampler, in.uv).xyz * 2.0 - 1.0)
+        * vec3f(material.normal_scale, material.normal_scale, 1.0);
+
+    // Derivatives are taken here rather than in `perturb_normal`, since the
+    // GLSL backend also emits helper functions into the vertex stage.
+    let dp1 = dpdx(in.world_position);
+    let dp2 = dpdy(in.world_position);
+    let duv1 = dpdx(in.uv);
+    let duv2 = dpdy(in.uv);
+    var n = normalize(in.world_normal);
+    if material.normal_scale != 0.0 {
+        n = perturb_normal(n, dp1, dp2, duv1, duv2, tangent_normal);
+    }
+    let v = normalize(camera.position.xyz - in.world_position);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    // Dielectrics reflect about 4% at normal incidence; metals tint it.
+    let f0 = mix(vec3f(0.04), albedo.rgb, metallic);
+    let a = roughness * roughness;
+
+    var result = vec3f(0.0);
+    for (var i = 0u; i < light_header.count; i++) {
+        let light = lights[i];
+        var l: vec3f;
+        var strength = light.intensity;
+        if light.kind == LIGHT_DIRECTIONAL {
+            l = -light.direction;
+        } else {
+            let to_light = light.position - in.world_position;
+            let distance = length(to_light);
+            l = to_light / distance;
+            strength *= attenuation(distance, light.range);
+            if light.kind == LIGHT_SPOT {
+                strength *= smoothstep(light.outer_cos, light.inner_cos, dot(-l, light.direction));
+            }
+        }
+
+        let n_dot_l = dot(n, l);
+        if n_dot_l <= 0.0 || strength <= 0.0 {
+            continue;
+        }
+        strength *= shadow_factor(light, in.world_position, normalize(in.world_normal));
+
+        let h = normalize(l + v);
+        if material.shading == SHADING_BLINN_PHONG {
+            let specular = pow(max(dot(n, h), 0.0), material.shininess);
+            result += (albedo.rgb * n_dot_l + material.specular * specular) * light.color * strength;
+            continue;
+        }
+        // Cook-Torrance: GGX distribution, Smith geometry, Schlick Fresnel.
+        let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+        let d = distribution_ggx(max(dot(n, h), 0.0), a);
+        let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+        let diffuse = (1.0 - f) * (1.0 - metallic) * albedo.rgb / PI;
+        result += (diffuse + specular) * light.color * strength * n_dot_l;
+    }
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, n, 0.0).rgb;
+    if material.shading == SHADING_BLINN_PHONG {
+        // Diffuse ambient only: Blinn-Phong has no model of rough reflections.
+        let ambient = (irradiance * environment.intensity + light_header.ambient) * albedo.rgb;
+        return vec4f(result + ambient * occlusion + emissive, albedo.a);
+    }
+
+    // Image-based ambient, split-sum approximation for the specular part.
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_d = (1.0 - f) * (1.0 - metallic);
+    let r = reflect(-v, n);
+    let lod = roughness * (environment.prefiltered_levels - 1.0);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, r, lod).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2f(n_dot_v, roughness), 0.0).rg;
+    let ambient = (k_d * irradiance * albedo.rgb + prefiltered * (f * brdf.x + brdf.y))
+        * environment.intensity + light_header.ambient * albedo.rgb;
+    result += ambient * occlusion + emissive;
+    return vec4f(result, albedo.a);
+}
@@ -0,0 +1,616 @@
+use crate::blend::BlendMode;
+use crate::material::{Material, MaterialData, ShadingModel};
+use crate::pipeline_cache::PipelineCache;
+use crate::texture::TextureData;
+use crate::Vertex;
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+/// CPU-side geometry, before it's uploaded into a `Mesh`.
+#[derive(Debug, Default, Clone)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into `ModelData::materials`, or the default material if `None`.
+    pub material: Option<usize>,
+}
+
+impl MeshData {
+    /// Axis-aligned bounds as (min, max), or `None` for an empty mesh.
+    pub fn bounds(&self) -> Option<(Point3<f32>, Point3<f32>)> {
+        let mut vertices = self.vertices.iter().map(|v| Point3::from(v.position));
+        let first = vertices.next()?;
+        Some(vertices.fold((first, first), |(min, max), p| {
+            (
+                Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        }))
+    }
+
+    /// A `size` x `size` square in the xz plane facing +y, centered on the
+    /// origin, with UVs repeating once per unit.
+    pub fn plane(size: f32) -> Self {
+        let h = size / 2.0;
+        let vertices = [[-h, -h], [h, -h], [h, h], [-h, h]]
+            .map(|[x, z]| Vertex {
+                position: [x, 0.0, z],
+                color: [1.0, 1.0, 1.0],
+                normal: [0.0, 1.0, 0.0],
+                uv: [x, z],
+            })
+            .to_vec();
+        Self {
+            name: "plane".to_string(),
+            vertices,
+            indices: vec![0, 2, 1, 0, 3, 2],
+            material: None,
+        }
+    }
+
+    /// A UV sphere centered on the origin with `rings` bands of latitude and
+    /// `segments` of longitude.
+    pub fn sphere(radius: f32, rings: u32, segments: u32) -> Self {
+        let mut vertices = vec![];
+        for i in 0..=rings {
+            let theta = i as f32 / rings as f32 * std::f32::consts::PI;
+            for j in 0..=segments {
+                let phi = j as f32 / segments as f32 * std::f32::consts::TAU;
+                let normal = [
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ];
+                vertices.push(Vertex {
+                    position: normal.map(|n| n * radius),
+                    color: [1.0, 1.0, 1.0],
+                    normal,
+                    uv: [j as f32 / segments as f32, i as f32 / rings as f32],
+                });
+            }
+        }
+        let mut indices = vec![];
+        for i in 0..rings {
+            for j in 0..segments {
+                let a = i * (segments + 1) + j;
+                let b = a + segments + 1;
+                indices.extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+            }
+        }
+        Self {
+            name: "sphere".to_string(),
+            vertices,
+            indices,
+            material: None,
+        }
+    }
+}
+
+/// CPU-side model: meshes plus the materials they reference.
+#[derive(Debug, Default, Clone)]
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+}
+
+impl ModelData {
+    /// Loads an OBJ, glTF or GLB file, picked by extension.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = match path.extension().and_then(|e| e.to_str()) {
+            Some("obj") => load_obj(path)?,
+            Some("gltf" | "glb") => load_gltf(path)?,
+            _ => return Err(format!("{}: unsupported model format", path.display())),
+        };
+        log::info!(
+            "Loaded {} ({} meshes, {} materials, {} triangles)",
+            path.display(),
+            data.meshes.len(),
+            data.materials.len(),
+            data.meshes
+                .iter()
+                .map(|m| m.indices.len() / 3)
+                .sum::<usize>()
+        );
+        Ok(data)
+    }
+
+    /// Switches every material to `shading`, including the default one
+    /// that meshes without a material get.
+    pub fn set_shading(&mut self, shading: ShadingModel) {
+        if self.meshes.iter().any(|mesh| mesh.material.is_none()) {
+            let default = self.materials.len();
+            self.materials.push(MaterialData::default());
+            for mesh in self.meshes.iter_mut().filter(|m| m.material.is_none()) {
+                mesh.material = Some(default);
+            }
+        }
+        for material in &mut self.materials {
+            material.shading = shading;
+        }
+    }
+}
+
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
+    pub num_indices: u32,
+    /// Index into `Model::materials`.
+    pub material: usize,
+    /// Center of the mesh's bounds, which transparent meshes are sorted by.
+    pub center: Point3<f32>,
+}
+
+impl Mesh {
+    /// Uploads `data`, using 16-bit indices whenever every index fits.
+    pub fn new(device: &wgpu::Device, data: &MeshData, material: usize) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} vertex buffer", data.name)),
+            contents: bytemuck::cast_slice(&data.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let (index_format, contents) = if data.vertices.len() <= u16::MAX as usize {
+            let indices: Vec<u16> = data.indices.iter().map(|&i| i as u16).collect();
+            (
+                wgpu::IndexFormat::Uint16,
+                bytemuck::cast_slice(&indices).to_vec(),
+            )
+        } else {
+            (
+                wgpu::IndexFormat::Uint32,
+                bytemuck::cast_slice(&data.indices).to_vec(),
+            )
+        };
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} index buffer", data.name)),
+            contents: &contents,
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_format,
+            num_indices: data.indices.len() as u32,
+            material,
+            center: data
+                .bounds()
+                .map_or(Point3::new(0.0, 0.0, 0.0), |(min, max)| {
+                    min + (max - min) / 2.0
+                }),
+        }
+    }
+}
+
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    /// The file's materials followed by a default one for meshes without.
+    pub materials: Vec<Material>,
+}
+
+impl Model {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        data: &ModelData,
+    ) -> Self {
+        let materials: Vec<Material> = data
+            .materials
+            .iter()
+            .chain(std::iter::once(&MaterialData::default()))
+            .map(|m| Material::new(device, queue, cache, m))
+            .collect();
+        let default_material = materials.len() - 1;
+        Self {
+            meshes: data
+                .meshes
+                .iter()
+                .map(|mesh| {
+                    let material = mesh
+                        .material
+                        .filter(|&i| i < default_material)
+                        .unwrap_or(default_material);
+                    Mesh::new(device, mesh, material)
+                })
+                .collect(),
+            materials,
+        }
+    }
+}
+
+pub fn load_obj(path: &Path) -> Result<ModelData, String> {
+    let (models, materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| format!("{}: {e}", path.display()))?;
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("{}: couldn't load materials: {e}", path.display());
+        vec![]
+    });
+
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let mesh = model.mesh;
+            let vertices = (0..mesh.positions.len() / 3)
+                .map(|i| {
+                    let vec3 = |v: &[f32]| [v[i * 3], v[i * 3 + 1], v[i * 3 + 2]];
+                    Vertex {
+                        position: vec3(&mesh.positions),
+                        color: if mesh.vertex_color.is_empty() {
+                            [1.0, 1.0, 1.0]
+                        } else {
+                            vec3(&mesh.vertex_color)
+                        },
+                        normal: if mesh.normals.is_empty() {
+                            [0.0, 0.0, 0.0]
+                        } else {
+                            vec3(&mesh.normals)
+                        },
+                        // OBJ puts the UV origin at the bottom left.
+                        uv: if mesh.texcoords.is_empty() {
+                            [0.0, 0.0]
+                        } else {
+                            [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+                        },
+                    }
+                })
+                .collect();
+            let mut data = MeshData {
+                name: model.name,
+                vertices,
+                indices: mesh.indices,
+                material: mesh.material_id,
+            };
+            if mesh.normals.is_empty() {
+                compute_normals(&mut data);
+            }
+            data
+        })
+        .collect();
+
+    let materials = materials
+        .into_iter()
+        .map(|m| {
+            let albedo_texture = (!m.diffuse_texture.is_empty())
+                .then(|| {
+                    let file = path.with_file_name(&m.diffuse_texture);
+                    TextureData::open(&file).map_err(|e| log::warn!("{e}")).ok()
+                })
+                .flatten();
+            // OBJ materials are Phong; map the exponent onto roughness with
+            // the usual Beckmann equivalence and treat them as dielectrics.
+            // The Phong terms are kept for switching to `BlinnPhong`.
+            MaterialData {
+                albedo: [m.diffuse[0], m.diffuse[1], m.diffuse[2], m.dissolve],
+                albedo_texture,
+                blend_mode: if m.dissolve < 1.0 {
+                    BlendMode::Alpha
+                } else {
+                    BlendMode::Opaque
+                },
+                roughness: (2.0 / (m.shininess.max(0.0) + 2.0)).sqrt(),
+                specular: m.specular,
+                shininess: m.shininess.max(1.0),
+                name: m.name,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Ok(ModelData { meshes, materials })
+}
+
+/// Fills in smooth vertex normals from the triangle faces.
+fn compute_normals(mesh: &mut MeshData) {
+    let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); mesh.vertices.len()];
+    for tri in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(mesh.vertices[tri[i] as usize].position));
+        // Area weighted, since the cross product isn't normalized.
+        let face = (b - a).cross(c - a);
+        for &i in tri {
+            normals[i as usize] += face;
+        }
+    }
+    for (vertex, normal) in mesh.vertices.iter_mut().zip(normals) {
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+}
+
+/// Reads every buffer referenced by a glTF document: the GLB binary chunk,
+/// base64 `data:` URIs and files next to `path`.
+pub fn load_gltf_buffers(path: &Path, gltf: &gltf::Gltf) -> Result<Vec<Vec<u8>>, String> {
+    gltf.buffers()
+        .map(|buffer| {
+            let mut data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf
+                    .blob
+                    .clone()
+                    .ok_or_else(|| format!("{}: missing GLB binary chunk", path.display()))?,
+                gltf::buffer::Source::Uri(uri) => load_gltf_uri(path, uri)?,
+            };
+            // Buffers may be padded past their declared length.
+            data.truncate(buffer.length());
+            Ok(data)
+        })
+        .collect()
+}
+
+fn load_gltf_uri(path: &Path, uri: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or_else(|| format!("{}: unsupported data URI", path.display()))?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("{}: {e}", path.display()))
+    } else {
+        let file = path.with_file_name(uri);
+        std::fs::read(&file).map_err(|e| format!("{}: {e}", file.display()))
+    }
+}
+
+/// Decodes a glTF image, whether it lives in a buffer view or behind a URI.
+/// KTX2 and DDS images stay compressed.
+pub fn load_gltf_image(
+    path: &Path,
+    image: gltf::Image,
+    buffers: &[Vec<u8>],
+) -> Result<TextureData, String> {
+    let bytes = match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let range = view.offset()..view.offset() + view.length();
+            buffers
+                .get(view.buffer().index())
+                .and_then(|buffer| buffer.get(range))
+                .ok_or_else(|| {
+                    format!(
+                        "{}: image buffer view {} runs past the end of its buffer",
+                        path.display(),
+                        view.index()
+                    )
+                })?
+                .to_vec()
+        }
+        gltf::image::Source::Uri { uri, .. } => load_gltf_uri(path, uri)?,
+    };
+    TextureData::from_bytes(&bytes).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Loads every triangle primitive in the default scene, with node transforms
+/// baked into the vertices.
+pub fn load_gltf(path: &Path) -> Result<ModelData, String> {
+    let gltf = gltf::Gltf::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let buffers = load_gltf_buffers(path, &gltf)?;
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| format!("{}: no scenes", path.display()))?;
+
+    let materials = gltf
+        .materials()
+        .map(|m| {
+            let pbr = m.pbr_metallic_roughness();
+            let image = |texture: gltf::Texture| {
+                load_gltf_image(path, texture.source(), &buffers)
+                    .map_err(|e| log::warn!("{e}"))
+                    .ok()
+            };
+            MaterialData {
+                name: m.name().unwrap_or("gltf material").to_string(),
+                albedo: pbr.base_color_factor(),
+                albedo_texture: pbr.base_color_texture().and_then(|t| image(t.texture())),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                metallic_roughness_texture: pbr
+                    .metallic_roughness_texture()
+                    .and_then(|t| image(t.texture())),
+                normal_texture: m.normal_texture().and_then(|t| image(t.texture())),
+                normal_scale: m.normal_texture().map_or(1.0, |t| t.scale()),
+                occlusion_texture: m.occlusion_texture().and_then(|t| image(t.texture())),
+                occlusion_strength: m.occlusion_texture().map_or(1.0, |t| t.strength()),
+                emissive: m.emissive_factor(),
+                emissive_texture: m.emissive_texture().and_then(|t| image(t.texture())),
+                // Masked materials have no alpha cutoff yet and are drawn
+                // opaque.
+                blend_mode: match m.alpha_mode() {
+                    gltf::material::AlphaMode::Blend => BlendMode::Alpha,
+                    _ => BlendMode::Opaque,
+                },
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let mut meshes = vec![];
+    let mut stack: Vec<_> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent)) = stack.pop() {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!(
+                        "{}: skipping {:?} primitive",
+                        path.display(),
+                        primitive.mode()
+                    );
+                    continue;
+                }
+                meshes.push(
+                    read_gltf_primitive(&primitive, &buffers, transform, mesh.name())
+                        .map_err(|e| format!("{}: {e}", path.display()))?,
+                );
+            }
+        }
+        stack.extend(node.children().map(|child| (child, transform)));
+    }
+    Ok(ModelData { meshes, materials })
+}
+
+/// Bakes `transform` into `primitive`'s vertices. Fails if its attributes
+/// don't all have one element per position or an index is past the last
+/// vertex.
+fn read_gltf_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    transform: Matrix4<f32>,
+    name: Option<&str>,
+) -> Result<MeshData, String> {
+    let name = name.unwrap_or("gltf mesh");
+    let reader = primitive.reader(|buffer| Some(buffers[buffer.index()].as_slice()));
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .map(|p| p.collect())
+        .unwrap_or_default();
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+    let colors: Option<Vec<[f32; 3]>> = reader.read_colors(0).map(|c| c.into_rgb_f32().collect());
+    let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+    for (attribute, count) in [
+        ("normals", normals.as_ref().map(Vec::len)),
+        ("colors", colors.as_ref().map(Vec::len)),
+        ("texture coordinates", uvs.as_ref().map(Vec::len)),
+    ] {
+        if let Some(count) = count.filter(|&count| count != positions.len()) {
+            return Err(format!(
+                "{name} has {count} {attribute} but {} positions",
+                positions.len()
+            ));
+        }
+    }
+
+    // Normals need the inverse transpose so non-uniform scales don't skew them.
+    let normal_matrix = Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    )
+    .invert()
+    .map(|m| m.transpose())
+    .unwrap_or_else(Matrix3::identity);
+
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let position = transform * Vector4::new(p[0], p[1], p[2], 1.0);
+            Vertex {
+                position: position.truncate().into(),
+                color: colors.as_ref().map_or([1.0, 1.0, 1.0], |c| c[i]),
+                normal: normals.as_ref().map_or([0.0, 0.0, 0.0], |n| {
+                    (normal_matrix * Vector3::from(n[i])).normalize().into()
+                }),
+                uv: uvs.as_ref().map_or([0.0, 0.0], |t| t[i]),
+            }
+        })
+        .collect();
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return Err(format!(
+            "{name} has index {index} but only {} vertices",
+            positions.len()
+        ));
+    }
+
+    let mut data = MeshData {
+        name: name.to_string(),
+        vertices,
+        indices,
+        material: primitive.material().index(),
+    };
+    if normals.is_none() {
+        compute_normals(&mut data);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use std::path::PathBuf;
+
+    /// Writes a one-triangle glTF with `normal_count` normals and `indices`
+    /// to a temporary file.
+    fn write_triangle(file_name: &str, normal_count: usize, indices: [u16; 3]) -> PathBuf {
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let normals = vec![[0.0f32, 0.0, 1.0]; normal_count];
+        let mut buffer = bytemuck::cast_slice::<_, u8>(&positions).to_vec();
+        buffer.extend_from_slice(bytemuck::cast_slice(&normals));
+        let indices_offset = buffer.len();
+        buffer.extend_from_slice(bytemuck::cast_slice(&indices));
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "meshes": [{{ "primitives": [{{
+                    "attributes": {{ "POSITION": 0, "NORMAL": 1 }},
+                    "indices": 2
+                }}] }}],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0] }},
+                    {{ "bufferView": 1, "componentType": 5126, "count": {normal_count}, "type": "VEC3" }},
+                    {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": {normals_length} }},
+                    {{ "buffer": 0, "byteOffset": {indices_offset}, "byteLength": 6 }}
+                ],
+                "buffers": [{{
+                    "byteLength": {length},
+                    "uri": "data:application/octet-stream;base64,{data}"
+                }}]
+            }}"#,
+            normals_length = 12 * normal_count,
+            length = buffer.len(),
+            data = base64::engine::general_purpose::STANDARD.encode(&buffer),
+        );
+        let path = std::env::temp_dir().join(file_name);
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_a_valid_primitive() {
+        let path = write_triangle("wgpu-setup-valid.gltf", 3, [0, 1, 2]);
+        let model = load_gltf(&path).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].vertices.len(), 3);
+        assert_eq!(model.meshes[0].indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn rejects_mismatched_attribute_counts() {
+        let path = write_triangle("wgpu-setup-normals.gltf", 2, [0, 1, 2]);
+        let error = load_gltf(&path).err().unwrap();
+        assert!(error.contains("has 2 normals but 3 positions"), "{error}");
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let path = write_triangle("wgpu-setup-indices.gltf", 3, [0, 1, 3]);
+        let error = load_gltf(&path).err().unwrap();
+        assert!(error.contains("has index 3 but only 3 vertices"), "{error}");
+    }
+}
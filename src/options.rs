@@ -0,0 +1,327 @@
+use crate::clock::{Clock, FixedClock, RealClock};
+use crate::export::ExportSettings;
+use crate::input;
+use crate::material::ShadingModel;
+use crate::resilience::Fault;
+use crate::shadow::ShadowSettings;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Command line options, parsed by hand from `std::env::args`.
+///
+/// Usage: `wgpu-setup [--msaa <1|2|4|8>] [--shadow-resolution N] [--shadow-cascades <1-4>]
+/// [--shadow-bias SLOPE] [--shading <metallic-roughness|blinn-phong>]
+/// [--env environment.hdr] [--skybox faces/]
+/// [--alpha-mode <opaque|premultiplied|postmultiplied|inherit>]
+/// [--present-mode <fifo|fifo-relaxed|mailbox|immediate>] [--frame-latency <1-3>]
+/// [--inject-fault <device-lost|out-of-memory|surface-lost|surface-outdated|timeout>@FRAME]
+/// [--image picture.png] [--exposure SCALE] [--tiled] [--image-window]
+/// [--input-config bindings.toml]
+/// [--fixed-step SECONDS] [--start-time SECONDS]
+/// [--record input.ron] [--replay input.ron [--replay-out frames/]]
+/// [--export <frames/|anim.gif|anim.png> [--export-size WxH] [--fps N] [--duration SECONDS]
+/// [--supersample <1-4>] [--turntable DEGREES_PER_SECOND]] [--golden [--bless]]
+/// [model.obj|model.gltf|model.glb ...]`
+///
+/// Passing any model switches from the 2D image view to the 3D mesh view.
+/// `--golden` runs the headless golden-image tests instead of opening a
+/// window, and `--bless` rewrites their reference images. `--export` renders
+/// an animation offscreen instead, see `ExportSettings`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Requested MSAA sample count. Falls back to the highest supported count
+    /// below it when the adapter can't do it.
+    pub msaa: u32,
+    pub shadow: ShadowSettings,
+    /// Overrides the shading model of every loaded material.
+    pub shading: Option<ShadingModel>,
+    /// Equirectangular HDR image lighting the 3D view.
+    pub environment: Option<PathBuf>,
+    /// Directory of `px`/`nx`/`py`/`ny`/`pz`/`nz` images drawn behind the
+    /// 3D view. The environment is shown when there is none.
+    pub skybox: Option<PathBuf>,
+    /// How the window is composited with what's behind it. Anything but
+    /// `Auto` and `Opaque` creates a transparent window. Falls back to the
+    /// surface's first mode when unsupported.
+    pub alpha_mode: wgpu::CompositeAlphaMode,
+    /// Falls back to the closest supported mode, see `pick_present_mode`.
+    pub present_mode: wgpu::PresentMode,
+    /// Frames the CPU may queue up before waiting for the GPU.
+    pub frame_latency: u32,
+    /// Pretends the fault happened on the given frame, to exercise recovery.
+    /// The `inject_device_loss` action (F9) injects a lost device at any
+    /// time.
+    pub inject_fault: Option<(Fault, usize)>,
+    /// Shown by the 2D view. PNG, JPEG, BMP, TGA, WebP, EXR and Radiance HDR
+    /// images are told apart by their contents.
+    pub image: PathBuf,
+    /// Scales HDR images in the 2D view before they're tonemapped.
+    pub exposure: f32,
+    /// Shows the 2D view's image as streamed tiles that can be panned and
+    /// zoomed, even if it fits in one texture. Larger images always are.
+    pub tiled: bool,
+    /// Opens a second window with the 2D image view, sharing the device
+    /// with the first.
+    pub image_window: bool,
+    /// Key, mouse and gamepad bindings, see `assets/input.toml`.
+    pub input_config: PathBuf,
+    /// Advances animation time by exactly this much every frame instead of
+    /// following the real clock.
+    pub fixed_step: Option<Duration>,
+    /// Animation time of the first frame.
+    pub start_time: Duration,
+    /// Writes the main window's input to this file when it closes.
+    pub record: Option<PathBuf>,
+    /// Plays input recorded with `record` back into the main window, with
+    /// the recorded frame times instead of the real clock, and closes it at
+    /// the end. Live input is ignored meanwhile.
+    pub replay: Option<PathBuf>,
+    /// Renders the replay offscreen into numbered PNGs in this directory
+    /// instead of opening a window.
+    pub replay_out: Option<PathBuf>,
+    pub export: ExportSettings,
+    pub models: Vec<PathBuf>,
+    pub golden: bool,
+    pub bless: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            msaa: 4,
+            shadow: ShadowSettings::default(),
+            shading: None,
+            environment: None,
+            skybox: None,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            present_mode: wgpu::PresentMode::Fifo,
+            frame_latency: 2,
+            inject_fault: None,
+            image: "assets/sshot.png".into(),
+            exposure: 1.0,
+            tiled: false,
+            image_window: false,
+            input_config: input::DEFAULT_CONFIG.into(),
+            fixed_step: None,
+            start_time: Duration::ZERO,
+            record: None,
+            replay: None,
+            replay_out: None,
+            export: ExportSettings::default(),
+            models: vec![],
+            golden: false,
+            bless: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        let mut opts = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--msaa" => {
+                    opts.msaa = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("--msaa takes a sample count (1, 2, 4 or 8)");
+                }
+                "--shadow-resolution" => {
+                    opts.shadow.resolution = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("--shadow-resolution takes a size in texels");
+                }
+                "--shadow-cascades" => {
+                    opts.shadow.cascades = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("--shadow-cascades takes a count (1 to 4)");
+                }
+                "--shadow-bias" => {
+                    opts.shadow.slope_bias = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("--shadow-bias takes a slope-scaled depth bias");
+                }
+                "--shading" => {
+                    opts.shading = Some(match args.next().as_deref() {
+                        Some("metallic-roughness") => ShadingModel::MetallicRoughness,
+                        Some("blinn-phong") => ShadingModel::BlinnPhong,
+                        _ => panic!("--shading takes metallic-roughness or blinn-phong"),
+                    });
+                }
+                "--env" => {
+                    opts.environment = Some(
+                        args.next()
+                            .expect("--env takes an equirectangular HDR image")
+                            .into(),
+                    );
+                }
+                "--skybox" => {
+                    opts.skybox = Some(
+                        args.next()
+                            .expect("--skybox takes a directory of six cube faces")
+                            .into(),
+                    );
+                }
+                "--alpha-mode" => {
+                    opts.alpha_mode = match args.next().as_deref() {
+                        Some("opaque") => wgpu::CompositeAlphaMode::Opaque,
+                        Some("premultiplied") => wgpu::CompositeAlphaMode::PreMultiplied,
+                        Some("postmultiplied") => wgpu::CompositeAlphaMode::PostMultiplied,
+                        Some("inherit") => wgpu::CompositeAlphaMode::Inherit,
+                        _ => panic!(
+                            "--alpha-mode takes opaque, premultiplied, postmultiplied or inherit"
+                        ),
+                    };
+                }
+                "--present-mode" => {
+                    opts.present_mode = match args.next().as_deref() {
+                        Some("fifo") => wgpu::PresentMode::Fifo,
+                        Some("fifo-relaxed") => wgpu::PresentMode::FifoRelaxed,
+                        Some("mailbox") => wgpu::PresentMode::Mailbox,
+                        Some("immediate") => wgpu::PresentMode::Immediate,
+                        _ => {
+                            panic!("--present-mode takes fifo, fifo-relaxed, mailbox or immediate")
+                        }
+                    };
+                }
+                "--frame-latency" => {
+                    opts.frame_latency = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|latency| (1..=3).contains(latency))
+                        .expect("--frame-latency takes a frame count (1 to 3)");
+                }
+                "--inject-fault" => {
+                    opts.inject_fault = Some(
+                        args.next()
+                            .and_then(|v| {
+                                let (fault, frame) = v.split_once('@')?;
+                                Some((fault.parse().ok()?, frame.parse().ok()?))
+                            })
+                            .expect(
+                                "--inject-fault takes a fault and a frame, like device-lost@100",
+                            ),
+                    );
+                }
+                "--image" => {
+                    opts.image = args.next().expect("--image takes an image file").into();
+                }
+                "--exposure" => {
+                    opts.exposure = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|&exposure: &f32| exposure > 0.0)
+                        .expect("--exposure takes a positive scale");
+                }
+                "--tiled" => opts.tiled = true,
+                "--image-window" => opts.image_window = true,
+                "--input-config" => {
+                    opts.input_config = args
+                        .next()
+                        .expect("--input-config takes a TOML file of bindings")
+                        .into();
+                }
+                "--fixed-step" => {
+                    opts.fixed_step = Some(
+                        args.next()
+                            .and_then(|v| Duration::try_from_secs_f64(v.parse().ok()?).ok())
+                            .filter(|step| !step.is_zero())
+                            .expect("--fixed-step takes a frame time in seconds"),
+                    );
+                }
+                "--start-time" => {
+                    opts.start_time = args
+                        .next()
+                        .and_then(|v| Duration::try_from_secs_f64(v.parse().ok()?).ok())
+                        .expect("--start-time takes a time in seconds");
+                }
+                "--record" => {
+                    opts.record = Some(args.next().expect("--record takes a file to write").into());
+                }
+                "--replay" => {
+                    opts.replay = Some(
+                        args.next()
+                            .expect("--replay takes a file written with --record")
+                            .into(),
+                    );
+                }
+                "--replay-out" => {
+                    opts.replay_out = Some(
+                        args.next()
+                            .expect("--replay-out takes a directory for the frames")
+                            .into(),
+                    );
+                }
+                "--export" => {
+                    opts.export.path = Some(
+                        args.next()
+                            .expect("--export takes a directory, a .gif or a .png file")
+                            .into(),
+                    );
+                }
+                "--export-size" => {
+                    (opts.export.width, opts.export.height) = args
+                        .next()
+                        .and_then(|v| {
+                            let (width, height) = v.split_once('x')?;
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        })
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .expect("--export-size takes a size like 640x480");
+                }
+                "--fps" => {
+                    opts.export.fps = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|&fps| fps > 0)
+                        .expect("--fps takes a frame rate");
+                }
+                "--duration" => {
+                    opts.export.duration = args
+                        .next()
+                        .and_then(|v| Duration::try_from_secs_f64(v.parse().ok()?).ok())
+                        .expect("--duration takes a length in seconds");
+                }
+                "--supersample" => {
+                    opts.export.supersample = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .filter(|factor| (1..=4).contains(factor))
+                        .expect("--supersample takes a factor (1 to 4)");
+                }
+                "--turntable" => {
+                    opts.export.turntable = args
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .expect("--turntable takes a speed in degrees per second");
+                }
+                "--golden" => opts.golden = true,
+                "--bless" => opts.bless = true,
+                _ if arg.starts_with("--") => log::warn!("Ignoring unknown argument {arg:?}"),
+                _ => opts.models.push(arg.into()),
+            }
+        }
+        opts
+    }
+
+    /// The clock animation time follows, from `fixed_step` and
+    /// `start_time`.
+    pub fn clock(&self) -> Box<dyn Clock> {
+        match self.fixed_step {
+            Some(step) => Box::new(FixedClock::new(self.start_time, step)),
+            None => Box::new(RealClock::new(self.start_time)),
+        }
+    }
+
+    /// Whether the window has to be created transparent for `alpha_mode`.
+    pub fn transparent_window(&self) -> bool {
+        !matches!(
+            self.alpha_mode,
+            wgpu::CompositeAlphaMode::Auto | wgpu::CompositeAlphaMode::Opaque
+        )
+    }
+}
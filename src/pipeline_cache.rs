@@ -0,0 +1,182 @@
+//! Reuses pipelines, shader modules and bind group layouts that were already
+//! created with an identical description. Switching the MSAA sample count or
+//! polygon mode back and forth only compiles each variant once.
+//!
+//! Everything is cached in memory for the lifetime of the cache. wgpu 0.16
+//! doesn't expose the compiled pipeline to the application on any backend,
+//! so there is no on-disk cache; the drivers' own shader caches (Mesa's,
+//! for one) are what keeps a restart cheap.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Lookups of one kind of object. Displayed as hits out of lookups.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HitCount {
+    pub hits: u32,
+    pub misses: u32,
+}
+
+impl HitCount {
+    fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub render_pipelines: HitCount,
+    pub shader_modules: HitCount,
+    pub bind_group_layouts: HitCount,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = [
+            ("pipelines", self.render_pipelines),
+            ("shaders", self.shader_modules),
+            ("layouts", self.bind_group_layouts),
+        ];
+        for (i, (name, count)) in counts.into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(
+                f,
+                "{separator}{name} {}/{}",
+                count.hits,
+                count.hits + count.misses
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Which shader a pipeline was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ShaderKey {
+    /// The index of its source in `PipelineCache::shader_modules`.
+    Source(usize),
+    /// A module compiled outside the cache.
+    Module(wgpu::Id<wgpu::ShaderModule>),
+}
+
+/// Everything a cached render pipeline was built from. Compared in full on
+/// a lookup, so two descriptions can't share a pipeline by hashing alike.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    pub shader: ShaderKey,
+    pub layout: wgpu::Id<wgpu::PipelineLayout>,
+    pub vertex_entry_point: String,
+    /// Stride, step mode and attributes of each vertex buffer.
+    pub vertex_buffers: Vec<(
+        wgpu::BufferAddress,
+        wgpu::VertexStepMode,
+        Vec<wgpu::VertexAttribute>,
+    )>,
+    pub color_format: Option<wgpu::TextureFormat>,
+    pub blend: Option<wgpu::BlendState>,
+    pub primitive: wgpu::PrimitiveState,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub sample_count: u32,
+}
+
+#[derive(Default)]
+pub struct PipelineCache {
+    render_pipelines: HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>,
+    /// By their source, along with the index pipelines using them are keyed
+    /// by.
+    shader_modules: HashMap<String, (usize, Rc<wgpu::ShaderModule>)>,
+    /// The source index of every module in `shader_modules`, so pipelines
+    /// using them are keyed by source rather than by module.
+    shader_sources: HashMap<wgpu::Id<wgpu::ShaderModule>, usize>,
+    bind_group_layouts: HashMap<Vec<wgpu::BindGroupLayoutEntry>, Rc<wgpu::BindGroupLayout>>,
+    stats: CacheStats,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Compiles the WGSL in `source`, unless a module with the same source
+    /// was compiled before.
+    pub fn shader_module(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+    ) -> Rc<wgpu::ShaderModule> {
+        self.stats
+            .shader_modules
+            .record(self.shader_modules.contains_key(source));
+        let index = self.shader_modules.len();
+        let (index, module) = self
+            .shader_modules
+            .entry(source.to_string())
+            .or_insert_with(|| {
+                let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(label),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                (index, Rc::new(module))
+            });
+        self.shader_sources.insert(module.global_id(), *index);
+        module.clone()
+    }
+
+    pub fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Rc<wgpu::BindGroupLayout> {
+        self.stats
+            .bind_group_layouts
+            .record(self.bind_group_layouts.contains_key(entries));
+        self.bind_group_layouts
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                Rc::new(
+                    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        label: Some(label),
+                        entries,
+                    }),
+                )
+            })
+            .clone()
+    }
+
+    /// What a pipeline using `shader` is keyed by: its source if it came
+    /// from `shader_module`, otherwise the module itself.
+    pub(crate) fn shader_key(&self, shader: &wgpu::ShaderModule) -> ShaderKey {
+        match self.shader_sources.get(&shader.global_id()) {
+            Some(&index) => ShaderKey::Source(index),
+            None => ShaderKey::Module(shader.global_id()),
+        }
+    }
+
+    /// The pipeline cached under `key`, created with `create` on a miss.
+    pub(crate) fn render_pipeline(
+        &mut self,
+        key: PipelineKey,
+        create: impl FnOnce() -> wgpu::RenderPipeline,
+    ) -> Rc<wgpu::RenderPipeline> {
+        let hit = self.render_pipelines.contains_key(&key);
+        self.stats.render_pipelines.record(hit);
+        if !hit {
+            log::debug!("Pipeline cache miss ({})", self.stats);
+        }
+        self.render_pipelines
+            .entry(key)
+            .or_insert_with(|| Rc::new(create()))
+            .clone()
+    }
+}
@@ -0,0 +1,283 @@
+//! A small C-style preprocessor for the WGSL shaders, which has no way to
+//! share code between files or compile permutations of one.
+//!
+//! Supported directives, each on a line of its own:
+//!
+//! - `#include "name.wgsl"` pastes in another shader from `SHADERS`. A file
+//!   is only included once however often it's asked for.
+//! - `#define FLAG` and `#undef FLAG` set and clear a flag.
+//! - `#ifdef FLAG`, `#ifndef FLAG`, `#else` and `#endif` keep or drop the
+//!   lines between them.
+//!
+//! The output keeps a map back to the file and line every line came from, so
+//! naga's errors point at the original source.
+//!
+//! Only the shaders in `SHADERS` go through it. The rest are handed to wgpu
+//! as they are, so directives in them are syntax errors.
+
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Every shader that goes through the preprocessor, by the name `#include`
+/// refers to it with.
+const SHADERS: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("common.wgsl")),
+    ("shader.wgsl", include_str!("shader.wgsl")),
+    ("tonemap.wgsl", include_str!("tonemap.wgsl")),
+];
+
+/// Preprocessed WGSL and where each of its lines came from.
+pub struct ProcessedShader {
+    pub name: String,
+    pub source: String,
+    /// File name and 1-based line number of every line in `source`.
+    lines: Vec<(&'static str, u32)>,
+}
+
+impl ProcessedShader {
+    /// `file:line:column` in the original source for a byte offset into
+    /// `source`.
+    fn origin(&self, location: naga::SourceLocation) -> String {
+        match self.lines.get(location.line_number as usize - 1) {
+            Some((file, line)) => format!("{file}:{line}:{}", location.line_position),
+            None => format!("{}:?", self.name),
+        }
+    }
+
+    /// Parses and validates the shader, reporting errors at their original
+    /// location.
+    pub fn parse(&self) -> Result<naga::Module, String> {
+        let module = naga::front::wgsl::parse_str(&self.source).map_err(|e| {
+            let mut message = match e.location(&self.source) {
+                Some(location) => format!("{}: {}", self.origin(location), e.message()),
+                None => format!("{}: {}", self.name, e.message()),
+            };
+            for (span, label) in e.labels().skip(1) {
+                let location = span.location(&self.source);
+                message += &format!("\n  {}: {label}", self.origin(location));
+            }
+            message
+        })?;
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .map_err(|e| {
+            // The innermost span is the most precise one.
+            let mut message = match e.spans().last() {
+                Some((span, _)) => format!("{}: {e}", self.origin(span.location(&self.source))),
+                None => format!("{}: {e}", self.name),
+            };
+            let mut source = e.source();
+            while let Some(cause) = source {
+                message += &format!(": {cause}");
+                source = cause.source();
+            }
+            message
+        })?;
+        Ok(module)
+    }
+}
+
+/// Runs the shader `name` through the preprocessor with `defines` set.
+pub fn preprocess(name: &str, defines: &[&str]) -> Result<ProcessedShader, String> {
+    preprocess_files(SHADERS, name, defines)
+}
+
+/// `preprocess`, with `#include` looking in `shaders` instead of `SHADERS`.
+fn preprocess_files(
+    shaders: &'static [(&'static str, &'static str)],
+    name: &str,
+    defines: &[&str],
+) -> Result<ProcessedShader, String> {
+    let mut state = State {
+        shaders,
+        defines: defines.iter().map(|d| d.to_string()).collect(),
+        included: HashSet::new(),
+        output: ProcessedShader {
+            name: name.to_string(),
+            source: String::new(),
+            lines: vec![],
+        },
+    };
+    state.include(name, None)?;
+    Ok(state.output)
+}
+
+struct State {
+    shaders: &'static [(&'static str, &'static str)],
+    defines: HashSet<String>,
+    included: HashSet<&'static str>,
+    output: ProcessedShader,
+}
+
+/// One `#ifdef` or `#ifndef` being processed.
+struct Conditional {
+    /// Whether the lines in the current branch are kept, before taking
+    /// enclosing conditionals into account.
+    active: bool,
+    seen_else: bool,
+    /// Where the conditional started, for unterminated ones.
+    line: u32,
+}
+
+impl State {
+    /// Appends the shader `name`. `from` is the location of the `#include`
+    /// asking for it.
+    fn include(&mut self, name: &str, from: Option<(&str, u32)>) -> Result<(), String> {
+        let Some(&(file, source)) = self.shaders.iter().find(|(file, _)| *file == name) else {
+            return Err(match from {
+                Some((file, line)) => format!("{file}:{line}: no shader named \"{name}\""),
+                None => format!("no shader named \"{name}\""),
+            });
+        };
+        if !self.included.insert(file) {
+            return Ok(());
+        }
+        let mut conditionals: Vec<Conditional> = vec![];
+        for (index, text) in source.lines().enumerate() {
+            let line = index as u32 + 1;
+            let error = |message: String| format!("{file}:{line}: {message}");
+            let active = conditionals.iter().all(|c| c.active);
+            let Some(directive) = text.trim_start().strip_prefix('#') else {
+                if active {
+                    self.output.source += text;
+                    self.output.source.push('\n');
+                    self.output.lines.push((file, line));
+                }
+                continue;
+            };
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(k, a)| (k, a.trim()));
+            let flag = || {
+                if argument.is_empty() || argument.contains(char::is_whitespace) {
+                    Err(error(format!("#{keyword} takes a single flag name")))
+                } else {
+                    Ok(argument)
+                }
+            };
+            match keyword {
+                "include" => {
+                    let included = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| error("#include takes a quoted file name".to_string()))?;
+                    if active {
+                        self.include(included, Some((file, line)))?;
+                    }
+                }
+                "define" => {
+                    let flag = flag()?;
+                    if active {
+                        self.defines.insert(flag.to_string());
+                    }
+                }
+                "undef" => {
+                    let flag = flag()?;
+                    if active {
+                        self.defines.remove(flag);
+                    }
+                }
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains(flag()?);
+                    conditionals.push(Conditional {
+                        active: defined == (keyword == "ifdef"),
+                        seen_else: false,
+                        line,
+                    });
+                }
+                "else" => match conditionals.last_mut() {
+                    Some(c) if !c.seen_else => {
+                        c.active = !c.active;
+                        c.seen_else = true;
+                    }
+                    Some(_) => return Err(error("#else after #else".to_string())),
+                    None => return Err(error("#else without #ifdef".to_string())),
+                },
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                _ => return Err(error(format!("unknown directive #{keyword}"))),
+            }
+        }
+        match conditionals.last() {
+            Some(c) => Err(format!("{file}:{}: #ifdef without #endif", c.line)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The error from preprocessing the first of `shaders`.
+    fn error(shaders: &'static [(&'static str, &'static str)]) -> String {
+        match preprocess_files(shaders, shaders[0].0, &[]) {
+            Ok(shader) => panic!("expected an error, got {:?}", shader.source),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn second_else_is_rejected() {
+        let shaders = &[("test.wgsl", "#ifdef A\na\n#else\nb\n#else\nc\n#endif\n")];
+        assert_eq!(error(shaders), "test.wgsl:5: #else after #else");
+    }
+
+    #[test]
+    fn unterminated_ifdef_is_rejected() {
+        let shaders = &[("test.wgsl", "a\n#ifndef A\nb\n")];
+        assert_eq!(error(shaders), "test.wgsl:2: #ifdef without #endif");
+    }
+
+    #[test]
+    fn stray_endif_is_rejected() {
+        let shaders = &[("test.wgsl", "a\n#endif\n")];
+        assert_eq!(error(shaders), "test.wgsl:2: #endif without #ifdef");
+    }
+
+    #[test]
+    fn unknown_include_is_rejected() {
+        let shaders = &[("test.wgsl", "#include \"missing.wgsl\"\n")];
+        assert_eq!(
+            error(shaders),
+            "test.wgsl:1: no shader named \"missing.wgsl\""
+        );
+    }
+
+    #[test]
+    fn recursive_include_is_only_pasted_once() {
+        let shaders = &[
+            ("a.wgsl", "#include \"b.wgsl\"\na\n"),
+            ("b.wgsl", "#include \"a.wgsl\"\nb\n"),
+        ];
+        let shader = preprocess_files(shaders, "a.wgsl", &[]).unwrap();
+        assert_eq!(shader.source, "b\na\n");
+    }
+
+    #[test]
+    fn lines_map_back_to_their_file() {
+        let shaders = &[
+            ("main.wgsl", "#define A\n#include \"lib.wgsl\"\nmain\n"),
+            ("lib.wgsl", "#ifdef A\nlib_a\n#else\nlib_b\n#endif\nlib\n"),
+        ];
+        let shader = preprocess_files(shaders, "main.wgsl", &[]).unwrap();
+        assert_eq!(shader.source, "lib_a\nlib\nmain\n");
+        let origin = |line_number| {
+            shader.origin(naga::SourceLocation {
+                line_number,
+                line_position: 3,
+                offset: 0,
+                length: 1,
+            })
+        };
+        assert_eq!(origin(1), "lib.wgsl:2:3");
+        assert_eq!(origin(2), "lib.wgsl:6:3");
+        assert_eq!(origin(3), "main.wgsl:3:3");
+    }
+}
@@ -0,0 +1,646 @@
+//! Checks the Rust side of a shader interface against the WGSL it's written
+//! for. Uniform structs and bind group layouts are duplicated by hand on both
+//! sides; parsing the shader with naga at startup turns silent drift into an
+//! error naming the field or binding that disagrees.
+
+use naga::proc::Layouter;
+use std::collections::HashSet;
+
+/// Where one field of a Rust struct lives, as reported by `offset_of!`.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+    /// What the field's scalars are, whether it's one or an array of them.
+    pub kind: naga::ScalarKind,
+}
+
+/// A field type that can line up with a WGSL member: a scalar, or an array
+/// of them standing in for a vector, matrix or array.
+pub trait ShaderField {
+    const KIND: naga::ScalarKind;
+}
+
+impl ShaderField for f32 {
+    const KIND: naga::ScalarKind = naga::ScalarKind::Float;
+}
+
+impl ShaderField for u32 {
+    const KIND: naga::ScalarKind = naga::ScalarKind::Uint;
+}
+
+impl ShaderField for i32 {
+    const KIND: naga::ScalarKind = naga::ScalarKind::Sint;
+}
+
+impl<T: ShaderField, const N: usize> ShaderField for [T; N] {
+    const KIND: naga::ScalarKind = T::KIND;
+}
+
+/// A `#[repr(C)]` type with a counterpart struct in WGSL. Implement it with
+/// `shader_struct!`.
+pub trait ShaderStruct: bytemuck::Pod {
+    /// Name of the matching struct in the shader.
+    const WGSL_NAME: &'static str;
+
+    /// Every field that has a member in the WGSL struct, padding excluded.
+    fn fields() -> Vec<FieldLayout>;
+}
+
+/// Implements `ShaderStruct`, listing the fields that must line up with the
+/// WGSL struct of the same member names.
+macro_rules! shader_struct {
+    ($ty:ty = $wgsl:literal { $($field:ident),* $(,)? }) => {
+        impl $crate::reflect::ShaderStruct for $ty {
+            const WGSL_NAME: &'static str = $wgsl;
+
+            fn fields() -> Vec<$crate::reflect::FieldLayout> {
+                vec![$($crate::reflect::FieldLayout {
+                    name: stringify!($field),
+                    offset: std::mem::offset_of!($ty, $field) as u32,
+                    size: $crate::reflect::field_size(|s: &$ty| &s.$field),
+                    kind: $crate::reflect::field_kind(|s: &$ty| &s.$field),
+                }),*]
+            }
+        }
+    };
+}
+pub(crate) use shader_struct;
+
+/// Size of the field `get` projects to, without needing a value of `T`.
+pub fn field_size<T, F>(_get: fn(&T) -> &F) -> u32 {
+    std::mem::size_of::<F>() as u32
+}
+
+/// Scalar kind of the field `get` projects to.
+pub fn field_kind<T, F: ShaderField>(_get: fn(&T) -> &F) -> naga::ScalarKind {
+    F::KIND
+}
+
+/// A parsed WGSL module with the layout of every type it declares.
+pub struct ShaderReflection {
+    module: naga::Module,
+    layouter: Layouter,
+}
+
+impl ShaderReflection {
+    /// `name` is only used in error messages.
+    pub fn new(name: &str, module: naga::Module) -> Result<Self, String> {
+        let mut layouter = Layouter::default();
+        layouter
+            .update(&module.types, &module.constants)
+            .map_err(|e| format!("{name}: {e}"))?;
+        Ok(Self { module, layouter })
+    }
+
+    /// Parses `source` as it is, for shaders that skip the preprocessor.
+    pub fn from_wgsl(name: &str, source: &str) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|e| format!("{name}: {}", e.emit_to_string(source)))?;
+        Self::new(name, module)
+    }
+
+    /// Checks that `T` has the size of its WGSL struct and that every field
+    /// sits at the offset, and has the size and scalar kind, of the member
+    /// with its name.
+    pub fn check_struct<T: ShaderStruct>(&self) -> Result<(), String> {
+        self.check_layout::<T>(false)
+    }
+
+    /// `check_struct` for the type of a uniform buffer, which may also have
+    /// trailing padding up to a multiple of 16 bytes: std140 backends round
+    /// uniform blocks up to that, and the buffer has to cover it.
+    pub fn check_uniform<T: ShaderStruct>(&self) -> Result<(), String> {
+        self.check_layout::<T>(true)
+    }
+
+    fn check_layout<T: ShaderStruct>(&self, uniform: bool) -> Result<(), String> {
+        let rust_name = std::any::type_name::<T>().rsplit("::").next().unwrap();
+        let wgsl_name = T::WGSL_NAME;
+        let (handle, members) = self
+            .module
+            .types
+            .iter()
+            .find_map(|(handle, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, .. }
+                    if ty.name.as_deref() == Some(wgsl_name) =>
+                {
+                    Some((handle, members))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| format!("no struct `{wgsl_name}` in the shader for `{rust_name}`"))?;
+
+        let fields = T::fields();
+        for member in members {
+            let name = member.name.as_deref().unwrap_or("");
+            let size = self.layouter[member.ty].size;
+            let Some(field) = fields.iter().find(|f| f.name == name) else {
+                return Err(format!(
+                    "`{wgsl_name}.{name}` (offset {}, {size} bytes) has no field in `{rust_name}`",
+                    member.offset
+                ));
+            };
+            if field.offset != member.offset {
+                return Err(format!(
+                    "`{rust_name}.{name}` is at offset {} but `{wgsl_name}.{name}` is at {}",
+                    field.offset, member.offset
+                ));
+            }
+            if field.size != size {
+                return Err(format!(
+                    "`{rust_name}.{name}` is {} bytes but `{wgsl_name}.{name}` is {size}",
+                    field.size
+                ));
+            }
+            let kind = self.scalar_kind(member.ty);
+            if kind != Some(field.kind) {
+                return Err(format!(
+                    "`{rust_name}.{name}` holds {:?} but `{wgsl_name}.{name}` holds {}",
+                    field.kind,
+                    kind.map_or("no scalars".to_string(), |kind| format!("{kind:?}"))
+                ));
+            }
+        }
+        if let Some(field) = fields
+            .iter()
+            .find(|f| !members.iter().any(|m| m.name.as_deref() == Some(f.name)))
+        {
+            return Err(format!(
+                "`{rust_name}.{}` has no member in `{wgsl_name}`",
+                field.name
+            ));
+        }
+        let size = std::mem::size_of::<T>() as u32;
+        let wgsl_size = self.layouter[handle].size;
+        let padded = if uniform {
+            wgsl_size.next_multiple_of(16)
+        } else {
+            wgsl_size
+        };
+        if !(wgsl_size..=padded).contains(&size) {
+            return Err(format!(
+                "`{rust_name}` is {size} bytes but `{wgsl_name}` is {wgsl_size}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// What `ty`'s scalars are, if it's made of a single kind of them.
+    fn scalar_kind(&self, ty: naga::Handle<naga::Type>) -> Option<naga::ScalarKind> {
+        match self.module.types[ty].inner {
+            naga::TypeInner::Scalar { kind, .. }
+            | naga::TypeInner::Vector { kind, .. }
+            | naga::TypeInner::Atomic { kind, .. } => Some(kind),
+            naga::TypeInner::Matrix { .. } => Some(naga::ScalarKind::Float),
+            naga::TypeInner::Array { base, .. } => self.scalar_kind(base),
+            _ => None,
+        }
+    }
+
+    /// Checks a hand-written bind group layout against the `@group(group)`
+    /// bindings the shader declares: every binding needs an entry of the
+    /// matching kind, visible to every stage that uses it.
+    pub fn check_bind_group_layout(
+        &self,
+        group: u32,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Result<(), String> {
+        for (handle, var) in self.module.global_variables.iter() {
+            let Some(binding) = var.binding.as_ref().filter(|b| b.group == group) else {
+                continue;
+            };
+            let var_name = var.name.as_deref().unwrap_or("?");
+            let what = format!(
+                "`{var_name}` (@group({group}) @binding({}))",
+                binding.binding
+            );
+            let Some(entry) = entries.iter().find(|e| e.binding == binding.binding) else {
+                return Err(format!("{what} has no bind group layout entry"));
+            };
+            let stages = self.stages_using(handle);
+            if !entry.visibility.contains(stages) {
+                return Err(format!(
+                    "{what} is used in {stages:?} but only visible to {:?}",
+                    entry.visibility
+                ));
+            }
+            self.check_binding_type(var, entry.ty)
+                .map_err(|e| format!("{what}: {e}"))?;
+        }
+        if let Some(entry) = entries.iter().find(|e| {
+            !self.module.global_variables.iter().any(|(_, v)| {
+                v.binding.as_ref()
+                    == Some(&naga::ResourceBinding {
+                        group,
+                        binding: e.binding,
+                    })
+            })
+        }) {
+            return Err(format!(
+                "bind group layout entry {} has no matching @group({group}) binding in the shader",
+                entry.binding
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_binding_type(
+        &self,
+        var: &naga::GlobalVariable,
+        ty: wgpu::BindingType,
+    ) -> Result<(), String> {
+        let inner = &self.module.types[var.ty].inner;
+        match (var.space, inner, ty) {
+            (
+                naga::AddressSpace::Uniform,
+                _,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    min_binding_size,
+                    ..
+                },
+            )
+            | (
+                naga::AddressSpace::Storage { .. },
+                _,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { .. },
+                    min_binding_size,
+                    ..
+                },
+            ) => {
+                let size = self.layouter[var.ty].size as u64;
+                match min_binding_size {
+                    Some(min) if min.get() != size => Err(format!(
+                        "min_binding_size is {min} but the shader's type is {size} bytes"
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            (
+                naga::AddressSpace::Handle,
+                naga::TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+                wgpu::BindingType::Texture {
+                    sample_type,
+                    view_dimension,
+                    multisampled,
+                },
+            ) => {
+                let expected = match (dim, arrayed) {
+                    (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                    (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                    (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                    (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                    (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                    (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                };
+                if view_dimension != expected {
+                    return Err(format!(
+                        "view dimension is {view_dimension:?} but the shader declares {expected:?}"
+                    ));
+                }
+                let (kind_matches, multi) = match *class {
+                    naga::ImageClass::Sampled { kind, multi } => {
+                        let matches = matches!(
+                            (kind, sample_type),
+                            (
+                                naga::ScalarKind::Float,
+                                wgpu::TextureSampleType::Float { .. }
+                            ) | (naga::ScalarKind::Sint, wgpu::TextureSampleType::Sint)
+                                | (naga::ScalarKind::Uint, wgpu::TextureSampleType::Uint)
+                        );
+                        (matches, multi)
+                    }
+                    naga::ImageClass::Depth { multi } => {
+                        (sample_type == wgpu::TextureSampleType::Depth, multi)
+                    }
+                    naga::ImageClass::Storage { .. } => {
+                        return Err("the shader declares a storage texture".to_string())
+                    }
+                };
+                if !kind_matches {
+                    return Err(format!(
+                        "sample type {sample_type:?} doesn't match the shader's {class:?}"
+                    ));
+                }
+                if multisampled != multi {
+                    return Err(format!(
+                        "multisampled is {multisampled} but the shader's texture {} multisampled",
+                        if multi { "is" } else { "isn't" }
+                    ));
+                }
+                Ok(())
+            }
+            (
+                naga::AddressSpace::Handle,
+                naga::TypeInner::Sampler { comparison },
+                wgpu::BindingType::Sampler(sampler),
+            ) => {
+                if *comparison != (sampler == wgpu::SamplerBindingType::Comparison) {
+                    return Err(format!(
+                        "sampler binding {sampler:?} doesn't match the shader's {}",
+                        if *comparison {
+                            "sampler_comparison"
+                        } else {
+                            "sampler"
+                        }
+                    ));
+                }
+                Ok(())
+            }
+            (space, inner, ty) => Err(format!(
+                "layout entry is {ty:?} but the shader declares {inner:?} in {space:?}"
+            )),
+        }
+    }
+
+    /// The stages whose entry points reach `global`, directly or through
+    /// the functions they call.
+    fn stages_using(&self, global: naga::Handle<naga::GlobalVariable>) -> wgpu::ShaderStages {
+        let mut stages = wgpu::ShaderStages::NONE;
+        for entry_point in &self.module.entry_points {
+            let mut visited = HashSet::new();
+            if self.function_uses(&entry_point.function, global, &mut visited) {
+                stages |= match entry_point.stage {
+                    naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                    naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                    naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                };
+            }
+        }
+        stages
+    }
+
+    fn function_uses(
+        &self,
+        function: &naga::Function,
+        global: naga::Handle<naga::GlobalVariable>,
+        visited: &mut HashSet<naga::Handle<naga::Function>>,
+    ) -> bool {
+        let direct = function
+            .expressions
+            .iter()
+            .any(|(_, e)| matches!(e, naga::Expression::GlobalVariable(g) if *g == global));
+        direct || {
+            let mut callees = vec![];
+            collect_calls(&function.body, &mut callees);
+            callees.into_iter().any(|callee| {
+                visited.insert(callee)
+                    && self.function_uses(&self.module.functions[callee], global, visited)
+            })
+        }
+    }
+}
+
+/// Runs `check` against one of the shaders built into the binary, panicking
+/// if it fails: the Rust side can only disagree with those through a bug in
+/// this build.
+pub fn assert_matches(
+    name: &str,
+    source: &str,
+    check: impl FnOnce(&ShaderReflection) -> Result<(), String>,
+) {
+    if let Err(e) = ShaderReflection::from_wgsl(name, source).and_then(|shader| check(&shader)) {
+        panic!("{name} doesn't match the Rust side: {e}");
+    }
+}
+
+fn collect_calls(block: &naga::Block, calls: &mut Vec<naga::Handle<naga::Function>>) {
+    for statement in block.iter() {
+        match statement {
+            naga::Statement::Call { function, .. } => calls.push(*function),
+            naga::Statement::Block(block) => collect_calls(block, calls),
+            naga::Statement::If { accept, reject, .. } => {
+                collect_calls(accept, calls);
+                collect_calls(reject, calls);
+            }
+            naga::Statement::Switch { cases, .. } => {
+                for case in cases {
+                    collect_calls(&case.body, calls);
+                }
+            }
+            naga::Statement::Loop {
+                body, continuing, ..
+            } => {
+                collect_calls(body, calls);
+                collect_calls(continuing, calls);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "
+        struct Params {
+            color: vec4f,
+            scale: f32,
+            count: u32,
+        }
+        @group(0) @binding(0) var<uniform> params: Params;
+
+        @fragment
+        fn fs_main() -> @location(0) vec4f {
+            return params.color * params.scale;
+        }
+    ";
+
+    fn reflection() -> ShaderReflection {
+        let module = naga::front::wgsl::parse_str(SHADER).unwrap();
+        ShaderReflection::new("test", module).unwrap()
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Matching {
+        color: [f32; 4],
+        scale: f32,
+        count: u32,
+        _pad: [u32; 2],
+    }
+    shader_struct!(Matching = "Params" { color, scale, count });
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Unpadded {
+        color: [f32; 4],
+        scale: f32,
+        count: u32,
+    }
+    shader_struct!(Unpadded = "Params" { color, scale, count });
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Swapped {
+        color: [f32; 4],
+        count: u32,
+        scale: f32,
+        _pad: [u32; 2],
+    }
+    shader_struct!(Swapped = "Params" { color, scale, count });
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct WrongType {
+        color: [f32; 4],
+        scale: u32,
+        count: u32,
+        _pad: [u32; 2],
+    }
+    shader_struct!(WrongType = "Params" { color, scale, count });
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Narrow {
+        color: [f32; 3],
+        scale: f32,
+        count: u32,
+        _pad: [u32; 3],
+    }
+    shader_struct!(Narrow = "Params" { color, scale, count });
+
+    #[repr(C)]
+    #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    struct OverPadded {
+        color: [f32; 4],
+        scale: f32,
+        count: u32,
+        _pad: [u32; 6],
+    }
+    shader_struct!(OverPadded = "Params" { color, scale, count });
+
+    fn uniform_entry(visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    #[test]
+    fn matching_struct_passes() {
+        assert_eq!(reflection().check_struct::<Matching>(), Ok(()));
+    }
+
+    #[test]
+    fn wrong_size_is_rejected() {
+        let error = reflection().check_struct::<Unpadded>().unwrap_err();
+        assert!(error.contains("`Unpadded` is 24 bytes"), "{error}");
+        assert!(error.contains("`Params` is 32"), "{error}");
+    }
+
+    #[test]
+    fn wrong_offset_names_the_field() {
+        let error = reflection().check_struct::<Swapped>().unwrap_err();
+        assert!(error.contains("`Swapped.scale` is at offset 20"), "{error}");
+    }
+
+    #[test]
+    fn wrong_type_names_the_field() {
+        let error = reflection().check_struct::<WrongType>().unwrap_err();
+        assert!(error.contains("`WrongType.scale` holds Uint"), "{error}");
+    }
+
+    #[test]
+    fn wrong_field_size_names_the_field() {
+        let error = reflection().check_struct::<Narrow>().unwrap_err();
+        assert!(error.contains("`Narrow.color` is 12 bytes"), "{error}");
+    }
+
+    #[test]
+    fn uniform_allows_tail_padding() {
+        let reflection = reflection();
+        assert_eq!(reflection.check_uniform::<Matching>(), Ok(()));
+        let error = reflection.check_uniform::<OverPadded>().unwrap_err();
+        assert!(error.contains("`OverPadded` is 48 bytes"), "{error}");
+    }
+
+    #[test]
+    fn matching_layout_passes() {
+        let entries = [uniform_entry(wgpu::ShaderStages::FRAGMENT)];
+        assert_eq!(reflection().check_bind_group_layout(0, &entries), Ok(()));
+    }
+
+    #[test]
+    fn missing_binding_is_rejected() {
+        let error = reflection().check_bind_group_layout(0, &[]).unwrap_err();
+        assert!(
+            error.contains("`params` (@group(0) @binding(0))"),
+            "{error}"
+        );
+        assert!(error.contains("has no bind group layout entry"), "{error}");
+    }
+
+    #[test]
+    fn extra_entry_is_rejected() {
+        let mut extra = uniform_entry(wgpu::ShaderStages::FRAGMENT);
+        extra.binding = 1;
+        let entries = [uniform_entry(wgpu::ShaderStages::FRAGMENT), extra];
+        let error = reflection()
+            .check_bind_group_layout(0, &entries)
+            .unwrap_err();
+        assert!(error.contains("entry 1 has no matching"), "{error}");
+    }
+
+    #[test]
+    fn wrong_visibility_is_rejected() {
+        let entries = [uniform_entry(wgpu::ShaderStages::VERTEX)];
+        let error = reflection()
+            .check_bind_group_layout(0, &entries)
+            .unwrap_err();
+        assert!(error.contains("used in ShaderStages(FRAGMENT)"), "{error}");
+        assert!(
+            error.contains("only visible to ShaderStages(VERTEX)"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn wrong_binding_type_is_rejected() {
+        let entries = [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }];
+        let error = reflection()
+            .check_bind_group_layout(0, &entries)
+            .unwrap_err();
+        assert!(
+            error.contains("`params` (@group(0) @binding(0)): "),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn wrong_min_binding_size_is_rejected() {
+        let mut entry = uniform_entry(wgpu::ShaderStages::FRAGMENT);
+        entry.ty = wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(16),
+        };
+        let error = reflection()
+            .check_bind_group_layout(0, &[entry])
+            .unwrap_err();
+        assert!(error.contains("min_binding_size is 16"), "{error}");
+    }
+}
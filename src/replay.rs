@@ -0,0 +1,250 @@
+//! Recording the window events that reach `State::input`, and playing them
+//! back. A recording stores each frame's events with the time `update` saw
+//! for it; replaying feeds the same events in on the same frames with that
+//! time standing in for the real clock, so every frame comes out the same.
+//! `--record` writes one, `--replay` plays it back in the window or, with
+//! `--replay-out`, renders it offscreen.
+
+use crate::headless::{Headless, OffscreenScene};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event::{
+    DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+    TouchPhase, VirtualKeyCode, WindowEvent,
+};
+
+/// The parts of a `WindowEvent` anything reacts to. Events that aren't user
+/// input, like the window being occluded, aren't recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    Key {
+        scancode: u32,
+        key: Option<VirtualKeyCode>,
+        state: ElementState,
+    },
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    CursorLeft,
+    LineScroll {
+        x: f32,
+        y: f32,
+    },
+    PixelScroll {
+        x: f64,
+        y: f64,
+    },
+    Focused(bool),
+    Resized {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl RecordedEvent {
+    pub fn from_event(event: &WindowEvent) -> Option<Self> {
+        Some(match event {
+            WindowEvent::KeyboardInput { input, .. } => RecordedEvent::Key {
+                scancode: input.scancode,
+                key: input.virtual_keycode,
+                state: input.state,
+            },
+            WindowEvent::MouseInput { button, state, .. } => RecordedEvent::MouseButton {
+                button: *button,
+                state: *state,
+            },
+            WindowEvent::CursorMoved { position, .. } => RecordedEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::CursorLeft { .. } => RecordedEvent::CursorLeft,
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(x, y),
+                ..
+            } => RecordedEvent::LineScroll { x: *x, y: *y },
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::PixelDelta(delta),
+                ..
+            } => RecordedEvent::PixelScroll {
+                x: delta.x,
+                y: delta.y,
+            },
+            WindowEvent::Focused(focused) => RecordedEvent::Focused(*focused),
+            WindowEvent::Resized(size) => RecordedEvent::Resized {
+                width: size.width,
+                height: size.height,
+            },
+            _ => return None,
+        })
+    }
+
+    /// The event as winit would have delivered it, from a made up device.
+    #[allow(deprecated)]
+    pub fn to_event(&self) -> WindowEvent<'static> {
+        // Only ever compared against, never passed back into winit.
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+        match *self {
+            RecordedEvent::Key {
+                scancode,
+                key,
+                state,
+            } => WindowEvent::KeyboardInput {
+                device_id,
+                input: KeyboardInput {
+                    scancode,
+                    state,
+                    virtual_keycode: key,
+                    modifiers,
+                },
+                is_synthetic: false,
+            },
+            RecordedEvent::MouseButton { button, state } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            },
+            RecordedEvent::CursorMoved { x, y } => WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(x, y),
+                modifiers,
+            },
+            RecordedEvent::CursorLeft => WindowEvent::CursorLeft { device_id },
+            RecordedEvent::LineScroll { x, y } => WindowEvent::MouseWheel {
+                device_id,
+                delta: MouseScrollDelta::LineDelta(x, y),
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            RecordedEvent::PixelScroll { x, y } => WindowEvent::MouseWheel {
+                device_id,
+                delta: MouseScrollDelta::PixelDelta(PhysicalPosition::new(x, y)),
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            RecordedEvent::Focused(focused) => WindowEvent::Focused(focused),
+            RecordedEvent::Resized { width, height } => {
+                WindowEvent::Resized(PhysicalSize::new(width, height))
+            }
+        }
+    }
+}
+
+/// The events that arrived before one `update`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    /// Seconds since the recording started.
+    pub time: f64,
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Frame {
+    pub fn time(&self) -> Duration {
+        Duration::from_secs_f64(self.time)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    /// The window's size when recording started, in physical pixels.
+    pub size: [u32; 2],
+    pub frames: Vec<Frame>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        ron::from_str(&source).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(path, source).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+/// Collects events into frames, and writes them to `path` when dropped.
+pub struct Recorder {
+    path: PathBuf,
+    recording: Recording,
+    pending: Vec<RecordedEvent>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, size: PhysicalSize<u32>) -> Self {
+        log::info!("Recording input to {}", path.display());
+        Self {
+            path,
+            recording: Recording {
+                size: [size.width, size.height],
+                frames: vec![],
+            },
+            pending: vec![],
+        }
+    }
+
+    pub fn event(&mut self, event: &WindowEvent) {
+        self.pending.extend(RecordedEvent::from_event(event));
+    }
+
+    /// Ends the frame `update` is running at `time`, taking every event
+    /// since the last one.
+    pub fn end_frame(&mut self, time: Duration) {
+        self.recording.frames.push(Frame {
+            time: time.as_secs_f64(),
+            events: std::mem::take(&mut self.pending),
+        });
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        match self.recording.save(&self.path) {
+            Ok(()) => log::info!(
+                "Saved {} frames of input to {}",
+                self.recording.frames.len(),
+                self.path.display()
+            ),
+            Err(e) => log::error!("Failed to save the input recording: {e}"),
+        }
+    }
+}
+
+/// Plays `recording` back into `offscreen`, which should start out the way
+/// the recorded window did, and renders every frame. The 2D view and the
+/// settings panel aren't drawn offscreen, so only what the recording does to
+/// the 3D view shows up.
+pub fn render(
+    headless: &Headless,
+    offscreen: &mut OffscreenScene,
+    recording: &Recording,
+    mut frame: impl FnMut(usize, image::RgbaImage),
+) {
+    let [width, height] = recording.size;
+    if offscreen.size() != (width, height) {
+        offscreen.resize(headless, width, height);
+    }
+    for (index, recorded) in recording.frames.iter().enumerate() {
+        for event in &recorded.events {
+            match *event {
+                RecordedEvent::Resized { width, height } if width > 0 && height > 0 => {
+                    offscreen.resize(headless, width, height)
+                }
+                _ => {
+                    offscreen.input(&event.to_event());
+                }
+            }
+        }
+        frame(index, offscreen.render_at(headless, recorded.time()));
+    }
+}
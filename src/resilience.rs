@@ -0,0 +1,147 @@
+//! The failures rendering a frame can run into, and noticing them. A lost or
+//! outdated surface only needs reconfiguring, but a lost device takes every
+//! GPU resource with it; `State::recover` rebuilds those from the CPU-side
+//! descriptions it keeps.
+
+use std::cell::Cell;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Something that went wrong rendering a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    SurfaceLost,
+    SurfaceOutdated,
+    /// No surface texture became available in time.
+    Timeout,
+    OutOfMemory,
+    /// The device, and everything created from it, is gone.
+    DeviceLost,
+}
+
+impl Fault {
+    /// Whether recovering takes a new device rather than reconfiguring the
+    /// surface.
+    pub fn needs_new_device(self) -> bool {
+        matches!(self, Fault::OutOfMemory | Fault::DeviceLost)
+    }
+}
+
+impl From<wgpu::SurfaceError> for Fault {
+    fn from(error: wgpu::SurfaceError) -> Self {
+        match error {
+            wgpu::SurfaceError::Lost => Fault::SurfaceLost,
+            wgpu::SurfaceError::Outdated => Fault::SurfaceOutdated,
+            wgpu::SurfaceError::Timeout => Fault::Timeout,
+            wgpu::SurfaceError::OutOfMemory => Fault::OutOfMemory,
+        }
+    }
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "surface-lost" => Ok(Fault::SurfaceLost),
+            "surface-outdated" => Ok(Fault::SurfaceOutdated),
+            "timeout" => Ok(Fault::Timeout),
+            "out-of-memory" => Ok(Fault::OutOfMemory),
+            "device-lost" => Ok(Fault::DeviceLost),
+            _ => Err(format!("unknown fault {s:?}")),
+        }
+    }
+}
+
+/// How wgpu-core 0.16 words `DeviceError::Lost`. A lost device has no error
+/// of its own in wgpu 0.16: it shows up as a validation error of whatever
+/// call noticed it, with this message among the causes.
+const DEVICE_LOST_MESSAGE: &str = "Parent device is lost";
+
+/// Whether a validation error's description says the device was lost.
+fn is_device_lost(description: &str) -> bool {
+    description.contains(DEVICE_LOST_MESSAGE)
+}
+
+/// Watches a device for errors that leave it unusable, and hands out faults
+/// injected to exercise recovery. Shared by every window using the device.
+pub struct DeviceHealth {
+    /// Set from wgpu's error handler, which may run on another thread.
+    fault: Arc<Mutex<Option<Fault>>>,
+    injected: Cell<Option<Fault>>,
+}
+
+impl DeviceHealth {
+    /// Replaces the device's uncaptured error handler. Running out of memory
+    /// or losing the device is recorded; any other error is a bug and
+    /// panics, like wgpu's default handler.
+    pub fn new(device: &wgpu::Device) -> Self {
+        let fault = Arc::new(Mutex::new(None));
+        let handler_fault = fault.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            let fault = match &error {
+                wgpu::Error::OutOfMemory { .. } => Fault::OutOfMemory,
+                wgpu::Error::Validation { description, .. } if is_device_lost(description) => {
+                    Fault::DeviceLost
+                }
+                wgpu::Error::Validation { .. } => panic!("wgpu error: {error}"),
+            };
+            log::error!("{error}");
+            handler_fault.lock().unwrap().get_or_insert(fault);
+        }));
+        Self {
+            fault,
+            injected: Cell::new(None),
+        }
+    }
+
+    /// Makes the next `check` report `fault`, as if it had really happened.
+    pub fn inject(&self, fault: Fault) {
+        log::warn!("Injecting {fault:?}");
+        self.injected.set(Some(fault));
+    }
+
+    /// The fault to recover from before rendering another frame, if any.
+    pub fn check(&self) -> Option<Fault> {
+        self.injected.take().or_else(|| *self.fault.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_faults() {
+        let faults = [
+            ("surface-lost", Fault::SurfaceLost),
+            ("surface-outdated", Fault::SurfaceOutdated),
+            ("timeout", Fault::Timeout),
+            ("out-of-memory", Fault::OutOfMemory),
+            ("device-lost", Fault::DeviceLost),
+        ];
+        for (name, fault) in faults {
+            assert_eq!(name.parse(), Ok(fault));
+        }
+        assert!("lost".parse::<Fault>().is_err());
+        assert!("DeviceLost".parse::<Fault>().is_err());
+    }
+
+    #[test]
+    fn only_device_faults_need_a_new_device() {
+        assert!(Fault::DeviceLost.needs_new_device());
+        assert!(Fault::OutOfMemory.needs_new_device());
+        assert!(!Fault::SurfaceLost.needs_new_device());
+        assert!(!Fault::SurfaceOutdated.needs_new_device());
+        assert!(!Fault::Timeout.needs_new_device());
+    }
+
+    #[test]
+    fn recognizes_device_loss() {
+        let lost = "Validation Error\n\nCaused by:\n    In Device::create_buffer\n    \
+            Parent device is lost\n";
+        assert!(is_device_lost(lost));
+        let surface = "Validation Error\n\nCaused by:\n    Surface image is lost\n";
+        assert!(!is_device_lost(surface));
+    }
+}
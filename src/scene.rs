@@ -0,0 +1,604 @@
+use crate::blend::BlendMode;
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::camera::{Camera, CameraController, CameraUniform, ControllerMode, Projection};
+use crate::debug_draw::{DebugDraw, DebugStyle};
+use crate::ibl::{self, Environment};
+use crate::input::{Action, Input};
+use crate::light::{Light, LightKind, Lights};
+use crate::material::Material;
+use crate::model::{Mesh, MeshData, Model, ModelData};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect;
+use crate::shadow::{ShadowMaps, ShadowSettings};
+use crate::skybox::Skybox;
+use crate::texture::CubeTexture;
+use crate::{TargetFormats, Vertex};
+use cgmath::MetricSpace;
+use cgmath::{EuclideanSpace, Matrix, Matrix3, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::FRAC_PI_2;
+use std::rc::Rc;
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use winit::event::*;
+
+/// One placement of a `Model` in the scene.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub model: usize,
+    pub transform: Matrix4<f32>,
+}
+
+/// Everything needed to build a `MeshScene`, kept on the CPU.
+#[derive(Debug, Clone)]
+pub struct SceneDesc {
+    pub models: Vec<ModelData>,
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    pub ambient: [f32; 3],
+    /// Equirectangular HDR environment for image-based lighting.
+    /// `ibl::procedural_sky` is used when there is none.
+    pub environment: Option<image::Rgba32FImage>,
+    pub environment_intensity: f32,
+    /// Six cube faces drawn behind the scene. Without them the skybox shows
+    /// the environment instead.
+    pub skybox: Option<[image::DynamicImage; 6]>,
+}
+
+/// Face size of the skybox cubemap converted from the environment.
+const SKYBOX_SIZE: u32 = 512;
+
+impl SceneDesc {
+    /// Places `models` side by side along +x on a ground plane, lit by
+    /// `Lights::default_rig`.
+    pub fn from_models(mut models: Vec<ModelData>) -> Self {
+        let mut objects = vec![];
+        let mut x = 0.0;
+        for (i, model) in models.iter().enumerate() {
+            let (min, max) = model_bounds(model);
+            objects.push(Object {
+                model: i,
+                transform: Matrix4::from_translation(Vector3::new(x - min.x, 0.0, 0.0)),
+            });
+            x += (max.x - min.x) * 1.25;
+        }
+
+        let (center, radius) = bounding_sphere(&models, &objects);
+        let floor = objects
+            .iter()
+            .map(|o| {
+                let (min, _) = model_bounds(&models[o.model]);
+                (o.transform * min.to_homogeneous()).y
+            })
+            .fold(f32::INFINITY, f32::min);
+        if floor.is_finite() {
+            objects.push(Object {
+                model: models.len(),
+                transform: Matrix4::from_translation(Vector3::new(center.x, floor, center.z)),
+            });
+            models.push(ModelData {
+                meshes: vec![MeshData::plane(radius * 4.0)],
+                materials: vec![],
+            });
+        }
+
+        Self {
+            lights: Lights::default_rig(center, radius),
+            ambient: [0.0, 0.0, 0.0],
+            environment: None,
+            environment_intensity: 0.3,
+            skybox: None,
+            models,
+            objects,
+        }
+    }
+}
+
+fn model_bounds(model: &ModelData) -> (Point3<f32>, Point3<f32>) {
+    model
+        .meshes
+        .iter()
+        .filter_map(MeshData::bounds)
+        .reduce(|(amin, amax), (bmin, bmax)| {
+            (
+                Point3::new(amin.x.min(bmin.x), amin.y.min(bmin.y), amin.z.min(bmin.z)),
+                Point3::new(amax.x.max(bmax.x), amax.y.max(bmax.y), amax.z.max(bmax.z)),
+            )
+        })
+        .unwrap_or((Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)))
+}
+
+/// Axis-aligned bounds of `model` placed with `transform`.
+fn world_bounds(model: &ModelData, transform: Matrix4<f32>) -> (Point3<f32>, Point3<f32>) {
+    let (min, max) = model_bounds(model);
+    let mut world_min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut world_max = Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for i in 0..8 {
+        let corner = Point3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        let p = Point3::from_homogeneous(transform * corner.to_homogeneous());
+        world_min = Point3::new(
+            world_min.x.min(p.x),
+            world_min.y.min(p.y),
+            world_min.z.min(p.z),
+        );
+        world_max = Point3::new(
+            world_max.x.max(p.x),
+            world_max.y.max(p.y),
+            world_max.z.max(p.z),
+        );
+    }
+    (world_min, world_max)
+}
+
+/// Bounding sphere (center, radius) around every object's model bounds.
+fn bounding_sphere(models: &[ModelData], objects: &[Object]) -> (Point3<f32>, f32) {
+    let corners: Vec<Point3<f32>> = objects
+        .iter()
+        .flat_map(|object| {
+            let (min, max) = model_bounds(&models[object.model]);
+            [min, max].map(|p| Point3::from_homogeneous(object.transform * p.to_homogeneous()))
+        })
+        .collect();
+    if corners.is_empty() {
+        return (Point3::origin(), 1.0);
+    }
+    let center = Point3::centroid(&corners);
+    let radius = corners
+        .iter()
+        .map(|p| cgmath::MetricSpace::distance(*p, center))
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+}
+
+impl InstanceRaw {
+    fn new(transform: Matrix4<f32>) -> Self {
+        let linear = Matrix3::from_cols(
+            transform.x.truncate(),
+            transform.y.truncate(),
+            transform.z.truncate(),
+        );
+        let normal = linear.invert().map(|m| m.transpose()).unwrap_or(linear);
+        Self {
+            model: transform.into(),
+            normal: normal.into(),
+        }
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x3,
+            10 => Float32x3,
+            11 => Float32x3,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// The 3D path: loaded models drawn through a perspective camera.
+pub struct MeshScene {
+    pub camera: Camera,
+    pub projection: Projection,
+    pub camera_controller: CameraController,
+    pub lights: Lights,
+    pub shadows: ShadowMaps,
+    environment: Environment,
+    skybox: Skybox,
+    /// Radius of the scene's bounding sphere; directional shadows reach a
+    /// few times this far from the camera.
+    scene_radius: f32,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    shader: Rc<wgpu::ShaderModule>,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// One for every blend mode the materials use.
+    pipelines: HashMap<BlendMode, Rc<wgpu::RenderPipeline>>,
+    targets: TargetFormats,
+    polygon_mode: wgpu::PolygonMode,
+    models: Vec<Model>,
+    objects: Vec<Object>,
+    /// World-space bounds of each object, for debug drawing.
+    object_bounds: Vec<(Point3<f32>, Point3<f32>)>,
+    instance_buffer: wgpu::Buffer,
+    /// Meshes with a transparent material as (object, mesh) indices, back
+    /// to front as seen from the camera at the last `update`.
+    transparent_draws: Vec<(usize, usize)>,
+}
+
+impl MeshScene {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+        width: u32,
+        height: u32,
+        desc: &SceneDesc,
+        shadow_settings: ShadowSettings,
+    ) -> Self {
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 5.0), Rad(-FRAC_PI_2), Rad(-0.3));
+        let projection = Projection::new(
+            width,
+            height,
+            Rad(std::f32::consts::FRAC_PI_4),
+            0.01,
+            1000.0,
+        );
+        let mut camera_controller = CameraController::new(ControllerMode::Orbit, 4.0, 0.005);
+        let (center, radius) = bounding_sphere(&desc.models, &desc.objects);
+        camera_controller.frame(&mut camera, center, radius);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera buffer"),
+            contents: bytemuck::bytes_of(&CameraUniform::new(&camera, &projection)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let (camera_bind_group_layout, camera_bind_group) = BindGroupBuilder::new("Camera")
+            .uniform(wgpu::ShaderStages::VERTEX_FRAGMENT, &camera_buffer)
+            .build_cached(device, cache);
+
+        let shadows = ShadowMaps::new(
+            device,
+            cache,
+            targets.depth,
+            &[Vertex::desc(), InstanceRaw::desc()],
+            shadow_settings,
+        );
+        let lights = Lights::new(device, cache, desc.lights.clone(), desc.ambient, &shadows);
+        let procedural_sky;
+        let equirect = match &desc.environment {
+            Some(equirect) => equirect,
+            None => {
+                procedural_sky = ibl::procedural_sky(512, 256);
+                &procedural_sky
+            }
+        };
+        let environment =
+            Environment::new(device, queue, cache, equirect, desc.environment_intensity);
+        let sky_cube = match &desc.skybox {
+            Some(faces) => {
+                CubeTexture::from_faces(device, queue, faces, "Skybox").unwrap_or_else(|e| {
+                    log::warn!("Falling back to the environment for the skybox: {e}");
+                    CubeTexture::from_equirect(
+                        device,
+                        queue,
+                        cache,
+                        equirect,
+                        SKYBOX_SIZE,
+                        "Skybox",
+                    )
+                })
+            }
+            None => {
+                CubeTexture::from_equirect(device, queue, cache, equirect, SKYBOX_SIZE, "Skybox")
+            }
+        };
+        let skybox = Skybox::new(device, cache, &camera_bind_group_layout, sky_cube, targets);
+        let material_layout = Material::bind_group_layout(device, queue, cache);
+        let models = desc
+            .models
+            .iter()
+            .map(|model| Model::new(device, queue, cache, model))
+            .collect();
+
+        let instances: Vec<InstanceRaw> = desc
+            .objects
+            .iter()
+            .map(|o| InstanceRaw::new(o.transform))
+            .collect();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let source = include_str!("mesh.wgsl");
+        reflect::assert_matches("mesh.wgsl", source, |shader| {
+            shader.check_uniform::<CameraUniform>()?;
+            Lights::check_shader(shader)?;
+            ShadowMaps::check_shader(shader)?;
+            Material::check_shader(shader)?;
+            Environment::check_shader(shader)
+        });
+        let shader = cache.shader_module(device, "Mesh shader", source);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mesh pipeline layout"),
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &lights.bind_group_layout,
+                &material_layout,
+                &environment.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let mut scene = Self {
+            camera,
+            projection,
+            camera_controller,
+            lights,
+            shadows,
+            environment,
+            skybox,
+            scene_radius: radius.max(0.1),
+            camera_buffer,
+            camera_bind_group,
+            shader,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            targets,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            models,
+            objects: desc.objects.clone(),
+            object_bounds: desc
+                .objects
+                .iter()
+                .map(|o| world_bounds(&desc.models[o.model], o.transform))
+                .collect(),
+            instance_buffer,
+            transparent_draws: vec![],
+        };
+        scene.create_pipelines(device, cache);
+        scene
+    }
+
+    /// Builds a pipeline for every blend mode in use. Transparent ones test
+    /// depth against the opaque meshes but don't write it.
+    fn create_pipelines(&mut self, device: &wgpu::Device, cache: &mut PipelineCache) {
+        let modes: HashSet<BlendMode> = std::iter::once(BlendMode::Opaque)
+            .chain(
+                self.models
+                    .iter()
+                    .flat_map(|model| model.materials.iter().map(|m| m.blend_mode)),
+            )
+            .collect();
+        self.pipelines = modes
+            .into_iter()
+            .map(|mode| {
+                let mut builder = PipelineBuilder::new(
+                    "Mesh pipeline",
+                    &self.pipeline_layout,
+                    &self.shader,
+                    self.targets.color,
+                )
+                .vertex_buffer(Vertex::desc())
+                .vertex_buffer(InstanceRaw::desc())
+                .cull(wgpu::Face::Back)
+                .polygon_mode(self.polygon_mode)
+                .targets(self.targets)
+                .blend_mode(mode);
+                if mode.is_transparent() {
+                    builder = builder.depth(self.targets.depth, wgpu::CompareFunction::Less, false);
+                }
+                (mode, builder.build_cached(device, cache))
+            })
+            .collect();
+    }
+
+    pub fn set_targets(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+    ) {
+        self.targets = targets;
+        self.create_pipelines(device, cache);
+        self.skybox.set_targets(device, cache, targets);
+    }
+
+    /// Draws the meshes filled, as wireframes or as points. The skybox is
+    /// always filled.
+    pub fn set_polygon_mode(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        polygon_mode: wgpu::PolygonMode,
+    ) {
+        self.polygon_mode = polygon_mode;
+        self.create_pipelines(device, cache);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.projection.resize(width, height);
+    }
+
+    /// Mouse events for the camera. Returns true if the event was consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera_controller.input(event)
+    }
+
+    /// The camera and light actions. Call before `update`.
+    pub fn handle_actions(&mut self, input: &Input) {
+        if input.pressed(Action::ToggleCameraMode) {
+            self.camera_controller.toggle_mode(&self.camera);
+        }
+        self.camera_controller.handle_actions(input);
+        self.lights.handle_actions(input);
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        dt: Duration,
+    ) {
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::bytes_of(&CameraUniform::new(&self.camera, &self.projection)),
+        );
+        self.lights.update(queue);
+        let grown = self.shadows.update(
+            device,
+            queue,
+            &self.lights.lights,
+            &self.lights.shadow_layers,
+            &self.camera,
+            &self.projection,
+            self.scene_radius * 4.0,
+            self.scene_radius * 2.0,
+        );
+        if grown {
+            self.lights.rebind_shadows(device, cache, &self.shadows);
+        }
+        self.sort_transparent_draws();
+    }
+
+    fn sort_transparent_draws(&mut self) {
+        let mut draws: Vec<(f32, (usize, usize))> = vec![];
+        for (i, object) in self.objects.iter().enumerate() {
+            let model = &self.models[object.model];
+            for (j, mesh) in model.meshes.iter().enumerate() {
+                if model.materials[mesh.material].blend_mode.is_transparent() {
+                    let center =
+                        Point3::from_homogeneous(object.transform * mesh.center.to_homogeneous());
+                    draws.push((self.camera.position.distance2(center), (i, j)));
+                }
+            }
+        }
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.transparent_draws = draws.into_iter().map(|(_, draw)| draw).collect();
+    }
+
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.projection.matrix() * self.camera.view_matrix()
+    }
+
+    /// Visualizes the scene: object bounds, light positions and directions,
+    /// and the world axes over a ground grid.
+    pub fn draw_debug(&self, debug: &mut DebugDraw) {
+        let radius = self.scene_radius;
+        let grid = DebugStyle::world([0.1, 0.1, 0.1, 0.6]);
+        debug.grid(
+            Point3::origin(),
+            Vector3::unit_x() * radius * 2.0,
+            Vector3::unit_z() * radius * 2.0,
+            20,
+            &grid,
+        );
+        debug.axes(
+            Matrix4::from_scale(1.0),
+            radius * 0.5,
+            &DebugStyle {
+                depth_test: false,
+                ..DebugStyle::world([1.0; 4])
+            },
+        );
+        let bounds = DebugStyle::world([1.0, 1.0, 0.0, 1.0]);
+        for &(min, max) in &self.object_bounds {
+            debug.aabb(min, max, &bounds);
+        }
+        for light in self.lights.lights.iter().filter(|light| light.enabled) {
+            let [r, g, b] = light.color;
+            let style = DebugStyle {
+                depth_test: false,
+                ..DebugStyle::world([r, g, b, 1.0])
+            };
+            let size = radius * 0.1;
+            match light.kind {
+                LightKind::Directional => {
+                    let to = Point3::origin() + Vector3::unit_y() * radius;
+                    debug.arrow(to - light.direction * radius * 0.5, to, &style);
+                }
+                LightKind::Point => {
+                    for normal in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+                        debug.circle(light.position, normal, size, &style);
+                    }
+                }
+                LightKind::Spot => {
+                    debug.arrow(
+                        light.position,
+                        light.position + light.direction * radius * 0.5,
+                        &style,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Renders the shadow maps. Has to be encoded before the pass that
+    /// calls `draw`. Transparent meshes cast shadows as if they were opaque.
+    pub fn render_shadows(&self, encoder: &mut wgpu::CommandEncoder) {
+        for layer in 0..self.shadows.active_layers() {
+            let mut render_pass = self.shadows.begin_render_pass(encoder, layer);
+            self.draw_meshes(&mut render_pass, false);
+        }
+    }
+
+    /// Draws the opaque meshes, then the skybox behind them, then the
+    /// transparent meshes back to front.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipelines[&BlendMode::Opaque]);
+        self.set_bind_groups(render_pass);
+        self.draw_meshes(render_pass, true);
+        self.skybox.draw(render_pass, &self.camera_bind_group);
+
+        self.set_bind_groups(render_pass);
+        let mut current = None;
+        for &(i, j) in &self.transparent_draws {
+            let model = &self.models[self.objects[i].model];
+            let mesh = &model.meshes[j];
+            let material = &model.materials[mesh.material];
+            if current != Some(material.blend_mode) {
+                current = Some(material.blend_mode);
+                render_pass.set_pipeline(&self.pipelines[&material.blend_mode]);
+            }
+            render_pass.set_bind_group(2, &material.bind_group, &[]);
+            Self::draw_mesh(render_pass, mesh, i);
+        }
+    }
+
+    fn set_bind_groups<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.lights.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.environment.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+    }
+
+    /// Draws every mesh, or with `materials` only the opaque ones with
+    /// their material bound.
+    fn draw_meshes<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, materials: bool) {
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for (i, object) in self.objects.iter().enumerate() {
+            let model = &self.models[object.model];
+            for mesh in &model.meshes {
+                if materials {
+                    let material = &model.materials[mesh.material];
+                    if material.blend_mode.is_transparent() {
+                        continue;
+                    }
+                    render_pass.set_bind_group(2, &material.bind_group, &[]);
+                }
+                Self::draw_mesh(render_pass, mesh, i);
+            }
+        }
+    }
+
+    fn draw_mesh<'a>(render_pass: &mut wgpu::RenderPass<'a>, mesh: &'a Mesh, object: usize) {
+        let instance = object as u32;
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+        render_pass.draw_indexed(0..mesh.num_indices, 0, instance..instance + 1);
+    }
+}
@@ -1,10 +1,24 @@
+#include "common.wgsl"
+
 struct ExampleUniform {
+    // Tint applied to the texture.
     color: vec4f,
     time: f32,
+    // Screen pixels per texel.
+    tile_scale: f32,
+    // Shifts the tiling, in fractions of the texture size.
+    tile_offset: vec2f,
+    // HDR images are multiplied by this before tonemapping.
+    exposure: f32,
+    // An OPERATOR_* constant.
+    curve: u32,
+    // Set for grayscale images, which only have a red channel.
+    gray: u32,
+    // Set for HDR images, which hold linear values past 1 instead of sRGB.
+    hdr: u32,
 }
 
 @group(0) @binding(0) var<uniform> uExampleUniform: ExampleUniform;
-// TODO: Pass in size of the image, so it can repeat.
 @group(0) @binding(1) var gradientTexture: texture_2d<f32>;
 
 
@@ -28,12 +42,30 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4f {
     // possible use of the color uniform, among many others).
-    let loc = vec2<i32>(in.position.xy);
     let dims = vec2i(textureDimensions(gradientTexture));
-    let pos = vec2<i32>(loc.x % dims.x, loc.y % dims.y);
-    let color = textureLoad(gradientTexture, pos, 0).rgb;
-    // Gamma-correction
-    let corrected_color = pow(color, vec3f(2.2));
-    //let corrected_color = color;
-    return vec4f(corrected_color, uExampleUniform.color.a);
-}
\ No newline at end of file
+    let texel = in.position.xy / uExampleUniform.tile_scale
+        + uExampleUniform.tile_offset * vec2f(dims);
+    let pos = tile_texel(vec2<i32>(floor(texel)), dims);
+    var color = textureLoad(gradientTexture, pos, 0).rgb;
+    if uExampleUniform.gray != 0u {
+        color = color.rrr;
+    }
+    if uExampleUniform.hdr != 0u {
+        color = tonemap(color * uExampleUniform.exposure, uExampleUniform.curve);
+        // Tonemapped colors are linear, encode them when the output won't.
+#ifndef GAMMA_CORRECT
+        color = linear_to_srgb(color);
+#endif
+    } else {
+        // Gamma-correction, the texture holds sRGB values.
+#ifdef GAMMA_CORRECT
+        color = pow(color, vec3f(2.2));
+#endif
+    }
+    let alpha = uExampleUniform.color.a;
+#ifdef PREMULTIPLIED_ALPHA
+    return vec4f(color * uExampleUniform.color.rgb * alpha, alpha);
+#else
+    return vec4f(color * uExampleUniform.color.rgb, alpha);
+#endif
+}
@@ -0,0 +1,446 @@
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX};
+use crate::light::{Light, LightKind};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{shader_struct, ShaderReflection};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
+use std::rc::Rc;
+
+/// Layers the shadow map array can grow to. Each directional light uses one
+/// layer per cascade and each spot light uses one.
+pub const MAX_SHADOW_LAYERS: u32 = 8;
+/// Cascade split distances are passed to the shader in a `vec4f`.
+pub const MAX_CASCADES: u32 = 4;
+
+// Dynamic uniform offsets have to be multiples of 256.
+const PASS_UNIFORM_STRIDE: u64 = 256;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    /// Width and height of each shadow map layer.
+    pub resolution: u32,
+    /// Cascades per directional light, 1 to `MAX_CASCADES`.
+    pub cascades: u32,
+    /// Rasterizer depth bias applied while rendering shadow maps.
+    pub constant_bias: i32,
+    pub slope_bias: f32,
+    /// World-space distance receivers are pushed along their normal before
+    /// the shadow lookup, in shadow map texels.
+    pub normal_bias: f32,
+    /// PCF kernel radius in texels; 0 takes a single comparison sample.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascades: 3,
+            constant_bias: 2,
+            slope_bias: 2.0,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// Matches `ShadowHeader` in mesh.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowHeader {
+    /// View-space distance at which each cascade ends.
+    cascade_splits: [f32; 4],
+    cascade_count: u32,
+    pcf_radius: u32,
+    texel_size: f32,
+    normal_bias: f32,
+}
+
+shader_struct!(ShadowHeader = "ShadowHeader" {
+    cascade_splits,
+    cascade_count,
+    pcf_radius,
+    texel_size,
+    normal_bias,
+});
+
+/// Hands out shadow map layers to the lights that cast shadows, in order,
+/// until the array runs out. Returns the first layer for each light.
+pub fn assign_layers(lights: &[Light], cascades: u32) -> Vec<Option<u32>> {
+    let mut next = 0;
+    lights
+        .iter()
+        .map(|light| {
+            let layers = match light.kind {
+                _ if !light.enabled || !light.cast_shadows => return None,
+                LightKind::Directional => cascades,
+                LightKind::Spot => 1,
+                LightKind::Point => return None,
+            };
+            if next + layers > MAX_SHADOW_LAYERS {
+                return None;
+            }
+            next += layers;
+            Some(next - layers)
+        })
+        .collect()
+}
+
+/// Shadow maps for directional (cascaded) and spot lights, rendered into
+/// layers of one depth texture array and sampled with PCF in mesh.wgsl.
+/// The array only has as many layers as the lights have needed so far, and
+/// is a 1x1 placeholder until one casts a shadow.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    depth_format: wgpu::TextureFormat,
+    /// One per allocated layer, none for the placeholder.
+    layer_views: Vec<wgpu::TextureView>,
+    /// Bound by `Lights`, which has to rebind them when `update` grows the
+    /// array.
+    pub texture: wgpu::Texture,
+    pub array_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub header_buffer: wgpu::Buffer,
+    /// `array<mat4x4f>` of light view-projections, one per layer.
+    pub views_buffer: wgpu::Buffer,
+    /// The same matrices at `PASS_UNIFORM_STRIDE`, bound with a dynamic
+    /// offset while rendering each layer.
+    pass_buffer: wgpu::Buffer,
+    pass_bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    /// Layers that were given a matrix in the last `update`.
+    active_layers: u32,
+}
+
+impl ShadowMaps {
+    /// Checks `ShadowHeader` against the mesh shader.
+    pub fn check_shader(shader: &ShaderReflection) -> Result<(), String> {
+        shader.check_uniform::<ShadowHeader>()
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        depth_format: wgpu::TextureFormat,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        settings: ShadowSettings,
+    ) -> Self {
+        let settings = ShadowSettings {
+            cascades: settings.cascades.clamp(1, MAX_CASCADES),
+            resolution: settings
+                .resolution
+                .clamp(1, device.limits().max_texture_dimension_2d),
+            ..settings
+        };
+        let (texture, layer_views, array_view) = Self::create_texture(device, depth_format, 1, 0);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow map sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let header_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow header buffer"),
+            size: std::mem::size_of::<ShadowHeader>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let views_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow views buffer"),
+            size: (std::mem::size_of::<[[f32; 4]; 4]>() as u32 * MAX_SHADOW_LAYERS) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pass_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow pass buffer"),
+            size: PASS_UNIFORM_STRIDE * MAX_SHADOW_LAYERS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (pass_bind_group_layout, pass_bind_group) = BindGroupBuilder::new("Shadow pass")
+            .dynamic_uniform(
+                wgpu::ShaderStages::VERTEX,
+                &pass_buffer,
+                wgpu::BufferSize::new(64).unwrap(),
+            )
+            .build_cached(device, cache);
+
+        let shader = cache.shader_module(device, "Shadow shader", include_str!("shadow.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow pipeline layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let mut builder = PipelineBuilder::depth_only("Shadow pipeline", &pipeline_layout, &shader);
+        for layout in vertex_layouts {
+            builder = builder.vertex_buffer(layout.clone());
+        }
+        // Unculled, since single-sided geometry like the ground plane still
+        // has to cast shadows.
+        let pipeline = builder
+            .depth(depth_format, wgpu::CompareFunction::LessEqual, true)
+            .depth_bias(settings.constant_bias, settings.slope_bias)
+            .build_cached(device, cache);
+
+        Self {
+            settings,
+            depth_format,
+            layer_views,
+            texture,
+            array_view,
+            sampler,
+            header_buffer,
+            views_buffer,
+            pass_buffer,
+            pass_bind_group,
+            pipeline,
+            active_layers: 0,
+        }
+    }
+
+    /// A `resolution`² array of `layers` layers, a view of each and one of
+    /// the whole array, or a 1x1 placeholder with no layer views if `layers`
+    /// is 0.
+    fn create_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        resolution: u32,
+        layers: u32,
+    ) -> (wgpu::Texture, Vec<wgpu::TextureView>, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow map texture"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: layers.max(1),
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let layer_views = (0..layers)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow map layer view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    aspect: wgpu::TextureAspect::DepthOnly,
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        // A single layer still has to be viewed as an array.
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow map array view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        (texture, layer_views, array_view)
+    }
+
+    /// Fits a light view-projection to each assigned layer. `shadow_distance`
+    /// is how far from the camera directional shadows reach, and
+    /// `caster_extent` how far behind a cascade casters can still be.
+    /// Returns whether the array had to grow, replacing `texture` and `array_view`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[Light],
+        layers: &[Option<u32>],
+        camera: &Camera,
+        projection: &Projection,
+        shadow_distance: f32,
+        caster_extent: f32,
+    ) -> bool {
+        let cascades = self.settings.cascades;
+        let far = shadow_distance.max(projection.znear * 2.0);
+        let splits = cascade_splits(projection.znear, far, cascades);
+
+        let mut matrices = vec![Matrix4::identity(); MAX_SHADOW_LAYERS as usize];
+        self.active_layers = 0;
+        for (light, first) in lights.iter().zip(layers) {
+            let Some(first) = *first else {
+                continue;
+            };
+            match light.kind {
+                LightKind::Directional => {
+                    let mut near = projection.znear;
+                    for (i, &split) in splits.iter().enumerate() {
+                        matrices[first as usize + i] = self.cascade_matrix(
+                            light.direction,
+                            camera,
+                            projection,
+                            near,
+                            split,
+                            caster_extent,
+                        );
+                        near = split;
+                    }
+                    self.active_layers = self.active_layers.max(first + cascades);
+                }
+                LightKind::Spot => {
+                    matrices[first as usize] = spot_matrix(light);
+                    self.active_layers = self.active_layers.max(first + 1);
+                }
+                LightKind::Point => {}
+            }
+        }
+
+        let grown = self.active_layers > self.layer_views.len() as u32;
+        if grown {
+            let resolution = self.settings.resolution;
+            log::info!(
+                "Growing the shadow map array to {} {resolution}x{resolution} layers",
+                self.active_layers
+            );
+            (self.texture, self.layer_views, self.array_view) =
+                Self::create_texture(device, self.depth_format, resolution, self.active_layers);
+        }
+
+        let mut cascade_splits = [f32::MAX; 4];
+        cascade_splits[..splits.len()].copy_from_slice(&splits);
+        queue.write_buffer(
+            &self.header_buffer,
+            0,
+            bytemuck::bytes_of(&ShadowHeader {
+                cascade_splits,
+                cascade_count: cascades,
+                pcf_radius: self.settings.pcf_radius,
+                texel_size: 1.0 / self.settings.resolution as f32,
+                normal_bias: self.settings.normal_bias,
+            }),
+        );
+        let raw: Vec<[[f32; 4]; 4]> = matrices.iter().map(|&m| m.into()).collect();
+        queue.write_buffer(&self.views_buffer, 0, bytemuck::cast_slice(&raw));
+        for (i, matrix) in raw.iter().enumerate().take(self.active_layers as usize) {
+            queue.write_buffer(
+                &self.pass_buffer,
+                i as u64 * PASS_UNIFORM_STRIDE,
+                bytemuck::bytes_of(matrix),
+            );
+        }
+        grown
+    }
+
+    /// An orthographic projection around the bounding sphere of the camera
+    /// frustum slice between `near` and `far`, snapped to whole texels so
+    /// shadow edges don't shimmer as the camera moves.
+    fn cascade_matrix(
+        &self,
+        direction: Vector3<f32>,
+        camera: &Camera,
+        projection: &Projection,
+        near: f32,
+        far: f32,
+        caster_extent: f32,
+    ) -> Matrix4<f32> {
+        let tan_y = (projection.fovy.0 / 2.0).tan();
+        let tan_x = tan_y * projection.aspect;
+        let inv_view = camera.view_matrix().invert().unwrap();
+        let corners: Vec<Point3<f32>> = [near, far]
+            .iter()
+            .flat_map(|&z| {
+                [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .map(|(x, y)| Point3::new(x * tan_x * z, y * tan_y * z, -z))
+            })
+            .map(|p| inv_view.transform_point(p))
+            .collect();
+        let center = Point3::centroid(&corners);
+        let radius = corners
+            .iter()
+            .map(|p| (p - center).magnitude())
+            .fold(0.0, f32::max)
+            // Rounding up keeps the texel size constant between frames.
+            .ceil();
+
+        let light_view = Matrix4::look_to_rh(Point3::origin(), direction, up_for(direction));
+        let mut light_center = light_view.transform_point(center);
+        let texel = 2.0 * radius / self.settings.resolution as f32;
+        light_center.x = (light_center.x / texel).floor() * texel;
+        light_center.y = (light_center.y / texel).floor() * texel;
+
+        let proj = cgmath::ortho(
+            light_center.x - radius,
+            light_center.x + radius,
+            light_center.y - radius,
+            light_center.y + radius,
+            -light_center.z - radius - caster_extent,
+            -light_center.z + radius,
+        );
+        OPENGL_TO_WGPU_MATRIX * proj * light_view
+    }
+
+    /// Layers that were given a light in the last `update`, each needing a
+    /// pass from `begin_render_pass`.
+    pub fn active_layers(&self) -> u32 {
+        self.active_layers
+    }
+
+    /// Starts a depth-only pass into `layer` with the shadow pipeline and
+    /// that layer's light matrix bound. The caller draws the scene with the
+    /// vertex and instance buffers at slots 0 and 1.
+    pub fn begin_render_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        layer: u32,
+    ) -> wgpu::RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.layer_views[layer as usize],
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(
+            0,
+            &self.pass_bind_group,
+            &[(layer as u64 * PASS_UNIFORM_STRIDE) as u32],
+        );
+        render_pass
+    }
+}
+
+/// Far distance of each cascade, blending logarithmic and uniform splits.
+fn cascade_splits(near: f32, far: f32, count: u32) -> Vec<f32> {
+    const LAMBDA: f32 = 0.75;
+    (1..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            LAMBDA * log + (1.0 - LAMBDA) * uniform
+        })
+        .collect()
+}
+
+fn spot_matrix(light: &Light) -> Matrix4<f32> {
+    let view = Matrix4::look_to_rh(light.position, light.direction, up_for(light.direction));
+    let proj = cgmath::perspective(light.outer_angle * 2.0, 1.0, 0.05, light.range.max(0.1));
+    OPENGL_TO_WGPU_MATRIX * proj * view
+}
+
+/// An up vector that isn't parallel to `direction`.
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.normalize().y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
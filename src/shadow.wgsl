@@ -0,0 +1,19 @@
+// Depth-only pass rendering the scene from one light's point of view.
+
+@group(0) @binding(0) var<uniform> light_view_proj: mat4x4f;
+
+struct VertexInput {
+    @location(0) position: vec3f,
+}
+struct InstanceInput {
+    @location(5) model_0: vec4f,
+    @location(6) model_1: vec4f,
+    @location(7) model_2: vec4f,
+    @location(8) model_3: vec4f,
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4f {
+    let model = mat4x4f(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    return light_view_proj * model * vec4f(in.position, 1.0);
+}
@@ -0,0 +1,87 @@
+use crate::builder::PipelineBuilder;
+use crate::camera::CameraUniform;
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect;
+use crate::texture::CubeTexture;
+use crate::TargetFormats;
+use std::rc::Rc;
+
+/// Draws a `CubeTexture` behind everything else in the main pass. Depth is
+/// tested against the scene's depth buffer but never written.
+pub struct Skybox {
+    // Kept alive alongside the bind group that references it.
+    _cube: CubeTexture,
+    bind_group: wgpu::BindGroup,
+    shader: Rc<wgpu::ShaderModule>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Skybox {
+    /// `camera_layout` is the layout of the bind group passed to `draw`,
+    /// holding the same `Camera` uniform the mesh shader uses.
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        camera_layout: &wgpu::BindGroupLayout,
+        cube: CubeTexture,
+        targets: TargetFormats,
+    ) -> Self {
+        let (cube_layout, bind_group) = cube.bind_group(device, cache);
+        let source = include_str!("skybox.wgsl");
+        reflect::assert_matches("skybox.wgsl", source, |shader| {
+            shader.check_uniform::<CameraUniform>()
+        });
+        let shader = cache.shader_module(device, "Skybox shader", source);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox pipeline layout"),
+            bind_group_layouts: &[camera_layout, &cube_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, cache, &pipeline_layout, &shader, targets);
+        Self {
+            _cube: cube,
+            bind_group,
+            shader,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        targets: TargetFormats,
+    ) -> Rc<wgpu::RenderPipeline> {
+        // The triangle sits exactly on the far plane, where the depth buffer
+        // was cleared to.
+        PipelineBuilder::new("Skybox pipeline", layout, shader, targets.color)
+            .depth(targets.depth, wgpu::CompareFunction::LessEqual, false)
+            .sample_count(targets.sample_count)
+            .build_cached(device, cache)
+    }
+
+    pub fn set_targets(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+    ) {
+        self.pipeline =
+            Self::create_pipeline(device, cache, &self.pipeline_layout, &self.shader, targets);
+    }
+    /// Has to come after the opaque geometry in the same pass so the depth
+    /// test can skip covered pixels.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
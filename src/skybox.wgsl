@@ -0,0 +1,40 @@
+// Draws a cubemap behind the scene with a fullscreen triangle on the far
+// plane, so only pixels nothing else covered pass the depth test.
+
+struct Camera {
+    view: mat4x4f,
+    proj: mat4x4f,
+    view_proj: mat4x4f,
+    position: vec4f,
+}
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@group(1) @binding(0) var sky: texture_cube<f32>;
+@group(1) @binding(1) var sky_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) direction: vec3f,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    // The view ray through this corner in view space, rotated back into
+    // world space. Only the rotation part of the view matrix matters.
+    let view_ray = vec3f(ndc.x / camera.proj[0][0], ndc.y / camera.proj[1][1], -1.0);
+    let rotation = mat3x3f(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+    var out: VertexOutput;
+    // z == w puts the triangle at depth 1.0, which the cleared depth buffer
+    // still passes with LessEqual.
+    out.clip_position = vec4f(ndc, 1.0, 1.0);
+    out.direction = transpose(rotation) * view_ray;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return vec4f(textureSample(sky, sky_sampler, normalize(in.direction)).rgb, 1.0);
+}
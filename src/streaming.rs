@@ -0,0 +1,350 @@
+//! Streaming CPU-side image updates into a texture without stalling.
+//!
+//! Writes go into a CPU copy of the image and mark the region dirty. Each
+//! frame the dirty regions are packed into one of a ring of staging buffers
+//! and copied into the texture by the frame's encoder. A staging buffer is
+//! only reused once the GPU is done with it and `map_async` has handed it
+//! back, so when the GPU falls behind, updates wait on the CPU (merging with
+//! later ones) instead of blocking the frame.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Enough for one upload per frame with up to 3 frames in flight.
+const STAGING_BUFFERS: usize = 3;
+/// More dirty regions than this are merged into their bounds.
+const MAX_DIRTY_RECTS: usize = 16;
+
+/// A region of the image, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    /// Whether the two overlap or share an edge. Such regions are merged
+    /// into their bounds rather than copied separately.
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+}
+
+/// Rows in a buffer copy have to be padded to a multiple of 256 bytes.
+fn padded_bytes_per_row(row_bytes: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    row_bytes.div_ceil(align) * align
+}
+
+/// Adds `rect` to `dirty`, merged with whatever it touches, repeatedly,
+/// since the merged rect may touch others. Past `MAX_DIRTY_RECTS` they're
+/// all merged into their bounds.
+fn add_dirty(dirty: &mut Vec<Rect>, rect: Rect) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+    let mut rect = rect;
+    while let Some(i) = dirty.iter().position(|r| r.touches(&rect)) {
+        rect = rect.union(&dirty.swap_remove(i));
+    }
+    dirty.push(rect);
+    if dirty.len() > MAX_DIRTY_RECTS {
+        *dirty = vec![bounds(dirty)];
+    }
+}
+
+fn bounds(rects: &[Rect]) -> Rect {
+    rects.iter().copied().reduce(|a, b| a.union(&b)).unwrap()
+}
+
+/// `rects`, or their bounds if their padded rows don't fit in `size`
+/// bytes. Each region's rows are padded separately, which for many narrow
+/// regions can take more room than the whole image.
+fn fit_staging(rects: Vec<Rect>, bytes_per_pixel: u32, size: u64) -> Vec<Rect> {
+    let packed_size: u64 = rects
+        .iter()
+        .map(|r| padded_bytes_per_row(r.width * bytes_per_pixel) as u64 * r.height as u64)
+        .sum();
+    match packed_size > size {
+        true => vec![bounds(&rects)],
+        false => rects,
+    }
+}
+
+struct StagingBuffer {
+    buffer: wgpu::Buffer,
+    /// Mapped and free to write into. Set from `map_async`'s callback.
+    ready: Arc<AtomicBool>,
+    /// Used by the frame being recorded, to be mapped again once it's
+    /// submitted.
+    in_use: bool,
+}
+
+/// What the last uploads did, for the settings panel.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StreamStats {
+    /// Bytes copied into the texture by the last frame.
+    pub uploaded_bytes: u64,
+    /// Frames that had something to upload but no free staging buffer.
+    pub deferred_frames: u64,
+}
+
+/// A texture's contents on the CPU, streamed to it as they change.
+pub struct StreamingTexture {
+    /// Tightly packed rows of texels.
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    dirty: Vec<Rect>,
+    staging: Vec<StagingBuffer>,
+    pub stats: StreamStats,
+}
+
+impl StreamingTexture {
+    /// Starts out with all of `data`, texels of `format`, dirty, so the
+    /// first `upload` fills the texture.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    ) -> Self {
+        let bytes_per_pixel = format
+            .block_size(None)
+            .expect("streaming needs an uncompressed color format");
+        assert_eq!(data.len(), (width * height * bytes_per_pixel) as usize);
+        // Big enough for the whole image, which any set of dirty regions
+        // can fall back to.
+        let size = padded_bytes_per_row(width * bytes_per_pixel) as u64 * height as u64;
+        let staging = (0..STAGING_BUFFERS)
+            .map(|_| StagingBuffer {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Streaming staging buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                }),
+                ready: Arc::new(AtomicBool::new(true)),
+                in_use: false,
+            })
+            .collect();
+        Self {
+            data,
+            width,
+            height,
+            bytes_per_pixel,
+            dirty: vec![Rect::new(0, 0, width, height)],
+            staging,
+            stats: StreamStats::default(),
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Replaces `rect` with `pixels`, tightly packed rows of texels.
+    pub fn write(&mut self, rect: Rect, pixels: &[u8]) {
+        assert!(rect.right() <= self.width && rect.bottom() <= self.height);
+        let row_bytes = (rect.width * self.bytes_per_pixel) as usize;
+        assert_eq!(pixels.len(), row_bytes * rect.height as usize);
+        let stride = (self.width * self.bytes_per_pixel) as usize;
+        for (row, src) in pixels.chunks_exact(row_bytes).enumerate() {
+            let start = (rect.y as usize + row) * stride + (rect.x * self.bytes_per_pixel) as usize;
+            self.data[start..start + row_bytes].copy_from_slice(src);
+        }
+        self.mark_dirty(rect);
+    }
+
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        add_dirty(&mut self.dirty, rect);
+    }
+
+    /// Records copies of the dirty regions into `texture`, if a staging
+    /// buffer is free. Otherwise they stay dirty for the next frame. Call
+    /// `submitted` once `encoder` has been submitted.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        self.stats.uploaded_bytes = 0;
+        if self.dirty.is_empty() {
+            return;
+        }
+        // Runs the callbacks of finished mappings, without waiting.
+        device.poll(wgpu::Maintain::Poll);
+        let Some(staging) = self
+            .staging
+            .iter_mut()
+            .find(|s| !s.in_use && s.ready.load(Ordering::Acquire))
+        else {
+            self.stats.deferred_frames += 1;
+            return;
+        };
+
+        let rects = fit_staging(
+            std::mem::take(&mut self.dirty),
+            self.bytes_per_pixel,
+            staging.buffer.size(),
+        );
+
+        let texel = self.bytes_per_pixel;
+        let stride = (self.width * texel) as usize;
+        let mut offset = 0;
+        {
+            let mut mapped = staging.buffer.slice(..).get_mapped_range_mut();
+            for rect in &rects {
+                let row_bytes = (rect.width * texel) as usize;
+                let padded = padded_bytes_per_row(rect.width * texel);
+                for row in 0..rect.height {
+                    let src = (rect.y + row) as usize * stride + (rect.x * texel) as usize;
+                    let dst = offset + (row * padded) as usize;
+                    mapped[dst..dst + row_bytes].copy_from_slice(&self.data[src..src + row_bytes]);
+                }
+                encoder.copy_buffer_to_texture(
+                    wgpu::ImageCopyBuffer {
+                        buffer: &staging.buffer,
+                        layout: wgpu::ImageDataLayout {
+                            offset: offset as u64,
+                            bytes_per_row: Some(padded),
+                            rows_per_image: Some(rect.height),
+                        },
+                    },
+                    wgpu::ImageCopyTexture {
+                        texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: rect.x,
+                            y: rect.y,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::Extent3d {
+                        width: rect.width,
+                        height: rect.height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+                offset += (padded * rect.height) as usize;
+                self.stats.uploaded_bytes += row_bytes as u64 * rect.height as u64;
+            }
+        }
+        staging.buffer.unmap();
+        staging.ready.store(false, Ordering::Release);
+        staging.in_use = true;
+    }
+
+    /// Maps the staging buffers the submitted frame used again, to be
+    /// reused once the GPU is done copying out of them.
+    pub fn submitted(&mut self) {
+        for staging in self.staging.iter_mut().filter(|s| s.in_use) {
+            staging.in_use = false;
+            let ready = staging.ready.clone();
+            staging
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Write, move |result| {
+                    if result.is_ok() {
+                        ready.store(true, Ordering::Release);
+                    }
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touching_rects() {
+        let a = Rect::new(0, 0, 4, 4);
+        // Overlapping.
+        assert!(a.touches(&Rect::new(2, 2, 4, 4)));
+        assert_eq!(a.union(&Rect::new(2, 2, 4, 4)), Rect::new(0, 0, 6, 6));
+        // Sharing the right edge or the bottom one.
+        assert!(a.touches(&Rect::new(4, 0, 2, 4)));
+        assert_eq!(a.union(&Rect::new(4, 0, 2, 4)), Rect::new(0, 0, 6, 4));
+        assert!(Rect::new(0, 4, 4, 1).touches(&a));
+        // Sharing a corner.
+        assert!(a.touches(&Rect::new(4, 4, 1, 1)));
+        // A column apart.
+        assert!(!a.touches(&Rect::new(5, 0, 1, 4)));
+        assert!(!Rect::new(0, 5, 4, 1).touches(&a));
+        // Contained.
+        assert_eq!(a.union(&Rect::new(1, 1, 1, 1)), a);
+    }
+
+    #[test]
+    fn dirty_rects_merge_transitively() {
+        let mut dirty = vec![];
+        add_dirty(&mut dirty, Rect::new(0, 0, 2, 2));
+        add_dirty(&mut dirty, Rect::new(10, 0, 2, 2));
+        add_dirty(&mut dirty, Rect::new(0, 0, 0, 5));
+        assert_eq!(dirty.len(), 2);
+        // Bridges the two, which then merge into one.
+        add_dirty(&mut dirty, Rect::new(2, 0, 8, 1));
+        assert_eq!(dirty, [Rect::new(0, 0, 12, 2)]);
+    }
+
+    #[test]
+    fn too_many_dirty_rects_merge_into_their_bounds() {
+        let mut dirty = vec![];
+        for i in 0..MAX_DIRTY_RECTS as u32 {
+            add_dirty(&mut dirty, Rect::new(3 * i, 10, 1, 1));
+        }
+        assert_eq!(dirty.len(), MAX_DIRTY_RECTS);
+        add_dirty(&mut dirty, Rect::new(0, 0, 1, 1));
+        let right = 3 * (MAX_DIRTY_RECTS as u32 - 1) + 1;
+        assert_eq!(dirty, [Rect::new(0, 0, right, 11)]);
+    }
+
+    #[test]
+    fn narrow_rects_fall_back_to_their_bounds() {
+        // A 64x64 RGBA image fits in 64 rows of 256 bytes, and so do eight
+        // columns a texel wide and 8 tall, each row padded to 256 bytes.
+        let size = padded_bytes_per_row(64 * 4) as u64 * 64;
+        let columns: Vec<_> = (0..8).map(|i| Rect::new(8 * i, 0, 1, 8)).collect();
+        assert_eq!(fit_staging(columns.clone(), 4, size), columns);
+        // One more row each doesn't.
+        let columns: Vec<_> = (0..8).map(|i| Rect::new(8 * i, 0, 1, 9)).collect();
+        assert_eq!(fit_staging(columns, 4, size), [Rect::new(0, 0, 57, 9)]);
+    }
+}
@@ -0,0 +1,816 @@
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{self, shader_struct};
+pub use ab_glyph::FontArc;
+use ab_glyph::{point, Font, GlyphId, PxScale, ScaleFont};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Font used for overlays when nothing else is asked for.
+pub const DEFAULT_FONT: &str = "assets/fonts/DejaVuSans.ttf";
+
+const ATLAS_SIZE: u32 = 1024;
+/// Empty texels kept around each glyph so filtering doesn't pick up its
+/// neighbours.
+const ATLAS_PADDING: u32 = 1;
+/// Pixel size SDF glyphs are rasterized at. Every other size is scaled from
+/// it.
+const SDF_SIZE: f32 = 48.0;
+/// How far, in pixels at `SDF_SIZE`, the distance field reaches either side
+/// of the outline.
+const SDF_SPREAD: f32 = 6.0;
+
+/// Reads a TrueType or OpenType font.
+pub fn load_font(path: &Path) -> Result<FontArc, String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    FontArc::try_from_vec(data).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// How glyphs are stored in the atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rasterization {
+    /// Coverage at the exact pixel size. Crisp, but every size is a separate
+    /// atlas entry.
+    Bitmap,
+    /// A signed distance field at `SDF_SIZE`, scaled to any size with smooth
+    /// edges.
+    Sdf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    /// Font size in pixels, from the highest ascender to the lowest
+    /// descender.
+    pub size: f32,
+    /// sRGB color with straight alpha.
+    pub color: [f32; 4],
+    /// Aligns each line within `max_width`, or within the widest line when
+    /// there is no limit.
+    pub align: Align,
+    /// Lines wrap at spaces to stay narrower than this many pixels. Single
+    /// words that don't fit overflow instead of being broken.
+    pub max_width: Option<f32>,
+    /// Multiplies the font's own line height.
+    pub line_spacing: f32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            align: Align::Left,
+            max_width: None,
+            line_spacing: 1.0,
+        }
+    }
+}
+
+/// A glyph placed by `layout`, relative to the top left of the text block.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionedGlyph {
+    pub id: GlyphId,
+    pub x: f32,
+    pub baseline: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Breaks `text` into lines at newlines and, with `max_width`, at spaces,
+/// applying kerning between neighbouring glyphs.
+pub fn layout(font: &FontArc, text: &str, style: &TextStyle) -> TextLayout {
+    let font = font.as_scaled(PxScale::from(style.size));
+    let line_height = (font.height() + font.line_gap()) * style.line_spacing;
+
+    // Glyphs with their x within the line, and the line's visible width.
+    let mut lines: Vec<(Vec<(GlyphId, f32)>, f32)> = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = vec![];
+        let mut line_width = 0.0;
+        let mut pen = 0.0;
+        let mut previous: Option<GlyphId> = None;
+        for word in paragraph.split_inclusive(' ') {
+            let ids: Vec<GlyphId> = word.chars().map(|c| font.glyph_id(c)).collect();
+            let mut word_pen = 0.0;
+            let mut word_width = 0.0;
+            let mut placed = vec![];
+            for (i, &id) in ids.iter().enumerate() {
+                if i > 0 {
+                    word_pen += font.kern(ids[i - 1], id);
+                }
+                placed.push((id, word_pen));
+                word_pen += font.h_advance(id);
+                if word.chars().nth(i) != Some(' ') {
+                    word_width = word_pen;
+                }
+            }
+            let kern = match (previous, ids.first()) {
+                (Some(a), Some(&b)) => font.kern(a, b),
+                _ => 0.0,
+            };
+            let overflows = style
+                .max_width
+                .is_some_and(|max| pen + kern + word_width > max);
+            if overflows && !line.is_empty() {
+                lines.push((std::mem::take(&mut line), line_width));
+                pen = 0.0;
+            } else {
+                pen += kern;
+            }
+            line.extend(placed.into_iter().map(|(id, x)| (id, pen + x)));
+            if word_width > 0.0 {
+                line_width = pen + word_width;
+            }
+            pen += word_pen;
+            previous = ids.last().copied();
+        }
+        lines.push((line, line_width));
+    }
+
+    let width = style
+        .max_width
+        .unwrap_or_else(|| lines.iter().map(|(_, width)| *width).fold(0.0, f32::max));
+    let factor = match style.align {
+        Align::Left => 0.0,
+        Align::Center => 0.5,
+        Align::Right => 1.0,
+    };
+    let mut glyphs = vec![];
+    for (i, (line, line_width)) in lines.iter().enumerate() {
+        let offset = (width - line_width) * factor;
+        let baseline = font.ascent() + i as f32 * line_height;
+        glyphs.extend(line.iter().map(|&(id, x)| PositionedGlyph {
+            id,
+            x: offset + x,
+            baseline,
+        }));
+    }
+    TextLayout {
+        glyphs,
+        width,
+        height: lines.len() as f32 * line_height,
+    }
+}
+
+/// Matches `Params` in text.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextUniform {
+    screen_size: [f32; 2],
+    sdf: u32,
+    _pad: u32,
+}
+
+shader_struct!(TextUniform = "Params" { screen_size, sdf });
+
+/// One glyph quad. Matches `Instance` in text.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    /// Left, top, width and height in pixels.
+    rect: [f32; 4],
+    /// The same for the atlas, normalized.
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+impl GlyphInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+struct Section {
+    text: String,
+    position: [f32; 2],
+    style: TextStyle,
+}
+
+/// Draws text over whatever is already in the target. Text is queued with
+/// `queue_text` during the frame, laid out and uploaded by `prepare`, and
+/// drawn by `render` as one batch of instanced quads.
+pub struct TextRenderer {
+    font: FontArc,
+    rasterization: Rasterization,
+    output_format: wgpu::TextureFormat,
+    atlas: GlyphAtlas,
+    sections: Vec<Section>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        output_format: wgpu::TextureFormat,
+        font: FontArc,
+        rasterization: Rasterization,
+    ) -> Self {
+        let atlas = GlyphAtlas::new(device, rasterization);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Text uniform buffer"),
+            size: std::mem::size_of::<TextUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("Text")
+            .uniform(wgpu::ShaderStages::VERTEX_FRAGMENT, &uniform_buffer)
+            .texture(wgpu::ShaderStages::FRAGMENT, &atlas.texture, &atlas.view)
+            .sampler(
+                wgpu::ShaderStages::FRAGMENT,
+                &atlas.sampler,
+                wgpu::SamplerBindingType::Filtering,
+            )
+            .build_cached(device, cache);
+        let source = include_str!("text.wgsl");
+        reflect::assert_matches("text.wgsl", source, |shader| {
+            shader.check_uniform::<TextUniform>()
+        });
+        let shader = cache.shader_module(device, "Text shader", source);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = PipelineBuilder::new("Text pipeline", &layout, &shader, output_format)
+            .vertex_buffer(GlyphInstance::desc())
+            .blend(wgpu::BlendState::ALPHA_BLENDING)
+            .topology(wgpu::PrimitiveTopology::TriangleStrip)
+            .build_cached(device, cache);
+        let instance_buffer = Self::create_instance_buffer(device, 256);
+        Self {
+            font,
+            rasterization,
+            output_format,
+            atlas,
+            sections: vec![],
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            instance_buffer,
+            instance_count: 0,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Glyph instance buffer"),
+            size: capacity * std::mem::size_of::<GlyphInstance>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Size of `text` as `queue_text` would lay it out.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> [f32; 2] {
+        let layout = layout(&self.font, text, style);
+        [layout.width, layout.height]
+    }
+
+    /// Adds `text` with its top left at `position`, in pixels from the top
+    /// left of the target, to the next `prepare`.
+    pub fn queue_text(&mut self, text: &str, position: [f32; 2], style: &TextStyle) {
+        self.sections.push(Section {
+            text: text.to_string(),
+            position,
+            style: *style,
+        });
+    }
+
+    /// Lays out everything queued since the last call, rasterizes glyphs the
+    /// atlas doesn't have yet and uploads the quads for `render`.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        let sections = std::mem::take(&mut self.sections);
+        let instances = match self.build_instances(queue, &sections) {
+            Some(instances) => instances,
+            None => {
+                // Start over with only the glyphs this frame needs.
+                log::debug!("Glyph atlas full, clearing it");
+                self.atlas.clear();
+                self.build_instances(queue, &sections).unwrap_or_else(|| {
+                    log::warn!("Text doesn't fit in the glyph atlas");
+                    vec![]
+                })
+            }
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&TextUniform {
+                screen_size: [width as f32, height as f32],
+                sdf: (self.rasterization == Rasterization::Sdf) as u32,
+                _pad: 0,
+            }),
+        );
+        let capacity = self.instance_buffer.size() / std::mem::size_of::<GlyphInstance>() as u64;
+        if instances.len() as u64 > capacity {
+            let capacity = (instances.len() as u64).next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        self.instance_count = instances.len() as u32;
+    }
+
+    /// `None` if the atlas ran out of room partway through.
+    fn build_instances(
+        &mut self,
+        queue: &wgpu::Queue,
+        sections: &[Section],
+    ) -> Option<Vec<GlyphInstance>> {
+        let mut instances = vec![];
+        for section in sections {
+            let style = &section.style;
+            let color = if self.output_format.is_srgb() {
+                let [r, g, b, a] = style.color;
+                [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+            } else {
+                style.color
+            };
+            let scale = match self.rasterization {
+                Rasterization::Bitmap => 1.0,
+                Rasterization::Sdf => style.size / SDF_SIZE,
+            };
+            for glyph in layout(&self.font, &section.text, style).glyphs {
+                let entry =
+                    self.atlas
+                        .get(queue, &self.font, self.rasterization, glyph.id, style.size);
+                let Some(entry) = entry.ok()? else {
+                    continue;
+                };
+                let (mut x, mut y) = (
+                    section.position[0] + glyph.x,
+                    section.position[1] + glyph.baseline,
+                );
+                if self.rasterization == Rasterization::Bitmap {
+                    // Keep texels on pixels.
+                    (x, y) = (x.round(), y.round());
+                }
+                let atlas_size = ATLAS_SIZE as f32;
+                instances.push(GlyphInstance {
+                    rect: [
+                        x + entry.offset[0] * scale,
+                        y + entry.offset[1] * scale,
+                        entry.size[0] as f32 * scale,
+                        entry.size[1] as f32 * scale,
+                    ],
+                    uv_rect: [
+                        entry.origin[0] as f32 / atlas_size,
+                        entry.origin[1] as f32 / atlas_size,
+                        entry.size[0] as f32 / atlas_size,
+                        entry.size[1] as f32 / atlas_size,
+                    ],
+                    color,
+                });
+            }
+        }
+        Some(instances)
+    }
+
+    /// Draws the quads from the last `prepare` over the existing contents of
+    /// `output`, which must have the format passed to `new`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..self.instance_count);
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Where a glyph lives in the atlas and how to place it relative to the pen.
+#[derive(Debug, Clone, Copy)]
+struct AtlasEntry {
+    origin: [u32; 2],
+    size: [u32; 2],
+    /// Top left of the bitmap relative to the pen position on the baseline,
+    /// at the size it was rasterized at.
+    offset: [f32; 2],
+}
+
+/// Bitmap glyphs are keyed by their exact size, SDF glyphs by id alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    id: GlyphId,
+    size_bits: u32,
+}
+
+/// An R8 texture of rasterized glyphs, packed into shelves. When it fills up
+/// it is cleared and refilled with whatever the current frame needs.
+struct GlyphAtlas {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    /// `None` for glyphs without an outline, such as spaces.
+    entries: HashMap<GlyphKey, Option<AtlasEntry>>,
+    cursor_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+}
+
+impl GlyphAtlas {
+    fn new(device: &wgpu::Device, rasterization: Rasterization) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        // Bitmap glyphs are drawn texel-aligned, so only distance fields
+        // benefit from filtering.
+        let filter = match rasterization {
+            Rasterization::Bitmap => wgpu::FilterMode::Nearest,
+            Rasterization::Sdf => wgpu::FilterMode::Linear,
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph atlas sampler"),
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+            entries: HashMap::new(),
+            cursor_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor_x = 0;
+        self.shelf_y = 0;
+        self.shelf_height = 0;
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        let (width, height) = (width + ATLAS_PADDING, height + ATLAS_PADDING);
+        if self.cursor_x + width > ATLAS_SIZE {
+            self.shelf_y += self.shelf_height;
+            self.cursor_x = 0;
+            self.shelf_height = 0;
+        }
+        if self.cursor_x + width > ATLAS_SIZE || self.shelf_y + height > ATLAS_SIZE {
+            return None;
+        }
+        let origin = [self.cursor_x, self.shelf_y];
+        self.cursor_x += width;
+        self.shelf_height = self.shelf_height.max(height);
+        Some(origin)
+    }
+
+    /// Looks up a glyph, rasterizing and uploading it on first use. Fails
+    /// only when the atlas has no room left.
+    fn get(
+        &mut self,
+        queue: &wgpu::Queue,
+        font: &FontArc,
+        rasterization: Rasterization,
+        id: GlyphId,
+        size: f32,
+    ) -> Result<Option<AtlasEntry>, ()> {
+        let key = GlyphKey {
+            id,
+            size_bits: match rasterization {
+                Rasterization::Bitmap => size.to_bits(),
+                Rasterization::Sdf => 0,
+            },
+        };
+        if let Some(entry) = self.entries.get(&key) {
+            return Ok(*entry);
+        }
+        let bitmap = match rasterization {
+            Rasterization::Bitmap => rasterize(font, id, size, 0),
+            Rasterization::Sdf => {
+                rasterize(font, id, SDF_SIZE, SDF_SPREAD.ceil() as u32).map(signed_distance)
+            }
+        };
+        let Some(bitmap) = bitmap else {
+            self.entries.insert(key, None);
+            return Ok(None);
+        };
+        let origin = self.allocate(bitmap.width, bitmap.height).ok_or(())?;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin[0],
+                    y: origin[1],
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &bitmap.texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bitmap.width),
+                rows_per_image: Some(bitmap.height),
+            },
+            wgpu::Extent3d {
+                width: bitmap.width,
+                height: bitmap.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let entry = AtlasEntry {
+            origin,
+            size: [bitmap.width, bitmap.height],
+            offset: bitmap.offset,
+        };
+        self.entries.insert(key, Some(entry));
+        Ok(Some(entry))
+    }
+}
+
+struct GlyphBitmap {
+    width: u32,
+    height: u32,
+    texels: Vec<u8>,
+    offset: [f32; 2],
+}
+
+/// Coverage of glyph `id` at `size` pixels, with `padding` empty texels on
+/// every side. `None` if the glyph has no outline.
+fn rasterize(font: &FontArc, id: GlyphId, size: f32, padding: u32) -> Option<GlyphBitmap> {
+    let outlined = font.outline_glyph(id.with_scale_and_position(size, point(0.0, 0.0)))?;
+    let bounds = outlined.px_bounds();
+    let width = bounds.width() as u32 + 2 * padding;
+    let height = bounds.height() as u32 + 2 * padding;
+    let mut texels = vec![0; (width * height) as usize];
+    outlined.draw(|x, y, coverage| {
+        let index = (y + padding) * width + x + padding;
+        texels[index as usize] = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+    });
+    Some(GlyphBitmap {
+        width,
+        height,
+        texels,
+        offset: [bounds.min.x - padding as f32, bounds.min.y - padding as f32],
+    })
+}
+
+/// Turns a coverage bitmap into a distance field: 0.5 on the outline, rising
+/// to 1 `SDF_SPREAD` pixels inside and falling to 0 as far outside.
+fn signed_distance(coverage: GlyphBitmap) -> GlyphBitmap {
+    let (width, height) = (coverage.width as usize, coverage.height as usize);
+    // Partially covered texels lie on the outline; their coverage says how
+    // far from it their center is, which keeps scaled-up edges smooth.
+    let mut to_outline_outside = vec![0.0; coverage.texels.len()];
+    let mut to_outline_inside = vec![0.0; coverage.texels.len()];
+    for (i, &c) in coverage.texels.iter().enumerate() {
+        let (outside, inside) = match c {
+            255 => (0.0, f32::MAX),
+            0 => (f32::MAX, 0.0),
+            _ => {
+                let d = 0.5 - c as f32 / 255.0;
+                (d.max(0.0).powi(2), d.min(0.0).powi(2))
+            }
+        };
+        to_outline_outside[i] = outside;
+        to_outline_inside[i] = inside;
+    }
+    let outside = distance_transform(to_outline_outside, width, height);
+    let inside = distance_transform(to_outline_inside, width, height);
+    let texels = outside
+        .iter()
+        .zip(&inside)
+        .map(|(outside, inside)| {
+            let distance = outside.sqrt() - inside.sqrt();
+            ((0.5 - distance / (2.0 * SDF_SPREAD)).clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect();
+    GlyphBitmap { texels, ..coverage }
+}
+
+/// Squared distance from every texel to the nearest seed, given each texel's
+/// own squared distance to a seed (`f32::MAX` if it isn't near one), using
+/// Felzenszwalb and Huttenlocher's exact Euclidean distance transform.
+fn distance_transform(mut grid: Vec<f32>, width: usize, height: usize) -> Vec<f32> {
+    let n = width.max(height);
+    let mut f = vec![0.0; n];
+    let mut d = vec![0.0; n];
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+    for x in 0..width {
+        for y in 0..height {
+            f[y] = grid[y * width + x];
+        }
+        distance_transform_1d(&f[..height], &mut d, &mut v, &mut z);
+        for y in 0..height {
+            grid[y * width + x] = d[y];
+        }
+    }
+    for y in 0..height {
+        f[..width].copy_from_slice(&grid[y * width..(y + 1) * width]);
+        distance_transform_1d(&f[..width], &mut d, &mut v, &mut z);
+        grid[y * width..(y + 1) * width].copy_from_slice(&d[..width]);
+    }
+    grid
+}
+
+/// Lower envelope of the parabolas rooted at each sample of `f`, written to
+/// the start of `d`. `v` and `z` are scratch space at least as long as `f`
+/// (plus one for `z`).
+fn distance_transform_1d(f: &[f32], d: &mut [f32], v: &mut [usize], z: &mut [f32]) {
+    // Large enough to stand in for infinity without overflowing below.
+    let sample = |i: usize| f[i].min(1e20);
+    let intersect = |q: usize, p: usize| {
+        ((sample(q) + (q * q) as f32) - (sample(p) + (p * p) as f32)) / (2.0 * (q - p) as f32)
+    };
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+    for q in 1..f.len() {
+        let mut s = intersect(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersect(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    for (q, out) in d.iter_mut().enumerate().take(f.len()) {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let p = v[k];
+        *out = (q as f32 - p as f32).powi(2) + sample(p);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font() -> FontArc {
+        load_font(Path::new(DEFAULT_FONT)).unwrap()
+    }
+
+    fn style(align: Align, max_width: Option<f32>) -> TextStyle {
+        TextStyle {
+            align,
+            max_width,
+            ..Default::default()
+        }
+    }
+
+    /// Each line's glyphs, from their baselines.
+    fn split_lines(layout: &TextLayout) -> Vec<Vec<PositionedGlyph>> {
+        let mut lines: Vec<Vec<PositionedGlyph>> = vec![];
+        for &glyph in &layout.glyphs {
+            match lines.last_mut() {
+                Some(line) if line[0].baseline == glyph.baseline => line.push(glyph),
+                _ => lines.push(vec![glyph]),
+            }
+        }
+        lines
+    }
+
+    fn ids(font: &FontArc, text: &str) -> Vec<GlyphId> {
+        text.chars().map(|c| font.glyph_id(c)).collect()
+    }
+
+    fn line_ids(line: &[PositionedGlyph]) -> Vec<GlyphId> {
+        line.iter().map(|g| g.id).collect()
+    }
+
+    #[test]
+    fn wraps_at_max_width() {
+        let font = font();
+        let two_words = layout(&font, "aaa bbb", &TextStyle::default()).width;
+        let text = layout(
+            &font,
+            "aaa bbb ccc",
+            &style(Align::Left, Some(two_words + 1.0)),
+        );
+        let lines = split_lines(&text);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(line_ids(&lines[0]), ids(&font, "aaa bbb "));
+        assert_eq!(line_ids(&lines[1]), ids(&font, "ccc"));
+        assert_eq!(lines[1][0].x, 0.0);
+        assert_eq!(text.width, two_words + 1.0);
+
+        // Just short of the first two words, the second one moves down.
+        let text = layout(
+            &font,
+            "aaa bbb ccc",
+            &style(Align::Left, Some(two_words - 1.0)),
+        );
+        let lines = split_lines(&text);
+        assert_eq!(line_ids(&lines[0]), ids(&font, "aaa "));
+        assert_eq!(line_ids(&lines[1])[..4], ids(&font, "bbb "));
+    }
+
+    #[test]
+    fn overlong_words_overflow() {
+        let font = font();
+        let text = layout(&font, "abcdefghijkl", &style(Align::Left, Some(10.0)));
+        let lines = split_lines(&text);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].last().unwrap().x > 10.0);
+
+        // They still get lines of their own.
+        let text = layout(&font, "a abcdefghijkl b", &style(Align::Left, Some(10.0)));
+        let lines = split_lines(&text);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(line_ids(&lines[1]), ids(&font, "abcdefghijkl "));
+    }
+
+    #[test]
+    fn aligns_each_line() {
+        let font = font();
+        let short = layout(&font, "ab", &TextStyle::default()).width;
+        let long = layout(&font, "abcd", &TextStyle::default()).width;
+        let first_x = |align, max_width| {
+            let text = layout(&font, "ab\nabcd", &style(align, max_width));
+            let lines = split_lines(&text);
+            (lines[0][0].x, lines[1][0].x)
+        };
+        // Within the widest line.
+        assert_eq!(first_x(Align::Left, None), (0.0, 0.0));
+        assert_eq!(first_x(Align::Center, None), ((long - short) / 2.0, 0.0));
+        assert_eq!(first_x(Align::Right, None), (long - short, 0.0));
+        // Within the limit.
+        let (center, _) = first_x(Align::Center, Some(200.0));
+        assert_eq!(center, (200.0 - short) / 2.0);
+        let (right, right_long) = first_x(Align::Right, Some(200.0));
+        assert_eq!((right, right_long), (200.0 - short, 200.0 - long));
+    }
+
+    #[test]
+    fn newlines_break_lines() {
+        let font = font();
+        let one = layout(&font, "a", &TextStyle::default());
+        let text = layout(&font, "a\n\nb", &TextStyle::default());
+        assert_eq!(text.height, 3.0 * one.height);
+        let lines = split_lines(&text);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][0].baseline, one.glyphs[0].baseline);
+        assert_eq!(
+            lines[1][0].baseline,
+            lines[0][0].baseline + 2.0 * one.height
+        );
+        assert_eq!(lines[1][0].x, 0.0);
+    }
+}
@@ -0,0 +1,50 @@
+// Instanced glyph quads sampling the glyph atlas, either as plain coverage or
+// as a signed distance field.
+
+struct Params {
+    // Target size in pixels.
+    screen_size: vec2f,
+    sdf: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var atlas: texture_2d<f32>;
+@group(0) @binding(2) var atlas_sampler: sampler;
+
+struct Instance {
+    // Left, top, width and height in pixels.
+    @location(0) rect: vec4f,
+    @location(1) uv_rect: vec4f,
+    @location(2) color: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+    @location(1) color: vec4f,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip over the four corners.
+    let corner = vec2f(f32(index & 1u), f32(index >> 1u));
+    let pixel = instance.rect.xy + corner * instance.rect.zw;
+    let ndc = pixel / params.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4f(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = instance.uv_rect.xy + corner * instance.uv_rect.zw;
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let value = textureSample(atlas, atlas_sampler, in.uv).r;
+    // Half a pixel either side of the outline, whatever the scale.
+    let edge = fwidth(value) * 0.5;
+    var coverage = value;
+    if params.sdf != 0u {
+        coverage = smoothstep(0.5 - edge, 0.5 + edge, value);
+    }
+    return vec4f(in.color.rgb, in.color.a * coverage);
+}
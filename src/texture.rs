@@ -0,0 +1,404 @@
+use image::GenericImageView;
+
+use crate::builder::BindGroupBuilder;
+use crate::compressed::{self, CompressedImage};
+use crate::pipeline_cache::PipelineCache;
+use std::rc::Rc;
+
+/// Texture contents as loaded from disk: a decoded image, or a KTX2/DDS
+/// mip chain kept in its (usually block-compressed) file format.
+#[derive(Debug, Clone)]
+pub enum TextureData {
+    Image(image::DynamicImage),
+    Compressed(CompressedImage),
+}
+
+impl TextureData {
+    /// Reads a KTX2 or DDS container, or anything the `image` crate reads.
+    pub fn open(path: &std::path::Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if compressed::is_container(bytes) {
+            CompressedImage::from_bytes(bytes).map(Self::Compressed)
+        } else {
+            image::load_from_memory(bytes)
+                .map(Self::Image)
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// A sampled 2D texture with its default view and sampler.
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Texture {
+    /// Uploads `img` as RGBA8. Color data (albedo and the like) should pass
+    /// `srgb: true` so it's linearized when sampled.
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: &str,
+        srgb: bool,
+    ) -> Self {
+        let rgba = img.to_rgba8();
+        let (width, height) = img.dimensions();
+        Self::from_rgba8(device, queue, &rgba, width, height, label, srgb)
+    }
+
+    /// Uploads either kind of `data`, see `from_image` and `from_compressed`.
+    pub fn from_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &TextureData,
+        label: &str,
+        srgb: bool,
+    ) -> Self {
+        match data {
+            TextureData::Image(img) => Self::from_image(device, queue, img, label, srgb),
+            TextureData::Compressed(img) => Self::from_compressed(device, queue, img, label, srgb),
+        }
+    }
+
+    /// Uploads the whole mip chain of `img` as-is when the device supports
+    /// its format, and decompresses it on the CPU otherwise. `srgb` overrides
+    /// the file's own color space, as with `from_image`.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &CompressedImage,
+        label: &str,
+        srgb: bool,
+    ) -> Self {
+        let decompressed;
+        let img = if img.supported(device.features()) {
+            img
+        } else {
+            log::warn!(
+                "{label}: {:?} isn't supported by the device, decompressing it",
+                img.format
+            );
+            decompressed = img.decompress().unwrap_or_else(|e| {
+                log::warn!("{label}: {e}");
+                CompressedImage {
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    width: 1,
+                    height: 1,
+                    levels: vec![vec![255; 4]],
+                }
+            });
+            &decompressed
+        };
+        let format = if srgb {
+            img.format.add_srgb_suffix()
+        } else {
+            img.format.remove_srgb_suffix()
+        };
+        let size = wgpu::Extent3d {
+            width: img.width,
+            height: img.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: img.levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_size(None).unwrap();
+        for (level, data) in img.levels.iter().enumerate() {
+            let size = size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.width.div_ceil(block_width) * block_size),
+                    rows_per_image: Some(size.height.div_ceil(block_height)),
+                },
+                size.physical_size(format),
+            );
+        }
+        log::info!(
+            "{label}: {}x{} {format:?}, {} mips, {} KiB instead of {} KiB as RGBA8 ({:.1}x smaller)",
+            img.width,
+            img.height,
+            img.levels.len(),
+            img.gpu_bytes() / 1024,
+            img.rgba8_bytes() / 1024,
+            img.rgba8_bytes() as f32 / img.gpu_bytes() as f32,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = create_sampler(device, label);
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A 1x1 texture of `color`, for materials without a texture.
+    pub fn solid(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        srgb: bool,
+    ) -> Self {
+        Self::from_rgba8(device, queue, &color, 1, 1, label, srgb)
+    }
+
+    fn from_rgba8(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: &str,
+        srgb: bool,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: if srgb {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            } else {
+                wgpu::TextureFormat::Rgba8Unorm
+            },
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = create_sampler(device, label);
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+/// Repeating trilinear sampler for material textures.
+fn create_sampler(device: &wgpu::Device, label: &str) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(label),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// File stems of the six faces read by `load_cube_faces`, in layer order.
+pub const CUBE_FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Reads `px`, `nx`, `py`, `ny`, `pz` and `nz` images (any extension the
+/// `image` crate understands) from `dir`.
+pub fn load_cube_faces(dir: &std::path::Path) -> Result<[image::DynamicImage; 6], String> {
+    let entries: Vec<std::path::PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("{}: {e}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    let mut faces = Vec::with_capacity(6);
+    for name in CUBE_FACE_NAMES {
+        let path = entries
+            .iter()
+            .find(|path| path.file_stem().is_some_and(|stem| stem == name))
+            .ok_or_else(|| format!("{}: missing cube face `{name}`", dir.display()))?;
+        faces.push(image::open(path).map_err(|e| format!("{}: {e}", path.display()))?);
+    }
+    Ok(faces.try_into().unwrap())
+}
+
+/// A cubemap with a `Cube` view. Layers are in the usual +X, -X, +Y, -Y, +Z,
+/// -Z order.
+pub struct CubeTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl CubeTexture {
+    /// Uploads six square faces of equal size. 8-bit faces are treated as
+    /// sRGB color; float faces (`.hdr`, `.exr`) are kept as Rgba16Float.
+    pub fn from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> Result<Self, String> {
+        let (size, height) = faces[0].dimensions();
+        if size != height {
+            return Err(format!(
+                "{label}: cube faces must be square, got {size}x{height}"
+            ));
+        }
+        if let Some(face) = faces.iter().find(|face| face.dimensions() != (size, size)) {
+            let (w, h) = face.dimensions();
+            return Err(format!(
+                "{label}: cube faces differ in size, {size}x{size} and {w}x{h}"
+            ));
+        }
+        let hdr = faces.iter().any(|face| {
+            matches!(
+                face,
+                image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+            )
+        });
+        let (format, texel_bytes) = if hdr {
+            (wgpu::TextureFormat::Rgba16Float, 8)
+        } else {
+            (wgpu::TextureFormat::Rgba8UnormSrgb, 4)
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (layer, face) in faces.iter().enumerate() {
+            let texels: Vec<u8> = if hdr {
+                let texels: Vec<u16> = face
+                    .to_rgba32f()
+                    .as_raw()
+                    .iter()
+                    .map(|&c| half::f16::from_f32(c).to_bits())
+                    .collect();
+                bytemuck::cast_slice(&texels).to_vec()
+            } else {
+                face.to_rgba8().into_raw()
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &texels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(texel_bytes * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        Ok(Self::from_texture(device, texture, label))
+    }
+
+    /// Converts an equirectangular HDR image into a cubemap with `size` texels
+    /// per face. The conversion runs on the GPU.
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        equirect: &image::Rgba32FImage,
+        size: u32,
+        label: &str,
+    ) -> Self {
+        let texture = crate::ibl::equirect_to_cube(device, queue, cache, equirect, size);
+        Self::from_texture(device, texture, label)
+    }
+
+    fn from_texture(device: &wgpu::Device, texture: wgpu::Texture, label: &str) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// The cube view at binding 0 and its sampler at binding 1, visible to
+    /// fragment shaders. Anything sampling reflections can share the layout.
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+    ) -> (Rc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        BindGroupBuilder::new("Cube texture")
+            .texture_as(
+                fragment,
+                &self.texture,
+                &self.view,
+                wgpu::TextureViewDimension::Cube,
+            )
+            .sampler(fragment, &self.sampler, wgpu::SamplerBindingType::Filtering)
+            .build_cached(device, cache)
+    }
+}
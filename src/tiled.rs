@@ -0,0 +1,805 @@
+//! A viewer for images too large for one texture. The image is split into
+//! tiles with a mip pyramid on the CPU, and the tiles the pan/zoom camera
+//! can see are streamed into a fixed-size cache texture, keyed by
+//! (level, x, y). Tiles that haven't arrived yet are drawn from the closest
+//! coarser level that has.
+//!
+//! The GPU only ever holds the cache, but the decoded image and every level
+//! of its pyramid stay in host memory, about 4/3 of 4 bytes per texel. So
+//! how large an image can be is bounded by RAM rather than by the device's
+//! texture size limits.
+
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::input::{Action, Input};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{self, shader_struct};
+use crate::TargetFormats;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+/// Texels along each side of a tile.
+pub const TILE_SIZE: u32 = 256;
+/// Texels of the neighboring tiles kept around each tile in the cache, so
+/// linear filtering doesn't pick up whatever is in the next slot.
+const TILE_BORDER: u32 = 1;
+const SLOT_SIZE: u32 = TILE_SIZE + 2 * TILE_BORDER;
+/// Slots along each side of the cache texture, 4128x4128 texels for 16.
+pub const CACHE_SLOTS: u32 = 16;
+/// Uploads per frame at most, so zooming into a new area spreads its tiles
+/// over a few frames instead of stalling one.
+const UPLOADS_PER_FRAME: usize = 16;
+/// How much one wheel notch zooms.
+const ZOOM_STEP: f64 = 1.2;
+/// Window pixels per second the pan actions scroll by.
+const PAN_SPEED: f64 = 600.0;
+/// How much the zoom actions zoom per second held.
+const ZOOM_SPEED: f64 = 4.0;
+const MIN_ZOOM: f64 = 1e-4;
+const MAX_ZOOM: f64 = 64.0;
+
+/// An 8-bit RGBA image and its successively halved levels, down to one that
+/// fits in a single tile.
+pub struct TilePyramid {
+    levels: Vec<RgbaImage>,
+}
+
+impl TilePyramid {
+    /// Decodes `path` without the `image` crate's allocation limit, which
+    /// would refuse anything past 512 MiB. HDR images are clamped to 8 bits.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        let mut reader = image::io::Reader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|e| err(&e))?;
+        reader.no_limits();
+        let image = reader.decode().map_err(|e| err(&e))?.into_rgba8();
+        let start = std::time::Instant::now();
+        let pyramid = Self::new(image);
+        log::info!(
+            "Split {} ({}x{}) into {} levels of tiles in {:.2}s",
+            path.display(),
+            pyramid.width(),
+            pyramid.height(),
+            pyramid.level_count(),
+            start.elapsed().as_secs_f32()
+        );
+        Ok(pyramid)
+    }
+
+    pub fn new(image: RgbaImage) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width() <= TILE_SIZE && last.height() <= TILE_SIZE {
+                break;
+            }
+            let next = downsample(last);
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height()
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// The smallest level, a single tile.
+    pub fn thumbnail(&self) -> &RgbaImage {
+        self.levels.last().unwrap()
+    }
+
+    /// Tiles across and down `level`.
+    fn tile_counts(&self, level: u32) -> (u32, u32) {
+        let image = &self.levels[level as usize];
+        (
+            image.width().div_ceil(TILE_SIZE),
+            image.height().div_ceil(TILE_SIZE),
+        )
+    }
+
+    /// Texels in `key`, less than `TILE_SIZE` along the right and bottom
+    /// edges.
+    fn tile_size(&self, key: TileKey) -> (u32, u32) {
+        let image = &self.levels[key.level as usize];
+        (
+            (image.width() - key.x * TILE_SIZE).min(TILE_SIZE),
+            (image.height() - key.y * TILE_SIZE).min(TILE_SIZE),
+        )
+    }
+
+    /// `key`'s texels with `TILE_BORDER` more on each side, the edge texels
+    /// repeated where the image ends, as tightly packed rows.
+    fn bordered_tile(&self, key: TileKey) -> Vec<u8> {
+        let image = &self.levels[key.level as usize];
+        let (width, height) = self.tile_size(key);
+        let (x0, y0) = (
+            (key.x * TILE_SIZE) as i64 - TILE_BORDER as i64,
+            (key.y * TILE_SIZE) as i64 - TILE_BORDER as i64,
+        );
+        let mut data = Vec::with_capacity(
+            ((width + 2 * TILE_BORDER) * (height + 2 * TILE_BORDER) * 4) as usize,
+        );
+        for y in 0..(height + 2 * TILE_BORDER) as i64 {
+            let y = (y0 + y).clamp(0, image.height() as i64 - 1) as u32;
+            for x in 0..(width + 2 * TILE_BORDER) as i64 {
+                let x = (x0 + x).clamp(0, image.width() as i64 - 1) as u32;
+                data.extend_from_slice(&image.get_pixel(x, y).0);
+            }
+        }
+        data
+    }
+}
+
+/// Halves `image` by averaging 2x2 blocks. Odd sizes round up, repeating
+/// the last row or column.
+fn downsample(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    RgbaImage::from_fn(width.div_ceil(2), height.div_ceil(2), |x, y| {
+        let (x0, y0) = (2 * x, 2 * y);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let texels = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].map(|(x, y)| image.get_pixel(x, y).0);
+        image::Rgba(std::array::from_fn(|c| {
+            ((texels.iter().map(|t| t[c] as u32).sum::<u32>() + 2) / 4) as u8
+        }))
+    })
+}
+
+/// A tile of one level of a `TilePyramid`, level 0 being the full image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileKey {
+    /// The tile covering this one on the next coarser level.
+    fn parent(self) -> Self {
+        Self {
+            level: self.level + 1,
+            x: self.x / 2,
+            y: self.y / 2,
+        }
+    }
+}
+
+/// Which part of the image the window shows: the image position at the
+/// window's center, in full-resolution texels, and how many window pixels a
+/// texel covers. Dragging pans and the wheel zooms around the cursor; the
+/// pan and zoom actions do the same around the window's center.
+#[derive(Debug, Clone)]
+pub struct PanZoom {
+    pub center: [f64; 2],
+    pub zoom: f64,
+    /// Window size in pixels, as of the last `TiledView::update`.
+    viewport: [f64; 2],
+    dragging: bool,
+    cursor: Option<PhysicalPosition<f64>>,
+}
+
+impl PanZoom {
+    /// Shows all of an image of `size` texels in `viewport` pixels.
+    pub fn fit(size: [f64; 2], viewport: [f64; 2]) -> Self {
+        Self {
+            center: [size[0] / 2.0, size[1] / 2.0],
+            zoom: (viewport[0] / size[0]).min(viewport[1] / size[1]),
+            viewport,
+            dragging: false,
+            cursor: None,
+        }
+    }
+
+    /// The image position under window pixel `pixel`.
+    fn to_image(&self, pixel: [f64; 2]) -> [f64; 2] {
+        [0, 1].map(|i| self.center[i] + (pixel[i] - self.viewport[i] / 2.0) / self.zoom)
+    }
+
+    /// The window pixel over image position `texel`.
+    fn to_window(&self, texel: [f64; 2]) -> [f64; 2] {
+        [0, 1].map(|i| (texel[i] - self.center[i]) * self.zoom + self.viewport[i] / 2.0)
+    }
+
+    /// Returns true if the event was consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.dragging, self.cursor) {
+                    self.center[0] -= (position.x - last.x) / self.zoom;
+                    self.center[1] -= (position.y - last.y) / self.zoom;
+                }
+                self.cursor = Some(*position);
+                self.dragging
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let notches = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y as f64,
+                    MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => *y / 50.0,
+                };
+                let anchor = self
+                    .cursor
+                    .map_or(self.window_center(), |cursor| [cursor.x, cursor.y]);
+                self.zoom_around(anchor, ZOOM_STEP.powf(notches));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pans and zooms by the actions held, for a frame lasting `dt`.
+    pub fn handle_actions(&mut self, input: &Input, dt: Duration) {
+        let dt = dt.as_secs_f64();
+        let amount =
+            |positive, negative| (input.held(positive) as i32 - input.held(negative) as i32) as f64;
+        let pan = [
+            amount(Action::PanRight, Action::PanLeft),
+            amount(Action::PanDown, Action::PanUp),
+        ];
+        self.center = [0, 1].map(|i| self.center[i] + pan[i] * PAN_SPEED * dt / self.zoom);
+        let zoom = amount(Action::ZoomIn, Action::ZoomOut);
+        if zoom != 0.0 {
+            self.zoom_around(self.window_center(), ZOOM_SPEED.powf(zoom * dt));
+        }
+    }
+
+    fn window_center(&self) -> [f64; 2] {
+        [self.viewport[0] / 2.0, self.viewport[1] / 2.0]
+    }
+
+    /// Scales the zoom by `factor`, keeping the texel under window pixel
+    /// `anchor` where it is.
+    fn zoom_around(&mut self, anchor: [f64; 2], factor: f64) {
+        let before = self.to_image(anchor);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let after = self.to_image(anchor);
+        self.center = [0, 1].map(|i| self.center[i] + before[i] - after[i]);
+    }
+}
+
+/// Matches `Params` in tiled.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TiledUniform {
+    screen_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+shader_struct!(TiledUniform = "Params" { screen_size });
+
+/// One tile quad. Matches `Instance` in tiled.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TileInstance {
+    /// Left, top, width and height in pixels.
+    rect: [f32; 4],
+    /// The same in the cache texture, normalized.
+    uv_rect: [f32; 4],
+}
+
+impl TileInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TileInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Which tile each slot of the cache texture holds. When it's full, the
+/// least recently drawn tile makes way, unless it was drawn this frame.
+struct TileCache {
+    slots: HashMap<TileKey, u32>,
+    /// The tile in each slot and the frame it was last drawn in.
+    entries: Vec<Option<(TileKey, u64)>>,
+}
+
+impl TileCache {
+    /// `slot_count` empty slots, `CACHE_SLOTS` squared for the texture.
+    fn new(slot_count: usize) -> Self {
+        Self {
+            slots: HashMap::new(),
+            entries: vec![None; slot_count],
+        }
+    }
+
+    /// The texture the slots are laid out in, `CACHE_SLOTS` along each side.
+    fn create_texture(device: &wgpu::Device, format: wgpu::TextureFormat) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Tile cache"),
+            size: wgpu::Extent3d {
+                width: CACHE_SLOTS * SLOT_SIZE,
+                height: CACHE_SLOTS * SLOT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    }
+
+    /// The slot holding `key`, marked as drawn in `frame`.
+    fn get(&mut self, key: TileKey, frame: u64) -> Option<u32> {
+        let slot = *self.slots.get(&key)?;
+        self.entries[slot as usize] = Some((key, frame));
+        Some(slot)
+    }
+
+    /// A slot for `key`, empty or taken from the least recently drawn tile.
+    /// `None` when every slot was drawn in `frame`.
+    fn allocate(&mut self, key: TileKey, frame: u64) -> Option<u32> {
+        let (slot, entry) = self
+            .entries
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, entry)| entry.map_or(0, |(_, used)| used + 1))?;
+        if entry.is_some_and(|(_, used)| used == frame) {
+            return None;
+        }
+        if let Some((old, _)) = entry {
+            self.slots.remove(&old);
+        }
+        self.entries[slot] = Some((key, frame));
+        self.slots.insert(key, slot as u32);
+        Some(slot as u32)
+    }
+
+    /// Top left texel of the tile in `slot`, past its border.
+    fn origin(slot: u32) -> [u32; 2] {
+        [
+            slot % CACHE_SLOTS * SLOT_SIZE + TILE_BORDER,
+            slot / CACHE_SLOTS * SLOT_SIZE + TILE_BORDER,
+        ]
+    }
+
+    fn resident(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+/// What the last `update` did, for the settings panel.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TileStats {
+    /// The pyramid level drawn, 0 being full resolution.
+    pub level: u32,
+    pub visible: usize,
+    /// Visible tiles drawn from a coarser level while they're missing.
+    pub fallbacks: usize,
+    pub uploaded: usize,
+    pub resident: usize,
+}
+
+/// Draws a `TilePyramid` through the pan/zoom camera into the main pass.
+/// Call `update` every frame before `draw`.
+pub struct TiledView {
+    pub pyramid: Rc<TilePyramid>,
+    pub camera: PanZoom,
+    cache: TileCache,
+    cache_texture: wgpu::Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    shader: Rc<wgpu::ShaderModule>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+    frame: u64,
+    pub stats: TileStats,
+}
+
+impl TiledView {
+    /// Starts out showing all of `pyramid` in a `width` x `height` window.
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+        pyramid: Rc<TilePyramid>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        // The tiles hold sRGB values. Sampling them as such only undoes the
+        // encoding when the target is going to redo it.
+        let format = if targets.color.is_srgb() {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let cache_texture = TileCache::create_texture(device, format);
+        let view = cache_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tile cache sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tiled view uniform buffer"),
+            size: std::mem::size_of::<TiledUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("Tiled view")
+            .uniform(wgpu::ShaderStages::VERTEX, &uniform_buffer)
+            .texture(wgpu::ShaderStages::FRAGMENT, &cache_texture, &view)
+            .sampler(
+                wgpu::ShaderStages::FRAGMENT,
+                &sampler,
+                wgpu::SamplerBindingType::Filtering,
+            )
+            .build_cached(device, cache);
+        let source = include_str!("tiled.wgsl");
+        reflect::assert_matches("tiled.wgsl", source, |shader| {
+            shader.check_uniform::<TiledUniform>()
+        });
+        let shader = cache.shader_module(device, "Tiled view shader", source);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tiled view pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, cache, &pipeline_layout, &shader, targets);
+        let camera = PanZoom::fit(
+            [pyramid.width() as f64, pyramid.height() as f64],
+            [width as f64, height as f64],
+        );
+        Self {
+            pyramid,
+            camera,
+            cache: TileCache::new((CACHE_SLOTS * CACHE_SLOTS) as usize),
+            cache_texture,
+            uniform_buffer,
+            bind_group,
+            shader,
+            pipeline_layout,
+            pipeline,
+            instance_buffer: Self::create_instance_buffer(device, 64),
+            instance_count: 0,
+            frame: 0,
+            stats: TileStats::default(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        targets: TargetFormats,
+    ) -> Rc<wgpu::RenderPipeline> {
+        PipelineBuilder::new("Tiled view pipeline", layout, shader, targets.color)
+            .vertex_buffer(TileInstance::desc())
+            .topology(wgpu::PrimitiveTopology::TriangleStrip)
+            .targets(targets)
+            .build_cached(device, cache)
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile instance buffer"),
+            size: capacity * std::mem::size_of::<TileInstance>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn set_targets(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        targets: TargetFormats,
+    ) {
+        self.pipeline =
+            Self::create_pipeline(device, cache, &self.pipeline_layout, &self.shader, targets);
+    }
+
+    /// Returns true if the event was consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera.input(event)
+    }
+
+    /// The pan and zoom actions, for a frame lasting `dt`. Call before
+    /// `update`.
+    pub fn handle_actions(&mut self, input: &Input, dt: Duration) {
+        self.camera.handle_actions(input, dt);
+    }
+
+    /// Picks the level matching the zoom, uploads up to `UPLOADS_PER_FRAME`
+    /// of the visible tiles the cache is missing, nearest the center first,
+    /// and lays out the frame's quads for a `width` x `height` target.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.frame += 1;
+        let pyramid = self.pyramid.clone();
+        let camera = &mut self.camera;
+        camera.viewport = [width as f64, height as f64];
+        camera.center[0] = camera.center[0].clamp(0.0, pyramid.width() as f64);
+        camera.center[1] = camera.center[1].clamp(0.0, pyramid.height() as f64);
+
+        // The level whose texels come closest to one window pixel.
+        let level =
+            ((1.0 / camera.zoom).log2().round().max(0.0) as u32).min(pyramid.level_count() - 1);
+        let scale = (1u64 << level) as f64;
+        let extent = TILE_SIZE as f64 * scale;
+        let (tiles_x, tiles_y) = pyramid.tile_counts(level);
+        let top_left = camera.to_image([0.0, 0.0]);
+        let bottom_right = camera.to_image(camera.viewport);
+        let range = |min: f64, max: f64, count: u32| {
+            let first = (min / extent).floor().clamp(0.0, count as f64) as u32;
+            let last = (max / extent).ceil().clamp(0.0, count as f64) as u32;
+            first..last
+        };
+        let mut visible: Vec<TileKey> = range(top_left[1], bottom_right[1], tiles_y)
+            .flat_map(|y| {
+                range(top_left[0], bottom_right[0], tiles_x).map(move |x| TileKey { level, x, y })
+            })
+            .collect();
+        let center = camera.center.map(|c| c / extent - 0.5);
+        visible.sort_by(|a, b| {
+            let distance =
+                |k: &TileKey| (k.x as f64 - center[0]).powi(2) + (k.y as f64 - center[1]).powi(2);
+            distance(a).total_cmp(&distance(b))
+        });
+
+        // The single tile of the coarsest level goes first and stays, so
+        // there's always something to fall back on.
+        let top = TileKey {
+            level: pyramid.level_count() - 1,
+            x: 0,
+            y: 0,
+        };
+        let mut uploaded = 0;
+        for &key in std::iter::once(&top).chain(&visible) {
+            if uploaded == UPLOADS_PER_FRAME {
+                break;
+            }
+            if self.cache.get(key, self.frame).is_some() {
+                continue;
+            }
+            let Some(slot) = self.cache.allocate(key, self.frame) else {
+                break;
+            };
+            let (tile_width, tile_height) = pyramid.tile_size(key);
+            let [x, y] = TileCache::origin(slot);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.cache_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: x - TILE_BORDER,
+                        y: y - TILE_BORDER,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &pyramid.bordered_tile(key),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * (tile_width + 2 * TILE_BORDER)),
+                    rows_per_image: Some(tile_height + 2 * TILE_BORDER),
+                },
+                wgpu::Extent3d {
+                    width: tile_width + 2 * TILE_BORDER,
+                    height: tile_height + 2 * TILE_BORDER,
+                    depth_or_array_layers: 1,
+                },
+            );
+            uploaded += 1;
+        }
+
+        let cache_size = (CACHE_SLOTS * SLOT_SIZE) as f64;
+        let mut fallbacks = 0;
+        let mut instances = Vec::with_capacity(visible.len());
+        for &key in &visible {
+            // The tile itself, or the closest coarser one that's resident.
+            let mut source = key;
+            let slot = loop {
+                if let Some(slot) = self.cache.get(source, self.frame) {
+                    break Some(slot);
+                }
+                if source.level == top.level {
+                    break None;
+                }
+                source = source.parent();
+            };
+            let Some(slot) = slot else {
+                continue;
+            };
+            if source != key {
+                fallbacks += 1;
+            }
+            let (tile_width, tile_height) = pyramid.tile_size(key);
+            let position = camera.to_window([key.x as f64 * extent, key.y as f64 * extent]);
+            let size = [tile_width as f64, tile_height as f64].map(|s| s * scale * camera.zoom);
+            // Where the tile lies in `source`, in `source`'s texels.
+            let shrink = (1u64 << (source.level - key.level)) as f64;
+            let offset = [
+                (key.x * TILE_SIZE) as f64 / shrink - (source.x * TILE_SIZE) as f64,
+                (key.y * TILE_SIZE) as f64 / shrink - (source.y * TILE_SIZE) as f64,
+            ];
+            let [x, y] = TileCache::origin(slot);
+            instances.push(TileInstance {
+                rect: [position[0], position[1], size[0], size[1]].map(|v| v as f32),
+                uv_rect: [
+                    (x as f64 + offset[0]) / cache_size,
+                    (y as f64 + offset[1]) / cache_size,
+                    tile_width as f64 / shrink / cache_size,
+                    tile_height as f64 / shrink / cache_size,
+                ]
+                .map(|v| v as f32),
+            });
+        }
+
+        let needed = (instances.len() * std::mem::size_of::<TileInstance>()) as u64;
+        if needed > self.instance_buffer.size() {
+            self.instance_buffer =
+                Self::create_instance_buffer(device, instances.len().next_power_of_two() as u64);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        self.instance_count = instances.len() as u32;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&TiledUniform {
+                screen_size: [width as f32, height as f32],
+                _pad: [0.0; 2],
+            }),
+        );
+        self.stats = TileStats {
+            level,
+            visible: visible.len(),
+            fallbacks,
+            uploaded,
+            resident: self.cache.resident(),
+        };
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..self.instance_count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(level: u32, x: u32, y: u32) -> TileKey {
+        TileKey { level, x, y }
+    }
+
+    /// An image whose texels hold their own coordinates.
+    fn coordinates(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([
+                (x % 256) as u8,
+                (x / 256) as u8,
+                (y % 256) as u8,
+                (y / 256) as u8,
+            ])
+        })
+    }
+
+    fn texel_coordinates(texel: &[u8]) -> (u32, u32) {
+        (
+            texel[0] as u32 + 256 * texel[1] as u32,
+            texel[2] as u32 + 256 * texel[3] as u32,
+        )
+    }
+
+    #[test]
+    fn cache_evicts_the_least_recently_drawn_tile() {
+        let mut cache = TileCache::new(2);
+        let first = cache.allocate(key(0, 0, 0), 0).unwrap();
+        let second = cache.allocate(key(0, 1, 0), 1).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(cache.get(key(0, 0, 0), 2), Some(first));
+
+        assert_eq!(cache.allocate(key(0, 2, 0), 3), Some(second));
+        assert_eq!(cache.get(key(0, 1, 0), 3), None);
+        assert_eq!(cache.get(key(0, 0, 0), 3), Some(first));
+        assert_eq!(cache.resident(), 2);
+    }
+
+    #[test]
+    fn cache_keeps_tiles_drawn_this_frame() {
+        let mut cache = TileCache::new(2);
+        cache.allocate(key(0, 0, 0), 5).unwrap();
+        cache.allocate(key(0, 1, 0), 5).unwrap();
+        assert_eq!(cache.allocate(key(0, 2, 0), 5), None);
+        assert_eq!(cache.resident(), 2);
+        assert!(cache.get(key(0, 0, 0), 5).is_some());
+        assert!(cache.get(key(0, 1, 0), 5).is_some());
+
+        assert!(cache.allocate(key(0, 2, 0), 6).is_some());
+    }
+
+    #[test]
+    fn zoom_keeps_the_anchor_still() {
+        let mut camera = PanZoom::fit([1000.0, 500.0], [800.0, 600.0]);
+        let anchor = [100.0, 450.0];
+        for factor in [3.0, 0.25, 1e9] {
+            let before = camera.to_image(anchor);
+            camera.zoom_around(anchor, factor);
+            let after = camera.to_image(anchor);
+            for i in 0..2 {
+                assert!((before[i] - after[i]).abs() < 1e-9, "{before:?} {after:?}");
+            }
+        }
+        assert_eq!(camera.zoom, MAX_ZOOM);
+    }
+
+    #[test]
+    fn edge_tiles_are_partial() {
+        let pyramid = TilePyramid::new(coordinates(600, 300));
+        assert_eq!(pyramid.level_count(), 3);
+        assert_eq!(pyramid.tile_counts(0), (3, 2));
+        assert_eq!(pyramid.tile_size(key(0, 0, 0)), (TILE_SIZE, TILE_SIZE));
+        assert_eq!(pyramid.tile_size(key(0, 2, 0)), (88, TILE_SIZE));
+        assert_eq!(pyramid.tile_size(key(0, 0, 1)), (TILE_SIZE, 44));
+        assert_eq!(pyramid.tile_size(key(0, 2, 1)), (88, 44));
+        assert_eq!(pyramid.tile_size(key(2, 0, 0)), (150, 75));
+    }
+
+    #[test]
+    fn bordered_tile_repeats_the_image_edge() {
+        let pyramid = TilePyramid::new(coordinates(600, 300));
+        let data = pyramid.bordered_tile(key(0, 2, 1));
+        let (width, height) = (88 + 2 * TILE_BORDER, 44 + 2 * TILE_BORDER);
+        assert_eq!(data.len(), (width * height * 4) as usize);
+        let at = |x: u32, y: u32| {
+            let start = ((y * width + x) * 4) as usize;
+            texel_coordinates(&data[start..start + 4])
+        };
+        // The border reaches into the neighboring tiles on the top and left
+        // and repeats the last column and row on the right and bottom.
+        assert_eq!(at(0, 0), (511, 255));
+        assert_eq!(at(1, 1), (512, 256));
+        assert_eq!(at(width - 2, height - 2), (599, 299));
+        assert_eq!(at(width - 1, height - 1), (599, 299));
+        assert_eq!(at(width - 1, 1), (599, 256));
+    }
+
+    #[test]
+    fn downsample_rounds_odd_sizes_up() {
+        let image = RgbaImage::from_fn(3, 5, |x, y| {
+            image::Rgba([(10 * x) as u8, (10 * y) as u8, 0, 255])
+        });
+        let half = downsample(&image);
+        assert_eq!(half.dimensions(), (2, 3));
+        assert_eq!(half.get_pixel(0, 0).0, [5, 5, 0, 255]);
+        // The last column and row only have themselves to average.
+        assert_eq!(half.get_pixel(1, 0).0, [20, 5, 0, 255]);
+        assert_eq!(half.get_pixel(0, 2).0, [5, 40, 0, 255]);
+        assert_eq!(half.get_pixel(1, 2).0, [20, 40, 0, 255]);
+    }
+}
@@ -0,0 +1,39 @@
+// Instanced tile quads sampling the tile cache.
+
+struct Params {
+    // Target size in pixels.
+    screen_size: vec2f,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var cache: texture_2d<f32>;
+@group(0) @binding(2) var cache_sampler: sampler;
+
+struct Instance {
+    // Left, top, width and height in pixels.
+    @location(0) rect: vec4f,
+    // The same in the cache texture, normalized.
+    @location(1) uv_rect: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) uv: vec2f,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip over the four corners.
+    let corner = vec2f(f32(index & 1u), f32(index >> 1u));
+    let pixel = instance.rect.xy + corner * instance.rect.zw;
+    let ndc = pixel / params.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4f(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = instance.uv_rect.xy + corner * instance.uv_rect.zw;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return vec4f(textureSample(cache, cache_sampler, in.uv).rgb, 1.0);
+}
@@ -0,0 +1,193 @@
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::input::{Action, Input};
+use crate::pipeline_cache::PipelineCache;
+use crate::preprocessor;
+use crate::reflect::{self, shader_struct};
+use std::rc::Rc;
+
+/// Format the 3D path renders into before tonemapping.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Aces = 0,
+    Reinhard = 1,
+}
+
+/// Matches `Params` in tonemap.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    curve: u32,
+    encode_srgb: u32,
+    _pad: u32,
+}
+
+shader_struct!(TonemapUniform = "Params" { exposure, curve, encode_srgb });
+
+/// Fullscreen pass mapping an HDR color texture into the output format.
+pub struct Tonemap {
+    pub operator: Operator,
+    /// Scene color is multiplied by this before the curve is applied.
+    pub exposure: f32,
+    output_format: wgpu::TextureFormat,
+    buffer: wgpu::Buffer,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    dirty: bool,
+}
+
+impl Tonemap {
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap buffer"),
+            size: std::mem::size_of::<TonemapUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The layout is derived from the input texture, so it's taken from
+        // a stand-in in the same format. `bind_input` gets the same one
+        // back from the cache.
+        let placeholder = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Tonemap placeholder input"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let placeholder_view = placeholder.create_view(&Default::default());
+        let (bind_group_layout, _) =
+            Self::create_bind_group(device, cache, &buffer, &placeholder, &placeholder_view);
+        let source = preprocessor::preprocess("tonemap.wgsl", &[])
+            .unwrap_or_else(|e| panic!("{e}"))
+            .source;
+        reflect::assert_matches("tonemap.wgsl", &source, |shader| {
+            shader.check_uniform::<TonemapUniform>()
+        });
+        let shader = cache.shader_module(device, "Tonemap shader", &source);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = PipelineBuilder::new("Tonemap pipeline", &layout, &shader, output_format)
+            .blend(wgpu::BlendState::REPLACE)
+            .build_cached(device, cache);
+        Self {
+            operator: Operator::Aces,
+            exposure: 1.0,
+            output_format,
+            buffer,
+            pipeline,
+            dirty: true,
+        }
+    }
+
+    /// A bind group reading `hdr_view` of `hdr`, which has to be a
+    /// single-sampled `HDR_FORMAT` texture. Has to be recreated whenever the
+    /// HDR texture is.
+    pub fn bind_input(
+        &self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        hdr: &wgpu::Texture,
+        hdr_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        Self::create_bind_group(device, cache, &self.buffer, hdr, hdr_view).1
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        buffer: &wgpu::Buffer,
+        input: &wgpu::Texture,
+        input_view: &wgpu::TextureView,
+    ) -> (Rc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
+        BindGroupBuilder::new("Tonemap")
+            .uniform(wgpu::ShaderStages::FRAGMENT, buffer)
+            .texture(wgpu::ShaderStages::FRAGMENT, input, input_view)
+            .build_cached(device, cache)
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&TonemapUniform {
+                exposure: self.exposure,
+                curve: self.operator as u32,
+                encode_srgb: !self.output_format.is_srgb() as u32,
+                _pad: 0,
+            }),
+        );
+        self.dirty = false;
+    }
+
+    /// The tonemap actions switch between ACES and Reinhard and scale the
+    /// exposure.
+    pub fn handle_actions(&mut self, input: &Input) {
+        let mut changed = false;
+        if input.pressed(Action::CycleTonemap) {
+            self.operator = match self.operator {
+                Operator::Aces => Operator::Reinhard,
+                Operator::Reinhard => Operator::Aces,
+            };
+            changed = true;
+        }
+        if input.pressed(Action::LowerExposure) {
+            self.exposure *= 0.8;
+            changed = true;
+        }
+        if input.pressed(Action::RaiseExposure) {
+            self.exposure *= 1.25;
+            changed = true;
+        }
+        if !changed {
+            return;
+        }
+        log::info!(
+            "Tonemapping: {:?}, exposure {}",
+            self.operator,
+            self.exposure
+        );
+        self.dirty = true;
+    }
+
+    /// Draws `input` into `output`, which must have the format passed to `new`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, input, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
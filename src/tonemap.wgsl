@@ -0,0 +1,29 @@
+// Maps the HDR scene color to the output range with a fullscreen triangle.
+
+#include "common.wgsl"
+
+struct Params {
+    exposure: f32,
+    curve: u32,
+    // Set when the output format isn't sRGB, so the shader has to encode.
+    encode_srgb: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var hdr: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let color = textureLoad(hdr, vec2i(position.xy), 0).rgb * params.exposure;
+    var mapped = tonemap(color, params.curve);
+    if params.encode_srgb != 0u {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4f(mapped, 1.0);
+}
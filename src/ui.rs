@@ -0,0 +1,494 @@
+use crate::builder::{BindGroupBuilder, PipelineBuilder};
+use crate::pipeline_cache::PipelineCache;
+use crate::reflect::{self, shader_struct};
+use crate::text::{self, Align, FontArc, Rasterization, TextRenderer, TextStyle};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use winit::event::*;
+
+const PANEL_WIDTH: f32 = 240.0;
+const PADDING: f32 = 8.0;
+const ROW_HEIGHT: f32 = 22.0;
+const LABEL_WIDTH: f32 = 96.0;
+const TEXT_SIZE: f32 = 14.0;
+
+// sRGB with straight alpha, like `TextStyle::color`.
+const PANEL_COLOR: [f32; 4] = [0.08, 0.08, 0.1, 0.85];
+const TITLE_COLOR: [f32; 4] = [0.2, 0.35, 0.6, 1.0];
+const WIDGET_COLOR: [f32; 4] = [0.22, 0.22, 0.27, 1.0];
+const HOVER_COLOR: [f32; 4] = [0.3, 0.3, 0.37, 1.0];
+const ACCENT_COLOR: [f32; 4] = [0.3, 0.55, 0.9, 1.0];
+const TEXT_COLOR: [f32; 4] = [0.92, 0.92, 0.92, 1.0];
+
+/// Matches `Params` in ui.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct UiUniform {
+    screen_size: [f32; 2],
+    _pad: [f32; 2],
+}
+
+shader_struct!(UiUniform = "Params" { screen_size });
+
+/// One filled rectangle. Matches `Instance` in ui.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct QuadInstance {
+    /// Left, top, width and height in pixels.
+    rect: [f32; 4],
+    color: [f32; 4],
+}
+
+impl QuadInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<QuadInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Left mouse button state, accumulated from window events between frames.
+#[derive(Debug, Default)]
+struct Pointer {
+    position: [f32; 2],
+    down: bool,
+    /// Went down or up since the last `prepare`.
+    pressed: bool,
+    released: bool,
+}
+
+/// A minimal immediate-mode GUI drawn over the finished frame. Widgets are
+/// declared every frame through `panel`, read the pointer state gathered by
+/// `input`, and write straight into the values they edit.
+pub struct Ui {
+    pub visible: bool,
+    pointer: Pointer,
+    /// The slider being dragged, if any.
+    active: Option<u64>,
+    /// Panels drawn last frame. Clicks inside them belong to the UI.
+    panel_rects: Vec<[f32; 4]>,
+    quads: Vec<QuadInstance>,
+    text: TextRenderer,
+    line_height: f32,
+    encode_srgb: bool,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: Rc<wgpu::RenderPipeline>,
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+}
+
+impl Ui {
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        output_format: wgpu::TextureFormat,
+        font: FontArc,
+    ) -> Self {
+        let text = TextRenderer::new(device, cache, output_format, font, Rasterization::Bitmap);
+        let line_height = text.measure("Ag", &Self::text_style())[1];
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("UI uniform buffer"),
+            size: std::mem::size_of::<UiUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (bind_group_layout, bind_group) = BindGroupBuilder::new("UI")
+            .uniform(wgpu::ShaderStages::VERTEX, &uniform_buffer)
+            .build_cached(device, cache);
+        let source = include_str!("ui.wgsl");
+        reflect::assert_matches("ui.wgsl", source, |shader| {
+            shader.check_uniform::<UiUniform>()
+        });
+        let shader = cache.shader_module(device, "UI shader", source);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("UI pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = PipelineBuilder::new("UI pipeline", &layout, &shader, output_format)
+            .vertex_buffer(QuadInstance::desc())
+            .blend(wgpu::BlendState::ALPHA_BLENDING)
+            .topology(wgpu::PrimitiveTopology::TriangleStrip)
+            .build_cached(device, cache);
+        Self {
+            visible: true,
+            pointer: Pointer::default(),
+            active: None,
+            panel_rects: vec![],
+            quads: vec![],
+            text,
+            line_height,
+            encode_srgb: output_format.is_srgb(),
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            instance_buffer: Self::create_instance_buffer(device, 256),
+            instance_count: 0,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("UI instance buffer"),
+            size: capacity * std::mem::size_of::<QuadInstance>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn text_style() -> TextStyle {
+        TextStyle {
+            size: TEXT_SIZE,
+            color: TEXT_COLOR,
+            ..Default::default()
+        }
+    }
+
+    fn hovered_panel(&self) -> bool {
+        let [x, y] = self.pointer.position;
+        self.visible
+            && self
+                .panel_rects
+                .iter()
+                .any(|r| x >= r[0] && x < r[0] + r[2] && y >= r[1] && y < r[1] + r[3])
+    }
+
+    /// Mouse events over a panel, or while a slider is dragged, are
+    /// consumed.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer.position = [position.x as f32, position.y as f32];
+                self.active.is_some()
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match state {
+                ElementState::Pressed if self.hovered_panel() => {
+                    self.pointer.down = true;
+                    self.pointer.pressed = true;
+                    true
+                }
+                ElementState::Released if self.pointer.down => {
+                    self.pointer.down = false;
+                    self.pointer.released = true;
+                    true
+                }
+                _ => false,
+            },
+            WindowEvent::MouseWheel { .. } => self.hovered_panel(),
+            _ => false,
+        }
+    }
+
+    /// Starts declaring this frame's widgets.
+    pub fn begin_frame(&mut self) {
+        self.quads.clear();
+        self.panel_rects.clear();
+    }
+
+    /// A column of widgets with its top left at `position`. The background
+    /// is sized to the widgets once the panel is dropped.
+    pub fn panel(&mut self, title: &str, position: [f32; 2]) -> Panel<'_> {
+        let [x, y] = position;
+        let background = self.quads.len();
+        self.quad([x, y, PANEL_WIDTH, 0.0], PANEL_COLOR);
+        self.quad([x, y, PANEL_WIDTH, ROW_HEIGHT], TITLE_COLOR);
+        self.label_text(title, [x + PADDING, y], PANEL_WIDTH, Align::Left);
+        let mut hasher = DefaultHasher::new();
+        title.hash(&mut hasher);
+        Panel {
+            id: hasher.finish(),
+            x: x + PADDING,
+            y: y + ROW_HEIGHT + PADDING,
+            width: PANEL_WIDTH - 2.0 * PADDING,
+            top: y,
+            background,
+            ui: self,
+        }
+    }
+
+    fn quad(&mut self, rect: [f32; 4], color: [f32; 4]) {
+        let color = if self.encode_srgb {
+            let [r, g, b, a] = color;
+            [
+                text::srgb_to_linear(r),
+                text::srgb_to_linear(g),
+                text::srgb_to_linear(b),
+                a,
+            ]
+        } else {
+            color
+        };
+        self.quads.push(QuadInstance { rect, color });
+    }
+
+    /// Text vertically centered in a row starting at `position`, aligned
+    /// within `width`.
+    fn label_text(&mut self, label: &str, position: [f32; 2], width: f32, align: Align) {
+        let top = position[1] + ((ROW_HEIGHT - self.line_height) / 2.0).round();
+        self.text.queue_text(
+            label,
+            [position[0], top],
+            &TextStyle {
+                align,
+                max_width: Some(width),
+                ..Self::text_style()
+            },
+        );
+    }
+
+    fn hovered(&self, rect: [f32; 4]) -> bool {
+        let [x, y] = self.pointer.position;
+        x >= rect[0] && x < rect[0] + rect[2] && y >= rect[1] && y < rect[1] + rect[3]
+    }
+
+    fn clicked(&self, rect: [f32; 4]) -> bool {
+        self.pointer.pressed && self.hovered(rect)
+    }
+
+    /// Uploads this frame's widgets and resets the per-frame pointer state.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.text.prepare(device, queue, width, height);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&UiUniform {
+                screen_size: [width as f32, height as f32],
+                _pad: [0.0; 2],
+            }),
+        );
+        let capacity = self.instance_buffer.size() / std::mem::size_of::<QuadInstance>() as u64;
+        if self.quads.len() as u64 > capacity {
+            let capacity = (self.quads.len() as u64).next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.quads));
+        self.instance_count = self.quads.len() as u32;
+        self.pointer.pressed = false;
+        self.pointer.released = false;
+        if !self.pointer.down {
+            self.active = None;
+        }
+    }
+
+    /// Draws the UI over the existing contents of `output`, which must have
+    /// the format passed to `new`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if !self.visible || self.instance_count == 0 {
+            return;
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+            render_pass.draw(0..4, 0..self.instance_count);
+        }
+        self.text.render(encoder, output);
+    }
+}
+
+/// Lays widgets out top to bottom, one row each. Every widget returns whether
+/// it changed its value this frame.
+pub struct Panel<'a> {
+    ui: &'a mut Ui,
+    id: u64,
+    x: f32,
+    y: f32,
+    width: f32,
+    top: f32,
+    /// Index of the background quad, resized on drop.
+    background: usize,
+}
+
+impl Panel<'_> {
+    fn widget_id(&self, label: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.id, label).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Moves to the next row, returning the label and widget rectangles of
+    /// the current one.
+    fn row(&mut self, label: &str) -> [f32; 4] {
+        self.ui
+            .label_text(label, [self.x, self.y], LABEL_WIDTH, Align::Left);
+        let rect = [
+            self.x + LABEL_WIDTH,
+            self.y + 2.0,
+            self.width - LABEL_WIDTH,
+            ROW_HEIGHT - 4.0,
+        ];
+        self.y += ROW_HEIGHT;
+        rect
+    }
+
+    /// A line of text spanning the panel.
+    pub fn label(&mut self, text: &str) {
+        self.ui
+            .label_text(text, [self.x, self.y], self.width, Align::Left);
+        self.y += ROW_HEIGHT;
+    }
+
+    pub fn separator(&mut self) {
+        self.ui.quad(
+            [self.x, self.y + PADDING / 2.0, self.width, 1.0],
+            WIDGET_COLOR,
+        );
+        self.y += PADDING;
+    }
+
+    /// Drag anywhere on the track to set the value.
+    pub fn slider(&mut self, label: &str, value: &mut f32, range: RangeInclusive<f32>) -> bool {
+        self.slider_colored(label, value, range, ACCENT_COLOR)
+    }
+
+    fn slider_colored(
+        &mut self,
+        label: &str,
+        value: &mut f32,
+        range: RangeInclusive<f32>,
+        fill: [f32; 4],
+    ) -> bool {
+        let id = self.widget_id(label);
+        let rect = self.row(label);
+        if self.ui.clicked(rect) {
+            self.ui.active = Some(id);
+        }
+        let (min, max) = (*range.start(), *range.end());
+        let mut changed = false;
+        if self.ui.active == Some(id) {
+            let t = ((self.ui.pointer.position[0] - rect[0]) / rect[2]).clamp(0.0, 1.0);
+            let new_value = min + t * (max - min);
+            changed = new_value != *value;
+            *value = new_value;
+        }
+        let background = if self.ui.hovered(rect) || self.ui.active == Some(id) {
+            HOVER_COLOR
+        } else {
+            WIDGET_COLOR
+        };
+        self.ui.quad(rect, background);
+        let t = ((*value - min) / (max - min)).clamp(0.0, 1.0);
+        self.ui.quad([rect[0], rect[1], rect[2] * t, rect[3]], fill);
+        self.ui.label_text(
+            &format!("{value:.2}"),
+            [rect[0], rect[1] - 2.0],
+            rect[2],
+            Align::Center,
+        );
+        changed
+    }
+
+    /// A swatch followed by a slider per channel.
+    pub fn color_edit(&mut self, label: &str, color: &mut [f32; 4]) -> bool {
+        let swatch = self.row(label);
+        self.ui.quad(swatch, WIDGET_COLOR);
+        self.ui.quad(
+            [
+                swatch[0] + 1.0,
+                swatch[1] + 1.0,
+                swatch[2] - 2.0,
+                swatch[3] - 2.0,
+            ],
+            *color,
+        );
+        let mut changed = false;
+        let channels = [
+            ("R", [0.75, 0.2, 0.2, 1.0]),
+            ("G", [0.2, 0.65, 0.25, 1.0]),
+            ("B", [0.25, 0.35, 0.85, 1.0]),
+            ("A", [0.6, 0.6, 0.6, 1.0]),
+        ];
+        for (c, (name, fill)) in color.iter_mut().zip(channels) {
+            // Indented under the swatch; the id still includes `label`.
+            changed |= self.slider_colored(&format!("   {label} {name}"), c, 0.0..=1.0, fill);
+        }
+        changed
+    }
+
+    pub fn checkbox(&mut self, label: &str, value: &mut bool) -> bool {
+        let rect = self.row(label);
+        let size = rect[3];
+        let bx = [rect[0], rect[1], size, size];
+        let changed = self.ui.clicked(rect);
+        if changed {
+            *value = !*value;
+        }
+        let background = if self.ui.hovered(rect) {
+            HOVER_COLOR
+        } else {
+            WIDGET_COLOR
+        };
+        self.ui.quad(bx, background);
+        if *value {
+            self.ui.quad(
+                [bx[0] + 4.0, bx[1] + 4.0, size - 8.0, size - 8.0],
+                ACCENT_COLOR,
+            );
+        }
+        changed
+    }
+
+    /// Picks one of `options`. Clicking the left half selects the previous
+    /// one, the right half the next.
+    pub fn choice(&mut self, label: &str, selected: &mut usize, options: &[&str]) -> bool {
+        let rect = self.row(label);
+        let mut changed = false;
+        if self.ui.clicked(rect) && !options.is_empty() {
+            let left = self.ui.pointer.position[0] < rect[0] + rect[2] / 2.0;
+            *selected = if left {
+                (*selected + options.len() - 1) % options.len()
+            } else {
+                (*selected + 1) % options.len()
+            };
+            changed = true;
+        }
+        let background = if self.ui.hovered(rect) {
+            HOVER_COLOR
+        } else {
+            WIDGET_COLOR
+        };
+        self.ui.quad(rect, background);
+        let name = options.get(*selected).copied().unwrap_or("");
+        let top = rect[1] - 2.0;
+        self.ui
+            .label_text("<", [rect[0] + 4.0, top], rect[2] - 8.0, Align::Left);
+        self.ui
+            .label_text(name, [rect[0], top], rect[2], Align::Center);
+        self.ui
+            .label_text(">", [rect[0] + 4.0, top], rect[2] - 8.0, Align::Right);
+        changed
+    }
+}
+
+impl Drop for Panel<'_> {
+    fn drop(&mut self) {
+        let height = self.y - self.top + PADDING;
+        self.ui.quads[self.background].rect[3] = height;
+        self.ui
+            .panel_rects
+            .push([self.x - PADDING, self.top, PANEL_WIDTH, height]);
+    }
+}
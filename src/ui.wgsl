@@ -0,0 +1,36 @@
+// Flat colored rectangles for the UI overlay, positioned in pixels.
+
+struct Params {
+    // Target size in pixels.
+    screen_size: vec2f,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+
+struct Instance {
+    // Left, top, width and height in pixels.
+    @location(0) rect: vec4f,
+    @location(1) color: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) color: vec4f,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip over the four corners.
+    let corner = vec2f(f32(index & 1u), f32(index >> 1u));
+    let pixel = instance.rect.xy + corner * instance.rect.zw;
+    let ndc = pixel / params.screen_size * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4f(ndc.x, -ndc.y, 0.0, 1.0);
+    out.color = instance.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return in.color;
+}